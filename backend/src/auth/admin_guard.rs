@@ -0,0 +1,100 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::Method,
+    middleware::Next,
+    web, Error, HttpMessage, HttpResponse,
+};
+use serde_json::json;
+use log::{error, warn};
+
+use crate::auth::{authenticate, AuthError};
+use crate::db;
+use crate::models::Role;
+
+// The signed-in user that passed the admin guard, available to handlers as ReqData
+#[derive(Debug, Clone)]
+pub struct AdminActor {
+    pub email: String,
+    pub role: Role,
+}
+
+// Guard for admin and debug scopes: auditors may read, only admins may change anything.
+// Denied attempts and allowed changes are written to the admin audit trail.
+pub async fn require_admin_role<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let pool = match req.app_data::<web::Data<sqlx::PgPool>>() {
+        Some(pool) => pool.clone(),
+        None => {
+            error!("Admin guard has no database pool");
+            let response = HttpResponse::InternalServerError().finish();
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    };
+
+    let method = req.method().clone();
+    let path = req.path().to_string();
+    let ip_address = req.connection_info().realip_remote_addr().map(|ip| ip.to_string());
+    let read_only = method == Method::GET || method == Method::HEAD;
+
+    // Admin access is only granted to browser sessions, never to access tokens
+    let email = match authenticate(req.request()).await {
+        Ok(user) if user.require_session().is_ok() => Some(user.email),
+        Ok(_) => None,
+        Err(AuthError::Database(e)) => {
+            error!("Database error in admin guard: {}", e);
+            let response = HttpResponse::InternalServerError().json(json!({
+                "error": "Database error",
+                "details": format!("{}", e)
+            }));
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+        Err(_) => None,
+    };
+
+    let role = match email {
+        Some(ref email) => db::roles::get_user_role(pool.get_ref(), email).await.unwrap_or_else(|e| {
+            error!("Failed to load role for {}: {}", email, e);
+            None
+        }),
+        None => None,
+    };
+
+    let actor = match (email, role) {
+        (Some(email), Some(role)) if role.can_write_admin() || (read_only && role.can_read_admin()) => {
+            AdminActor { email, role }
+        }
+        (email, _) => {
+            let (mut response, reason) = match email {
+                None => (HttpResponse::Unauthorized(), "not_authenticated"),
+                Some(_) => (HttpResponse::Forbidden(), "insufficient_role"),
+            };
+            warn!("Denied {} {} for {:?}: {}", method, path, email, reason);
+            if let Err(e) = db::roles::log_admin_access(
+                pool.get_ref(), email.as_deref(), method.as_str(), &path, false, reason, ip_address.as_deref(),
+            ).await {
+                error!("Failed to write admin audit entry: {}", e);
+            }
+
+            let response = response.json(json!({
+                "success": false,
+                "error": if email.is_none() { "Not authenticated" } else { "You do not have permission to access this resource" },
+                "reason": reason
+            }));
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    };
+
+    if !read_only {
+        if let Err(e) = db::roles::log_admin_access(
+            pool.get_ref(), Some(&actor.email), method.as_str(), &path, true, actor.role.as_str(), ip_address.as_deref(),
+        ).await {
+            error!("Failed to write admin audit entry: {}", e);
+        }
+    }
+
+    req.extensions_mut().insert(actor);
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
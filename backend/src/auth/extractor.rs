@@ -0,0 +1,166 @@
+use actix_web::{
+    dev::Payload, http::{header, StatusCode}, web, FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+use serde_json::json;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use log::error;
+
+use crate::db;
+use crate::models::TokenScope;
+
+// How the caller proved who they are
+#[derive(Debug, Clone)]
+pub enum AuthMethod {
+    // Browser login; the session token from the cookie
+    Session(String),
+    // Personal access token from an `Authorization: Bearer` header
+    AccessToken { scopes: Vec<TokenScope> },
+}
+
+// The signed-in user, resolved from either the session cookie or a bearer token
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub email: String,
+    pub name: Option<String>,
+    pub picture: Option<String>,
+    pub refresh_token: Option<String>,
+    pub method: AuthMethod,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    NotAuthenticated,
+    InvalidSession,
+    InvalidAccessToken,
+    MissingScope(TokenScope),
+    SessionRequired,
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::NotAuthenticated => write!(f, "Not authenticated"),
+            AuthError::InvalidSession => write!(f, "Invalid or expired session"),
+            AuthError::InvalidAccessToken => write!(f, "Access token is invalid, expired or revoked"),
+            AuthError::MissingScope(scope) => write!(f, "Access token lacks the {} scope", scope.as_str()),
+            AuthError::SessionRequired => write!(f, "This action requires a browser session"),
+            AuthError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::NotAuthenticated | AuthError::InvalidSession | AuthError::InvalidAccessToken => StatusCode::UNAUTHORIZED,
+            AuthError::MissingScope(_) | AuthError::SessionRequired => StatusCode::FORBIDDEN,
+            AuthError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let body = match self {
+            AuthError::Database(e) => json!({
+                "error": "Database error",
+                "details": format!("{}", e)
+            }),
+            AuthError::MissingScope(scope) => json!({
+                "success": false,
+                "error": self.to_string(),
+                "required_scope": scope
+            }),
+            _ => json!({
+                "success": false,
+                "error": self.to_string()
+            }),
+        };
+        HttpResponse::build(self.status_code()).json(body)
+    }
+}
+
+impl AuthenticatedUser {
+    // Session users may do anything; access tokens only what they were granted
+    pub fn require_scope(&self, scope: TokenScope) -> Result<(), AuthError> {
+        match &self.method {
+            AuthMethod::Session(_) => Ok(()),
+            AuthMethod::AccessToken { scopes, .. } if scopes.contains(&scope) => Ok(()),
+            AuthMethod::AccessToken { .. } => Err(AuthError::MissingScope(scope)),
+        }
+    }
+
+    // Managing sessions and tokens is kept away from tokens themselves
+    pub fn require_session(&self) -> Result<&str, AuthError> {
+        match &self.method {
+            AuthMethod::Session(session_token) => Ok(session_token),
+            AuthMethod::AccessToken { .. } => Err(AuthError::SessionRequired),
+        }
+    }
+}
+
+// Resolve the caller; a bearer header takes precedence over the session cookie
+pub async fn authenticate(req: &HttpRequest) -> Result<AuthenticatedUser, AuthError> {
+    let pool = match req.app_data::<web::Data<sqlx::PgPool>>() {
+        Some(pool) => pool.clone(),
+        None => {
+            error!("Authentication has no database pool");
+            return Err(AuthError::NotAuthenticated);
+        }
+    };
+
+    if let Some(authorization) = req.headers().get(header::AUTHORIZATION) {
+        let token = authorization
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(AuthError::InvalidAccessToken)?;
+
+        return match db::access_tokens::get_user_by_access_token(pool.get_ref(), token).await {
+            Ok(Some(user)) => Ok(AuthenticatedUser {
+                email: user.email,
+                name: user.name,
+                picture: user.picture,
+                refresh_token: user.refresh_token,
+                method: AuthMethod::AccessToken { scopes: user.scopes },
+            }),
+            Ok(None) => Err(AuthError::InvalidAccessToken),
+            Err(e) => {
+                error!("Database error during authentication: {}", e);
+                Err(AuthError::Database(e))
+            }
+        };
+    }
+
+    let session_token = match req.cookie("session") {
+        Some(cookie) => cookie.value().to_string(),
+        None => return Err(AuthError::NotAuthenticated),
+    };
+
+    match db::get_user_by_session(pool.get_ref(), &session_token).await {
+        Ok(Some((email, name, picture, refresh_token))) => Ok(AuthenticatedUser {
+            email,
+            name,
+            picture,
+            refresh_token,
+            method: AuthMethod::Session(session_token),
+        }),
+        Ok(None) => Err(AuthError::InvalidSession),
+        Err(e) => {
+            error!("Database error during authentication: {}", e);
+            Err(AuthError::Database(e))
+        }
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AuthError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move { authenticate(&req).await })
+    }
+}
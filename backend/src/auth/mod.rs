@@ -1,9 +1,15 @@
 // Import and re-export components
-mod client;
 mod session;
-mod google;
+mod view_link;
+mod admin_guard;
+mod extractor;
+mod provider;
+mod oidc;
 
 // Re-export public items
-pub use client::create_oauth_client;
-pub use session::create_logout_cookie;
-pub use google::FRONTEND_URL;
+pub use session::{generate_access_token, create_session_cookie, create_logout_cookie, create_oauth_state_cookie, clear_oauth_state_cookie, OAUTH_STATE_TTL_SECONDS};
+pub use view_link::{create_view_link, verify_view_token, verify_view_token_signature, ViewLinkError};
+pub use admin_guard::{require_admin_role, AdminActor};
+pub use extractor::{authenticate, AuthenticatedUser, AuthError};
+pub use provider::{identity_providers, IdentityProviderConfig, GOOGLE_PROVIDER_ID};
+pub use oidc::IdentityProviders;
@@ -0,0 +1,419 @@
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::auth::provider::{IdentityProviderConfig, GOOGLE_PROVIDER_ID};
+use crate::models::GoogleUserInfo;
+
+// How long discovery documents and signing keys are reused before fetching them again
+const METADATA_CACHE_TTL: Duration = Duration::from_secs(3600);
+// Unknown key ids trigger a JWKS refresh, but no more often than this
+const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+// Only asymmetric signatures; a shared-secret token could be forged by anyone holding the client secret
+const ALLOWED_ALGORITHMS: [Algorithm; 7] = [
+    Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+    Algorithm::PS256, Algorithm::ES256, Algorithm::ES384, Algorithm::EdDSA,
+];
+
+#[derive(Debug)]
+pub enum OidcError {
+    UnknownProvider,
+    Discovery(String),
+    Exchange(String),
+    InvalidIdToken(String),
+    EmailUnverified,
+}
+
+impl OidcError {
+    // Short machine-readable reason for the frontend
+    pub fn reason(&self) -> &'static str {
+        match self {
+            OidcError::UnknownProvider => "unknown_provider",
+            OidcError::Discovery(_) => "unavailable",
+            OidcError::Exchange(_) => "exchange_failed",
+            OidcError::InvalidIdToken(_) => "invalid_id_token",
+            OidcError::EmailUnverified => "email_unverified",
+        }
+    }
+}
+
+impl fmt::Display for OidcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OidcError::UnknownProvider => write!(f, "Unknown identity provider"),
+            OidcError::Discovery(e) => write!(f, "Provider discovery failed: {}", e),
+            OidcError::Exchange(e) => write!(f, "Code exchange failed: {}", e),
+            OidcError::InvalidIdToken(e) => write!(f, "Invalid ID token: {}", e),
+            OidcError::EmailUnverified => write!(f, "The provider has not verified this email address"),
+        }
+    }
+}
+
+// The parts of the discovery document we use
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcTokens {
+    pub id_token: String,
+    pub refresh_token: Option<String>,
+}
+
+// A signed-in identity taken from a validated ID token
+pub struct VerifiedIdentity {
+    // Stable account id at the provider; emails can change, this cannot
+    pub subject: String,
+    // None when the provider does not say
+    pub email_verified: Option<bool>,
+    pub profile: GoogleUserInfo,
+}
+
+impl VerifiedIdentity {
+    // Accounts are keyed by email, so only a provider that vouches for the address
+    // may create one or claim an existing one
+    pub fn may_create_account(&self) -> bool {
+        self.email_verified == Some(true)
+    }
+}
+
+struct Cached<T> {
+    value: T,
+    fetched_at: Instant,
+}
+
+// All configured identity providers, with their discovery documents and signing keys cached
+pub struct IdentityProviders {
+    providers: Vec<IdentityProviderConfig>,
+    http_client: reqwest::Client,
+    metadata: RwLock<HashMap<String, Cached<ProviderMetadata>>>,
+    jwks: RwLock<HashMap<String, Cached<JwkSet>>>,
+}
+
+impl IdentityProviders {
+    pub fn new(providers: Vec<IdentityProviderConfig>) -> Self {
+        IdentityProviders {
+            providers,
+            http_client: reqwest::Client::new(),
+            metadata: RwLock::new(HashMap::new()),
+            jwks: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Result<&IdentityProviderConfig, OidcError> {
+        self.providers.iter().find(|p| p.id == id).ok_or(OidcError::UnknownProvider)
+    }
+
+    pub fn list(&self) -> &[IdentityProviderConfig] {
+        &self.providers
+    }
+
+    async fn metadata(&self, provider: &IdentityProviderConfig) -> Result<ProviderMetadata, OidcError> {
+        if let Some(cached) = self.metadata.read().await.get(&provider.id) {
+            if cached.fetched_at.elapsed() < METADATA_CACHE_TTL {
+                return Ok(cached.value.clone());
+            }
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", provider.issuer);
+        let metadata: ProviderMetadata = self.http_client
+            .get(&url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| OidcError::Discovery(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| OidcError::Discovery(e.to_string()))?;
+
+        // The document must describe the issuer we were configured with
+        if metadata.issuer.trim_end_matches('/') != provider.issuer && !metadata.issuer.contains("{tenantid}") {
+            return Err(OidcError::Discovery(format!("issuer mismatch: {}", metadata.issuer)));
+        }
+
+        self.metadata.write().await.insert(
+            provider.id.clone(),
+            Cached { value: metadata.clone(), fetched_at: Instant::now() },
+        );
+        Ok(metadata)
+    }
+
+    async fn signing_keys(&self, provider: &IdentityProviderConfig, jwks_uri: &str, kid: &str) -> Result<JwkSet, OidcError> {
+        if let Some(cached) = self.jwks.read().await.get(&provider.id) {
+            let fresh = cached.fetched_at.elapsed() < METADATA_CACHE_TTL;
+            let known = cached.value.find(kid).is_some();
+            // Providers rotate keys; an unknown kid is worth one refetch
+            if fresh && (known || cached.fetched_at.elapsed() < JWKS_MIN_REFRESH_INTERVAL) {
+                return Ok(cached.value.clone());
+            }
+        }
+
+        let jwks: JwkSet = self.http_client
+            .get(jwks_uri)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| OidcError::Discovery(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| OidcError::Discovery(e.to_string()))?;
+
+        self.jwks.write().await.insert(
+            provider.id.clone(),
+            Cached { value: jwks.clone(), fetched_at: Instant::now() },
+        );
+        Ok(jwks)
+    }
+
+    // Where to send the browser to sign in
+    pub async fn authorization_url(
+        &self,
+        provider: &IdentityProviderConfig,
+        state: &str,
+        nonce: &str,
+        pkce_challenge: &str,
+        extra_params: &[(&str, &str)],
+    ) -> Result<String, OidcError> {
+        let metadata = self.metadata(provider).await?;
+        let scope = provider.scopes.join(" ");
+
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", provider.client_id.as_str()),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+            ("scope", scope.as_str()),
+            ("state", state),
+            ("nonce", nonce),
+            ("code_challenge", pkce_challenge),
+            ("code_challenge_method", "S256"),
+        ];
+        params.extend_from_slice(extra_params);
+
+        reqwest::Url::parse_with_params(&metadata.authorization_endpoint, &params)
+            .map(|url| url.to_string())
+            .map_err(|e| OidcError::Discovery(e.to_string()))
+    }
+
+    // Trade the authorization code for tokens
+    pub async fn exchange_code(
+        &self,
+        provider: &IdentityProviderConfig,
+        code: &str,
+        pkce_verifier: &str,
+    ) -> Result<OidcTokens, OidcError> {
+        let metadata = self.metadata(provider).await?;
+        let params = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+            ("client_id", provider.client_id.as_str()),
+            ("client_secret", provider.client_secret.as_str()),
+            ("code_verifier", pkce_verifier),
+        ];
+
+        let response = self.http_client
+            .post(&metadata.token_endpoint)
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| OidcError::Exchange(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(OidcError::Exchange(format!("status {}", response.status())));
+        }
+
+        response.json::<OidcTokens>().await.map_err(|e| OidcError::Exchange(e.to_string()))
+    }
+
+    // Check the ID token's signature, issuer, audience, expiry and nonce, then map its claims
+    pub async fn validate_id_token(
+        &self,
+        provider: &IdentityProviderConfig,
+        id_token: &str,
+        expected_nonce: &str,
+    ) -> Result<VerifiedIdentity, OidcError> {
+        let invalid = |e: &dyn fmt::Display| OidcError::InvalidIdToken(e.to_string());
+
+        let metadata = self.metadata(provider).await?;
+        let header = decode_header(id_token).map_err(|e| invalid(&e))?;
+        if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+            return Err(invalid(&format!("algorithm {:?} is not allowed", header.alg)));
+        }
+        let kid = header.kid.ok_or_else(|| invalid(&"missing key id"))?;
+
+        let jwks = self.signing_keys(provider, &metadata.jwks_uri, &kid).await?;
+        let jwk = jwks.find(&kid).ok_or_else(|| invalid(&format!("unknown key id {}", kid)))?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| invalid(&e))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = decode::<HashMap<String, Value>>(id_token, &key, &validation)
+            .map_err(|e| invalid(&e))?
+            .claims;
+        let claim = |name: &str| claims.get(name).and_then(Value::as_str);
+
+        // Multi-tenant issuers are templates filled in with the token's tenant
+        let expected_issuer = match claim("tid") {
+            Some(tenant) => metadata.issuer.replace("{tenantid}", tenant),
+            None => metadata.issuer.clone(),
+        };
+        let issuer = claim("iss").unwrap_or_default();
+        let google_legacy_issuer = provider.id == GOOGLE_PROVIDER_ID && issuer == "accounts.google.com";
+        if issuer != expected_issuer && !google_legacy_issuer {
+            return Err(invalid(&format!("unexpected issuer {}", issuer)));
+        }
+
+        if claim("nonce") != Some(expected_nonce) {
+            return Err(invalid(&"nonce mismatch"));
+        }
+
+        identity_from_claims(provider, &claims)
+    }
+}
+
+// Booleans arrive as true, "true" or, from some issuers, 1
+fn bool_claim(claims: &HashMap<String, Value>, name: &str) -> Option<bool> {
+    match claims.get(name)? {
+        Value::Bool(value) => Some(*value),
+        Value::String(value) => Some(value == "true" || value == "1"),
+        Value::Number(value) => Some(value.as_i64() == Some(1)),
+        _ => None,
+    }
+}
+
+// Map a validated token's claims to the signed-in identity, deciding whether its email is verified
+fn identity_from_claims(
+    provider: &IdentityProviderConfig,
+    claims: &HashMap<String, Value>,
+) -> Result<VerifiedIdentity, OidcError> {
+    let claim = |name: &str| claims.get(name).and_then(Value::as_str);
+
+    // An explicit no from the standard claim refuses the login outright
+    if bool_claim(claims, "email_verified") == Some(false) {
+        return Err(OidcError::EmailUnverified);
+    }
+
+    let email = provider.claims.email.iter()
+        .find_map(|name| claim(name))
+        .ok_or_else(|| OidcError::InvalidIdToken("no email claim".to_string()))?
+        .to_lowercase();
+
+    // Issuers that send no verification claim can still be trusted for some tenants or domains
+    let trusted_tenant = claim("tid")
+        .map(|tenant| provider.claims.trusted_tenants.contains(&tenant.to_lowercase()))
+        .unwrap_or(false);
+    let trusted_domain = email.rsplit_once('@')
+        .map(|(_, domain)| provider.claims.trusted_domains.iter().any(|d| d == domain))
+        .unwrap_or(false);
+    let claimed = provider.claims.email_verified.iter().find_map(|name| bool_claim(claims, name));
+    let email_verified = if trusted_tenant || trusted_domain { Some(true) } else { claimed };
+
+    Ok(VerifiedIdentity {
+        subject: claim("sub").unwrap_or_default().to_string(),
+        email_verified,
+        profile: GoogleUserInfo {
+            email,
+            name: claim(&provider.claims.name).map(str::to_string),
+            picture: claim(&provider.claims.picture).map(str::to_string),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::identity_providers;
+    use crate::auth::provider::ClaimMapping;
+    use crate::config::{AppConfig, MicrosoftConfig};
+    use serde_json::json;
+
+    const TENANT: &str = "9188040d-6c67-4c5b-b112-36a304b66dad";
+
+    fn microsoft(trusted_tenants: &[&str]) -> IdentityProviderConfig {
+        let mut config = AppConfig::default();
+        config.identity.microsoft = Some(MicrosoftConfig {
+            client_id: "client".to_string(),
+            trusted_tenants: trusted_tenants.iter().map(|t| t.to_string()).collect(),
+            ..MicrosoftConfig::default()
+        });
+        identity_providers(&config).into_iter().find(|p| p.id == "microsoft").expect("microsoft provider")
+    }
+
+    // The claims of an Entra ID v2.0 token: no email_verified, the domain check in xms_edov if enabled
+    fn entra_claims(extra: Value) -> HashMap<String, Value> {
+        let mut claims = json!({
+            "iss": format!("https://login.microsoftonline.com/{}/v2.0", TENANT),
+            "aud": "client",
+            "sub": "AAAAAAAAAAAAAAAAAAAAAIkzqFVrSaSaFHy782bbtaQ",
+            "oid": "00000000-0000-0000-66f3-3332eca7ea81",
+            "tid": TENANT,
+            "email": "Ada@Contoso.com",
+            "name": "Ada Lovelace",
+            "preferred_username": "ada@contoso.onmicrosoft.com",
+        });
+        if let (Value::Object(claims), Value::Object(extra)) = (&mut claims, extra) {
+            claims.extend(extra);
+        }
+        serde_json::from_value(claims).unwrap()
+    }
+
+    #[test]
+    fn microsoft_token_with_domain_owner_verified_creates_an_account() {
+        let identity = identity_from_claims(&microsoft(&[]), &entra_claims(json!({ "xms_edov": true }))).unwrap();
+        assert!(identity.may_create_account());
+        assert_eq!(identity.profile.email, "ada@contoso.com");
+    }
+
+    #[test]
+    fn microsoft_token_from_a_trusted_tenant_creates_an_account() {
+        let identity = identity_from_claims(&microsoft(&[TENANT.to_uppercase().as_str()]), &entra_claims(json!({}))).unwrap();
+        assert!(identity.may_create_account());
+    }
+
+    #[test]
+    fn microsoft_token_without_any_verification_cannot_create_an_account() {
+        let identity = identity_from_claims(&microsoft(&[]), &entra_claims(json!({}))).unwrap();
+        assert!(!identity.may_create_account());
+
+        let identity = identity_from_claims(&microsoft(&["another-tenant"]), &entra_claims(json!({ "xms_edov": false }))).unwrap();
+        assert!(!identity.may_create_account());
+    }
+
+    #[test]
+    fn explicitly_unverified_email_is_refused() {
+        let result = identity_from_claims(&microsoft(&[TENANT]), &entra_claims(json!({ "email_verified": false })));
+        assert!(matches!(result, Err(OidcError::EmailUnverified)));
+    }
+
+    #[test]
+    fn generic_issuer_is_trusted_only_for_its_configured_domains() {
+        let provider = IdentityProviderConfig {
+            id: "okta".to_string(),
+            display_name: "Okta".to_string(),
+            issuer: "https://example.okta.com".to_string(),
+            client_id: "client".to_string(),
+            client_secret: String::new(),
+            redirect_uri: String::new(),
+            scopes: Vec::new(),
+            claims: ClaimMapping { trusted_domains: vec!["example.com".to_string()], ..ClaimMapping::default() },
+            links_gmail: false,
+        };
+        let claims = |email: &str| -> HashMap<String, Value> {
+            serde_json::from_value(json!({ "sub": "00u1", "email": email })).unwrap()
+        };
+
+        assert!(identity_from_claims(&provider, &claims("ada@example.com")).unwrap().may_create_account());
+        assert!(!identity_from_claims(&provider, &claims("ada@example.org")).unwrap().may_create_account());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::config::{AppConfig, MicrosoftConfig, OidcConfig};
+
+pub const GOOGLE_PROVIDER_ID: &str = "google";
+
+// Scopes that let us read and send mail on the user's behalf
+pub const GMAIL_SCOPES: [&str; 3] = [
+    "https://www.googleapis.com/auth/gmail.readonly",
+    "https://www.googleapis.com/auth/gmail.send",
+    "https://www.googleapis.com/auth/gmail.modify",
+];
+
+const DEFAULT_SCOPES: &str = "openid email profile";
+
+// Which ID token claims make up the user's profile
+#[derive(Debug, Clone)]
+pub struct ClaimMapping {
+    // Tried in order; the first claim present is the email address
+    pub email: Vec<String>,
+    pub name: String,
+    pub picture: String,
+    // Tried in order; the first boolean claim present says whether the email is verified
+    pub email_verified: Vec<String>,
+    // Tenants (the tid claim) whose directory is trusted to assign email addresses
+    pub trusted_tenants: Vec<String>,
+    // Domains whose addresses this issuer is trusted to assert without a verification claim
+    pub trusted_domains: Vec<String>,
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        ClaimMapping {
+            email: vec!["email".to_string()],
+            name: "name".to_string(),
+            picture: "picture".to_string(),
+            email_verified: vec!["email_verified".to_string()],
+            trusted_tenants: Vec::new(),
+            trusted_domains: Vec::new(),
+        }
+    }
+}
+
+fn lowercase_all(values: &[String]) -> Vec<String> {
+    values.iter().map(|v| v.trim().to_lowercase()).filter(|v| !v.is_empty()).collect()
+}
+
+// An OpenID Connect issuer users can sign in with
+#[derive(Debug, Clone)]
+pub struct IdentityProviderConfig {
+    pub id: String,
+    pub display_name: String,
+    // Discovery document is read from {issuer}/.well-known/openid-configuration
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    pub claims: ClaimMapping,
+    // Google sign-in also grants Gmail access, so the mailbox is linked in the same step
+    pub links_gmail: bool,
+}
+
+fn redirect_uri(config: &AppConfig, id: &str, configured: &Option<String>) -> String {
+    configured.clone().unwrap_or_else(|| {
+        format!("{}/auth/{}/callback", config.server.backend_url.trim_end_matches('/'), id)
+    })
+}
+
+fn parse_scopes(scopes: &str) -> Vec<String> {
+    scopes.split_whitespace().map(str::to_string).collect()
+}
+
+// Google is always available; it is also the only mail provider
+fn google_provider(config: &AppConfig) -> IdentityProviderConfig {
+    let mut scopes = parse_scopes(DEFAULT_SCOPES);
+    scopes.extend(GMAIL_SCOPES.iter().map(|s| s.to_string()));
+
+    IdentityProviderConfig {
+        id: GOOGLE_PROVIDER_ID.to_string(),
+        display_name: "Google".to_string(),
+        issuer: "https://accounts.google.com".to_string(),
+        client_id: config.google.client_id.clone(),
+        client_secret: config.google.client_secret.expose().to_string(),
+        redirect_uri: redirect_uri(config, GOOGLE_PROVIDER_ID, &config.google.redirect_uri),
+        scopes,
+        claims: ClaimMapping::default(),
+        links_gmail: true,
+    }
+}
+
+// Microsoft Entra ID; the tenant "organizations" admits any work or school account
+fn microsoft_provider(config: &AppConfig, microsoft: &MicrosoftConfig) -> IdentityProviderConfig {
+    IdentityProviderConfig {
+        id: "microsoft".to_string(),
+        display_name: "Microsoft".to_string(),
+        issuer: format!("https://login.microsoftonline.com/{}/v2.0", microsoft.tenant),
+        client_id: microsoft.client_id.clone(),
+        client_secret: microsoft.client_secret.expose().to_string(),
+        redirect_uri: redirect_uri(config, "microsoft", &microsoft.redirect_uri),
+        scopes: parse_scopes(DEFAULT_SCOPES),
+        // The sign-in name (preferred_username) is not an email address anyone has verified,
+        // so it never stands in for a missing email claim.
+        // Entra sends no email_verified; xms_edov (an optional claim) says the tenant owns the domain,
+        // and configured tenants are trusted for every address they assign.
+        // Domains are never trusted on their own, since any tenant can put any address on a user.
+        claims: ClaimMapping {
+            email_verified: vec!["email_verified".to_string(), "xms_edov".to_string()],
+            trusted_tenants: lowercase_all(&microsoft.trusted_tenants),
+            ..ClaimMapping::default()
+        },
+        links_gmail: false,
+    }
+}
+
+fn generic_provider(config: &AppConfig, oidc: &OidcConfig) -> IdentityProviderConfig {
+    let defaults = ClaimMapping::default();
+    IdentityProviderConfig {
+        id: oidc.id.clone(),
+        display_name: oidc.name.clone().unwrap_or_else(|| oidc.id.clone()),
+        issuer: oidc.issuer.trim_end_matches('/').to_string(),
+        client_id: oidc.client_id.clone(),
+        client_secret: oidc.client_secret.expose().to_string(),
+        redirect_uri: redirect_uri(config, &oidc.id, &oidc.redirect_uri),
+        scopes: oidc.scopes.clone().unwrap_or_else(|| parse_scopes(DEFAULT_SCOPES)),
+        claims: ClaimMapping {
+            email: oidc.email_claim.clone().map(|c| vec![c]).unwrap_or(defaults.email),
+            name: oidc.name_claim.clone().unwrap_or(defaults.name),
+            picture: oidc.picture_claim.clone().unwrap_or(defaults.picture),
+            email_verified: oidc.email_verified_claim.clone().map(|c| vec![c]).unwrap_or(defaults.email_verified),
+            trusted_tenants: Vec::new(),
+            trusted_domains: lowercase_all(&oidc.trusted_domains),
+        },
+        links_gmail: false,
+    }
+}
+
+// Every provider configured for this deployment, Google first.
+// Ids are checked for duplicates when the config is validated.
+pub fn identity_providers(config: &AppConfig) -> Vec<IdentityProviderConfig> {
+    let mut providers = vec![google_provider(config)];
+    providers.extend(config.identity.microsoft.iter().map(|microsoft| microsoft_provider(config, microsoft)));
+    providers.extend(config.identity.oidc.iter().map(|oidc| generic_provider(config, oidc)));
+    providers
+}
@@ -1,29 +1,68 @@
 use actix_web::cookie::Cookie;
+use base64::{encode_config, URL_SAFE_NO_PAD};
+use rand::{RngCore, rngs::OsRng};
 use uuid::Uuid;
 
+use crate::db::sessions::SESSION_TTL_DAYS;
+
 // Generate a session token
 pub fn generate_session_token() -> String {
     Uuid::new_v4().to_string()
 }
 
-// Create a session cookie
-pub fn create_session_cookie(session_token: &str) -> Cookie<'static> {
+// Prefix that makes personal access tokens easy to spot in scripts and secret scanners
+pub const ACCESS_TOKEN_PREFIX: &str = "qe_pat_";
+
+// Generate a personal access token
+pub fn generate_access_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", ACCESS_TOKEN_PREFIX, encode_config(bytes, URL_SAFE_NO_PAD))
+}
+
+// Create a session cookie; `secure` comes from ServerConfig::cookies_secure
+pub fn create_session_cookie(session_token: &str, secure: bool) -> Cookie<'static> {
     Cookie::build("session", session_token.to_owned())
         .path("/")
         .http_only(true)
-        .secure(false) // For development on localhost
+        .secure(secure)
         .same_site(actix_web::cookie::SameSite::Lax)
-        .max_age(actix_web::cookie::time::Duration::days(7)) // Make session last for 7 days
+        .max_age(actix_web::cookie::time::Duration::days(SESSION_TTL_DAYS as i64))
         .finish()
 }
 
 // Create a logout cookie (expired session cookie)
-pub fn create_logout_cookie() -> Cookie<'static> {
+pub fn create_logout_cookie(secure: bool) -> Cookie<'static> {
     Cookie::build("session", String::new())
         .path("/")
         .http_only(true)
-        .secure(false)
+        .secure(secure)
         .same_site(actix_web::cookie::SameSite::Lax)
         .max_age(actix_web::cookie::time::Duration::seconds(-1)) // Negative duration makes it expire immediately
         .finish()
 }
+
+// How long a login attempt may take before its state expires
+pub const OAUTH_STATE_TTL_SECONDS: i64 = 600;
+
+// Create a cookie binding an OAuth state to the browser that started the login
+pub fn create_oauth_state_cookie(state: &str, secure: bool) -> Cookie<'static> {
+    Cookie::build("oauth_state", state.to_owned())
+        .path("/auth")
+        .http_only(true)
+        .secure(secure)
+        .same_site(actix_web::cookie::SameSite::Lax)
+        .max_age(actix_web::cookie::time::Duration::seconds(OAUTH_STATE_TTL_SECONDS))
+        .finish()
+}
+
+// Clear the OAuth state cookie once the callback has used it
+pub fn clear_oauth_state_cookie(secure: bool) -> Cookie<'static> {
+    Cookie::build("oauth_state", String::new())
+        .path("/auth")
+        .http_only(true)
+        .secure(secure)
+        .same_site(actix_web::cookie::SameSite::Lax)
+        .max_age(actix_web::cookie::time::Duration::seconds(-1))
+        .finish()
+}
@@ -0,0 +1,105 @@
+use base64::{encode_config, decode_config, URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// How long a link in a notification email stays valid
+pub const VIEW_LINK_TTL_HOURS: i64 = 72;
+
+// Claims carried inside a signed view token
+#[derive(Debug, Clone)]
+pub struct ViewLinkClaims {
+    pub email_id: String,
+    pub recipient: String,
+    pub expires_at: i64,
+}
+
+// Reasons a view token can be rejected
+#[derive(Debug)]
+pub enum ViewLinkError {
+    Malformed,
+    BadSignature,
+    Expired,
+}
+
+impl ViewLinkError {
+    // Short machine-readable reason for the frontend
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ViewLinkError::Malformed => "invalid",
+            ViewLinkError::BadSignature => "invalid",
+            ViewLinkError::Expired => "expired",
+        }
+    }
+}
+
+fn signing_key() -> Vec<u8> {
+    config::get().secrets.view_link_secret.expose().as_bytes().to_vec()
+}
+
+fn sign(payload: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(&signing_key())
+        .expect("HMAC accepts keys of any length");
+    mac.update(payload);
+    mac
+}
+
+// Create a token binding the email, its recipient and an expiry time
+pub fn create_view_token(email_id: &str, recipient: &str) -> String {
+    let expires_at = chrono::Utc::now().timestamp() + VIEW_LINK_TTL_HOURS * 3600;
+    let payload = format!("{}\n{}\n{}", email_id, recipient.to_lowercase(), expires_at);
+    let signature = sign(payload.as_bytes()).finalize().into_bytes();
+
+    format!(
+        "{}.{}",
+        encode_config(payload.as_bytes(), URL_SAFE_NO_PAD),
+        encode_config(signature, URL_SAFE_NO_PAD)
+    )
+}
+
+// Build the frontend link that goes into the notification email
+pub fn create_view_link(frontend_url: &str, email_id: &str, recipient: &str) -> String {
+    format!("{}/?view={}", frontend_url, create_view_token(email_id, recipient))
+}
+
+// Check the signature and expiry of a view token
+pub fn verify_view_token(token: &str) -> Result<ViewLinkClaims, ViewLinkError> {
+    let claims = verify_view_token_signature(token)?;
+
+    if claims.expires_at < chrono::Utc::now().timestamp() {
+        return Err(ViewLinkError::Expired);
+    }
+
+    Ok(claims)
+}
+
+// Check only the signature, so expired links can still be renewed
+pub fn verify_view_token_signature(token: &str) -> Result<ViewLinkClaims, ViewLinkError> {
+    let (payload_b64, signature_b64) = token.split_once('.').ok_or(ViewLinkError::Malformed)?;
+
+    let payload = decode_config(payload_b64, URL_SAFE_NO_PAD).map_err(|_| ViewLinkError::Malformed)?;
+    let signature = decode_config(signature_b64, URL_SAFE_NO_PAD).map_err(|_| ViewLinkError::Malformed)?;
+
+    sign(&payload)
+        .verify_slice(&signature)
+        .map_err(|_| ViewLinkError::BadSignature)?;
+
+    let payload = String::from_utf8(payload).map_err(|_| ViewLinkError::Malformed)?;
+    let mut fields = payload.splitn(3, '\n');
+
+    let email_id = fields.next().ok_or(ViewLinkError::Malformed)?;
+    let recipient = fields.next().ok_or(ViewLinkError::Malformed)?;
+    let expires_at = fields
+        .next()
+        .and_then(|v| v.parse::<i64>().ok())
+        .ok_or(ViewLinkError::Malformed)?;
+
+    Ok(ViewLinkClaims {
+        email_id: email_id.to_string(),
+        recipient: recipient.to_string(),
+        expires_at,
+    })
+}
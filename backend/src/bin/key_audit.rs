@@ -0,0 +1,193 @@
+use quantum_email_backend::encryption::transparency::{
+    self, ConsistencyProof, KeyHistoryBundle, SignedTreeHead,
+};
+use colored::*;
+use serde_json::Value;
+use std::{env, fs, process};
+
+const USAGE: &str = "Usage: key_audit <bundle.json> --log-key <base64> [--expect-key <base64>] [--trusted-head <head.json> --consistency <proof.json>]
+
+  <bundle.json>     Response of GET /api/transparency/keys/{email} (signed in)
+  --log-key         Pinned Ed25519 key of the log (from GET /api/transparency/head)
+  --expect-key      Public key you were served for this user; must be their current logged key
+  --trusted-head    Tree head saved from an earlier audit
+  --consistency     Response of GET /api/transparency/proof/consistency?first=<trusted size>&second=<bundle size>";
+
+struct Args {
+    bundle_path: String,
+    log_key: String,
+    expect_key: Option<String>,
+    trusted_head_path: Option<String>,
+    consistency_path: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut bundle_path = None;
+    let mut log_key = None;
+    let mut expect_key = None;
+    let mut trusted_head_path = None;
+    let mut consistency_path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--log-key" => log_key = Some(value("--log-key")?),
+            "--expect-key" => expect_key = Some(value("--expect-key")?),
+            "--trusted-head" => trusted_head_path = Some(value("--trusted-head")?),
+            "--consistency" => consistency_path = Some(value("--consistency")?),
+            "-h" | "--help" => return Err(String::new()),
+            other if other.starts_with("--") => return Err(format!("Unknown option {}", other)),
+            other => bundle_path = Some(other.to_string()),
+        }
+    }
+
+    if trusted_head_path.is_some() != consistency_path.is_some() {
+        return Err("--trusted-head and --consistency must be given together".to_string());
+    }
+
+    Ok(Args {
+        bundle_path: bundle_path.ok_or("Missing bundle file")?,
+        log_key: log_key.ok_or("Missing --log-key")?,
+        expect_key,
+        trusted_head_path,
+        consistency_path,
+    })
+}
+
+// Accept either the raw API response or just the object inside it
+fn read_json<T: serde::de::DeserializeOwned>(path: &str, field: &str) -> Result<T, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let value: Value = serde_json::from_str(&data).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+    let value = match value.get(field) {
+        Some(inner) => inner.clone(),
+        None => value,
+    };
+    serde_json::from_value(value).map_err(|e| format!("{} is not a valid {}: {}", path, field, e))
+}
+
+fn check(ok: bool, message: &str, failures: &mut usize) {
+    if ok {
+        println!("  {} {}", "PASS".green().bold(), message);
+    } else {
+        println!("  {} {}", "FAIL".red().bold(), message);
+        *failures += 1;
+    }
+}
+
+fn verify_head(head: &SignedTreeHead, log_key: &str, label: &str, failures: &mut usize) {
+    let result = head.verify(log_key);
+    check(
+        result.is_ok(),
+        &format!("{} tree head (size {}) is signed by the log", label, head.tree_size),
+        failures,
+    );
+}
+
+fn audit(args: &Args) -> Result<usize, String> {
+    let bundle: KeyHistoryBundle = read_json(&args.bundle_path, "bundle")?;
+    let mut failures = 0;
+
+    println!("{}", format!("Auditing key history for {}", bundle.email).bright_white().bold());
+    verify_head(&bundle.tree_head, &args.log_key, "Latest", &mut failures);
+    let root = bundle.tree_head.root().map_err(|e| format!("Invalid root hash: {}", e))?;
+
+    // Every entry must belong to this user and be included in the signed tree
+    let mut previous_index = None;
+    let mut current_key: Option<&str> = None;
+    for entry in &bundle.entries {
+        let leaf = &entry.leaf;
+        println!(
+            "{} #{} {} {} ({}...)",
+            "Entry".bright_blue(),
+            leaf.leaf_index,
+            leaf.logged_at,
+            leaf.action.bold(),
+            &leaf.public_key[..leaf.public_key.len().min(16)]
+        );
+
+        check(leaf.email == bundle.email.to_lowercase(), "entry belongs to the audited user", &mut failures);
+        check(previous_index.is_none_or(|i| leaf.leaf_index > i), "entries are in log order", &mut failures);
+        previous_index = Some(leaf.leaf_index);
+
+        let path: Result<Vec<_>, _> = entry.inclusion_proof.audit_path.iter().map(|h| transparency::decode_hash(h)).collect();
+        let included = match path {
+            Ok(path) => {
+                entry.inclusion_proof.leaf_index == leaf.leaf_index
+                    && entry.inclusion_proof.tree_size == bundle.tree_head.tree_size
+                    && transparency::verify_inclusion(leaf.leaf_index, bundle.tree_head.tree_size, &leaf.leaf_hash(), &path, &root)
+            }
+            Err(_) => false,
+        };
+        check(included, "inclusion proof matches the signed root", &mut failures);
+
+        // Keys must be published before they are rotated or revoked
+        let valid_transition = match leaf.action.as_str() {
+            transparency::ACTION_PUBLISH => current_key.is_none(),
+            transparency::ACTION_ROTATE => current_key.is_some_and(|key| key != leaf.public_key),
+            transparency::ACTION_REVOKE => current_key == Some(leaf.public_key.as_str()),
+            _ => false,
+        };
+        check(valid_transition, "action follows from the previous entry", &mut failures);
+        current_key = if leaf.action == transparency::ACTION_REVOKE { None } else { Some(&leaf.public_key) };
+    }
+
+    if bundle.entries.is_empty() {
+        println!("  {} no keys have been logged for this user", "NOTE".yellow().bold());
+    }
+
+    if let Some(ref expect_key) = args.expect_key {
+        check(
+            current_key == Some(expect_key.as_str()),
+            "the key you were served is the user's current logged key",
+            &mut failures,
+        );
+    }
+
+    // Optional: the log must only have grown since a tree head we trusted earlier
+    if let (Some(head_path), Some(proof_path)) = (&args.trusted_head_path, &args.consistency_path) {
+        let trusted_head: SignedTreeHead = read_json(head_path, "tree_head")?;
+        let proof: ConsistencyProof = read_json(proof_path, "consistency_proof")?;
+        println!("{}", "Consistency with trusted tree head".bright_blue());
+        verify_head(&trusted_head, &args.log_key, "Trusted", &mut failures);
+
+        let first_root = trusted_head.root().map_err(|e| format!("Invalid root hash: {}", e))?;
+        let proof_hashes: Result<Vec<_>, _> = proof.proof.iter().map(|h| transparency::decode_hash(h)).collect();
+        let consistent = match proof_hashes {
+            Ok(proof_hashes) => {
+                proof.first_size == trusted_head.tree_size
+                    && proof.second_size == bundle.tree_head.tree_size
+                    && transparency::verify_consistency(trusted_head.tree_size, bundle.tree_head.tree_size, &first_root, &root, &proof_hashes)
+            }
+            Err(_) => false,
+        };
+        check(consistent, "latest tree extends the trusted tree", &mut failures);
+    }
+
+    Ok(failures)
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{}", message.red());
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    match audit(&args) {
+        Ok(0) => println!("{}", "Key history verified against the transparency log".green().bold()),
+        Ok(failures) => {
+            println!("{}", format!("{} check(s) failed", failures).red().bold());
+            process::exit(1);
+        }
+        Err(message) => {
+            eprintln!("{}", message.red());
+            process::exit(2);
+        }
+    }
+}
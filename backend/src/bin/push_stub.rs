@@ -0,0 +1,102 @@
+use colored::*;
+use quantum_email_backend::gmail_push::push_envelope;
+use std::{env, process};
+
+const USAGE: &str = "Usage: push_stub --token <verification token> --email <mailbox> --history-id <id> [--url <push endpoint>] [--count <n>]
+
+  --token        google.push.verification_token of the backend
+  --email        Gmail address whose mailbox changed; must be linked to a user
+  --history-id   historyId to report; syncs only run when it is past the stored one
+  --url          Push endpoint (default http://localhost:8080/api/gmail/push)
+  --count        Send n notifications with consecutive history ids, like a burst of changes (default 1)
+
+Posts the same envelope a Cloud Pub/Sub push subscription delivers for a Gmail watch.";
+
+struct Args {
+    url: String,
+    token: String,
+    email: String,
+    history_id: u64,
+    count: u64,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut url = "http://localhost:8080/api/gmail/push".to_string();
+    let mut token = None;
+    let mut email = None;
+    let mut history_id = None;
+    let mut count = 1;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--url" => url = value("--url")?,
+            "--token" => token = Some(value("--token")?),
+            "--email" => email = Some(value("--email")?),
+            "--history-id" => {
+                history_id = Some(value("--history-id")?.parse().map_err(|_| "--history-id must be a number".to_string())?)
+            }
+            "--count" => count = value("--count")?.parse().map_err(|_| "--count must be a number".to_string())?,
+            "-h" | "--help" => return Err(String::new()),
+            other => return Err(format!("Unknown argument {}", other)),
+        }
+    }
+
+    Ok(Args {
+        url,
+        token: token.ok_or("Missing --token")?,
+        email: email.ok_or("Missing --email")?,
+        history_id: history_id.ok_or("Missing --history-id")?,
+        count,
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{}", message.red());
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let client = reqwest::Client::new();
+    let mut failures = 0;
+    for sequence in 0..args.count {
+        let history_id = args.history_id + sequence;
+        let response = client
+            .post(&args.url)
+            .query(&[("token", &args.token)])
+            .json(&push_envelope(&args.email, history_id, sequence))
+            .send()
+            .await;
+
+        match response {
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                // Pub/Sub treats any 2xx as acknowledged and redelivers everything else
+                let line = format!("history {} -> {} {}", history_id, status.as_u16(), body);
+                if status.is_success() {
+                    println!("{}", line.green());
+                } else {
+                    failures += 1;
+                    println!("{}", line.red());
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                eprintln!("{}", format!("history {} -> {}", history_id, e).red());
+            }
+        }
+    }
+
+    if failures > 0 {
+        process::exit(1);
+    }
+}
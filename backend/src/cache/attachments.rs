@@ -0,0 +1,183 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+use log::info;
+
+use crate::config::AttachmentsConfig;
+use crate::models::AttachmentInfo;
+
+// Gmail attachments kept on disk, so downloading one again costs no quota. Each user has a directory;
+// an entry is the content plus a .json file with its AttachmentInfo, both named by a hash of the
+// mailbox, message and part ids so no id ever ends up in a path and another mailbox linked later
+// never sees them. The least recently read entries are evicted once the cache grows past its limit.
+pub struct AttachmentCache {
+    dir: PathBuf,
+    max_size: u64,
+    max_total: u64,
+    // Bytes of content cached, kept up to date by writers. One writer at a time, so eviction
+    // sees a settled directory.
+    total: Mutex<u64>,
+}
+
+pub struct CachedAttachment {
+    pub info: AttachmentInfo,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+impl AttachmentCache {
+    pub fn new(config: &AttachmentsConfig) -> io::Result<Self> {
+        let dir = PathBuf::from(&config.cache_dir);
+        fs::create_dir_all(&dir)?;
+        // Counted once; from here on every write and removal adjusts the total
+        let total = cached_entries(&dir)?.iter().map(|(_, size, _)| size).sum();
+        info!("Caching Gmail attachments in {} ({} bytes cached)", dir.display(), total);
+        Ok(Self {
+            dir,
+            max_size: config.max_size_bytes,
+            max_total: config.cache_max_bytes,
+            total: Mutex::new(total),
+        })
+    }
+
+    // Largest attachment that may be served or cached
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    pub async fn get(&self, user_id: &str, mailbox: &str, message_id: &str, part_id: &str) -> Option<CachedAttachment> {
+        let path = self.entry_path(user_id, mailbox, message_id, part_id);
+        let info = tokio::fs::read(path.with_extension("json")).await.ok()?;
+        let info = serde_json::from_slice(&info).ok()?;
+
+        let size = tokio::task::spawn_blocking({
+            let path = path.clone();
+            move || -> io::Result<u64> {
+                let file = fs::File::open(&path)?;
+                // Eviction goes by modification time, so a read marks the entry as recently used
+                let _ = file.set_modified(SystemTime::now());
+                Ok(file.metadata()?.len())
+            }
+        }).await.ok()?.ok()?;
+        Some(CachedAttachment { info, path, size })
+    }
+
+    pub async fn put(
+        &self,
+        user_id: &str,
+        mailbox: &str,
+        message_id: &str,
+        part_id: &str,
+        info: &AttachmentInfo,
+        data: &[u8],
+    ) -> io::Result<PathBuf> {
+        let path = self.entry_path(user_id, mailbox, message_id, part_id);
+        let mut total = self.total.lock().await;
+
+        if let Some(user_dir) = path.parent() {
+            tokio::fs::create_dir_all(user_dir).await?;
+        }
+        // A download racing another may replace an entry that is already counted
+        let replaced = tokio::fs::metadata(&path).await.map(|metadata| metadata.len()).unwrap_or(0);
+        // Written aside and renamed, so a reader never sees half a file
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, data).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        tokio::fs::write(path.with_extension("json"), serde_json::to_vec(info)?).await?;
+        *total = total.saturating_sub(replaced) + data.len() as u64;
+
+        if *total > self.max_total {
+            let (dir, max_total, keep) = (self.dir.clone(), self.max_total, path.clone());
+            *total = tokio::task::spawn_blocking(move || evict(&dir, max_total, &keep))
+                .await
+                .map_err(io::Error::other)??;
+        }
+        Ok(path)
+    }
+
+    // Drop everything cached for a user, as when their mailbox is unlinked or their account deleted
+    pub async fn remove_user(&self, user_id: &str) -> io::Result<()> {
+        let mut total = self.total.lock().await;
+        let user_dir = self.dir.join(digest(&[user_id]));
+        let removed = tokio::task::spawn_blocking(move || -> io::Result<u64> {
+            let size = match user_entries(&user_dir) {
+                Ok(entries) => entries.iter().map(|(_, size, _)| size).sum(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+                Err(e) => return Err(e),
+            };
+            fs::remove_dir_all(&user_dir)?;
+            Ok(size)
+        }).await.map_err(io::Error::other)??;
+        *total = total.saturating_sub(removed);
+        Ok(())
+    }
+
+    fn entry_path(&self, user_id: &str, mailbox: &str, message_id: &str, part_id: &str) -> PathBuf {
+        self.dir.join(digest(&[user_id])).join(digest(&[mailbox, message_id, part_id]))
+    }
+}
+
+// Remove the least recently used entries until the cache fits, sparing the one just written.
+// Returns what is left cached, which also corrects any drift in the running total.
+fn evict(dir: &Path, max_total: u64, keep: &Path) -> io::Result<u64> {
+    let mut entries = cached_entries(dir)?;
+    let mut total = entries.iter().map(|(_, size, _)| size).sum();
+
+    entries.sort();
+    let mut evicted = 0;
+    for (_, size, path) in entries {
+        if total <= max_total {
+            break;
+        }
+        if path == keep {
+            continue;
+        }
+        fs::remove_file(&path)?;
+        let _ = fs::remove_file(path.with_extension("json"));
+        total -= size;
+        evicted += 1;
+    }
+    if evicted > 0 {
+        info!("Evicted {} cached attachments", evicted);
+    }
+    Ok(total)
+}
+
+// Every entry in the cache with its modification time and size
+fn cached_entries(dir: &Path) -> io::Result<Vec<(SystemTime, u64, PathBuf)>> {
+    let mut entries = Vec::new();
+    for user_dir in fs::read_dir(dir)? {
+        let user_dir = user_dir?.path();
+        if user_dir.is_dir() {
+            entries.extend(user_entries(&user_dir)?);
+        }
+    }
+    Ok(entries)
+}
+
+fn user_entries(user_dir: &Path) -> io::Result<Vec<(SystemTime, u64, PathBuf)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(user_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // Sidecars and partial writes are accounted with their entry
+        if path.extension().is_some() {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        entries.push((metadata.modified()?, metadata.len(), path));
+    }
+    Ok(entries)
+}
+
+fn digest(ids: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for id in ids {
+        hasher.update(id.as_bytes());
+        hasher.update([0]);
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -1,29 +1,37 @@
+mod attachments;
+
+pub use attachments::{AttachmentCache, CachedAttachment};
+
 use redis::{Client, AsyncCommands, RedisError};
-use std::env;
 use std::sync::Arc;
 use std::time::Duration;
+use crate::config::{AttachmentsConfig, CacheConfig, RedisConfig};
 use crate::models::{Email, GmailLabel};
 use crate::gmail::GmailMessageId;
 use log::{error, info};
 
-// Constants for cache TTL
-const EMAIL_CACHE_TTL: usize = 7200; // Increase to 2 hours
-const MESSAGE_LIST_CACHE_TTL: usize = 3600; // Increase to 1 hour
-const LABEL_CACHE_TTL: usize = 14400; // Increase to 4 hours
 const RETRY_DELAY: Duration = Duration::from_secs(1);
 const MAX_RETRIES: u32 = 3;
 const DEFAULT_PAGE_SIZE: usize = 50;
+// How long a cached list of message ids lasts
+const MESSAGE_LIST_CACHE_TTL: usize = 3600;
 
 pub struct RedisCache {
     client: Client,
+    // Cache TTLs in seconds
+    email_ttl: usize,
+    label_ttl: usize,
 }
 
 impl RedisCache {
-    pub fn new() -> Result<Self, RedisError> {
-        let redis_url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
-        info!("Initializing Redis cache at {}", redis_url);
-        let client = Client::open(redis_url)?;
-        Ok(Self { client })
+    pub fn new(redis: &RedisConfig, cache: &CacheConfig) -> Result<Self, RedisError> {
+        let client = Client::open(redis.url.expose())?;
+        info!("Initializing Redis cache at {}", client.get_connection_info().addr);
+        Ok(Self {
+            client,
+            email_ttl: cache.email_ttl_seconds,
+            label_ttl: cache.label_ttl_seconds,
+        })
     }
     
     // Connect to Redis with retries
@@ -134,7 +142,7 @@ impl RedisCache {
             RedisError::from((redis::ErrorKind::IoError, "Serialization error", e.to_string()))
         })?;
 
-        match conn.set_ex::<_, _, ()>(&cache_key, &json, EMAIL_CACHE_TTL).await {
+        match conn.set_ex::<_, _, ()>(&cache_key, &json, self.email_ttl).await {
             Ok(_) => {
                 info!("Cached {} emails for {}", emails.len(), category);
                 Ok(())
@@ -156,7 +164,7 @@ impl RedisCache {
             RedisError::from((redis::ErrorKind::IoError, "Serialization error", e.to_string()))
         })?;
 
-        match conn.set_ex::<_, _, ()>(&cache_key, &json, EMAIL_CACHE_TTL).await {
+        match conn.set_ex::<_, _, ()>(&cache_key, &json, self.email_ttl).await {
             Ok(_) => {
                 info!("Cached email {}", email_id);
                 Ok(())
@@ -196,6 +204,14 @@ impl RedisCache {
         }
     }
     
+    // Drop a cached email that changed in Gmail
+    pub async fn remove_cached_email(&self, user_id: &str, email_id: &str) -> Result<(), RedisError> {
+        let mut conn = self.get_connection_with_retry().await?;
+        let cache_key = format!("email:{}:{}", user_id, email_id);
+        let _: () = conn.del(&cache_key).await?;
+        Ok(())
+    }
+    
     // Get cached emails for a user
     pub async fn get_cached_emails(&self, user_id: &str, category: &str) -> Result<Option<Vec<Email>>, RedisError> {
         let mut conn = self.get_connection_with_retry().await?;
@@ -234,7 +250,7 @@ impl RedisCache {
             RedisError::from((redis::ErrorKind::IoError, "Serialization error", e.to_string()))
         })?;
 
-        match conn.set_ex::<_, _, ()>(&cache_key, &json, LABEL_CACHE_TTL).await {
+        match conn.set_ex::<_, _, ()>(&cache_key, &json, self.label_ttl).await {
             Ok(_) => {
                 info!("Cached {} labels", labels.len());
                 Ok(())
@@ -294,6 +310,23 @@ impl RedisCache {
         }
     }
 
+    // Remove every key belonging to a user, including labels and sync markers
+    pub async fn delete_user_keys(&self, user_id: &str) -> Result<usize, RedisError> {
+        let mut conn = self.get_connection_with_retry().await?;
+        let mut deleted = 0;
+
+        for pattern in [format!("*:{}:*", user_id), format!("*:{}", user_id)] {
+            let keys: Vec<String> = conn.keys(&pattern).await?;
+            if !keys.is_empty() {
+                let _: () = conn.del(&keys).await?;
+                deleted += keys.len();
+            }
+        }
+
+        info!("Deleted {} cache keys for user {}", deleted, user_id);
+        Ok(deleted)
+    }
+
     pub async fn update_email_lists(&self, user_id: &str, email: &Email, is_sent: bool) -> Result<(), RedisError> {
         // Get current email lists
         let category = if is_sent { "sent" } else { "received" };
@@ -323,7 +356,7 @@ impl RedisCache {
                     RedisError::from((redis::ErrorKind::IoError, "Serialization error", e.to_string()))
                 })?;
                 
-                let _: () = conn.set_ex::<_, _, ()>(&page_key, &json, EMAIL_CACHE_TTL).await?;
+                let _: () = conn.set_ex::<_, _, ()>(&page_key, &json, self.email_ttl).await?;
                 
                 // Update index (this is a simplification - ideally we'd update the entire index)
                 // For production, you'd need to rebuild the entire index
@@ -359,9 +392,15 @@ impl RedisCache {
         let mut conn = self.get_connection_with_retry().await?;
         let page_size = page_size.unwrap_or(DEFAULT_PAGE_SIZE);
         
+        // Drop pages left over from a longer list
+        let old_pages: Vec<String> = conn.keys(format!("emails:{}:{}:page:*", user_id, category)).await?;
+        if !old_pages.is_empty() {
+            let _: () = conn.del(&old_pages).await?;
+        }
+        
         // Store total count
         let count_key = format!("emails:{}:{}:count", user_id, category);
-        let _: () = conn.set_ex(&count_key, emails.len(), EMAIL_CACHE_TTL).await?;
+        let _: () = conn.set_ex(&count_key, emails.len(), self.email_ttl).await?;
         
         // Store emails in pages
         let chunks = emails.chunks(page_size);
@@ -372,7 +411,7 @@ impl RedisCache {
                 RedisError::from((redis::ErrorKind::IoError, "Serialization error", e.to_string()))
             })?;
             
-            let _: () = conn.set_ex::<_, _, ()>(&page_key, &json, EMAIL_CACHE_TTL).await?;
+            let _: () = conn.set_ex::<_, _, ()>(&page_key, &json, self.email_ttl).await?;
         }
         
         // Store index of emails for quick lookup
@@ -386,7 +425,7 @@ impl RedisCache {
             RedisError::from((redis::ErrorKind::IoError, "Serialization error", e.to_string()))
         })?;
         
-        let _: () = conn.set_ex::<_, _, ()>(&index_key, &index_json, EMAIL_CACHE_TTL).await?;
+        let _: () = conn.set_ex::<_, _, ()>(&index_key, &index_json, self.email_ttl).await?;
         
         info!("Cached {} emails in {} pages for {}", emails.len(), (emails.len() as f64 / page_size as f64).ceil(), category);
         Ok(())
@@ -474,11 +513,53 @@ impl RedisCache {
         Ok(())
     }
 
-    // Get last sync timestamp
-    pub async fn get_last_sync(&self, user_id: &str) -> Result<Option<i64>, RedisError> {
+    // Claim a short-lived cooldown slot; returns false if one is already held
+    pub async fn claim_cooldown(&self, key: &str, ttl_seconds: usize) -> Result<bool, RedisError> {
         let mut conn = self.get_connection_with_retry().await?;
-        let key = format!("last_sync:{}", user_id);
-        conn.get::<_, Option<i64>>(&key).await
+        let key = format!("cooldown:{}", key);
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(&key)
+            .arg(chrono::Utc::now().timestamp())
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl_seconds)
+            .query_async(&mut conn)
+            .await?;
+        Ok(claimed.is_some())
+    }
+
+    // Count an attempt in a fixed window that starts with the first one; returns the attempts so far
+    pub async fn count_attempt(&self, key: &str, window_seconds: usize) -> Result<u64, RedisError> {
+        let mut conn = self.get_connection_with_retry().await?;
+        let key = format!("attempts:{}", key);
+        let (_, attempts): (Option<String>, u64) = redis::pipe()
+            .atomic()
+            .cmd("SET").arg(&key).arg(0).arg("NX").arg("EX").arg(window_seconds)
+            .incr(&key, 1)
+            .query_async(&mut conn)
+            .await?;
+        Ok(attempts)
+    }
+
+    // Remember what an OAuth login attempt needs to finish, keyed by its state
+    pub async fn store_oauth_state(&self, state: &str, payload: &str, ttl_seconds: usize) -> Result<(), RedisError> {
+        let mut conn = self.get_connection_with_retry().await?;
+        let key = format!("oauth_state:{}", state);
+        let _: () = conn.set_ex::<_, _, ()>(&key, payload, ttl_seconds).await?;
+        Ok(())
+    }
+
+    // Fetch and delete the login attempt so each state can only be used once
+    pub async fn take_oauth_state(&self, state: &str) -> Result<Option<String>, RedisError> {
+        let mut conn = self.get_connection_with_retry().await?;
+        let key = format!("oauth_state:{}", state);
+        let (payload, _): (Option<String>, i64) = redis::pipe()
+            .atomic()
+            .get(&key)
+            .del(&key)
+            .query_async(&mut conn)
+            .await?;
+        Ok(payload)
     }
 
     // Track email read status
@@ -499,15 +580,11 @@ impl RedisCache {
     }
 }
 
-// Create a shared Redis cache
-pub fn create_redis_cache() -> Arc<RedisCache> {
-    match RedisCache::new() {
-        Ok(cache) => Arc::new(cache),
-        Err(e) => {
-            error!("Failed to create Redis cache: {}", e);
-            Arc::new(RedisCache { 
-                client: Client::open("redis://127.0.0.1:6379").expect("Failed to create Redis client")
-            })
-        }
-    }
-} 
\ No newline at end of file
+// Create a shared Redis cache; the URL was checked when the config was loaded
+pub fn create_redis_cache(redis: &RedisConfig, cache: &CacheConfig) -> Arc<RedisCache> {
+    Arc::new(RedisCache::new(redis, cache).expect("Failed to create Redis client"))
+}
+
+pub fn create_attachment_cache(attachments: &AttachmentsConfig) -> Arc<AttachmentCache> {
+    Arc::new(AttachmentCache::new(attachments).expect("Failed to create attachment cache directory"))
+}
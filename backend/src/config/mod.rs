@@ -0,0 +1,869 @@
+use base64::{decode_config, STANDARD};
+use serde::{Deserialize, Deserializer};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+/// File read when CONFIG_FILE is not set; it may be absent, in which case only env vars are used
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+static CONFIG: OnceLock<Arc<AppConfig>> = OnceLock::new();
+
+/// A secret given inline or as `{ file = "/run/secrets/..." }`; never printed
+#[derive(Clone, Default, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.trim().is_empty()
+    }
+
+    fn from_file(path: &str) -> Result<Self, String> {
+        fs::read_to_string(path)
+            .map(|value| Secret(value.trim_end_matches(['\r', '\n']).to_string()))
+            .map_err(|e| format!("cannot read secret file {}: {}", path, e))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret([redacted])")
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Source {
+            Inline(String),
+            File { file: String },
+        }
+
+        match Source::deserialize(deserializer)? {
+            Source::Inline(value) => Ok(Secret(value)),
+            Source::File { file } => Secret::from_file(&file).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: u16,
+    /// Public URL of this backend, used for OAuth redirect URIs
+    pub backend_url: String,
+    pub frontend_url: String,
+    /// Browser origins allowed to call the API with credentials; defaults to frontend_url's origin
+    pub allowed_origins: Vec<String>,
+    /// Mark cookies Secure; required once the site is served over HTTPS, implied by `tls`
+    pub secure_cookies: bool,
+    /// Serve HTTPS directly instead of behind a reverse proxy
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            port: 8080,
+            backend_url: "http://localhost:8080".to_string(),
+            frontend_url: "http://localhost:3000".to_string(),
+            allowed_origins: Vec::new(),
+            secure_cookies: false,
+            tls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Whether cookies carry the Secure flag; always true when this server terminates TLS
+    pub fn cookies_secure(&self) -> bool {
+        self.secure_cookies || self.tls.is_some()
+    }
+
+    /// Origins trusted for CORS and CSRF checks, without trailing slashes
+    pub fn trusted_origins(&self) -> Vec<String> {
+        if self.allowed_origins.is_empty() {
+            return origin_of(&self.frontend_url).into_iter().collect();
+        }
+        self.allowed_origins.iter().map(|origin| origin.trim().trim_end_matches('/').to_string()).collect()
+    }
+}
+
+// scheme://host[:port] of a URL, as browsers send it in the Origin header
+fn origin_of(url: &str) -> Option<String> {
+    reqwest::Url::parse(url).ok().map(|url| url.origin().ascii_serialization())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first
+    pub cert_path: String,
+    /// PEM private key (PKCS#8, RSA or EC)
+    pub key_path: String,
+    /// How often the files are checked; a changed certificate is picked up without a restart
+    pub reload_interval_seconds: u64,
+    /// Plain HTTP port that redirects to HTTPS; unset disables the listener
+    pub http_redirect_port: Option<u16>,
+    /// Strict-Transport-Security max-age; 0 disables HSTS
+    pub hsts_max_age_seconds: u64,
+    pub hsts_include_subdomains: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            cert_path: String::new(),
+            key_path: String::new(),
+            reload_interval_seconds: 300,
+            http_redirect_port: None,
+            hsts_max_age_seconds: 31_536_000,
+            hsts_include_subdomains: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: Secret,
+    pub max_connections: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig { url: Secret::default(), max_connections: 5 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    pub url: Secret,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig { url: Secret("redis://127.0.0.1:6379".to_string()) }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub email_ttl_seconds: usize,
+    pub label_ttl_seconds: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            email_ttl_seconds: 7200,
+            label_ttl_seconds: 14400,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GoogleConfig {
+    pub client_id: String,
+    pub client_secret: Secret,
+    /// Defaults to {backend_url}/auth/google/callback
+    pub redirect_uri: Option<String>,
+    /// Most message ids a Gmail listing collects when the caller sets no bound
+    pub max_listed_messages: usize,
+    /// Gmail quota units each user may spend per second; Google allows 250
+    pub quota_units_per_second: u32,
+    /// Gmail push notifications through Cloud Pub/Sub; unset leaves syncing to manual refreshes
+    pub push: Option<GmailPushConfig>,
+}
+
+impl Default for GoogleConfig {
+    fn default() -> Self {
+        GoogleConfig {
+            client_id: String::new(),
+            client_secret: Secret::default(),
+            redirect_uri: None,
+            max_listed_messages: 5000,
+            quota_units_per_second: 250,
+            push: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GmailPushConfig {
+    /// Topic Gmail publishes mailbox changes to, projects/<project>/topics/<topic>
+    pub topic: String,
+    /// Sent by the push subscription as ?token=...; the endpoint rejects requests without it
+    pub verification_token: Secret,
+    /// Only changes to these labels are published; empty watches the whole mailbox
+    pub label_ids: Vec<String>,
+    /// Watches expiring within this window are renewed; Gmail drops a watch after 7 days
+    pub renew_before_seconds: u64,
+}
+
+impl Default for GmailPushConfig {
+    fn default() -> Self {
+        GmailPushConfig {
+            topic: String::new(),
+            verification_token: Secret::default(),
+            label_ids: Vec::new(),
+            renew_before_seconds: 86_400,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MicrosoftConfig {
+    pub client_id: String,
+    pub client_secret: Secret,
+    /// "organizations" admits any work or school account
+    pub tenant: String,
+    pub redirect_uri: Option<String>,
+    /// Tenant ids whose accounts may create users without the xms_edov claim
+    pub trusted_tenants: Vec<String>,
+}
+
+impl Default for MicrosoftConfig {
+    fn default() -> Self {
+        MicrosoftConfig {
+            client_id: String::new(),
+            client_secret: Secret::default(),
+            tenant: "organizations".to_string(),
+            redirect_uri: None,
+            trusted_tenants: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcConfig {
+    pub id: String,
+    pub name: Option<String>,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Secret,
+    pub redirect_uri: Option<String>,
+    pub scopes: Option<Vec<String>>,
+    pub email_claim: Option<String>,
+    pub name_claim: Option<String>,
+    pub picture_claim: Option<String>,
+    pub email_verified_claim: Option<String>,
+    /// Domains whose addresses the issuer is trusted to assert when it sends no verification claim
+    pub trusted_domains: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentityConfig {
+    pub microsoft: Option<MicrosoftConfig>,
+    pub oidc: Vec<OidcConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretsConfig {
+    pub view_link_secret: Secret,
+    /// Base64 of 32 bytes
+    pub refresh_token_key: Secret,
+    /// Base64 of 32 bytes
+    pub transparency_signing_key: Secret,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    /// Promoted to admin while the deployment has no admin yet
+    pub bootstrap_admin_email: Option<String>,
+    /// Days between a deletion request and the purge; 0 deletes immediately
+    pub deletion_grace_days: i32,
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        AccountsConfig { bootstrap_admin_email: None, deletion_grace_days: 7 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachmentsConfig {
+    /// Where downloaded Gmail attachments are kept; holds message content, so keep it private
+    pub cache_dir: String,
+    /// Largest attachment served; Gmail itself accepts up to 25 MB
+    pub max_size_bytes: u64,
+    /// Least recently used attachments are evicted once the cache grows past this
+    pub cache_max_bytes: u64,
+}
+
+impl Default for AttachmentsConfig {
+    fn default() -> Self {
+        AttachmentsConfig {
+            cache_dir: "attachment_cache".to_string(),
+            max_size_bytes: 26_214_400,
+            cache_max_bytes: 1_073_741_824,
+        }
+    }
+}
+
+/// Every setting the server reads, loaded and validated once at startup
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub redis: RedisConfig,
+    pub cache: CacheConfig,
+    pub google: GoogleConfig,
+    pub identity: IdentityConfig,
+    pub secrets: SecretsConfig,
+    pub accounts: AccountsConfig,
+    pub attachments: AttachmentsConfig,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "Cannot read config file {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "Invalid config file {}: {}", path.display(), e),
+            ConfigError::Invalid(problems) => {
+                writeln!(f, "Invalid configuration:")?;
+                for problem in problems {
+                    writeln!(f, "  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Read the config file named by CONFIG_FILE (default config.toml), apply env overrides and validate
+pub fn load() -> Result<AppConfig, ConfigError> {
+    let (path, required) = match env::var("CONFIG_FILE") {
+        Ok(path) => (PathBuf::from(path), true),
+        Err(_) => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+    };
+
+    let mut config = match fs::read_to_string(&path) {
+        Ok(contents) => toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.clone(), e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => AppConfig::default(),
+        Err(e) => return Err(ConfigError::Read(path, e)),
+    };
+
+    let mut problems = Vec::new();
+    apply_env_overrides(&mut config, &mut problems);
+    config.validate(&mut problems);
+
+    if problems.is_empty() {
+        Ok(config)
+    } else {
+        Err(ConfigError::Invalid(problems))
+    }
+}
+
+/// Make the loaded config available to code that runs outside request handlers
+pub fn install(config: AppConfig) -> Arc<AppConfig> {
+    CONFIG.get_or_init(|| Arc::new(config)).clone()
+}
+
+/// The installed config; loads it from the environment if nothing was installed yet
+pub fn get() -> &'static AppConfig {
+    CONFIG.get_or_init(|| Arc::new(load().unwrap_or_else(|e| panic!("{}", e))))
+}
+
+fn env_string(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+fn override_string(target: &mut String, name: &str) {
+    if let Some(value) = env_string(name) {
+        *target = value;
+    }
+}
+
+fn override_option(target: &mut Option<String>, name: &str) {
+    if let Some(value) = env_string(name) {
+        *target = Some(value);
+    }
+}
+
+fn override_parsed<T: FromStr>(target: &mut T, name: &str, problems: &mut Vec<String>) {
+    if let Some(value) = env_string(name) {
+        match value.trim().parse() {
+            Ok(parsed) => *target = parsed,
+            Err(_) => problems.push(format!("{} has an invalid value {:?}", name, value)),
+        }
+    }
+}
+
+// Secrets come from NAME, or from the file named by NAME_FILE
+fn override_secret(target: &mut Secret, name: &str, problems: &mut Vec<String>) {
+    if let Some(value) = env_string(name) {
+        *target = Secret(value);
+    } else if let Some(path) = env_string(&format!("{}_FILE", name)) {
+        match Secret::from_file(&path) {
+            Ok(secret) => *target = secret,
+            Err(e) => problems.push(format!("{}_FILE: {}", name, e)),
+        }
+    }
+}
+
+// The variables the server has always read keep working and take precedence over the file
+fn apply_env_overrides(config: &mut AppConfig, problems: &mut Vec<String>) {
+    override_parsed(&mut config.server.port, "PORT", problems);
+    override_string(&mut config.server.backend_url, "BACKEND_URL");
+    override_string(&mut config.server.frontend_url, "FRONTEND_URL");
+    override_parsed(&mut config.server.secure_cookies, "SECURE_COOKIES", problems);
+    if let Some(origins) = env_string("CORS_ALLOWED_ORIGINS") {
+        config.server.allowed_origins = origins.split(',').map(str::trim).filter(|o| !o.is_empty()).map(str::to_string).collect();
+    }
+
+    if env_string("TLS_CERT_PATH").is_some() || config.server.tls.is_some() {
+        let tls = config.server.tls.get_or_insert_with(TlsConfig::default);
+        override_string(&mut tls.cert_path, "TLS_CERT_PATH");
+        override_string(&mut tls.key_path, "TLS_KEY_PATH");
+        override_parsed(&mut tls.reload_interval_seconds, "TLS_RELOAD_INTERVAL_SECONDS", problems);
+        if env_string("TLS_HTTP_REDIRECT_PORT").is_some() {
+            let mut port = tls.http_redirect_port.unwrap_or_default();
+            override_parsed(&mut port, "TLS_HTTP_REDIRECT_PORT", problems);
+            tls.http_redirect_port = Some(port);
+        }
+        override_parsed(&mut tls.hsts_max_age_seconds, "HSTS_MAX_AGE_SECONDS", problems);
+        override_parsed(&mut tls.hsts_include_subdomains, "HSTS_INCLUDE_SUBDOMAINS", problems);
+    }
+
+    override_secret(&mut config.database.url, "DATABASE_URL", problems);
+    override_parsed(&mut config.database.max_connections, "DATABASE_MAX_CONNECTIONS", problems);
+    override_secret(&mut config.redis.url, "REDIS_URL", problems);
+
+    override_string(&mut config.google.client_id, "GOOGLE_CLIENT_ID");
+    override_secret(&mut config.google.client_secret, "GOOGLE_CLIENT_SECRET", problems);
+    override_option(&mut config.google.redirect_uri, "GOOGLE_REDIRECT_URI");
+    override_parsed(&mut config.google.max_listed_messages, "GMAIL_MAX_LISTED_MESSAGES", problems);
+    override_parsed(&mut config.google.quota_units_per_second, "GMAIL_QUOTA_UNITS_PER_SECOND", problems);
+    if env_string("GMAIL_PUBSUB_TOPIC").is_some() || config.google.push.is_some() {
+        let push = config.google.push.get_or_insert_with(GmailPushConfig::default);
+        override_string(&mut push.topic, "GMAIL_PUBSUB_TOPIC");
+        override_secret(&mut push.verification_token, "GMAIL_PUSH_VERIFICATION_TOKEN", problems);
+        if let Some(labels) = env_string("GMAIL_PUSH_LABEL_IDS") {
+            push.label_ids = labels.split(',').map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect();
+        }
+        override_parsed(&mut push.renew_before_seconds, "GMAIL_WATCH_RENEW_BEFORE_SECONDS", problems);
+    }
+
+    override_secret(&mut config.secrets.view_link_secret, "VIEW_LINK_SECRET", problems);
+    override_secret(&mut config.secrets.refresh_token_key, "REFRESH_TOKEN_KEY", problems);
+    override_secret(&mut config.secrets.transparency_signing_key, "TRANSPARENCY_SIGNING_KEY", problems);
+
+    override_option(&mut config.accounts.bootstrap_admin_email, "BOOTSTRAP_ADMIN_EMAIL");
+    override_parsed(&mut config.accounts.deletion_grace_days, "ACCOUNT_DELETION_GRACE_DAYS", problems);
+
+    override_string(&mut config.attachments.cache_dir, "ATTACHMENT_CACHE_DIR");
+    override_parsed(&mut config.attachments.max_size_bytes, "ATTACHMENT_MAX_SIZE_BYTES", problems);
+    override_parsed(&mut config.attachments.cache_max_bytes, "ATTACHMENT_CACHE_MAX_BYTES", problems);
+
+    if env_string("MICROSOFT_CLIENT_ID").is_some() || config.identity.microsoft.is_some() {
+        let microsoft = config.identity.microsoft.get_or_insert_with(MicrosoftConfig::default);
+        override_string(&mut microsoft.client_id, "MICROSOFT_CLIENT_ID");
+        override_secret(&mut microsoft.client_secret, "MICROSOFT_CLIENT_SECRET", problems);
+        override_string(&mut microsoft.tenant, "MICROSOFT_TENANT");
+        override_option(&mut microsoft.redirect_uri, "MICROSOFT_REDIRECT_URI");
+        if let Some(tenants) = env_string("MICROSOFT_TRUSTED_TENANTS") {
+            microsoft.trusted_tenants = tenants.split(',').map(str::trim).filter(|t| !t.is_empty()).map(str::to_string).collect();
+        }
+    }
+
+    // OIDC_PROVIDERS lists ids configured through OIDC_<ID>_* variables
+    if let Some(ids) = env_string("OIDC_PROVIDERS") {
+        for id in ids.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+            let id = id.to_lowercase();
+            let index = match config.identity.oidc.iter().position(|p| p.id == id) {
+                Some(index) => index,
+                None => {
+                    config.identity.oidc.push(OidcConfig { id: id.clone(), ..OidcConfig::default() });
+                    config.identity.oidc.len() - 1
+                }
+            };
+            let provider = &mut config.identity.oidc[index];
+            let var = |name: &str| format!("OIDC_{}_{}", id.to_uppercase(), name);
+
+            override_option(&mut provider.name, &var("NAME"));
+            override_string(&mut provider.issuer, &var("ISSUER"));
+            override_string(&mut provider.client_id, &var("CLIENT_ID"));
+            override_secret(&mut provider.client_secret, &var("CLIENT_SECRET"), problems);
+            override_option(&mut provider.redirect_uri, &var("REDIRECT_URI"));
+            if let Some(scopes) = env_string(&var("SCOPES")) {
+                provider.scopes = Some(scopes.split_whitespace().map(str::to_string).collect());
+            }
+            override_option(&mut provider.email_claim, &var("EMAIL_CLAIM"));
+            override_option(&mut provider.name_claim, &var("NAME_CLAIM"));
+            override_option(&mut provider.picture_claim, &var("PICTURE_CLAIM"));
+            override_option(&mut provider.email_verified_claim, &var("EMAIL_VERIFIED_CLAIM"));
+            if let Some(domains) = env_string(&var("TRUSTED_DOMAINS")) {
+                provider.trusted_domains = domains.split(',').map(str::trim).filter(|d| !d.is_empty()).map(str::to_string).collect();
+            }
+        }
+    }
+}
+
+fn check_url(problems: &mut Vec<String>, key: &str, value: &str) {
+    match reqwest::Url::parse(value) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+        _ => problems.push(format!("{} must be an http(s) URL, got {:?}", key, value)),
+    }
+}
+
+fn check_required(problems: &mut Vec<String>, key: &str, env_name: &str, missing: bool) {
+    if missing {
+        problems.push(format!("{} is required (or set {})", key, env_name));
+    }
+}
+
+fn check_key(problems: &mut Vec<String>, key: &str, env_name: &str, secret: &Secret) {
+    if secret.is_empty() {
+        check_required(problems, key, env_name, true);
+    } else if decode_config(secret.expose().trim(), STANDARD).map(|bytes| bytes.len()) != Ok(32) {
+        problems.push(format!("{} must be base64 of exactly 32 bytes", key));
+    }
+}
+
+impl AppConfig {
+    fn validate(&self, problems: &mut Vec<String>) {
+        check_url(problems, "server.backend_url", &self.server.backend_url);
+        check_url(problems, "server.frontend_url", &self.server.frontend_url);
+        for origin in &self.server.allowed_origins {
+            // Credentialed CORS with a wildcard would let any site act as the signed-in user
+            if origin_of(origin).as_deref() != Some(origin.trim().trim_end_matches('/')) {
+                problems.push(format!("server.allowed_origins entry {:?} must be an origin like https://mail.example.com", origin));
+            }
+        }
+
+        if let Some(ref tls) = self.server.tls {
+            check_required(problems, "server.tls.cert_path", "TLS_CERT_PATH", tls.cert_path.trim().is_empty());
+            check_required(problems, "server.tls.key_path", "TLS_KEY_PATH", tls.key_path.trim().is_empty());
+            if tls.reload_interval_seconds == 0 {
+                problems.push("server.tls.reload_interval_seconds must be greater than 0".to_string());
+            }
+            if tls.http_redirect_port == Some(self.server.port) {
+                problems.push("server.tls.http_redirect_port must differ from server.port".to_string());
+            }
+            // OAuth redirect URIs and Secure cookies both depend on the public URL being HTTPS
+            if !self.server.backend_url.starts_with("https://") {
+                problems.push("server.backend_url must be an https URL when server.tls is enabled".to_string());
+            }
+        }
+
+        check_required(problems, "database.url", "DATABASE_URL", self.database.url.is_empty());
+        if self.database.max_connections == 0 {
+            problems.push("database.max_connections must be at least 1".to_string());
+        }
+        if let Err(e) = redis::Client::open(self.redis.url.expose()) {
+            problems.push(format!("redis.url is not a valid Redis URL: {}", e));
+        }
+
+        for (key, ttl) in [
+            ("cache.email_ttl_seconds", self.cache.email_ttl_seconds),
+            ("cache.label_ttl_seconds", self.cache.label_ttl_seconds),
+        ] {
+            if ttl == 0 {
+                problems.push(format!("{} must be greater than 0", key));
+            }
+        }
+
+        check_required(problems, "google.client_id", "GOOGLE_CLIENT_ID", self.google.client_id.trim().is_empty());
+        check_required(problems, "google.client_secret", "GOOGLE_CLIENT_SECRET", self.google.client_secret.is_empty());
+        if let Some(ref uri) = self.google.redirect_uri {
+            check_url(problems, "google.redirect_uri", uri);
+        }
+        if self.google.max_listed_messages == 0 {
+            problems.push("google.max_listed_messages must be at least 1".to_string());
+        }
+        // A single messages.send costs 100 units, so a smaller bucket could never afford it
+        if self.google.quota_units_per_second < 100 {
+            problems.push("google.quota_units_per_second must be at least 100".to_string());
+        }
+        if let Some(ref push) = self.google.push {
+            let topic_parts: Vec<&str> = push.topic.trim().split('/').collect();
+            if push.topic.trim().is_empty() {
+                check_required(problems, "google.push.topic", "GMAIL_PUBSUB_TOPIC", true);
+            } else if !matches!(topic_parts.as_slice(), ["projects", project, "topics", topic] if !project.is_empty() && !topic.is_empty()) {
+                problems.push(format!("google.push.topic must look like projects/<project>/topics/<topic>, got {:?}", push.topic));
+            }
+            // The token is all that stands between the endpoint and anyone who wants to trigger syncs
+            if push.verification_token.is_empty() {
+                check_required(problems, "google.push.verification_token", "GMAIL_PUSH_VERIFICATION_TOKEN", true);
+            } else if push.verification_token.expose().len() < 16 {
+                problems.push("google.push.verification_token must be at least 16 characters".to_string());
+            }
+            // Renewal runs every few minutes; a shorter window leaves no room to retry a failed renewal
+            if push.renew_before_seconds < 3600 {
+                problems.push("google.push.renew_before_seconds must be at least 3600".to_string());
+            }
+        }
+
+        if let Some(ref microsoft) = self.identity.microsoft {
+            check_required(problems, "identity.microsoft.client_id", "MICROSOFT_CLIENT_ID", microsoft.client_id.trim().is_empty());
+            check_required(problems, "identity.microsoft.client_secret", "MICROSOFT_CLIENT_SECRET", microsoft.client_secret.is_empty());
+            check_required(problems, "identity.microsoft.tenant", "MICROSOFT_TENANT", microsoft.tenant.trim().is_empty());
+            if let Some(ref uri) = microsoft.redirect_uri {
+                check_url(problems, "identity.microsoft.redirect_uri", uri);
+            }
+        }
+
+        let mut seen_ids = vec!["google".to_string()];
+        if self.identity.microsoft.is_some() {
+            seen_ids.push("microsoft".to_string());
+        }
+        for provider in &self.identity.oidc {
+            let id = &provider.id;
+            let env_prefix = format!("OIDC_{}", id.to_uppercase());
+            if id.is_empty() || !id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_') {
+                problems.push(format!("identity.oidc id {:?} must be lowercase letters, digits, '-' or '_'", id));
+                continue;
+            }
+            if seen_ids.contains(id) {
+                problems.push(format!("identity provider {} is configured twice", id));
+            }
+            seen_ids.push(id.clone());
+
+            if provider.issuer.trim().is_empty() {
+                check_required(problems, &format!("identity.oidc[{}].issuer", id), &format!("{}_ISSUER", env_prefix), true);
+            } else {
+                check_url(problems, &format!("identity.oidc[{}].issuer", id), &provider.issuer);
+            }
+            check_required(problems, &format!("identity.oidc[{}].client_id", id), &format!("{}_CLIENT_ID", env_prefix), provider.client_id.trim().is_empty());
+            check_required(problems, &format!("identity.oidc[{}].client_secret", id), &format!("{}_CLIENT_SECRET", env_prefix), provider.client_secret.is_empty());
+            if let Some(ref uri) = provider.redirect_uri {
+                check_url(problems, &format!("identity.oidc[{}].redirect_uri", id), uri);
+            }
+        }
+
+        check_required(problems, "secrets.view_link_secret", "VIEW_LINK_SECRET", self.secrets.view_link_secret.is_empty());
+        // It is the HMAC key for bearer view links, so it must not be guessable
+        if !self.secrets.view_link_secret.is_empty() && self.secrets.view_link_secret.expose().len() < 32 {
+            problems.push("secrets.view_link_secret must be at least 32 bytes".to_string());
+        }
+        check_key(problems, "secrets.refresh_token_key", "REFRESH_TOKEN_KEY", &self.secrets.refresh_token_key);
+        check_key(problems, "secrets.transparency_signing_key", "TRANSPARENCY_SIGNING_KEY", &self.secrets.transparency_signing_key);
+
+        if self.accounts.deletion_grace_days < 0 {
+            problems.push("accounts.deletion_grace_days must not be negative".to_string());
+        }
+
+        check_required(problems, "attachments.cache_dir", "ATTACHMENT_CACHE_DIR", self.attachments.cache_dir.trim().is_empty());
+        if self.attachments.max_size_bytes == 0 {
+            problems.push("attachments.max_size_bytes must be greater than 0".to_string());
+        }
+        // The cache must be able to hold at least the largest attachment it serves
+        if self.attachments.cache_max_bytes < self.attachments.max_size_bytes {
+            problems.push("attachments.cache_max_bytes must be at least attachments.max_size_bytes".to_string());
+        }
+    }
+
+    /// Bootstrap admin email, ignoring an empty setting
+    pub fn bootstrap_admin_email(&self) -> Option<&str> {
+        self.accounts.bootstrap_admin_email.as_deref().map(str::trim).filter(|email| !email.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // The environment is shared by every test in the process
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    // Run with the given variables set (or removed), putting back whatever was there before
+    fn with_env<T>(vars: &[(&str, Option<&str>)], run: impl FnOnce() -> T) -> T {
+        let _guard = ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let previous: Vec<(String, Option<String>)> = vars.iter().map(|(name, _)| (name.to_string(), env::var(name).ok())).collect();
+        for (name, value) in vars {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+        let result = run();
+        for (name, value) in previous {
+            match value {
+                Some(value) => env::set_var(&name, value),
+                None => env::remove_var(&name),
+            }
+        }
+        result
+    }
+
+    fn secret_file(contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("quant-config-test-{}", uuid::Uuid::new_v4()));
+        fs::write(&path, contents).expect("secret file");
+        path
+    }
+
+    fn valid_config() -> AppConfig {
+        toml::from_str(&format!(
+            r#"
+            [database]
+            url = "postgres://localhost/quant"
+
+            [google]
+            client_id = "file-client"
+            client_secret = "google-secret"
+
+            [secrets]
+            view_link_secret = "0123456789abcdef0123456789abcdef"
+            refresh_token_key = "{KEY}"
+            transparency_signing_key = "{KEY}"
+            "#
+        ))
+        .expect("config")
+    }
+
+    fn problems_of(config: &AppConfig) -> Vec<String> {
+        let mut problems = Vec::new();
+        config.validate(&mut problems);
+        problems
+    }
+
+    #[test]
+    fn config_files_are_parsed_with_defaults_and_file_secrets() {
+        let path = secret_file("from-a-file\n");
+        let config: AppConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            port = 9090
+            frontend_url = "https://mail.example.com"
+
+            [google]
+            client_secret = {{ file = "{}" }}
+
+            [[identity.oidc]]
+            id = "okta"
+            issuer = "https://example.okta.com"
+            trusted_domains = ["example.com"]
+            "#,
+            path.display()
+        ))
+        .expect("config");
+        fs::remove_file(&path).ok();
+
+        assert_eq!(config.server.port, 9090);
+        assert_eq!(config.server.backend_url, "http://localhost:8080");
+        assert_eq!(config.server.trusted_origins(), vec!["https://mail.example.com".to_string()]);
+        assert_eq!(config.google.client_secret.expose(), "from-a-file");
+        assert_eq!(config.identity.oidc[0].trusted_domains, vec!["example.com".to_string()]);
+        assert!(!format!("{:?}", config).contains("from-a-file"));
+
+        assert!(toml::from_str::<AppConfig>("[server]\nprot = 1\n").is_err());
+        assert!(toml::from_str::<AppConfig>(r#"[google]
+client_secret = { file = "/nonexistent/quant-secret" }"#).is_err());
+    }
+
+    #[test]
+    fn environment_variables_override_the_file() {
+        let mut config = valid_config();
+        let problems = with_env(
+            &[
+                ("GOOGLE_CLIENT_ID", Some("env-client")),
+                ("PORT", Some("9191")),
+                ("GMAIL_QUOTA_UNITS_PER_SECOND", Some("lots")),
+                ("CORS_ALLOWED_ORIGINS", Some("https://a.example.com, https://b.example.com")),
+            ],
+            || {
+                let mut problems = Vec::new();
+                apply_env_overrides(&mut config, &mut problems);
+                problems
+            },
+        );
+
+        assert_eq!(config.google.client_id, "env-client");
+        assert_eq!(config.server.port, 9191);
+        assert_eq!(config.server.allowed_origins, vec!["https://a.example.com".to_string(), "https://b.example.com".to_string()]);
+        // A value that does not parse is reported and leaves the file's setting alone
+        assert_eq!(config.google.quota_units_per_second, GoogleConfig::default().quota_units_per_second);
+        assert_eq!(problems, vec!["GMAIL_QUOTA_UNITS_PER_SECOND has an invalid value \"lots\"".to_string()]);
+    }
+
+    #[test]
+    fn secrets_are_read_from_file_variables() {
+        let path = secret_file("a-view-link-secret-that-is-long-enough\r\n");
+        let path_text = path.display().to_string();
+        let mut config = valid_config();
+        let problems = with_env(
+            &[
+                ("VIEW_LINK_SECRET", None),
+                ("VIEW_LINK_SECRET_FILE", Some(&path_text)),
+                ("GOOGLE_CLIENT_SECRET", None),
+                ("GOOGLE_CLIENT_SECRET_FILE", Some("/nonexistent/quant-secret")),
+            ],
+            || {
+                let mut problems = Vec::new();
+                apply_env_overrides(&mut config, &mut problems);
+                problems
+            },
+        );
+        fs::remove_file(&path).ok();
+
+        assert_eq!(config.secrets.view_link_secret.expose(), "a-view-link-secret-that-is-long-enough");
+        assert_eq!(config.google.client_secret.expose(), "google-secret");
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("GOOGLE_CLIENT_SECRET_FILE: cannot read secret file"));
+    }
+
+    #[test]
+    fn validation_reports_every_problem_at_once() {
+        assert_eq!(problems_of(&valid_config()), Vec::<String>::new());
+
+        let problems = problems_of(&AppConfig::default());
+        for expected in [
+            "database.url is required (or set DATABASE_URL)",
+            "google.client_id is required (or set GOOGLE_CLIENT_ID)",
+            "google.client_secret is required (or set GOOGLE_CLIENT_SECRET)",
+            "secrets.view_link_secret is required (or set VIEW_LINK_SECRET)",
+            "secrets.refresh_token_key is required (or set REFRESH_TOKEN_KEY)",
+            "secrets.transparency_signing_key is required (or set TRANSPARENCY_SIGNING_KEY)",
+        ] {
+            assert!(problems.iter().any(|p| p == expected), "missing {:?} in {:?}", expected, problems);
+        }
+
+        let mut config = valid_config();
+        config.database.max_connections = 0;
+        config.server.allowed_origins = vec!["*".to_string()];
+        config.secrets.view_link_secret = Secret("too-short".to_string());
+        config.secrets.refresh_token_key = Secret("c2hvcnQ=".to_string());
+        config.accounts.deletion_grace_days = -1;
+        assert_eq!(
+            problems_of(&config),
+            vec![
+                "server.allowed_origins entry \"*\" must be an origin like https://mail.example.com".to_string(),
+                "database.max_connections must be at least 1".to_string(),
+                "secrets.view_link_secret must be at least 32 bytes".to_string(),
+                "secrets.refresh_token_key must be base64 of exactly 32 bytes".to_string(),
+                "accounts.deletion_grace_days must not be negative".to_string(),
+            ]
+        );
+    }
+}
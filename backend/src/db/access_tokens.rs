@@ -0,0 +1,180 @@
+use sqlx::{PgPool, Row, types::time};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::models::{AccessTokenInfo, TokenScope};
+
+// Characters of the token kept in plaintext so users can tell their tokens apart
+const TOKEN_PREFIX_LEN: usize = 12;
+
+// The user behind a valid personal access token
+pub struct AccessTokenUser {
+    pub scopes: Vec<TokenScope>,
+    pub email: String,
+    pub name: Option<String>,
+    pub picture: Option<String>,
+    pub refresh_token: Option<String>,
+}
+
+// Access tokens are only stored as a SHA-256 digest
+fn hash_access_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn parse_scopes(scopes: Vec<String>) -> Vec<TokenScope> {
+    scopes.iter().filter_map(|s| TokenScope::parse(s)).collect()
+}
+
+// Create the access_tokens table if it doesn't exist
+pub async fn init_access_tokens_table(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS access_tokens (
+            id UUID PRIMARY KEY,
+            user_email TEXT NOT NULL REFERENCES users(email) ON DELETE CASCADE,
+            name TEXT NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            token_prefix TEXT NOT NULL,
+            scopes TEXT[] NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            last_used_at TIMESTAMPTZ,
+            expires_at TIMESTAMPTZ
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_access_tokens_user_email ON access_tokens(user_email)")
+        .execute(pool)
+        .await?;
+
+    println!("Access tokens table initialized successfully");
+    Ok(())
+}
+
+// Store a new token for a user; the plaintext token is never saved
+pub async fn create_access_token(
+    pool: &PgPool,
+    email: &str,
+    name: &str,
+    token: &str,
+    scopes: &[TokenScope],
+    expires_in_days: Option<i32>,
+) -> Result<AccessTokenInfo, sqlx::Error> {
+    let id = Uuid::new_v4();
+    let scope_names: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+    let token_prefix: String = token.chars().take(TOKEN_PREFIX_LEN).collect();
+
+    let row = sqlx::query(
+        r#"
+        INSERT INTO access_tokens (id, user_email, name, token_hash, token_prefix, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, NOW() + make_interval(days => $7))
+        RETURNING created_at, expires_at
+        "#
+    )
+    .bind(id)
+    .bind(email)
+    .bind(name)
+    .bind(hash_access_token(token))
+    .bind(&token_prefix)
+    .bind(&scope_names)
+    .bind(expires_in_days)
+    .fetch_one(pool)
+    .await?;
+
+    let created_at: time::OffsetDateTime = row.get("created_at");
+    let expires_at: Option<time::OffsetDateTime> = row.get("expires_at");
+    Ok(AccessTokenInfo {
+        id: id.to_string(),
+        name: name.to_string(),
+        scopes: scopes.to_vec(),
+        token_prefix,
+        created_at: created_at.to_string(),
+        last_used_at: None,
+        expires_at: expires_at.map(|t| t.to_string()),
+    })
+}
+
+// List a user's unexpired tokens
+pub async fn list_access_tokens(pool: &PgPool, email: &str) -> Result<Vec<AccessTokenInfo>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id::text AS id, name, token_prefix, scopes, created_at, last_used_at, expires_at
+        FROM access_tokens
+        WHERE user_email = $1 AND (expires_at IS NULL OR expires_at > NOW())
+        ORDER BY created_at DESC
+        "#
+    )
+    .bind(email)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| {
+        let created_at: time::OffsetDateTime = row.get("created_at");
+        let last_used_at: Option<time::OffsetDateTime> = row.get("last_used_at");
+        let expires_at: Option<time::OffsetDateTime> = row.get("expires_at");
+        AccessTokenInfo {
+            id: row.get("id"),
+            name: row.get("name"),
+            scopes: parse_scopes(row.get("scopes")),
+            token_prefix: row.get("token_prefix"),
+            created_at: created_at.to_string(),
+            last_used_at: last_used_at.map(|t| t.to_string()),
+            expires_at: expires_at.map(|t| t.to_string()),
+        }
+    }).collect())
+}
+
+// Delete one of a user's tokens; returns false if it doesn't exist or belongs to someone else
+pub async fn revoke_access_token(pool: &PgPool, email: &str, token_id: &str) -> Result<bool, sqlx::Error> {
+    let token_id = match Uuid::parse_str(token_id) {
+        Ok(id) => id,
+        Err(_) => return Ok(false),
+    };
+
+    let result = sqlx::query("DELETE FROM access_tokens WHERE id = $1 AND user_email = $2")
+        .bind(token_id)
+        .bind(email)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Delete all of a user's tokens
+pub async fn revoke_all_access_tokens(pool: &PgPool, email: &str) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM access_tokens WHERE user_email = $1")
+        .bind(email)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Look up the user behind a bearer token, rejecting expired tokens and recording use
+pub async fn get_user_by_access_token(pool: &PgPool, token: &str) -> Result<Option<AccessTokenUser>, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        WITH used AS (
+            UPDATE access_tokens SET last_used_at = NOW()
+            WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > NOW())
+            RETURNING user_email, scopes
+        )
+        SELECT used.scopes, u.email, u.name, u.picture, u.refresh_token
+        FROM users u
+        JOIN used ON used.user_email = u.email
+        "#
+    )
+    .bind(hash_access_token(token))
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| AccessTokenUser {
+        scopes: parse_scopes(r.get("scopes")),
+        email: r.get("email"),
+        name: r.get("name"),
+        picture: r.get("picture"),
+        refresh_token: r.get("refresh_token"),
+    }))
+}
@@ -0,0 +1,235 @@
+use sqlx::{PgPool, Row, types::time};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::models::AccountDeletionReceipt;
+
+// Undo tokens are only stored as a SHA-256 digest
+fn hash_undo_token(undo_token: &str) -> String {
+    format!("{:x}", Sha256::digest(undo_token.as_bytes()))
+}
+
+fn receipt_from_row(row: &sqlx::postgres::PgRow) -> AccountDeletionReceipt {
+    let requested_at: time::OffsetDateTime = row.get("requested_at");
+    let scheduled_for: time::OffsetDateTime = row.get("scheduled_for");
+    let completed_at: Option<time::OffsetDateTime> = row.get("completed_at");
+    AccountDeletionReceipt {
+        receipt_id: row.get("id"),
+        status: row.get("status"),
+        requested_at: requested_at.to_string(),
+        scheduled_for: scheduled_for.to_string(),
+        completed_at: completed_at.map(|t| t.to_string()),
+        google_grant_revoked: row.get("google_grant_revoked"),
+        undo_token: None,
+    }
+}
+
+const RECEIPT_COLUMNS: &str = "id::text AS id, status, requested_at, scheduled_for, completed_at, google_grant_revoked";
+
+// Create the account_deletions table if it doesn't exist.
+// Receipts outlive the account, so there is no foreign key to users.
+pub async fn init_account_deletions_table(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS account_deletions (
+            id UUID PRIMARY KEY,
+            user_email TEXT,
+            status TEXT NOT NULL CHECK (status IN ('pending', 'cancelled', 'completed')),
+            undo_token_hash TEXT,
+            requested_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            scheduled_for TIMESTAMPTZ NOT NULL,
+            completed_at TIMESTAMPTZ,
+            google_grant_revoked BOOLEAN
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // At most one pending deletion per user
+    sqlx::query(
+        r#"
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_account_deletions_pending
+        ON account_deletions(user_email) WHERE status = 'pending'
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    println!("Account deletions table initialized successfully");
+    Ok(())
+}
+
+// Schedule a user's account for deletion after the grace period
+pub async fn schedule_account_deletion(
+    pool: &PgPool,
+    email: &str,
+    grace_days: i32,
+    undo_token: &str,
+) -> Result<AccountDeletionReceipt, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        r#"
+        INSERT INTO account_deletions (id, user_email, status, undo_token_hash, scheduled_for)
+        VALUES ($1, $2, 'pending', $3, NOW() + make_interval(days => $4))
+        RETURNING {}
+        "#,
+        RECEIPT_COLUMNS
+    ))
+    .bind(Uuid::new_v4())
+    .bind(email)
+    .bind(hash_undo_token(undo_token))
+    .bind(grace_days)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(receipt_from_row(&row))
+}
+
+// The pending deletion for a user, if any
+pub async fn get_pending_deletion(pool: &PgPool, email: &str) -> Result<Option<AccountDeletionReceipt>, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        "SELECT {} FROM account_deletions WHERE user_email = $1 AND status = 'pending'",
+        RECEIPT_COLUMNS
+    ))
+    .bind(email)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.as_ref().map(receipt_from_row))
+}
+
+// Look up a receipt by its id
+pub async fn get_deletion_receipt(pool: &PgPool, receipt_id: &str) -> Result<Option<AccountDeletionReceipt>, sqlx::Error> {
+    let receipt_id = match Uuid::parse_str(receipt_id) {
+        Ok(id) => id,
+        Err(_) => return Ok(None),
+    };
+
+    let row = sqlx::query(&format!("SELECT {} FROM account_deletions WHERE id = $1", RECEIPT_COLUMNS))
+        .bind(receipt_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.as_ref().map(receipt_from_row))
+}
+
+// Cancel a pending deletion; only the holder of the undo token may do this
+pub async fn cancel_account_deletion(
+    pool: &PgPool,
+    receipt_id: &str,
+    undo_token: &str,
+) -> Result<Option<AccountDeletionReceipt>, sqlx::Error> {
+    let receipt_id = match Uuid::parse_str(receipt_id) {
+        Ok(id) => id,
+        Err(_) => return Ok(None),
+    };
+
+    let row = sqlx::query(&format!(
+        r#"
+        UPDATE account_deletions SET status = 'cancelled', undo_token_hash = NULL
+        WHERE id = $1 AND status = 'pending' AND undo_token_hash = $2
+        RETURNING {}
+        "#,
+        RECEIPT_COLUMNS
+    ))
+    .bind(receipt_id)
+    .bind(hash_undo_token(undo_token))
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.as_ref().map(receipt_from_row))
+}
+
+// Pending deletions whose grace period has run out, as (receipt id, email)
+pub async fn get_due_deletions(pool: &PgPool) -> Result<Vec<(String, String)>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id::text AS id, user_email FROM account_deletions
+        WHERE status = 'pending' AND scheduled_for <= NOW()
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|r| (r.get("id"), r.get("user_email"))).collect())
+}
+
+// Remove everything the user owns. Messages they sent to other account holders
+// stay in those recipients' mailboxes, everything else involving them goes.
+// Sessions and access tokens are removed by the users foreign keys.
+pub async fn delete_user_data(pool: &PgPool, email: &str) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        CREATE TEMP TABLE deleted_emails ON COMMIT DROP AS
+        SELECT id FROM emails
+        WHERE recipient_email = $1
+           OR (sender_email = $1 AND recipient_email NOT IN (SELECT email FROM users WHERE email <> $1))
+        "#
+    )
+    .bind(email)
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM email_labels
+        WHERE email_id IN (SELECT id FROM deleted_emails)
+           OR label_id IN (SELECT id FROM labels WHERE user_id = $1)
+        "#
+    )
+    .bind(email)
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query("DELETE FROM emails WHERE id IN (SELECT id FROM deleted_emails)")
+        .execute(&mut tx)
+        .await?;
+
+    sqlx::query("DELETE FROM labels WHERE user_id = $1")
+        .bind(email)
+        .execute(&mut tx)
+        .await?;
+
+    sqlx::query("DELETE FROM user_keys WHERE email = $1")
+        .bind(email)
+        .execute(&mut tx)
+        .await?;
+
+    sqlx::query("DELETE FROM users WHERE email = $1")
+        .bind(email)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await
+}
+
+// Close the receipt; the email address is dropped along with the account
+pub async fn complete_account_deletion(
+    pool: &PgPool,
+    receipt_id: &str,
+    google_grant_revoked: bool,
+) -> Result<Option<AccountDeletionReceipt>, sqlx::Error> {
+    let receipt_id = match Uuid::parse_str(receipt_id) {
+        Ok(id) => id,
+        Err(_) => return Ok(None),
+    };
+
+    let row = sqlx::query(&format!(
+        r#"
+        UPDATE account_deletions
+        SET status = 'completed', completed_at = NOW(), google_grant_revoked = $2,
+            user_email = NULL, undo_token_hash = NULL
+        WHERE id = $1
+        RETURNING {}
+        "#,
+        RECEIPT_COLUMNS
+    ))
+    .bind(receipt_id)
+    .bind(google_grant_revoked)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.as_ref().map(receipt_from_row))
+}
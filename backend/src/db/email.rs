@@ -1,4 +1,4 @@
-use sqlx::{PgPool, Row, types::time};
+use sqlx::{PgPool, Row, postgres::PgRow, types::time};
 use crate::models::{Email, EmailFilter, SortField, SortOrder};
 use uuid::Uuid;
 
@@ -24,6 +24,41 @@ pub async fn init_email_table(pool: &PgPool) -> Result<(), sqlx::Error> {
     )
     .execute(pool)
     .await?;
+
+    // Track sender-initiated recalls of unread messages
+    sqlx::query("ALTER TABLE emails ADD COLUMN IF NOT EXISTS recalled_at TIMESTAMPTZ")
+        .execute(pool)
+        .await?;
+
+    // Conversation threading: the RFC 5322 Message-ID, In-Reply-To and References of each message,
+    // and the id of the message that started its thread
+    sqlx::query(
+        r#"
+        ALTER TABLE emails
+            ADD COLUMN IF NOT EXISTS message_id TEXT,
+            ADD COLUMN IF NOT EXISTS in_reply_to TEXT,
+            ADD COLUMN IF NOT EXISTS reference_ids TEXT[] NOT NULL DEFAULT '{}',
+            ADD COLUMN IF NOT EXISTS thread_id TEXT
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Messages stored before threading each start their own thread
+    sqlx::query(
+        r#"
+        UPDATE emails
+        SET message_id = '<' || id::text || '@' || COALESCE(NULLIF(split_part(sender_email, '@', 2), ''), 'quant.local') || '>',
+            thread_id = id::text
+        WHERE message_id IS NULL
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_emails_thread_id ON emails(thread_id)")
+        .execute(pool)
+        .await?;
     
     println!("Emails table initialized successfully");
     Ok(())
@@ -43,13 +78,15 @@ pub async fn store_email(
     // Generate a new UUID
     let email_uuid = Uuid::new_v4();
     let email_id = email_uuid.to_string();
+    let message_id = new_message_id(&email_id, sender_email);
     
+    // Every message starts its own thread; db::threads::link_reply moves a reply into its parent's
     let query = match raw_encrypted_content {
         Some(content) => {
             sqlx::query(
                 r#"
-                INSERT INTO emails (id, sender_id, sender_email, recipient_email, subject, body, is_encrypted, raw_encrypted_content)
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                INSERT INTO emails (id, sender_id, sender_email, recipient_email, subject, body, is_encrypted, raw_encrypted_content, message_id, thread_id)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $1::text)
                 "#
             )
             .bind(email_uuid)
@@ -60,12 +97,13 @@ pub async fn store_email(
             .bind(body)
             .bind(is_encrypted)
             .bind(content)
+            .bind(&message_id)
         },
         None => {
             sqlx::query(
                 r#"
-                INSERT INTO emails (id, sender_id, sender_email, recipient_email, subject, body, is_encrypted)
-                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                INSERT INTO emails (id, sender_id, sender_email, recipient_email, subject, body, is_encrypted, message_id, thread_id)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $1::text)
                 "#
             )
             .bind(email_uuid)
@@ -75,6 +113,7 @@ pub async fn store_email(
             .bind(subject)
             .bind(body)
             .bind(is_encrypted)
+            .bind(&message_id)
         }
     };
     
@@ -83,11 +122,70 @@ pub async fn store_email(
     Ok(email_id)
 }
 
+// Remove a stored email whose notification never went out, with its portal link and labels
+pub async fn delete_unsent_email(pool: &PgPool, email_id: &str) -> Result<(), sqlx::Error> {
+    let uuid = match Uuid::parse_str(email_id) {
+        Ok(uuid) => uuid,
+        Err(_) => return Ok(()),
+    };
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM email_labels WHERE email_id = $1")
+        .bind(uuid)
+        .execute(&mut tx)
+        .await?;
+    // portal_access and its log go with the email
+    sqlx::query("DELETE FROM emails WHERE id = $1")
+        .bind(uuid)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// An RFC 5322 Message-ID for a stored message, in the sender's domain
+fn new_message_id(email_id: &str, sender_email: &str) -> String {
+    let domain = sender_email.rsplit_once('@')
+        .map(|(_, domain)| domain)
+        .filter(|domain| !domain.is_empty())
+        .unwrap_or("quant.local");
+    format!("<{}@{}>", email_id, domain)
+}
+
 // Helper function to format timestamp as ISO string
-fn format_timestamp(timestamp: Option<time::OffsetDateTime>) -> Option<String> {
+pub(crate) fn format_timestamp(timestamp: Option<time::OffsetDateTime>) -> Option<String> {
     timestamp.map(|ts| ts.to_string())
 }
 
+// Build an Email from a row selecting the columns of the emails table and aggregated label_ids
+pub(crate) fn email_from_row(row: &PgRow) -> Email {
+    let sent_at: Option<time::OffsetDateTime> = row.get("sent_at");
+    let read_at: Option<time::OffsetDateTime> = row.get("read_at");
+    let recalled_at: Option<time::OffsetDateTime> = row.get("recalled_at");
+    let label_ids: Option<Vec<String>> = row.try_get("label_ids").ok();
+    
+    Email {
+        id: row.get("id"),
+        sender_id: row.get("sender_id"),
+        sender_email: row.get("sender_email"),
+        sender_name: row.get("sender_name"),
+        recipient_email: row.get("recipient_email"),
+        subject: row.get("subject"),
+        body: row.get("body"),
+        html_body: None,
+        attachments: Vec::new(),
+        sent_at: format_timestamp(sent_at).unwrap_or_default(),
+        read_at: format_timestamp(read_at),
+        gmail_id: row.get("gmail_id"),
+        label_ids,
+        is_encrypted: row.try_get("is_encrypted").unwrap_or(false),
+        raw_encrypted_content: row.try_get("raw_encrypted_content").ok(),
+        recalled_at: format_timestamp(recalled_at),
+        thread_id: row.get("thread_id"),
+    }
+}
+
 // Get emails for a user with filtering and sorting options
 pub async fn get_emails_for_user(
     pool: &PgPool,
@@ -100,16 +198,22 @@ pub async fn get_emails_for_user(
         if is_sender {
             r#"
             SELECT e.id::text, e.sender_id, e.sender_email, e.sender_name, 
-                   e.recipient_email, e.subject, e.body, e.sent_at, e.read_at, e.gmail_id,
+                   e.recipient_email, e.subject, e.body, e.sent_at, e.read_at, e.gmail_id, e.recalled_at, e.thread_id,
+                   e.is_encrypted, e.raw_encrypted_content,
                    ARRAY_AGG(el.label_id) FILTER (WHERE el.label_id IS NOT NULL) AS label_ids
             FROM emails e
             LEFT JOIN email_labels el ON e.id = el.email_id
             WHERE e.sender_email = $1
             "#
         } else {
+            // Bodies of unread messages are left out; opening one marks it read, which is what ends
+            // the sender's chance to recall it
             r#"
             SELECT e.id::text, e.sender_id, e.sender_email, e.sender_name, 
-                   e.recipient_email, e.subject, e.body, e.sent_at, e.read_at, e.gmail_id,
+                   e.recipient_email, e.subject, e.sent_at, e.read_at, e.gmail_id, e.recalled_at, e.thread_id,
+                   CASE WHEN e.read_at IS NULL THEN '' ELSE e.body END AS body,
+                   e.is_encrypted,
+                   CASE WHEN e.read_at IS NULL THEN NULL ELSE e.raw_encrypted_content END AS raw_encrypted_content,
                    ARRAY_AGG(el.label_id) FILTER (WHERE el.label_id IS NOT NULL) AS label_ids
             FROM emails e
             LEFT JOIN email_labels el ON e.id = el.email_id
@@ -139,11 +243,17 @@ pub async fn get_emails_for_user(
             }
         }
         
-        // Filter by search term (subject, body, sender, recipient)
+        // Filter by search term (subject, body, sender, recipient); unread bodies cannot be searched either
         if let Some(search) = &filter.search {
             let search_param = format!("%{}%", search);
-            query_string.push_str(&format!(" AND (e.subject ILIKE ${} OR e.body ILIKE ${} OR e.sender_email ILIKE ${} OR e.recipient_email ILIKE ${})",
-                params.len() + 1, params.len() + 1, params.len() + 1, params.len() + 1));
+            let param = params.len() + 1;
+            let body_match = if is_sender {
+                format!("e.body ILIKE ${}", param)
+            } else {
+                format!("(e.read_at IS NOT NULL AND e.body ILIKE ${})", param)
+            };
+            query_string.push_str(&format!(" AND (e.subject ILIKE ${} OR {} OR e.sender_email ILIKE ${} OR e.recipient_email ILIKE ${})",
+                param, body_match, param, param));
             params.push(search_param);
         }
         
@@ -206,27 +316,7 @@ pub async fn get_emails_for_user(
     let rows = query.fetch_all(pool).await?;
     
     // Convert rows to emails
-    let emails = rows.into_iter().map(|row| {
-        let sent_at: Option<time::OffsetDateTime> = row.get("sent_at");
-        let read_at: Option<time::OffsetDateTime> = row.get("read_at");
-        let label_ids: Option<Vec<String>> = row.try_get("label_ids").ok();
-        
-        Email {
-            id: row.get("id"),
-            sender_id: row.get("sender_id"),
-            sender_email: row.get("sender_email"),
-            sender_name: row.get("sender_name"),
-            recipient_email: row.get("recipient_email"),
-            subject: row.get("subject"),
-            body: row.get("body"),
-            sent_at: format_timestamp(sent_at).unwrap_or_else(|| "".to_string()),
-            read_at: format_timestamp(read_at),
-            gmail_id: row.get("gmail_id"),
-            label_ids,
-            is_encrypted: row.try_get("is_encrypted").unwrap_or(false),
-            raw_encrypted_content: row.try_get("raw_encrypted_content").ok(),
-        }
-    }).collect();
+    let emails = rows.iter().map(email_from_row).collect();
     
     Ok(emails)
 }
@@ -247,8 +337,9 @@ pub async fn get_email(
 
     let row = sqlx::query(
         r#"
-        SELECT e.id, e.sender_id, e.sender_email, e.sender_name, e.recipient_email, 
-               e.subject, e.body, e.sent_at, e.read_at, e.gmail_id,
+        SELECT e.id::text, e.sender_id, e.sender_email, e.sender_name, e.recipient_email, 
+               e.subject, e.body, e.sent_at, e.read_at, e.gmail_id, e.recalled_at, e.thread_id,
+               e.is_encrypted, e.raw_encrypted_content,
                ARRAY_AGG(el.label_id) FILTER (WHERE el.label_id IS NOT NULL) AS label_ids
         FROM emails e
         LEFT JOIN email_labels el ON e.id = el.email_id
@@ -260,27 +351,7 @@ pub async fn get_email(
     .fetch_optional(pool)
     .await?;
     
-    let email = row.map(|row| {
-        let sent_at: Option<time::OffsetDateTime> = row.get("sent_at");
-        let read_at: Option<time::OffsetDateTime> = row.get("read_at");
-        let label_ids: Option<Vec<String>> = row.try_get("label_ids").ok();
-        
-        Email {
-            id: row.get("id"),
-            sender_id: row.get("sender_id"),
-            sender_email: row.get("sender_email"),
-            sender_name: row.get("sender_name"),
-            recipient_email: row.get("recipient_email"),
-            subject: row.get("subject"),
-            body: row.get("body"),
-            sent_at: format_timestamp(sent_at).unwrap_or_else(|| "".to_string()),
-            read_at: format_timestamp(read_at),
-            gmail_id: row.get("gmail_id"),
-            label_ids,
-            is_encrypted: row.try_get("is_encrypted").unwrap_or(false),
-            raw_encrypted_content: row.try_get("raw_encrypted_content").ok(),
-        }
-    });
+    let email = row.as_ref().map(email_from_row);
     
     Ok(email)
 }
@@ -346,3 +417,119 @@ pub async fn remove_label_from_email(
     
     Ok(())
 }
+
+// Body shown to the recipient in place of a recalled message
+pub const RECALLED_EMAIL_BODY: &str = "This message was recalled by the sender.";
+
+// Result of a sender's attempt to recall a message
+#[derive(Debug)]
+pub enum RecallOutcome {
+    Recalled(Box<Email>),
+    AlreadyRead(String),
+    AlreadyRecalled(String),
+    NotSender,
+    NotFound,
+}
+
+// Mark an email read for its recipient and return it, in one statement: a recall either lands
+// first, and the body is gone, or finds the email read and fails. None when the email is not
+// this recipient's or has been recalled; the flag is set when this was the first read.
+pub async fn mark_email_read(
+    pool: &PgPool,
+    email_id: &str,
+    recipient_email: &str,
+) -> Result<Option<(Email, bool)>, sqlx::Error> {
+    let uuid = match Uuid::parse_str(email_id) {
+        Ok(uuid) => uuid,
+        Err(_) => return Ok(None),
+    };
+
+    // NOW() is fixed for the statement, so only a read_at set just now equals it
+    let row = sqlx::query(
+        r#"
+        WITH opened AS (
+            UPDATE emails
+            SET read_at = COALESCE(read_at, NOW())
+            WHERE id = $1 AND recipient_email = $2 AND recalled_at IS NULL
+            RETURNING *
+        )
+        SELECT e.id::text, e.sender_id, e.sender_email, e.sender_name, e.recipient_email,
+               e.subject, e.body, e.sent_at, e.read_at, e.gmail_id, e.recalled_at, e.thread_id,
+               e.is_encrypted, e.raw_encrypted_content, e.read_at = NOW() AS newly_read,
+               (SELECT ARRAY_AGG(el.label_id) FROM email_labels el WHERE el.email_id = e.id) AS label_ids
+        FROM opened e
+        "#
+    )
+    .bind(uuid)
+    .bind(recipient_email)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| (email_from_row(&row), row.get("newly_read"))))
+}
+
+// Recall an unread email: drop the body and key material and leave a placeholder.
+// The read check and the update happen in one statement so a concurrent read cannot slip in between.
+pub async fn recall_email(
+    pool: &PgPool,
+    email_id: &str,
+    sender_email: &str,
+) -> Result<RecallOutcome, sqlx::Error> {
+    let uuid = match Uuid::parse_str(email_id) {
+        Ok(uuid) => uuid,
+        Err(_) => return Ok(RecallOutcome::NotFound),
+    };
+
+    let updated = sqlx::query(
+        r#"
+        UPDATE emails
+        SET body = $3, raw_encrypted_content = NULL, is_encrypted = FALSE, recalled_at = NOW()
+        WHERE id = $1 AND sender_email = $2 AND read_at IS NULL AND recalled_at IS NULL
+        "#
+    )
+    .bind(uuid)
+    .bind(sender_email)
+    .bind(RECALLED_EMAIL_BODY)
+    .execute(pool)
+    .await?;
+
+    if updated.rows_affected() > 0 {
+        return match get_email(pool, email_id).await? {
+            Some(email) => Ok(RecallOutcome::Recalled(Box::new(email))),
+            None => Ok(RecallOutcome::NotFound),
+        };
+    }
+
+    // Nothing was updated, work out why
+    let row = sqlx::query(
+        r#"
+        SELECT sender_email, read_at, recalled_at FROM emails
+        WHERE id = $1
+        "#
+    )
+    .bind(uuid)
+    .fetch_optional(pool)
+    .await?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(RecallOutcome::NotFound),
+    };
+
+    let owner: String = row.get("sender_email");
+    if owner != sender_email {
+        return Ok(RecallOutcome::NotSender);
+    }
+
+    let read_at: Option<time::OffsetDateTime> = row.get("read_at");
+    let recalled_at: Option<time::OffsetDateTime> = row.get("recalled_at");
+
+    if let Some(recalled_at) = format_timestamp(recalled_at) {
+        return Ok(RecallOutcome::AlreadyRecalled(recalled_at));
+    }
+
+    match format_timestamp(read_at) {
+        Some(read_at) => Ok(RecallOutcome::AlreadyRead(read_at)),
+        None => Ok(RecallOutcome::NotFound),
+    }
+}
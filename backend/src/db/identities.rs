@@ -0,0 +1,182 @@
+use sqlx::{PgPool, Row};
+
+use crate::db::mailbox_sync::forget_mailbox;
+use crate::encryption::refresh_token::seal_refresh_token;
+
+// Create the user_identities table and the mailbox link column if they don't exist.
+// A user may sign in through several providers; each (provider, subject) pair belongs to one user.
+pub async fn init_identities_table(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_identities (
+            provider TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            user_email TEXT NOT NULL REFERENCES users(email) ON DELETE CASCADE,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            last_login_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (provider, subject)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_user_identities_user_email ON user_identities(user_email)")
+        .execute(pool)
+        .await?;
+
+    // The Gmail address whose grant is stored in refresh_token; may differ from the login email
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS mailbox_email TEXT")
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// Find the user an identity provider account signs in as, recording the login
+pub async fn find_user_by_identity(
+    pool: &PgPool,
+    provider: &str,
+    subject: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        UPDATE user_identities SET last_login_at = NOW()
+        WHERE provider = $1 AND subject = $2
+        RETURNING user_email
+        "#
+    )
+    .bind(provider)
+    .bind(subject)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| r.get("user_email")))
+}
+
+// Attach an identity provider account to a user
+pub async fn link_identity(
+    pool: &PgPool,
+    provider: &str,
+    subject: &str,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO user_identities (provider, subject, user_email)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (provider, subject) DO NOTHING
+        "#
+    )
+    .bind(provider)
+    .bind(subject)
+    .bind(email)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Result of linking a Gmail mailbox
+#[derive(Debug, PartialEq)]
+pub enum MailboxLink {
+    Linked,
+    // Another user has already linked this mailbox
+    InUse,
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error().and_then(|e| e.code()).as_deref() == Some("23505")
+}
+
+// Store the Gmail grant for a user's mailbox.
+// The token is sealed to the login email, since that is who uses it.
+// A mailbox belongs to one user at a time, so push notifications name a single account.
+pub async fn link_mailbox(
+    pool: &PgPool,
+    email: &str,
+    mailbox_email: &str,
+    refresh_token: &Option<String>,
+) -> Result<MailboxLink, sqlx::Error> {
+    let sealed_refresh_token = match refresh_token {
+        Some(token) => Some(
+            seal_refresh_token(token, email).map_err(|e| sqlx::Error::Configuration(e.to_string().into()))?
+        ),
+        None => None,
+    };
+
+    if let Some(owner) = find_user_by_mailbox(pool, mailbox_email).await? {
+        if owner != email {
+            return Ok(MailboxLink::InUse);
+        }
+    }
+
+    // What was synced from another mailbox does not belong to this one
+    let changed = !get_mailbox_email(pool, email).await?
+        .is_some_and(|current| current.eq_ignore_ascii_case(mailbox_email));
+    if changed {
+        forget_mailbox(pool, email).await?;
+    }
+
+    // Google only hands out a new refresh token on consent; keep the old one otherwise,
+    // unless it was granted for a different mailbox
+    let result = sqlx::query(
+        r#"
+        UPDATE users
+        SET mailbox_email = $2,
+            refresh_token = CASE WHEN $4 THEN $3 ELSE COALESCE($3, refresh_token) END,
+            updated_at = NOW()
+        WHERE email = $1
+        "#
+    )
+    .bind(email)
+    .bind(mailbox_email)
+    .bind(sealed_refresh_token)
+    .bind(changed)
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(_) => Ok(MailboxLink::Linked),
+        // Someone else linked it between the check and the update
+        Err(e) if is_unique_violation(&e) => Ok(MailboxLink::InUse),
+        Err(e) => Err(e),
+    }
+}
+
+// Forget the user's mailbox and its grant
+pub async fn unlink_mailbox(pool: &PgPool, email: &str) -> Result<(), sqlx::Error> {
+    forget_mailbox(pool, email).await?;
+
+    sqlx::query(
+        r#"
+        UPDATE users SET mailbox_email = NULL, refresh_token = NULL, updated_at = NOW()
+        WHERE email = $1
+        "#
+    )
+    .bind(email)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// The Gmail address linked to a user, if any
+pub async fn get_mailbox_email(pool: &PgPool, email: &str) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query("SELECT mailbox_email FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.and_then(|r| r.get("mailbox_email")))
+}
+
+// The user whose linked Gmail mailbox is this address
+pub async fn find_user_by_mailbox(pool: &PgPool, mailbox_email: &str) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query("SELECT email FROM users WHERE lower(mailbox_email) = lower($1)")
+        .bind(mailbox_email)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|r| r.get("email")))
+}
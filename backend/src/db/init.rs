@@ -1,6 +1,13 @@
 use sqlx::PgPool;
 use crate::db::email::init_email_table;
 use crate::db::labels::init_labels_table;
+use crate::db::portal::init_portal_tables;
+use crate::db::sessions::init_sessions_table;
+use crate::db::roles::init_roles;
+use crate::db::access_tokens::init_access_tokens_table;
+use crate::db::account_deletion::init_account_deletions_table;
+use crate::db::identities::init_identities_table;
+use crate::db::mailbox_sync::init_mailbox_sync_tables;
 
 pub async fn init(pool: &PgPool) -> Result<(), sqlx::Error> {
     // Create the users table if it doesn't exist
@@ -11,7 +18,6 @@ pub async fn init(pool: &PgPool) -> Result<(), sqlx::Error> {
             email TEXT NOT NULL UNIQUE,
             name TEXT,
             picture TEXT,
-            session_token TEXT,
             refresh_token TEXT,
             created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
             updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
@@ -41,12 +47,97 @@ pub async fn init(pool: &PgPool) -> Result<(), sqlx::Error> {
     .execute(pool)
     .await?;
     
+    // Append-only key transparency log of every published, rotated or revoked public key
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS key_transparency_log (
+            leaf_index BIGINT PRIMARY KEY,
+            email TEXT NOT NULL,
+            action TEXT NOT NULL,
+            public_key TEXT NOT NULL,
+            logged_at TEXT NOT NULL,
+            leaf_hash TEXT NOT NULL
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_key_transparency_log_email ON key_transparency_log(email)")
+        .execute(pool)
+        .await?;
+    
+    // Signed tree head for every size the log has reached
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS key_transparency_heads (
+            tree_size BIGINT PRIMARY KEY,
+            root_hash TEXT NOT NULL,
+            signed_at TEXT NOT NULL,
+            signature TEXT NOT NULL
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    // Roots of the perfect subtrees along the right edge of each head, so appends need not read every leaf
+    sqlx::query("ALTER TABLE key_transparency_heads ADD COLUMN IF NOT EXISTS frontier TEXT")
+        .execute(pool)
+        .await?;
+    
+    // Reject updates and deletes so history cannot be rewritten
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION key_transparency_append_only() RETURNS trigger AS $$
+        BEGIN
+            RAISE EXCEPTION 'key transparency log is append-only';
+        END;
+        $$ LANGUAGE plpgsql
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    for table in ["key_transparency_log", "key_transparency_heads"] {
+        sqlx::query(&format!("DROP TRIGGER IF EXISTS {0}_append_only ON {0}", table))
+            .execute(pool)
+            .await?;
+        sqlx::query(&format!(
+            "CREATE TRIGGER {0}_append_only BEFORE UPDATE OR DELETE ON {0} FOR EACH ROW EXECUTE FUNCTION key_transparency_append_only()",
+            table
+        ))
+        .execute(pool)
+        .await?;
+    }
+    
     // Initialize email table
     init_email_table(pool).await?;
     
     // Initialize labels table
     init_labels_table(pool).await?;
     
+    // Initialize external recipient portal tables
+    init_portal_tables(pool).await?;
+    
+    // Initialize per-device login sessions table
+    init_sessions_table(pool).await?;
+    
+    // Initialize user roles and the admin audit trail
+    init_roles(pool).await?;
+    
+    // Initialize personal access tokens table
+    init_access_tokens_table(pool).await?;
+    
+    // Initialize account deletion receipts table
+    init_account_deletions_table(pool).await?;
+    
+    // Initialize identity provider links and the mailbox column
+    init_identities_table(pool).await?;
+    
+    // Initialize the synced Gmail mailbox tables
+    init_mailbox_sync_tables(pool).await?;
+    
     println!("Database initialized successfully");
     Ok(())
 }
@@ -0,0 +1,377 @@
+use sqlx::{postgres::PgArguments, query::Query, PgPool, Postgres, Row};
+
+use crate::models::{AttachmentInfo, Email};
+
+// Insert a message, or refresh every field of one we already have
+const UPSERT_MESSAGE: &str = r#"
+    INSERT INTO gmail_messages (
+        user_email, gmail_id, thread_id, label_ids, sender_email, sender_name,
+        recipient_email, subject, body, internal_date, is_encrypted, html_body, attachments
+    )
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+    ON CONFLICT (user_email, gmail_id) DO UPDATE
+    SET thread_id = EXCLUDED.thread_id,
+        label_ids = EXCLUDED.label_ids,
+        sender_email = EXCLUDED.sender_email,
+        sender_name = EXCLUDED.sender_name,
+        recipient_email = EXCLUDED.recipient_email,
+        subject = EXCLUDED.subject,
+        body = EXCLUDED.body,
+        internal_date = EXCLUDED.internal_date,
+        is_encrypted = EXCLUDED.is_encrypted,
+        html_body = EXCLUDED.html_body,
+        attachments = EXCLUDED.attachments,
+        synced_at = NOW()
+"#;
+
+// Create the tables that mirror each user's Gmail mailbox.
+// gmail_messages holds the synced messages; mailbox_sync_state holds the Gmail historyId they are current as of;
+// gmail_watches records the push notification watches registered with Gmail.
+pub async fn init_mailbox_sync_tables(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS gmail_messages (
+            user_email TEXT NOT NULL REFERENCES users(email) ON DELETE CASCADE,
+            gmail_id TEXT NOT NULL,
+            thread_id TEXT NOT NULL,
+            label_ids TEXT[] NOT NULL DEFAULT '{}',
+            sender_email TEXT NOT NULL,
+            sender_name TEXT,
+            recipient_email TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            body TEXT NOT NULL,
+            internal_date BIGINT NOT NULL DEFAULT 0,
+            is_encrypted BOOLEAN NOT NULL DEFAULT FALSE,
+            synced_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (user_email, gmail_id)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Parsed HTML and the attachment descriptors, kept as JSON
+    sqlx::query(
+        r#"
+        ALTER TABLE gmail_messages
+            ADD COLUMN IF NOT EXISTS html_body TEXT,
+            ADD COLUMN IF NOT EXISTS attachments TEXT NOT NULL DEFAULT '[]'
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_gmail_messages_user_date ON gmail_messages(user_email, internal_date DESC)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_gmail_messages_user_thread ON gmail_messages(user_email, thread_id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS mailbox_sync_state (
+            user_email TEXT PRIMARY KEY REFERENCES users(email) ON DELETE CASCADE,
+            history_id TEXT NOT NULL,
+            full_sync_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            synced_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS gmail_watches (
+            user_email TEXT PRIMARY KEY REFERENCES users(email) ON DELETE CASCADE,
+            topic TEXT NOT NULL,
+            expires_at TIMESTAMPTZ NOT NULL,
+            renewed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// A Gmail message as it is kept in Postgres
+#[derive(Debug, Clone)]
+pub struct SyncedMessage {
+    pub gmail_id: String,
+    pub thread_id: String,
+    pub label_ids: Vec<String>,
+    pub sender_email: String,
+    pub sender_name: Option<String>,
+    pub recipient_email: String,
+    pub subject: String,
+    pub body: String,
+    pub html_body: Option<String>,
+    pub attachments: Vec<AttachmentInfo>,
+    // Milliseconds since the epoch, as Gmail reports it
+    pub internal_date: i64,
+    pub is_encrypted: bool,
+}
+
+impl SyncedMessage {
+    // The shape the rest of the API serves; synced messages are addressed as gmail_<id>
+    pub fn to_email(&self) -> Email {
+        Email {
+            id: format!("gmail_{}", self.gmail_id),
+            sender_id: self.sender_email.clone(),
+            sender_email: self.sender_email.clone(),
+            sender_name: self.sender_name.clone(),
+            recipient_email: self.recipient_email.clone(),
+            subject: self.subject.clone(),
+            body: self.body.clone(),
+            html_body: self.html_body.clone(),
+            attachments: self.attachments.clone(),
+            sent_at: self.internal_date.to_string(),
+            read_at: None,
+            gmail_id: Some(self.gmail_id.clone()),
+            label_ids: Some(self.label_ids.clone()),
+            is_encrypted: self.is_encrypted,
+            raw_encrypted_content: None,
+            recalled_at: None,
+            thread_id: Some(format!("gmail_{}", self.thread_id)),
+        }
+    }
+}
+
+// The historyId the user's synced messages are current as of, if a sync ever finished
+pub async fn get_history_id(pool: &PgPool, email: &str) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query("SELECT history_id FROM mailbox_sync_state WHERE user_email = $1")
+        .bind(email)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|r| r.get("history_id")))
+}
+
+// Record how far an incremental sync got
+pub async fn save_history_id(pool: &PgPool, email: &str, history_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO mailbox_sync_state (user_email, history_id)
+        VALUES ($1, $2)
+        ON CONFLICT (user_email) DO UPDATE
+        SET history_id = EXCLUDED.history_id, synced_at = NOW()
+        "#
+    )
+    .bind(email)
+    .bind(history_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+fn upsert_message<'q>(email: &'q str, message: &'q SyncedMessage) -> Query<'q, Postgres, PgArguments> {
+    sqlx::query(UPSERT_MESSAGE)
+        .bind(email)
+        .bind(&message.gmail_id)
+        .bind(&message.thread_id)
+        .bind(&message.label_ids)
+        .bind(&message.sender_email)
+        .bind(&message.sender_name)
+        .bind(&message.recipient_email)
+        .bind(&message.subject)
+        .bind(&message.body)
+        .bind(message.internal_date)
+        .bind(message.is_encrypted)
+        .bind(&message.html_body)
+        .bind(serde_json::to_string(&message.attachments).unwrap_or_else(|_| "[]".to_string()))
+}
+
+// Replace everything synced for the user with a fresh listing, current as of history_id
+pub async fn replace_synced_messages(
+    pool: &PgPool,
+    email: &str,
+    messages: &[SyncedMessage],
+    history_id: &str,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM gmail_messages WHERE user_email = $1")
+        .bind(email)
+        .execute(&mut tx)
+        .await?;
+
+    for message in messages {
+        upsert_message(email, message)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO mailbox_sync_state (user_email, history_id)
+        VALUES ($1, $2)
+        ON CONFLICT (user_email) DO UPDATE
+        SET history_id = EXCLUDED.history_id, full_sync_at = NOW(), synced_at = NOW()
+        "#
+    )
+    .bind(email)
+    .bind(history_id)
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await
+}
+
+// Store a message that appeared in the mailbox
+pub async fn upsert_synced_message(pool: &PgPool, email: &str, message: &SyncedMessage) -> Result<(), sqlx::Error> {
+    upsert_message(email, message)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// Forget a message that was deleted from the mailbox
+pub async fn delete_synced_message(pool: &PgPool, email: &str, gmail_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM gmail_messages WHERE user_email = $1 AND gmail_id = $2")
+        .bind(email)
+        .bind(gmail_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// Apply a label change to a synced message; messages we never synced are left alone
+pub async fn update_synced_labels(
+    pool: &PgPool,
+    email: &str,
+    gmail_id: &str,
+    added: &[String],
+    removed: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE gmail_messages
+        SET label_ids = ARRAY(
+                SELECT DISTINCT label FROM unnest(label_ids || $3::TEXT[]) AS label
+                WHERE label <> ALL($4::TEXT[])
+                ORDER BY label
+            ),
+            synced_at = NOW()
+        WHERE user_email = $1 AND gmail_id = $2
+        "#
+    )
+    .bind(email)
+    .bind(gmail_id)
+    .bind(added)
+    .bind(removed)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// The most recent synced messages carrying a label, newest first
+pub async fn get_synced_messages(
+    pool: &PgPool,
+    email: &str,
+    label_id: &str,
+    limit: i64,
+) -> Result<Vec<SyncedMessage>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT gmail_id, thread_id, label_ids, sender_email, sender_name, recipient_email,
+               subject, body, internal_date, is_encrypted, html_body, attachments
+        FROM gmail_messages
+        WHERE user_email = $1 AND $2 = ANY(label_ids)
+        ORDER BY internal_date DESC
+        LIMIT $3
+        "#
+    )
+    .bind(email)
+    .bind(label_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|r| SyncedMessage {
+        gmail_id: r.get("gmail_id"),
+        thread_id: r.get("thread_id"),
+        label_ids: r.get("label_ids"),
+        sender_email: r.get("sender_email"),
+        sender_name: r.get("sender_name"),
+        recipient_email: r.get("recipient_email"),
+        subject: r.get("subject"),
+        body: r.get("body"),
+        html_body: r.get("html_body"),
+        attachments: serde_json::from_str(r.get("attachments")).unwrap_or_default(),
+        internal_date: r.get("internal_date"),
+        is_encrypted: r.get("is_encrypted"),
+    }).collect())
+}
+
+// Drop the synced copy of a mailbox, e.g. when the user links a different one
+pub async fn forget_mailbox(pool: &PgPool, email: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM gmail_messages WHERE user_email = $1")
+        .bind(email)
+        .execute(pool)
+        .await?;
+
+    sqlx::query("DELETE FROM mailbox_sync_state WHERE user_email = $1")
+        .bind(email)
+        .execute(pool)
+        .await?;
+
+    sqlx::query("DELETE FROM gmail_watches WHERE user_email = $1")
+        .bind(email)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// Record a watch Gmail accepted; expires_at_millis is Gmail's expiration
+pub async fn save_watch(pool: &PgPool, email: &str, topic: &str, expires_at_millis: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO gmail_watches (user_email, topic, expires_at)
+        VALUES ($1, $2, to_timestamp($3::BIGINT / 1000.0))
+        ON CONFLICT (user_email) DO UPDATE
+        SET topic = EXCLUDED.topic, expires_at = EXCLUDED.expires_at, renewed_at = NOW()
+        "#
+    )
+    .bind(email)
+    .bind(topic)
+    .bind(expires_at_millis)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Users with a linked mailbox whose watch on the topic is missing or expires within renew_before_seconds,
+// with their sealed refresh tokens
+pub async fn get_mailboxes_due_for_watch(
+    pool: &PgPool,
+    topic: &str,
+    renew_before_seconds: i64,
+) -> Result<Vec<(String, String)>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT u.email, u.refresh_token
+        FROM users u
+        LEFT JOIN gmail_watches w ON w.user_email = u.email
+        WHERE u.refresh_token IS NOT NULL
+          AND (w.user_email IS NULL
+               OR w.topic <> $1
+               OR w.expires_at < NOW() + $2::BIGINT * INTERVAL '1 second')
+        "#
+    )
+    .bind(topic)
+    .bind(renew_before_seconds)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|r| (r.get("email"), r.get("refresh_token"))).collect())
+}
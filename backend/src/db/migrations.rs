@@ -1,6 +1,9 @@
-use sqlx::PgPool;
+use sqlx::{PgPool, Row};
 use log::info;
 
+use crate::db::mailbox_sync::forget_mailbox;
+use crate::encryption::refresh_token::{seal_refresh_token, SEALED_PREFIX};
+
 /// Migrates existing user profile picture URLs to ensure they use HTTPS and have proper size
 pub async fn migrate_profile_pictures(pool: &PgPool) -> Result<(), sqlx::Error> {
     info!("Running migration: Update profile picture URLs");
@@ -32,4 +35,140 @@ pub async fn migrate_profile_pictures(pool: &PgPool) -> Result<(), sqlx::Error>
     info!("Updated {} profile picture URLs with larger size", size_updated.rows_affected());
     
     Ok(())
-} 
\ No newline at end of file
+} 
+/// Moves login sessions from the old single `users.session_token` column into the sessions table
+pub async fn migrate_session_tokens(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let has_column: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM information_schema.columns
+            WHERE table_name = 'users' AND column_name = 'session_token'
+        )
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+    
+    if !has_column {
+        return Ok(());
+    }
+    
+    info!("Running migration: Move session tokens into the sessions table");
+    
+    let mut tx = pool.begin().await?;
+    let migrated = sqlx::query(
+        r#"
+        INSERT INTO sessions (id, user_email, token_hash, expires_at)
+        SELECT gen_random_uuid(), email, encode(sha256(session_token::bytea), 'hex'), updated_at + INTERVAL '7 days'
+        FROM users
+        WHERE session_token IS NOT NULL AND updated_at + INTERVAL '7 days' > NOW()
+        ON CONFLICT (token_hash) DO NOTHING
+        "#
+    )
+    .execute(&mut tx)
+    .await?;
+    
+    sqlx::query("ALTER TABLE users DROP COLUMN session_token")
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    
+    info!("Migrated {} existing sessions", migrated.rows_affected());
+    
+    Ok(())
+}
+
+/// Encrypts Google refresh tokens that were stored in plaintext before sealing was introduced
+pub async fn migrate_refresh_token_encryption(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT email, refresh_token FROM users
+        WHERE refresh_token IS NOT NULL AND refresh_token NOT LIKE $1 || '%'
+        "#
+    )
+    .bind(SEALED_PREFIX)
+    .fetch_all(pool)
+    .await?;
+    
+    if rows.is_empty() {
+        return Ok(());
+    }
+    
+    info!("Running migration: Encrypt stored refresh tokens");
+    
+    let mut tx = pool.begin().await?;
+    for row in &rows {
+        let email: String = row.get("email");
+        let refresh_token: String = row.get("refresh_token");
+        let sealed = seal_refresh_token(&refresh_token, &email)
+            .map_err(|e| sqlx::Error::Configuration(e.to_string().into()))?;
+        
+        sqlx::query("UPDATE users SET refresh_token = $1 WHERE email = $2")
+            .bind(sealed)
+            .bind(&email)
+            .execute(&mut tx)
+            .await?;
+    }
+    tx.commit().await?;
+    
+    info!("Encrypted {} stored refresh tokens", rows.len());
+    
+    Ok(())
+}
+
+/// Records the Gmail mailbox of users who signed in with Google before mailboxes were linked separately
+pub async fn migrate_mailbox_links(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let linked = sqlx::query(
+        r#"
+        UPDATE users SET mailbox_email = email
+        WHERE refresh_token IS NOT NULL AND mailbox_email IS NULL
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    if linked.rows_affected() > 0 {
+        info!("Recorded Gmail mailbox for {} existing users", linked.rows_affected());
+    }
+    
+    Ok(())
+}
+
+/// Leaves each Gmail mailbox linked to one user, keeping the most recent link, and enforces that from now on
+pub async fn migrate_unique_mailbox_links(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let duplicates: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT email FROM (
+            SELECT email, ROW_NUMBER() OVER (
+                PARTITION BY lower(mailbox_email) ORDER BY updated_at DESC, id DESC
+            ) AS rank
+            FROM users
+            WHERE mailbox_email IS NOT NULL
+        ) links
+        WHERE rank > 1
+        "#
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    
+    // Their grants belong to a mailbox someone else now holds
+    for email in &duplicates {
+        sqlx::query("UPDATE users SET mailbox_email = NULL, refresh_token = NULL, updated_at = NOW() WHERE email = $1")
+            .bind(email)
+            .execute(&mut tx)
+            .await?;
+    }
+    
+    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_users_mailbox_email ON users (lower(mailbox_email))")
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    
+    for email in &duplicates {
+        forget_mailbox(pool, email).await?;
+        info!("Unlinked a mailbox that {} shared with another user", email);
+    }
+    
+    Ok(())
+}
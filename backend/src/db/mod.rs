@@ -4,16 +4,29 @@ pub mod email;
 mod init;
 mod labels;
 mod migrations;
+pub mod portal;
+pub mod sessions;
+pub mod roles;
+pub mod access_tokens;
+pub mod account_deletion;
+pub mod identities;
+pub mod mailbox_sync;
+pub mod threads;
 
 // Export functions from modules
 pub use users::store_user;
 pub use users::get_user_by_session;
 pub use users::list_users;
 pub use users::get_user_info;
+pub use users::get_user_refresh_token;
 
 pub use email::store_email;
+pub use email::delete_unsent_email;
 pub use email::get_email;
 pub use email::get_emails_for_user;
+pub use email::mark_email_read;
+pub use email::recall_email;
+pub use email::RecallOutcome;
 
 pub use labels::store_label;
 pub use labels::get_labels_for_user;
@@ -21,3 +34,7 @@ pub use labels::get_labels_for_user;
 pub use init::init;
 
 pub use migrations::migrate_profile_pictures;
+pub use migrations::migrate_session_tokens;
+pub use migrations::migrate_refresh_token_encryption;
+pub use migrations::migrate_mailbox_links;
+pub use migrations::migrate_unique_mailbox_links;
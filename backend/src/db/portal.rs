@@ -0,0 +1,295 @@
+use sqlx::{PgPool, Row, types::time};
+use uuid::Uuid;
+
+// Failed passcode attempts allowed before the portal link is locked
+pub const MAX_FAILED_PORTAL_ATTEMPTS: i32 = 5;
+// How long a portal link stays locked after too many failures
+pub const PORTAL_LOCKOUT_MINUTES: i32 = 15;
+// Failed passcode attempts over the link's whole life before it stops working for good
+pub const MAX_TOTAL_FAILED_PORTAL_ATTEMPTS: i32 = 20;
+// How long an external recipient can use the portal link
+pub const PORTAL_LINK_TTL_DAYS: i32 = 30;
+
+// Portal access state for a message sent to an external recipient
+#[derive(Debug, Clone)]
+pub struct PortalAccess {
+    pub email_id: String,
+    pub locked_until: Option<time::OffsetDateTime>,
+    pub expires_at: time::OffsetDateTime,
+    pub total_failed_attempts: i32,
+}
+
+impl PortalAccess {
+    pub fn is_locked(&self) -> bool {
+        self.locked_until.is_some_and(|until| until > time::OffsetDateTime::now_utc())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at < time::OffsetDateTime::now_utc()
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.total_failed_attempts >= MAX_TOTAL_FAILED_PORTAL_ATTEMPTS
+    }
+}
+
+// Create the portal tables if they don't exist
+pub async fn init_portal_tables(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS portal_access (
+            email_id UUID PRIMARY KEY REFERENCES emails(id) ON DELETE CASCADE,
+            access_token TEXT NOT NULL UNIQUE,
+            failed_attempts INTEGER NOT NULL DEFAULT 0,
+            total_failed_attempts INTEGER NOT NULL DEFAULT 0,
+            locked_until TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            expires_at TIMESTAMPTZ NOT NULL
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("ALTER TABLE portal_access ADD COLUMN IF NOT EXISTS total_failed_attempts INTEGER NOT NULL DEFAULT 0")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS portal_access_log (
+            id SERIAL PRIMARY KEY,
+            email_id UUID NOT NULL REFERENCES emails(id) ON DELETE CASCADE,
+            action TEXT NOT NULL,
+            success BOOLEAN NOT NULL,
+            ip_address TEXT,
+            user_agent TEXT,
+            attempted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    println!("Portal tables initialized successfully");
+    Ok(())
+}
+
+// Register a portal link for a stored email
+pub async fn create_portal_access(
+    pool: &PgPool,
+    email_id: &str,
+    access_token: &str,
+) -> Result<(), sqlx::Error> {
+    let uuid = Uuid::parse_str(email_id).map_err(|_| sqlx::Error::RowNotFound)?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO portal_access (email_id, access_token, expires_at)
+        VALUES ($1, $2, NOW() + make_interval(days => $3))
+        "#
+    )
+    .bind(uuid)
+    .bind(access_token)
+    .bind(PORTAL_LINK_TTL_DAYS)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Look up a portal link by its token
+pub async fn get_portal_access(
+    pool: &PgPool,
+    access_token: &str,
+) -> Result<Option<PortalAccess>, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT email_id::text, locked_until, expires_at, total_failed_attempts FROM portal_access
+        WHERE access_token = $1
+        "#
+    )
+    .bind(access_token)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| PortalAccess {
+        email_id: r.get("email_id"),
+        locked_until: r.get("locked_until"),
+        expires_at: r.get("expires_at"),
+        total_failed_attempts: r.get("total_failed_attempts"),
+    }))
+}
+
+// Write a portal access attempt to the audit log
+pub async fn log_portal_attempt(
+    pool: &PgPool,
+    email_id: &str,
+    action: &str,
+    success: bool,
+    ip_address: Option<&str>,
+    user_agent: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let uuid = Uuid::parse_str(email_id).map_err(|_| sqlx::Error::RowNotFound)?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO portal_access_log (email_id, action, success, ip_address, user_agent)
+        VALUES ($1, $2, $3, $4, $5)
+        "#
+    )
+    .bind(uuid)
+    .bind(action)
+    .bind(success)
+    .bind(ip_address)
+    .bind(user_agent)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Count a passcode attempt as failed before the passcode is checked, so concurrent guesses cannot
+// all get in under the limit. The attempt that reaches the limit still runs, but locks the link
+// for every later one; the lifetime count is never reset by a lock, so the link stops working
+// for good after MAX_TOTAL_FAILED_PORTAL_ATTEMPTS. False when the link is locked, revoked or gone.
+pub async fn reserve_portal_attempt(pool: &PgPool, email_id: &str) -> Result<bool, sqlx::Error> {
+    let uuid = Uuid::parse_str(email_id).map_err(|_| sqlx::Error::RowNotFound)?;
+
+    let reserved = sqlx::query(
+        r#"
+        UPDATE portal_access
+        SET failed_attempts = CASE WHEN failed_attempts + 1 >= $2 THEN 0 ELSE failed_attempts + 1 END,
+            total_failed_attempts = total_failed_attempts + 1,
+            locked_until = CASE WHEN failed_attempts + 1 >= $2
+                THEN NOW() + make_interval(mins => $3)
+                ELSE locked_until END
+        WHERE email_id = $1 AND (locked_until IS NULL OR locked_until < NOW()) AND expires_at > NOW()
+            AND total_failed_attempts < $4
+        RETURNING email_id
+        "#
+    )
+    .bind(uuid)
+    .bind(MAX_FAILED_PORTAL_ATTEMPTS)
+    .bind(PORTAL_LOCKOUT_MINUTES)
+    .bind(MAX_TOTAL_FAILED_PORTAL_ATTEMPTS)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(reserved.is_some())
+}
+
+// Log a passcode attempt reserved with reserve_portal_attempt. A correct passcode clears the
+// failure counter and any lock, and takes itself back off the lifetime count; a wrong one was
+// counted already.
+pub async fn record_portal_attempt(
+    pool: &PgPool,
+    email_id: &str,
+    action: &str,
+    success: bool,
+    ip_address: Option<&str>,
+    user_agent: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    log_portal_attempt(pool, email_id, action, success, ip_address, user_agent).await?;
+
+    if success {
+        let uuid = Uuid::parse_str(email_id).map_err(|_| sqlx::Error::RowNotFound)?;
+        sqlx::query(
+            r#"
+            UPDATE portal_access
+            SET failed_attempts = 0, locked_until = NULL,
+                total_failed_attempts = GREATEST(total_failed_attempts - 1, 0)
+            WHERE email_id = $1
+            "#
+        )
+            .bind(uuid)
+            .execute(pool)
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Needs a throwaway database named by TEST_DATABASE_URL, whose schema it creates:
+    //   TEST_DATABASE_URL=postgres://localhost/quant_test cargo test -- --ignored
+    async fn portal_link() -> (PgPool, String) {
+        let database_url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL");
+        let pool = PgPool::connect(&database_url).await.expect("database");
+        crate::db::init(&pool).await.expect("schema");
+
+        let sender = format!("portal-test-{}@test.io", Uuid::new_v4());
+        let email_id = crate::db::store_email(&pool, &sender, &sender, "outside@test.io", "Hi", "", true, Some("{}"))
+            .await
+            .expect("email");
+        create_portal_access(&pool, &email_id, &Uuid::new_v4().to_string()).await.expect("portal link");
+        (pool, email_id)
+    }
+
+    async fn unlock(pool: &PgPool, email_id: &str) {
+        sqlx::query("UPDATE portal_access SET locked_until = NOW() - INTERVAL '1 minute' WHERE email_id = $1")
+            .bind(Uuid::parse_str(email_id).unwrap())
+            .execute(pool)
+            .await
+            .expect("unlock");
+    }
+
+    async fn remove(pool: &PgPool, email_id: &str) {
+        crate::db::delete_unsent_email(pool, email_id).await.expect("cleanup");
+    }
+
+    async fn reserve_all(pool: &PgPool, email_id: &str, attempts: i32) -> Vec<bool> {
+        let mut reserved = Vec::new();
+        for _ in 0..attempts {
+            reserved.push(reserve_portal_attempt(pool, email_id).await.expect("reserve"));
+        }
+        reserved
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn portal_links_lock_after_too_many_failures() {
+        let (pool, email_id) = portal_link().await;
+
+        let reserved = reserve_all(&pool, &email_id, MAX_FAILED_PORTAL_ATTEMPTS + 1).await;
+        unlock(&pool, &email_id).await;
+        let after_lock = reserve_portal_attempt(&pool, &email_id).await.expect("reserve");
+
+        remove(&pool, &email_id).await;
+        assert!(reserved[..MAX_FAILED_PORTAL_ATTEMPTS as usize].iter().all(|&r| r));
+        assert!(!reserved[MAX_FAILED_PORTAL_ATTEMPTS as usize], "the link must be locked");
+        assert!(after_lock, "the lock must lift once it runs out");
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn portal_links_stop_working_after_too_many_failures_in_total() {
+        let (pool, email_id) = portal_link().await;
+
+        let mut reserved = Vec::new();
+        for _ in 0..MAX_TOTAL_FAILED_PORTAL_ATTEMPTS / MAX_FAILED_PORTAL_ATTEMPTS + 1 {
+            reserved.extend(reserve_all(&pool, &email_id, MAX_FAILED_PORTAL_ATTEMPTS).await);
+            unlock(&pool, &email_id).await;
+        }
+        let access = get_portal_access_by_email(&pool, &email_id).await;
+
+        remove(&pool, &email_id).await;
+        let allowed = reserved.iter().filter(|&&r| r).count();
+        assert_eq!(allowed, MAX_TOTAL_FAILED_PORTAL_ATTEMPTS as usize);
+        assert!(reserved[allowed..].iter().all(|&r| !r), "no attempt may follow the lifetime limit");
+        assert!(access.is_revoked() && !access.is_locked());
+    }
+
+    async fn get_portal_access_by_email(pool: &PgPool, email_id: &str) -> PortalAccess {
+        let token: String = sqlx::query("SELECT access_token FROM portal_access WHERE email_id = $1")
+            .bind(Uuid::parse_str(email_id).unwrap())
+            .fetch_one(pool)
+            .await
+            .expect("portal link")
+            .get("access_token");
+        get_portal_access(pool, &token).await.expect("lookup").expect("portal link")
+    }
+}
@@ -0,0 +1,157 @@
+use sqlx::{PgPool, Row, types::time};
+
+use crate::models::{Role, AdminAuditEntry};
+
+// Add the role column and the admin audit trail
+pub async fn init_roles(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        ALTER TABLE users ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'user'
+            CHECK (role IN ('user', 'auditor', 'admin'))
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS admin_audit_log (
+            id BIGSERIAL PRIMARY KEY,
+            actor_email TEXT,
+            method TEXT NOT NULL,
+            path TEXT NOT NULL,
+            allowed BOOLEAN NOT NULL,
+            reason TEXT NOT NULL,
+            ip_address TEXT,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    println!("Roles initialized successfully");
+    Ok(())
+}
+
+// Get a user's role
+pub async fn get_user_role(pool: &PgPool, email: &str) -> Result<Option<Role>, sqlx::Error> {
+    let row = sqlx::query("SELECT role FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.and_then(|r| Role::parse(r.get("role"))))
+}
+
+// Result of changing a user's role
+#[derive(Debug)]
+pub enum RoleChange {
+    Changed,
+    NotFound,
+    // The user is the only admin and the new role is not admin
+    LastAdmin,
+}
+
+// Set a user's role, refusing to demote the last admin. The admin rows stay locked until the
+// change commits, so two admins demoting each other at once cannot both succeed.
+pub async fn set_user_role(pool: &PgPool, email: &str, role: Role) -> Result<RoleChange, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let admins: Vec<String> = sqlx::query_scalar("SELECT email FROM users WHERE role = 'admin' FOR UPDATE")
+        .fetch_all(&mut tx)
+        .await?;
+    if role != Role::Admin && admins.len() <= 1 && admins.iter().any(|admin| admin == email) {
+        return Ok(RoleChange::LastAdmin);
+    }
+
+    let result = sqlx::query("UPDATE users SET role = $1, updated_at = NOW() WHERE email = $2")
+        .bind(role.as_str())
+        .bind(email)
+        .execute(&mut tx)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Ok(RoleChange::NotFound);
+    }
+
+    tx.commit().await?;
+    Ok(RoleChange::Changed)
+}
+
+// Promote the configured bootstrap admin, but only while no admin exists yet and only once
+// they have signed in with Google, which verifies the address
+pub async fn bootstrap_admin(pool: &PgPool, email: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users SET role = 'admin', updated_at = NOW()
+        WHERE LOWER(email) = LOWER($1)
+          AND NOT EXISTS (SELECT 1 FROM users WHERE role = 'admin')
+          AND EXISTS (
+              SELECT 1 FROM user_identities
+              WHERE provider = 'google' AND LOWER(user_email) = LOWER($1)
+          )
+        "#
+    )
+    .bind(email)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Record an admin route access, allowed or denied
+pub async fn log_admin_access(
+    pool: &PgPool,
+    actor_email: Option<&str>,
+    method: &str,
+    path: &str,
+    allowed: bool,
+    reason: &str,
+    ip_address: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO admin_audit_log (actor_email, method, path, allowed, reason, ip_address)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#
+    )
+    .bind(actor_email)
+    .bind(method)
+    .bind(path)
+    .bind(allowed)
+    .bind(reason)
+    .bind(ip_address)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Most recent entries of the admin audit trail
+pub async fn list_admin_audit_log(pool: &PgPool, limit: i64) -> Result<Vec<AdminAuditEntry>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, actor_email, method, path, allowed, reason, ip_address, created_at
+        FROM admin_audit_log
+        ORDER BY id DESC
+        LIMIT $1
+        "#
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| {
+        let created_at: time::OffsetDateTime = row.get("created_at");
+        AdminAuditEntry {
+            id: row.get("id"),
+            actor_email: row.get("actor_email"),
+            method: row.get("method"),
+            path: row.get("path"),
+            allowed: row.get("allowed"),
+            reason: row.get("reason"),
+            ip_address: row.get("ip_address"),
+            created_at: created_at.to_string(),
+        }
+    }).collect())
+}
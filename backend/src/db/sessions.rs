@@ -0,0 +1,154 @@
+use sqlx::{PgPool, Row, types::time};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::models::SessionInfo;
+
+// Absolute lifetime of a login session, regardless of activity
+pub const SESSION_TTL_DAYS: i32 = 7;
+
+// Session tokens are only stored as a SHA-256 digest
+pub fn hash_session_token(session_token: &str) -> String {
+    format!("{:x}", Sha256::digest(session_token.as_bytes()))
+}
+
+// Create the sessions table if it doesn't exist
+pub async fn init_sessions_table(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sessions (
+            id UUID PRIMARY KEY,
+            user_email TEXT NOT NULL REFERENCES users(email) ON DELETE CASCADE,
+            token_hash TEXT NOT NULL UNIQUE,
+            user_agent TEXT,
+            ip_address TEXT,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            expires_at TIMESTAMPTZ NOT NULL
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_sessions_user_email ON sessions(user_email)")
+        .execute(pool)
+        .await?;
+
+    println!("Sessions table initialized successfully");
+    Ok(())
+}
+
+// Start a new session for one device
+pub async fn create_session(
+    pool: &PgPool,
+    email: &str,
+    session_token: &str,
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+) -> Result<String, sqlx::Error> {
+    // Expired sessions are never valid again, so clear them out as new ones arrive
+    sqlx::query("DELETE FROM sessions WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+
+    let id = Uuid::new_v4();
+    sqlx::query(
+        r#"
+        INSERT INTO sessions (id, user_email, token_hash, user_agent, ip_address, expires_at)
+        VALUES ($1, $2, $3, $4, $5, NOW() + make_interval(days => $6))
+        "#
+    )
+    .bind(id)
+    .bind(email)
+    .bind(hash_session_token(session_token))
+    .bind(user_agent)
+    .bind(ip_address)
+    .bind(SESSION_TTL_DAYS)
+    .execute(pool)
+    .await?;
+
+    Ok(id.to_string())
+}
+
+// List a user's unexpired sessions, marking the one making the request
+pub async fn list_sessions(
+    pool: &PgPool,
+    email: &str,
+    current_session_token: &str,
+) -> Result<Vec<SessionInfo>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id::text AS id, token_hash, user_agent, ip_address, created_at, last_seen_at, expires_at
+        FROM sessions
+        WHERE user_email = $1 AND expires_at > NOW()
+        ORDER BY last_seen_at DESC
+        "#
+    )
+    .bind(email)
+    .fetch_all(pool)
+    .await?;
+
+    let current_hash = hash_session_token(current_session_token);
+    Ok(rows.into_iter().map(|row| {
+        let token_hash: String = row.get("token_hash");
+        let created_at: time::OffsetDateTime = row.get("created_at");
+        let last_seen_at: time::OffsetDateTime = row.get("last_seen_at");
+        let expires_at: time::OffsetDateTime = row.get("expires_at");
+        SessionInfo {
+            id: row.get("id"),
+            user_agent: row.get("user_agent"),
+            ip_address: row.get("ip_address"),
+            created_at: created_at.to_string(),
+            last_seen_at: last_seen_at.to_string(),
+            expires_at: expires_at.to_string(),
+            current: token_hash == current_hash,
+        }
+    }).collect())
+}
+
+// End one of a user's sessions; returns false if it doesn't exist or belongs to someone else
+pub async fn revoke_session(pool: &PgPool, email: &str, session_id: &str) -> Result<bool, sqlx::Error> {
+    let session_id = match Uuid::parse_str(session_id) {
+        Ok(id) => id,
+        Err(_) => return Ok(false),
+    };
+
+    let result = sqlx::query("DELETE FROM sessions WHERE id = $1 AND user_email = $2")
+        .bind(session_id)
+        .bind(email)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// End all of a user's sessions, optionally keeping the current one
+pub async fn revoke_all_sessions(
+    pool: &PgPool,
+    email: &str,
+    keep_session_token: Option<&str>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM sessions
+        WHERE user_email = $1 AND ($2::text IS NULL OR token_hash <> $2)
+        "#
+    )
+    .bind(email)
+    .bind(keep_session_token.map(hash_session_token))
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// End the session behind a cookie, used by logout
+pub async fn revoke_session_by_token(pool: &PgPool, session_token: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM sessions WHERE token_hash = $1")
+        .bind(hash_session_token(session_token))
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,153 @@
+use sqlx::{PgPool, Row, types::time};
+use uuid::Uuid;
+
+use crate::db::email::{email_from_row, format_timestamp};
+use crate::models::{snippet, Email, ThreadSource, ThreadSummary};
+
+// Most threads returned in one listing
+pub const MAX_THREADS_LISTED: i64 = 200;
+
+// Move a stored reply into its parent's thread: In-Reply-To names the parent's Message-ID and
+// References carries the parent's references plus the parent itself, as RFC 5322 describes.
+// Returns the thread the reply joined, or None if either message does not exist.
+pub async fn link_reply(pool: &PgPool, reply_id: &str, parent_id: &str) -> Result<Option<String>, sqlx::Error> {
+    let (reply_uuid, parent_uuid) = match (Uuid::parse_str(reply_id), Uuid::parse_str(parent_id)) {
+        (Ok(reply_uuid), Ok(parent_uuid)) => (reply_uuid, parent_uuid),
+        _ => return Ok(None),
+    };
+
+    let row = sqlx::query(
+        r#"
+        UPDATE emails e
+        SET in_reply_to = p.message_id,
+            reference_ids = p.reference_ids || p.message_id,
+            thread_id = p.thread_id
+        FROM emails p
+        WHERE e.id = $1 AND p.id = $2
+        RETURNING e.thread_id
+        "#
+    )
+    .bind(reply_uuid)
+    .bind(parent_uuid)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| row.get("thread_id")))
+}
+
+// The user's conversations, most recently active first: Quant threads from the emails table
+// and Gmail threads from the synced copy of the mailbox. Unread Quant messages give no snippet,
+// since only opening them marks them read.
+pub async fn list_threads(
+    pool: &PgPool,
+    email: &str,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<ThreadSummary>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT *
+        FROM (
+            SELECT e.thread_id AS id,
+                   'quant' AS source,
+                   (ARRAY_AGG(e.subject ORDER BY e.sent_at ASC))[1] AS subject,
+                   ARRAY(SELECT DISTINCT p FROM unnest(ARRAY_AGG(e.sender_email) || ARRAY_AGG(e.recipient_email)) AS p ORDER BY p) AS participants,
+                   COUNT(*) AS message_count,
+                   COUNT(*) FILTER (WHERE e.recipient_email = $1 AND e.read_at IS NULL) AS unread_count,
+                   (ARRAY_AGG(CASE WHEN e.recipient_email = $1 AND e.read_at IS NULL THEN '' ELSE e.body END
+                              ORDER BY e.sent_at DESC))[1] AS latest_body,
+                   MAX(e.sent_at) AS latest_at,
+                   NULL::BIGINT AS latest_internal_date
+            FROM emails e
+            WHERE (e.sender_email = $1 OR e.recipient_email = $1) AND e.thread_id IS NOT NULL
+            GROUP BY e.thread_id
+
+            UNION ALL
+
+            SELECT 'gmail_' || g.thread_id,
+                   'gmail',
+                   (ARRAY_AGG(g.subject ORDER BY g.internal_date ASC))[1],
+                   ARRAY(SELECT DISTINCT p FROM unnest(ARRAY_AGG(g.sender_email) || ARRAY_AGG(g.recipient_email)) AS p WHERE p <> '' ORDER BY p),
+                   COUNT(*),
+                   COUNT(*) FILTER (WHERE 'UNREAD' = ANY(g.label_ids)),
+                   (ARRAY_AGG(g.body ORDER BY g.internal_date DESC))[1],
+                   to_timestamp(MAX(g.internal_date) / 1000.0),
+                   MAX(g.internal_date)
+            FROM gmail_messages g
+            WHERE g.user_email = $1
+            GROUP BY g.thread_id
+        ) threads
+        ORDER BY latest_at DESC
+        LIMIT $2 OFFSET $3
+        "#
+    )
+    .bind(email)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.iter().map(|row| {
+        let source = match row.get::<String, _>("source").as_str() {
+            "gmail" => ThreadSource::Gmail,
+            _ => ThreadSource::Quant,
+        };
+        let latest_body: String = row.get("latest_body");
+        // Formatted the same as the sent_at of the thread's messages: Gmail's milliseconds, or our timestamps
+        let latest_at = match source {
+            ThreadSource::Gmail => row.get::<Option<i64>, _>("latest_internal_date").unwrap_or(0).to_string(),
+            ThreadSource::Quant => format_timestamp(row.get::<Option<time::OffsetDateTime>, _>("latest_at")).unwrap_or_default(),
+        };
+
+        ThreadSummary {
+            id: row.get("id"),
+            source,
+            subject: row.get("subject"),
+            participants: row.get("participants"),
+            message_count: row.get("message_count"),
+            unread_count: row.get("unread_count"),
+            snippet: snippet(&latest_body),
+            latest_at,
+        }
+    }).collect())
+}
+
+// Open a thread: every Quant message of it the user took part in, oldest first, with the ones
+// they received marked read. Empty when the thread does not exist or the user is in none of its messages.
+pub async fn open_thread(pool: &PgPool, email: &str, thread_id: &str) -> Result<Vec<Email>, sqlx::Error> {
+    // Marked read before anything is selected, so a recall either lands first or finds them read
+    sqlx::query(
+        r#"
+        UPDATE emails
+        SET read_at = NOW()
+        WHERE thread_id = $1 AND recipient_email = $2 AND read_at IS NULL AND recalled_at IS NULL
+        "#
+    )
+    .bind(thread_id)
+    .bind(email)
+    .execute(pool)
+    .await?;
+
+    // A reply that arrived since is still unread and keeps its body to itself
+    let rows = sqlx::query(
+        r#"
+        SELECT e.id::text, e.sender_id, e.sender_email, e.sender_name, e.recipient_email,
+               e.subject, e.sent_at, e.read_at, e.gmail_id, e.recalled_at, e.thread_id,
+               CASE WHEN e.recipient_email = $2 AND e.read_at IS NULL THEN '' ELSE e.body END AS body,
+               e.is_encrypted,
+               CASE WHEN e.recipient_email = $2 AND e.read_at IS NULL THEN NULL ELSE e.raw_encrypted_content END AS raw_encrypted_content,
+               ARRAY_AGG(el.label_id) FILTER (WHERE el.label_id IS NOT NULL) AS label_ids
+        FROM emails e
+        LEFT JOIN email_labels el ON e.id = el.email_id
+        WHERE e.thread_id = $1 AND (e.sender_email = $2 OR e.recipient_email = $2)
+        GROUP BY e.id
+        ORDER BY e.sent_at ASC
+        "#
+    )
+    .bind(thread_id)
+    .bind(email)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.iter().map(email_from_row).collect())
+}
@@ -1,47 +1,61 @@
 use sqlx::{PgPool, Row};
 
+use crate::db::sessions::hash_session_token;
+use crate::encryption::refresh_token::seal_refresh_token;
+
 // Store or update user in database
 pub async fn store_user(
     pool: &PgPool,
     email: &str,
     name: &Option<String>,
     picture: &Option<String>,
-    session_token: &str,
     refresh_token: &Option<String>,
 ) -> Result<(), sqlx::Error> {
+    // Refresh tokens never reach the database in plaintext
+    let sealed_refresh_token = match refresh_token {
+        Some(token) => Some(
+            seal_refresh_token(token, email).map_err(|e| sqlx::Error::Configuration(e.to_string().into()))?
+        ),
+        None => None,
+    };
+    
     sqlx::query(
         r#"
-        INSERT INTO users (email, name, picture, session_token, refresh_token)
-        VALUES ($1, $2, $3, $4, $5)
+        INSERT INTO users (email, name, picture, refresh_token)
+        VALUES ($1, $2, $3, $4)
         ON CONFLICT (email) DO UPDATE
-        SET name = $2, picture = $3, session_token = $4, 
-            refresh_token = CASE WHEN $5 IS NOT NULL THEN $5 ELSE users.refresh_token END,
+        SET name = $2, picture = $3, 
+            refresh_token = CASE WHEN $4 IS NOT NULL THEN $4 ELSE users.refresh_token END,
             updated_at = NOW()
         "#
     )
     .bind(email)
     .bind(name)
     .bind(picture)
-    .bind(session_token)
-    .bind(refresh_token)
+    .bind(sealed_refresh_token)
     .execute(pool)
     .await?;
     
     Ok(())
 }
 
-// Get user by session token
+// Get user by session token, rejecting expired sessions and recording activity
 pub async fn get_user_by_session(
     pool: &PgPool,
     session_token: &str,
 ) -> Result<Option<(String, Option<String>, Option<String>, Option<String>)>, sqlx::Error> {
     let row = sqlx::query(
         r#"
-        SELECT email, name, picture, refresh_token FROM users
-        WHERE session_token = $1
+        WITH active AS (
+            UPDATE sessions SET last_seen_at = NOW()
+            WHERE token_hash = $1 AND expires_at > NOW()
+            RETURNING user_email
+        )
+        SELECT u.email, u.name, u.picture, u.refresh_token FROM users u
+        JOIN active ON active.user_email = u.email
         "#
     )
-    .bind(session_token)
+    .bind(hash_session_token(session_token))
     .fetch_optional(pool)
     .await?;
     
@@ -71,11 +85,11 @@ pub async fn get_user_refresh_token(
     Ok(row.and_then(|r| r.get("refresh_token")))
 }
 
-// List all users (for debugging)
-pub async fn list_users(pool: &PgPool) -> Result<Vec<(String, Option<String>)>, sqlx::Error> {
+// List all users with their roles (for debugging)
+pub async fn list_users(pool: &PgPool) -> Result<Vec<(String, Option<String>, String)>, sqlx::Error> {
     let rows = sqlx::query(
         r#"
-        SELECT email, name FROM users
+        SELECT email, name, role FROM users
         ORDER BY created_at DESC
         "#
     )
@@ -85,6 +99,7 @@ pub async fn list_users(pool: &PgPool) -> Result<Vec<(String, Option<String>)>,
     Ok(rows.into_iter().map(|r| (
         r.get("email"),
         r.get("name"),
+        r.get("role"),
     )).collect())
 }
 
@@ -95,7 +110,7 @@ pub async fn get_user_info(
 ) -> Result<Option<crate::models::GoogleUserInfo>, sqlx::Error> {
     let row = sqlx::query(
         r#"
-        SELECT email, name, picture, refresh_token FROM users
+        SELECT email, name, picture FROM users
         WHERE email = $1
         "#
     )
@@ -107,6 +122,5 @@ pub async fn get_user_info(
         email: r.get("email"),
         name: r.get("name"),
         picture: r.get("picture"),
-        refresh_token: r.get("refresh_token"),
     }))
 }
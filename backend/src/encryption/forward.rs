@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+use pqcrypto_kyber::kyber768::{self, encapsulate, decapsulate};
+use pqcrypto_traits::kem::{PublicKey, SecretKey, Ciphertext, SharedSecret};
+use base64::{encode_config, decode_config, STANDARD};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, aead::{Aead, KeyInit}};
+use rand::{RngCore, rngs::OsRng};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+use super::{EncryptedMessage, is_legacy_encapsulated_key};
+
+/// One hop in the forwarding history of an encrypted message
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ForwardingRecord {
+    pub from: String,
+    pub to: String,
+    pub forwarded_at: String,
+}
+
+/// Short digest of a content key, used to detect a wrong private key before re-wrapping
+pub fn key_check_value(content_key: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"quant-client-content-key-check");
+    hasher.update(content_key);
+    encode_config(&hasher.finalize()[..8], STANDARD)
+}
+
+/// Wraps a content key under a Kyber shared secret
+pub fn wrap_content_key(content_key: &[u8], shared_secret: &[u8]) -> Result<String, Box<dyn Error>> {
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(shared_secret));
+    let wrapped = cipher
+        .encrypt(Nonce::from_slice(&nonce), content_key)
+        .map_err(|_| "Failed to wrap content key")?;
+
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&wrapped);
+    Ok(encode_config(blob, STANDARD))
+}
+
+/// Unwraps a content key with the Kyber shared secret of the current key slot
+pub fn unwrap_content_key(wrapped_key_b64: &str, shared_secret: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let blob = decode_config(wrapped_key_b64, STANDARD)?;
+    if blob.len() <= 12 {
+        return Err("Wrapped content key is too short".into());
+    }
+
+    let (nonce, wrapped) = blob.split_at(12);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(shared_secret));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), wrapped)
+        .map_err(|_| "Content key cannot be unwrapped with this private key".into())
+}
+
+// Repeat the key over the data, matching the stream used by encrypt_message
+fn xor_with_key(data: &[u8], key: &[u8]) -> Vec<u8> {
+    data.iter().zip(key.iter().cycle()).map(|(b, k)| b ^ k).collect()
+}
+
+/// Whether a message predates the encapsulation fix: its encapsulated key is the raw 32-byte
+/// shared secret rather than a Kyber ciphertext, and its content key was never stored
+pub fn is_legacy_envelope(encrypted_msg: &EncryptedMessage) -> bool {
+    decode_config(&encrypted_msg.encapsulated_key, STANDARD)
+        .map(|bytes| is_legacy_encapsulated_key(&bytes))
+        .unwrap_or(false)
+}
+
+/// Recovers the content key from the caller's key slot.
+/// Returns None for the legacy demo format, which carries no key at all.
+pub fn recover_content_key(encrypted_msg: &EncryptedMessage, secret_key_b64: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    if is_legacy_envelope(encrypted_msg) {
+        return Ok(None);
+    }
+    let kyber_ciphertext_bytes = decode_config(&encrypted_msg.encapsulated_key, STANDARD)?;
+
+    let sk_bytes = decode_config(secret_key_b64, STANDARD)?;
+    let sk = kyber768::SecretKey::from_bytes(&sk_bytes)
+        .map_err(|e| format!("Failed to decode secret key: {}", e))?;
+    let kyber_ciphertext = kyber768::Ciphertext::from_bytes(&kyber_ciphertext_bytes)
+        .map_err(|e| format!("Failed to decode Kyber ciphertext: {}", e))?;
+
+    let shared_secret = decapsulate(&kyber_ciphertext, &sk);
+    let content_key = match &encrypted_msg.wrapped_key {
+        Some(wrapped_key) => unwrap_content_key(wrapped_key, shared_secret.as_bytes())?,
+        None => shared_secret.as_bytes().to_vec(),
+    };
+
+    // Kyber decapsulation never fails outright, so confirm the key really belongs to this message
+    let key_matches = match &encrypted_msg.key_check {
+        Some(key_check) => key_check == &key_check_value(&content_key),
+        None => {
+            let body = decode_config(&encrypted_msg.ciphertext, STANDARD)?;
+            String::from_utf8(xor_with_key(&body, &content_key)).is_ok()
+        }
+    };
+
+    if !key_matches {
+        return Err("Message cannot be decrypted with this private key".into());
+    }
+
+    Ok(Some(content_key))
+}
+
+/// Re-wraps a message's content key for a new recipient.
+/// The body ciphertext is carried over untouched and the hop is appended to the forwarding chain.
+/// Legacy messages are refused: their encapsulated key is a bare shared secret, and copying it
+/// would hand that secret to the new recipient.
+pub fn rewrap_for_recipient(
+    encrypted_msg: &EncryptedMessage,
+    forwarder_secret_key_b64: &str,
+    recipient_public_key_b64: &str,
+    forwarded_from: &str,
+    forwarded_to: &str,
+) -> Result<EncryptedMessage, Box<dyn Error>> {
+    let mut forwarding_chain = encrypted_msg.forwarding_chain.clone();
+    forwarding_chain.push(ForwardingRecord {
+        from: forwarded_from.to_string(),
+        to: forwarded_to.to_string(),
+        forwarded_at: chrono::Utc::now().to_rfc3339(),
+    });
+
+    let content_key = recover_content_key(encrypted_msg, forwarder_secret_key_b64)?
+        .ok_or("Message uses the legacy encryption format and cannot be forwarded")?;
+
+    let pk_bytes = decode_config(recipient_public_key_b64, STANDARD)?;
+    let pk = kyber768::PublicKey::from_bytes(&pk_bytes)
+        .map_err(|e| format!("Failed to decode public key: {}", e))?;
+    let (shared_secret, kyber_ciphertext) = encapsulate(&pk);
+
+    Ok(EncryptedMessage {
+        ciphertext: encrypted_msg.ciphertext.clone(),
+        encapsulated_key: encode_config(kyber_ciphertext.as_bytes(), STANDARD),
+        wrapped_key: Some(wrap_content_key(&content_key, shared_secret.as_bytes())?),
+        key_check: Some(key_check_value(&content_key)),
+        forwarding_chain,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{encrypt_message, decrypt_message, generate_keypair};
+
+    const MESSAGE: &str = "Quarterly numbers attached below.";
+
+    #[test]
+    fn forwarded_messages_decrypt_for_the_new_recipient_only() {
+        let (alice, bob, carol) = (generate_keypair().unwrap(), generate_keypair().unwrap(), generate_keypair().unwrap());
+        let original = encrypt_message(MESSAGE, &alice.public_key).unwrap();
+
+        let forwarded = rewrap_for_recipient(&original, &alice.secret_key, &bob.public_key, "alice@test.io", "bob@test.io").unwrap();
+        assert_eq!(forwarded.ciphertext, original.ciphertext);
+        assert_eq!(forwarded.forwarding_chain.len(), 1);
+        assert_eq!(decrypt_message(&forwarded, &bob.secret_key).unwrap(), MESSAGE);
+        assert!(decrypt_message(&forwarded, &alice.secret_key).is_err());
+
+        let again = rewrap_for_recipient(&forwarded, &bob.secret_key, &carol.public_key, "bob@test.io", "carol@test.io").unwrap();
+        assert_eq!(again.forwarding_chain.len(), 2);
+        assert_eq!(decrypt_message(&again, &carol.secret_key).unwrap(), MESSAGE);
+
+        assert!(rewrap_for_recipient(&original, &bob.secret_key, &carol.public_key, "bob@test.io", "carol@test.io").is_err());
+    }
+
+    #[test]
+    fn legacy_messages_are_not_forwarded() {
+        let (alice, bob) = (generate_keypair().unwrap(), generate_keypair().unwrap());
+        let shared_secret = [7u8; 32];
+        let legacy = EncryptedMessage {
+            ciphertext: encode_config(xor_with_key(MESSAGE.as_bytes(), &[1, 2, 3]), STANDARD),
+            encapsulated_key: encode_config(shared_secret, STANDARD),
+            wrapped_key: None,
+            key_check: None,
+            forwarding_chain: Vec::new(),
+        };
+
+        assert!(is_legacy_envelope(&legacy));
+        assert!(rewrap_for_recipient(&legacy, &alice.secret_key, &bob.public_key, "alice@test.io", "bob@test.io").is_err());
+
+        let current = encrypt_message(MESSAGE, &alice.public_key).unwrap();
+        assert!(!is_legacy_envelope(&current));
+    }
+}
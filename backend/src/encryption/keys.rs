@@ -3,14 +3,29 @@ use sqlx::PgPool;
 use std::error::Error;
 use log::info;
 
+use super::transparency;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct KeyPair {
     pub public_key: String,
     pub secret_key: String,
 }
 
-/// Store a user's key pair in the database
+/// Store a user's key pair in the database and record the public key in the transparency log
 pub async fn store_keypair(pool: &PgPool, email: &str, keypair: &KeyPair) -> Result<(), Box<dyn Error>> {
+    let mut tx = pool.begin().await?;
+    
+    let previous = sqlx::query!(
+        r#"
+        SELECT public_key FROM user_keys
+        WHERE email = $1
+        FOR UPDATE
+        "#,
+        email
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    
     sqlx::query!(
         r#"
         INSERT INTO user_keys (email, public_key, private_key, created_at)
@@ -22,13 +37,51 @@ pub async fn store_keypair(pool: &PgPool, email: &str, keypair: &KeyPair) -> Res
         keypair.public_key,
         keypair.secret_key
     )
-    .execute(pool)
+    .execute(&mut tx)
     .await?;
     
+    let action = match previous {
+        None => Some(transparency::ACTION_PUBLISH),
+        Some(r) if r.public_key != keypair.public_key => Some(transparency::ACTION_ROTATE),
+        Some(_) => None,
+    };
+    if let Some(action) = action {
+        transparency::append_log_entry(&mut tx, email, action, &keypair.public_key).await?;
+    }
+    
+    tx.commit().await?;
+    
     info!("Stored key pair for user: {}", email);
     Ok(())
 }
 
+/// Delete a user's key pair and record the revocation in the transparency log
+pub async fn revoke_keypair(pool: &PgPool, email: &str) -> Result<bool, Box<dyn Error>> {
+    let mut tx = pool.begin().await?;
+    
+    let revoked = sqlx::query!(
+        r#"
+        DELETE FROM user_keys
+        WHERE email = $1
+        RETURNING public_key
+        "#,
+        email
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    
+    let revoked = match revoked {
+        Some(r) => r,
+        None => return Ok(false),
+    };
+    
+    transparency::append_log_entry(&mut tx, email, transparency::ACTION_REVOKE, &revoked.public_key).await?;
+    tx.commit().await?;
+    
+    info!("Revoked key pair for user: {}", email);
+    Ok(true)
+}
+
 /// Retrieve a user's key pair from the database
 pub async fn get_keypair(pool: &PgPool, email: &str) -> Result<Option<KeyPair>, Box<dyn Error>> {
     let record = sqlx::query!(
@@ -5,12 +5,16 @@ use std::error::Error;
 use pqcrypto_traits::kem::{PublicKey, SecretKey, Ciphertext, SharedSecret};
 
 pub mod keys;
+pub mod portal;
+pub mod forward;
+pub mod transparency;
+pub mod refresh_token;
 
 pub use keys::KeyPair;
+pub use forward::ForwardingRecord;
 
 const ENCRYPTION_MARKER: &str = "[Q-ENCRYPTED]";
-// Based on the error message, we know the expected size
-const EXPECTED_KYBER_CIPHERTEXT_SIZE: usize = 1088;
+const EXPECTED_KYBER_CIPHERTEXT_SIZE: usize = kyber768::ciphertext_bytes();
 
 // Enable this flag to see detailed quantum encryption visualization in the terminal
 pub static mut DEBUG_MODE: bool = true;
@@ -28,6 +32,12 @@ macro_rules! debug_print {
 pub struct EncryptedMessage {
     pub ciphertext: String,   // Base64 encoded XOR-encrypted message
     pub encapsulated_key: String,  // Base64 encoded Kyber768 ciphertext
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_key: Option<String>,  // Content key wrapped under the Kyber secret (forwarded messages)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_check: Option<String>,  // Digest of the content key to detect a wrong private key
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forwarding_chain: Vec<ForwardingRecord>,
 }
 
 /// Generates a new key pair for post-quantum encryption
@@ -55,9 +65,11 @@ pub fn encrypt_message(message: &str, public_key_b64: &str) -> Result<EncryptedM
     let pk = kyber768::PublicKey::from_bytes(&pk_bytes).map_err(|e| format!("Failed to decode public key: {}", e))?;
     debug_print!("✅ Kyber-768 public key validated");
     
-    // Generate a shared secret and Kyber ciphertext using key encapsulation
+    // Generate a shared secret and Kyber ciphertext using key encapsulation.
+    // encapsulate returns the secret first; messages written while these were swapped stored the
+    // secret as the encapsulated key and were keyed with a ciphertext nobody kept.
     debug_print!("🔄 Performing Kyber768 key encapsulation...");
-    let (kyber_ciphertext, shared_secret) = encapsulate(&pk);
+    let (shared_secret, kyber_ciphertext) = encapsulate(&pk);
     debug_print!("🔐 Key encapsulation completed");
     
     // Get raw bytes
@@ -68,7 +80,7 @@ pub fn encrypt_message(message: &str, public_key_b64: &str) -> Result<EncryptedM
     debug_print!("   ├─ Expected Kyber ciphertext size: {} bytes", EXPECTED_KYBER_CIPHERTEXT_SIZE);
     debug_print!("   └─ Shared secret size: {} bytes", shared_secret_bytes.len());
     
-    if kyber_ciphertext_bytes.len() != EXPECTED_KYBER_CIPHERTEXT_SIZE && kyber_ciphertext_bytes.len() != 32 {
+    if kyber_ciphertext_bytes.len() != EXPECTED_KYBER_CIPHERTEXT_SIZE && !is_legacy_encapsulated_key(kyber_ciphertext_bytes) {
         debug_print!("⚠️ Warning: Actual ciphertext size doesn't match expected size");
     }
     
@@ -118,9 +130,18 @@ pub fn encrypt_message(message: &str, public_key_b64: &str) -> Result<EncryptedM
     Ok(EncryptedMessage {
         ciphertext: message_ciphertext_b64,
         encapsulated_key: kyber_ciphertext_b64,
+        wrapped_key: None,
+        key_check: Some(forward::key_check_value(shared_secret_bytes)),
+        forwarding_chain: Vec::new(),
     })
 }
 
+// Messages encrypted before the encapsulation fix carry the bare shared secret where the Kyber
+// ciphertext belongs
+pub(crate) fn is_legacy_encapsulated_key(encapsulated_key: &[u8]) -> bool {
+    encapsulated_key.len() == kyber768::shared_secret_bytes()
+}
+
 /// Decrypts a message using the recipient's private key
 pub fn decrypt_message(encrypted_msg: &EncryptedMessage, secret_key_b64: &str) -> Result<String, Box<dyn Error>> {
     debug_print!("\n===== QUANTUM DECRYPTION PROCESS STARTING =====");
@@ -154,7 +175,7 @@ pub fn decrypt_message(encrypted_msg: &EncryptedMessage, secret_key_b64: &str) -
             debug_print!("❌ Error creating Kyber ciphertext: {}", e);
             
             // Try to use a workaround if the size is wrong
-            if kyber_ciphertext_bytes.len() == 32 && EXPECTED_KYBER_CIPHERTEXT_SIZE == 1088 {
+            if is_legacy_encapsulated_key(&kyber_ciphertext_bytes) {
                 debug_print!("⚠️ Attempting workaround for 32-byte vs 1088-byte ciphertext mismatch");
                 debug_print!("   This version of Kyber-768 expects 1088 bytes but we received 32 bytes");
                 debug_print!("   The current implementation likely has a mismatch in ciphertext representation");
@@ -192,13 +213,22 @@ pub fn decrypt_message(encrypted_msg: &EncryptedMessage, secret_key_b64: &str) -
     let encrypted_bytes = decode_config(&encrypted_msg.ciphertext, STANDARD)?;
     debug_print!("   └─ XOR-encrypted message size: {} bytes", encrypted_bytes.len());
     
-    // Use the shared secret to decrypt the message
+    // Forwarded messages carry the original content key wrapped under this recipient's shared secret
+    let content_key = match &encrypted_msg.wrapped_key {
+        Some(wrapped_key) => {
+            debug_print!("🔄 Unwrapping forwarded content key...");
+            forward::unwrap_content_key(wrapped_key, shared_secret.as_bytes())?
+        }
+        None => shared_secret.as_bytes().to_vec(),
+    };
+    
+    // Use the content key to decrypt the message
     let mut decrypted_bytes = vec![0u8; encrypted_bytes.len()];
-    let mut key_bytes = shared_secret.as_bytes().to_vec();
+    let mut key_bytes = content_key.clone();
     
     // Extend the key if needed
     while key_bytes.len() < encrypted_bytes.len() {
-        key_bytes.extend_from_slice(shared_secret.as_bytes());
+        key_bytes.extend_from_slice(&content_key);
     }
     
     debug_print!("🔄 Applying quantum-derived XOR decryption");
@@ -275,4 +305,58 @@ pub fn set_debug_mode(enabled: bool) {
         DEBUG_MODE = enabled;
         println!("Quantum encryption debug mode: {}", if enabled { "ENABLED" } else { "DISABLED" });
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = "Meet at the usual place at nine.";
+
+    #[test]
+    fn encrypted_messages_carry_the_kyber_ciphertext_and_decrypt() {
+        let recipient = generate_keypair().unwrap();
+        let encrypted = encrypt_message(MESSAGE, &recipient.public_key).unwrap();
+
+        // Before encapsulate's pair was read in the right order, this held the 32-byte shared secret
+        let encapsulated_key = decode_config(&encrypted.encapsulated_key, STANDARD).unwrap();
+        assert_eq!(encapsulated_key.len(), EXPECTED_KYBER_CIPHERTEXT_SIZE);
+
+        let serialized = serialize_encrypted_message(&encrypted).unwrap();
+        let received = deserialize_encrypted_message(&serialized).unwrap();
+        assert_eq!(decrypt_message(&received, &recipient.secret_key).unwrap(), MESSAGE);
+    }
+
+    #[test]
+    fn encrypted_messages_do_not_decrypt_with_another_key() {
+        let recipient = generate_keypair().unwrap();
+        let stranger = generate_keypair().unwrap();
+        let encrypted = encrypt_message(MESSAGE, &recipient.public_key).unwrap();
+
+        let decrypted = decrypt_message(&encrypted, &stranger.secret_key);
+        assert!(!matches!(decrypted, Ok(ref plaintext) if plaintext == MESSAGE));
+        assert!(forward::recover_content_key(&encrypted, &stranger.secret_key).is_err());
+    }
+
+    // Messages written before the fix: the shared secret stored as the encapsulated key and the body
+    // XORed with the Kyber ciphertext, which was never stored. Nobody can decrypt them, the
+    // recipient included, so they must be recognised as legacy rather than treated as keyed.
+    #[test]
+    fn messages_encrypted_before_the_fix_are_recognised_as_legacy() {
+        let recipient = generate_keypair().unwrap();
+        let pk = kyber768::PublicKey::from_bytes(&decode_config(&recipient.public_key, STANDARD).unwrap()).unwrap();
+        let (shared_secret, kyber_ciphertext) = encapsulate(&pk);
+        let body: Vec<u8> = MESSAGE.bytes().zip(kyber_ciphertext.as_bytes().iter().cycle()).map(|(b, k)| b ^ k).collect();
+        let legacy = EncryptedMessage {
+            ciphertext: encode_config(body, STANDARD),
+            encapsulated_key: encode_config(shared_secret.as_bytes(), STANDARD),
+            wrapped_key: None,
+            key_check: None,
+            forwarding_chain: Vec::new(),
+        };
+
+        assert!(forward::recover_content_key(&legacy, &recipient.secret_key).unwrap().is_none());
+        let decrypted = decrypt_message(&legacy, &recipient.secret_key).unwrap();
+        assert_ne!(decrypted, MESSAGE);
+    }
+}
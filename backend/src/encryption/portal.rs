@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use base64::{encode_config, decode_config, STANDARD};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, aead::{Aead, KeyInit}};
+use rand::{Rng, RngCore, rngs::OsRng};
+use sha2::Sha256;
+use std::error::Error;
+
+// PBKDF2 work factor for passcode-derived keys
+const PASSCODE_KDF_ITERATIONS: u32 = 210_000;
+// Passcodes avoid characters that are easy to misread when shared out of band
+const PASSCODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const PASSCODE_LENGTH: usize = 12;
+
+/// Stored in place of the body for messages delivered through the portal
+pub const PORTAL_PLACEHOLDER_BODY: &str = "This message is protected by a passcode and can be read in the secure portal.";
+
+/// Message encrypted for a recipient without a Quant Client account
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PasscodeEnvelope {
+    pub scheme: String,      // Always "passcode-v1"
+    pub iterations: u32,
+    pub salt: String,        // Base64 encoded KDF salt
+    pub nonce: String,       // Base64 encoded AEAD nonce
+    pub ciphertext: String,  // Base64 encoded ChaCha20-Poly1305 ciphertext
+}
+
+/// Generates a one-time passcode, grouped in fours for readability
+pub fn generate_passcode() -> String {
+    let mut rng = OsRng;
+    let chars: Vec<char> = (0..PASSCODE_LENGTH)
+        .map(|_| PASSCODE_ALPHABET[rng.gen_range(0..PASSCODE_ALPHABET.len())] as char)
+        .collect();
+
+    chars
+        .chunks(4)
+        .map(|chunk| chunk.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Generates a random token for the portal URL
+pub fn generate_portal_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+// Passcodes are compared without separators or case so typing is forgiving
+fn normalize_passcode(passcode: &str) -> String {
+    passcode
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn derive_key(passcode: &str, salt: &[u8], iterations: u32) -> Key {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(normalize_passcode(passcode).as_bytes(), salt, iterations, &mut key);
+    Key::from(key)
+}
+
+/// Encrypts a message under a key derived from a passcode
+pub fn encrypt_with_passcode(message: &str, passcode: &str) -> Result<PasscodeEnvelope, Box<dyn Error>> {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passcode, &salt, PASSCODE_KDF_ITERATIONS));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), message.as_bytes())
+        .map_err(|_| "Failed to encrypt message with passcode")?;
+
+    Ok(PasscodeEnvelope {
+        scheme: "passcode-v1".to_string(),
+        iterations: PASSCODE_KDF_ITERATIONS,
+        salt: encode_config(salt, STANDARD),
+        nonce: encode_config(nonce, STANDARD),
+        ciphertext: encode_config(ciphertext, STANDARD),
+    })
+}
+
+/// Decrypts a passcode envelope; a wrong passcode fails authentication
+pub fn decrypt_with_passcode(envelope: &PasscodeEnvelope, passcode: &str) -> Result<String, Box<dyn Error>> {
+    let salt = decode_config(&envelope.salt, STANDARD)?;
+    let nonce = decode_config(&envelope.nonce, STANDARD)?;
+    let ciphertext = decode_config(&envelope.ciphertext, STANDARD)?;
+
+    if nonce.len() != 12 {
+        return Err("Invalid nonce length".into());
+    }
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passcode, &salt, envelope.iterations));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| "Incorrect passcode")?;
+
+    Ok(String::from_utf8(plaintext)?)
+}
+
+/// Serializes a passcode envelope for storage
+pub fn serialize_passcode_envelope(envelope: &PasscodeEnvelope) -> Result<String, Box<dyn Error>> {
+    serde_json::to_string(envelope).map_err(|e| e.into())
+}
+
+/// Deserializes a passcode envelope from storage
+pub fn deserialize_passcode_envelope(data: &str) -> Result<PasscodeEnvelope, Box<dyn Error>> {
+    serde_json::from_str(data).map_err(|e| e.into())
+}
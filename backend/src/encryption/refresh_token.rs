@@ -0,0 +1,52 @@
+use base64::{encode_config, decode_config, STANDARD};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, aead::{Aead, KeyInit, Payload}};
+use rand::{RngCore, rngs::OsRng};
+use std::error::Error;
+
+use crate::config;
+
+/// Prefix marking a refresh token that has already been sealed
+pub const SEALED_PREFIX: &str = "enc:v1:";
+
+/// Validated as base64 of 32 bytes when the config is loaded
+fn token_key() -> [u8; 32] {
+    decode_config(config::get().secrets.refresh_token_key.expose().trim(), STANDARD)
+        .expect("secrets.refresh_token_key must be base64")
+        .try_into()
+        .expect("secrets.refresh_token_key must decode to 32 bytes")
+}
+
+/// Encrypt a Google refresh token for storage.
+/// The owner's email is bound as associated data so a sealed token cannot be moved to another user.
+pub fn seal_refresh_token(refresh_token: &str, email: &str) -> Result<String, Box<dyn Error>> {
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&token_key()));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: refresh_token.as_bytes(), aad: email.as_bytes() })
+        .map_err(|_| "Failed to seal refresh token")?;
+
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&ciphertext);
+    Ok(format!("{}{}", SEALED_PREFIX, encode_config(blob, STANDARD)))
+}
+
+/// Decrypt a sealed refresh token for use against Google's token endpoint
+pub fn open_refresh_token(sealed: &str, email: &str) -> Result<String, Box<dyn Error>> {
+    let encoded = sealed
+        .strip_prefix(SEALED_PREFIX)
+        .ok_or("Refresh token is not sealed")?;
+    let blob = decode_config(encoded, STANDARD)?;
+    if blob.len() <= 12 {
+        return Err("Sealed refresh token is too short".into());
+    }
+
+    let (nonce, ciphertext) = blob.split_at(12);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&token_key()));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: email.as_bytes() })
+        .map_err(|_| "Refresh token cannot be decrypted with the configured key")?;
+
+    Ok(String::from_utf8(plaintext)?)
+}
@@ -0,0 +1,828 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use base64::{encode_config, decode_config, STANDARD};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use log::info;
+
+use crate::config;
+
+/// Hash of a leaf or interior node of the log
+pub type Hash = [u8; 32];
+
+/// Actions recorded in the key transparency log
+pub const ACTION_PUBLISH: &str = "publish";
+pub const ACTION_ROTATE: &str = "rotate";
+pub const ACTION_REVOKE: &str = "revoke";
+
+/// One published, rotated or revoked public key
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogLeaf {
+    pub leaf_index: u64,
+    pub email: String,
+    pub action: String,
+    pub public_key: String,
+    pub logged_at: String,
+}
+
+impl LogLeaf {
+    /// Canonical encoding that the leaf hash commits to
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        format!(
+            "kt-leaf-v1\n{}\n{}\n{}\n{}\n{}",
+            self.leaf_index, self.email, self.action, self.public_key, self.logged_at
+        )
+        .into_bytes()
+    }
+
+    pub fn leaf_hash(&self) -> Hash {
+        leaf_hash(&self.canonical_bytes())
+    }
+}
+
+/// A leaf's position and hash, without the email and key it commits to.
+/// Enough to rebuild the tree, so the log can be audited without listing who is in it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LeafHashEntry {
+    pub leaf_index: u64,
+    pub leaf_hash: String,
+}
+
+impl From<&LogLeaf> for LeafHashEntry {
+    fn from(leaf: &LogLeaf) -> Self {
+        LeafHashEntry { leaf_index: leaf.leaf_index, leaf_hash: encode_hash(&leaf.leaf_hash()) }
+    }
+}
+
+/// Tree head signed by the log's Ed25519 key
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignedTreeHead {
+    pub tree_size: u64,
+    pub root_hash: String,
+    pub signed_at: String,
+    pub signature: String,
+}
+
+impl SignedTreeHead {
+    fn signed_bytes(tree_size: u64, root_hash: &str, signed_at: &str) -> Vec<u8> {
+        format!("kt-sth-v1\n{}\n{}\n{}", tree_size, root_hash, signed_at).into_bytes()
+    }
+
+    /// Check the signature against the log's published verification key
+    pub fn verify(&self, log_public_key_b64: &str) -> Result<(), Box<dyn Error>> {
+        let key_bytes: [u8; 32] = decode_config(log_public_key_b64, STANDARD)?
+            .try_into()
+            .map_err(|_| "Log public key must be 32 bytes")?;
+        let verifying_key = VerifyingKey::from_bytes(&key_bytes)?;
+        let signature = Signature::from_slice(&decode_config(&self.signature, STANDARD)?)?;
+
+        verifying_key
+            .verify(&Self::signed_bytes(self.tree_size, &self.root_hash, &self.signed_at), &signature)
+            .map_err(|_| "Tree head signature is invalid".into())
+    }
+
+    pub fn root(&self) -> Result<Hash, Box<dyn Error>> {
+        decode_hash(&self.root_hash)
+    }
+}
+
+/// Audit path proving a leaf is included in a tree of the given size
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InclusionProof {
+    pub leaf_index: u64,
+    pub tree_size: u64,
+    pub audit_path: Vec<String>,
+}
+
+/// Proof that a smaller tree is a prefix of a larger one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConsistencyProof {
+    pub first_size: u64,
+    pub second_size: u64,
+    pub proof: Vec<String>,
+}
+
+/// A log entry together with its inclusion proof
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyHistoryEntry {
+    pub leaf: LogLeaf,
+    pub inclusion_proof: InclusionProof,
+}
+
+/// Everything needed to audit one user's key history offline
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyHistoryBundle {
+    pub email: String,
+    pub tree_head: SignedTreeHead,
+    pub entries: Vec<KeyHistoryEntry>,
+}
+
+// Merkle tree hashing follows RFC 6962, with domain-separated leaf and node hashes
+
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+pub fn encode_hash(hash: &Hash) -> String {
+    encode_config(hash, STANDARD)
+}
+
+pub fn decode_hash(hash_b64: &str) -> Result<Hash, Box<dyn Error>> {
+    decode_config(hash_b64, STANDARD)?
+        .try_into()
+        .map_err(|_| "Hash must be 32 bytes".into())
+}
+
+// Largest power of two strictly smaller than n (n > 1)
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+/// Root hash of the tree built from the given leaf hashes
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => Sha256::digest([]).into(),
+        1 => leaves[0],
+        n => {
+            let k = split_point(n);
+            node_hash(&merkle_root(&leaves[..k]), &merkle_root(&leaves[k..]))
+        }
+    }
+}
+
+/// Roots of the perfect subtrees a tree of `tree_size` leaves splits into, largest first.
+/// Appending a leaf only touches the smallest ones, so the log grows in O(log n).
+pub fn append_to_frontier(frontier: &mut Vec<Hash>, tree_size: u64, leaf: Hash) {
+    let mut hash = leaf;
+    let mut size = tree_size;
+    // Each set low bit is a subtree as large as the one being carried, so they merge
+    while size & 1 == 1 {
+        let left = frontier.pop().expect("frontier holds one subtree per set bit of the tree size");
+        hash = node_hash(&left, &hash);
+        size >>= 1;
+    }
+    frontier.push(hash);
+}
+
+/// Frontier of the tree built from the given leaf hashes
+pub fn frontier_of(leaves: &[Hash]) -> Vec<Hash> {
+    let mut frontier = Vec::new();
+    for (size, leaf) in leaves.iter().enumerate() {
+        append_to_frontier(&mut frontier, size as u64, *leaf);
+    }
+    frontier
+}
+
+/// Root hash of the tree whose frontier is given; equal to `merkle_root` of its leaves
+pub fn frontier_root(frontier: &[Hash]) -> Hash {
+    match frontier.split_last() {
+        None => merkle_root(&[]),
+        Some((last, rest)) => rest.iter().rev().fold(*last, |right, left| node_hash(left, &right)),
+    }
+}
+
+fn encode_frontier(frontier: &[Hash]) -> String {
+    frontier.iter().map(encode_hash).collect::<Vec<_>>().join(",")
+}
+
+fn decode_frontier(frontier: &str) -> Result<Vec<Hash>, Box<dyn Error>> {
+    frontier.split(',').filter(|h| !h.is_empty()).map(decode_hash).collect()
+}
+
+/// Audit path for the leaf at `index`
+pub fn inclusion_path(index: usize, leaves: &[Hash]) -> Vec<Hash> {
+    let n = leaves.len();
+    if n <= 1 {
+        return Vec::new();
+    }
+
+    let k = split_point(n);
+    if index < k {
+        let mut path = inclusion_path(index, &leaves[..k]);
+        path.push(merkle_root(&leaves[k..]));
+        path
+    } else {
+        let mut path = inclusion_path(index - k, &leaves[k..]);
+        path.push(merkle_root(&leaves[..k]));
+        path
+    }
+}
+
+/// Consistency proof between the first `m` leaves and the whole tree
+pub fn consistency_path(m: usize, leaves: &[Hash]) -> Vec<Hash> {
+    fn subproof(m: usize, leaves: &[Hash], complete: bool) -> Vec<Hash> {
+        let n = leaves.len();
+        if m == n {
+            return if complete { Vec::new() } else { vec![merkle_root(leaves)] };
+        }
+
+        let k = split_point(n);
+        if m <= k {
+            let mut proof = subproof(m, &leaves[..k], complete);
+            proof.push(merkle_root(&leaves[k..]));
+            proof
+        } else {
+            let mut proof = subproof(m - k, &leaves[k..], false);
+            proof.push(merkle_root(&leaves[..k]));
+            proof
+        }
+    }
+
+    if m == 0 || m > leaves.len() {
+        return Vec::new();
+    }
+    subproof(m, leaves, true)
+}
+
+/// Verify an inclusion proof (RFC 9162, section 2.1.3.2)
+pub fn verify_inclusion(leaf_index: u64, tree_size: u64, leaf: &Hash, path: &[Hash], root: &Hash) -> bool {
+    if leaf_index >= tree_size {
+        return false;
+    }
+
+    let (mut f_n, mut s_n) = (leaf_index, tree_size - 1);
+    let mut r = *leaf;
+    for p in path {
+        if s_n == 0 {
+            return false;
+        }
+        if f_n & 1 == 1 || f_n == s_n {
+            r = node_hash(p, &r);
+            while f_n & 1 == 0 && f_n != 0 {
+                f_n >>= 1;
+                s_n >>= 1;
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+
+    s_n == 0 && &r == root
+}
+
+/// Verify a consistency proof (RFC 9162, section 2.1.4.2)
+pub fn verify_consistency(first_size: u64, second_size: u64, first_root: &Hash, second_root: &Hash, proof: &[Hash]) -> bool {
+    if first_size == 0 || first_size > second_size {
+        return false;
+    }
+    if first_size == second_size {
+        return proof.is_empty() && first_root == second_root;
+    }
+
+    let mut path = proof.to_vec();
+    if first_size.is_power_of_two() {
+        path.insert(0, *first_root);
+    }
+    if path.is_empty() {
+        return false;
+    }
+
+    let (mut f_n, mut s_n) = (first_size - 1, second_size - 1);
+    while f_n & 1 == 1 {
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+
+    let (mut f_r, mut s_r) = (path[0], path[0]);
+    for c in &path[1..] {
+        if s_n == 0 {
+            return false;
+        }
+        if f_n & 1 == 1 || f_n == s_n {
+            f_r = node_hash(c, &f_r);
+            s_r = node_hash(c, &s_r);
+            while f_n & 1 == 0 && f_n != 0 {
+                f_n >>= 1;
+                s_n >>= 1;
+            }
+        } else {
+            s_r = node_hash(&s_r, c);
+        }
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+
+    s_n == 0 && &f_r == first_root && &s_r == second_root
+}
+
+fn log_signing_key() -> SigningKey {
+    let seed: [u8; 32] = decode_config(config::get().secrets.transparency_signing_key.expose().trim(), STANDARD)
+        .expect("secrets.transparency_signing_key must be base64")
+        .try_into()
+        .expect("secrets.transparency_signing_key must decode to 32 bytes");
+    SigningKey::from_bytes(&seed)
+}
+
+/// Base64 Ed25519 key that auditors use to check tree head signatures
+pub fn log_public_key() -> String {
+    encode_config(log_signing_key().verifying_key().as_bytes(), STANDARD)
+}
+
+fn sign_tree_head(signing_key: &SigningKey, tree_size: u64, root: &Hash) -> SignedTreeHead {
+    let root_hash = encode_hash(root);
+    let signed_at = chrono::Utc::now().to_rfc3339();
+    let signature = signing_key.sign(&SignedTreeHead::signed_bytes(tree_size, &root_hash, &signed_at));
+
+    SignedTreeHead {
+        tree_size,
+        root_hash,
+        signed_at,
+        signature: encode_config(signature.to_bytes(), STANDARD),
+    }
+}
+
+fn row_to_leaf(row: &sqlx::postgres::PgRow) -> LogLeaf {
+    LogLeaf {
+        leaf_index: row.get::<i64, _>("leaf_index") as u64,
+        email: row.get("email"),
+        action: row.get("action"),
+        public_key: row.get("public_key"),
+        logged_at: row.get("logged_at"),
+    }
+}
+
+fn row_to_tree_head(row: &sqlx::postgres::PgRow) -> SignedTreeHead {
+    SignedTreeHead {
+        tree_size: row.get::<i64, _>("tree_size") as u64,
+        root_hash: row.get("root_hash"),
+        signed_at: row.get("signed_at"),
+        signature: row.get("signature"),
+    }
+}
+
+/// Append an entry to the log and sign the new tree head.
+/// Runs inside the caller's transaction so the key change and its log entry commit together.
+pub async fn append_log_entry(
+    tx: &mut Transaction<'_, Postgres>,
+    email: &str,
+    action: &str,
+    public_key: &str,
+) -> Result<SignedTreeHead, Box<dyn Error>> {
+    // Serialize appends so leaf indexes stay contiguous
+    sqlx::query("LOCK TABLE key_transparency_log IN EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await?;
+
+    // The latest head's frontier is all that is needed to extend the tree
+    let latest = sqlx::query("SELECT tree_size, frontier FROM key_transparency_heads ORDER BY tree_size DESC LIMIT 1")
+        .fetch_optional(&mut *tx)
+        .await?;
+    let tree_size = latest.as_ref().map_or(0, |row| row.get::<i64, _>("tree_size") as u64);
+    let stored_frontier = match latest.as_ref().and_then(|row| row.get::<Option<String>, _>("frontier")) {
+        Some(frontier) => Some(decode_frontier(&frontier)?),
+        None => None,
+    };
+    let mut frontier = match stored_frontier {
+        Some(frontier) if frontier.len() == tree_size.count_ones() as usize => frontier,
+        // Heads signed before frontiers were stored need the leaves read once
+        _ => {
+            let rows = sqlx::query("SELECT leaf_hash FROM key_transparency_log WHERE leaf_index < $1 ORDER BY leaf_index")
+                .bind(tree_size as i64)
+                .fetch_all(&mut *tx)
+                .await?;
+            let leaves = rows
+                .iter()
+                .map(|row| decode_hash(row.get("leaf_hash")))
+                .collect::<Result<Vec<Hash>, _>>()?;
+            if leaves.len() as u64 != tree_size {
+                return Err(format!("Log has {} entries but its head covers {}", leaves.len(), tree_size).into());
+            }
+            frontier_of(&leaves)
+        }
+    };
+
+    let leaf = LogLeaf {
+        leaf_index: tree_size,
+        email: email.to_lowercase(),
+        action: action.to_string(),
+        public_key: public_key.to_string(),
+        logged_at: chrono::Utc::now().to_rfc3339(),
+    };
+    let hash = leaf.leaf_hash();
+
+    sqlx::query(
+        r#"
+        INSERT INTO key_transparency_log (leaf_index, email, action, public_key, logged_at, leaf_hash)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#
+    )
+    .bind(leaf.leaf_index as i64)
+    .bind(&leaf.email)
+    .bind(&leaf.action)
+    .bind(&leaf.public_key)
+    .bind(&leaf.logged_at)
+    .bind(encode_hash(&hash))
+    .execute(&mut *tx)
+    .await?;
+
+    append_to_frontier(&mut frontier, tree_size, hash);
+    let tree_head = sign_tree_head(&log_signing_key(), tree_size + 1, &frontier_root(&frontier));
+
+    sqlx::query(
+        r#"
+        INSERT INTO key_transparency_heads (tree_size, root_hash, signed_at, signature, frontier)
+        VALUES ($1, $2, $3, $4, $5)
+        "#
+    )
+    .bind(tree_head.tree_size as i64)
+    .bind(&tree_head.root_hash)
+    .bind(&tree_head.signed_at)
+    .bind(&tree_head.signature)
+    .bind(encode_frontier(&frontier))
+    .execute(&mut *tx)
+    .await?;
+
+    info!("Key transparency log: {} {} at index {}", leaf.action, leaf.email, leaf.leaf_index);
+    Ok(tree_head)
+}
+
+/// Most recent signed tree head, if anything has been logged
+pub async fn get_latest_tree_head(pool: &PgPool) -> Result<Option<SignedTreeHead>, Box<dyn Error>> {
+    let row = sqlx::query(
+        "SELECT tree_size, root_hash, signed_at, signature FROM key_transparency_heads ORDER BY tree_size DESC LIMIT 1"
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.as_ref().map(row_to_tree_head))
+}
+
+/// Signed tree head for a specific size
+pub async fn get_tree_head(pool: &PgPool, tree_size: u64) -> Result<Option<SignedTreeHead>, Box<dyn Error>> {
+    let row = sqlx::query(
+        "SELECT tree_size, root_hash, signed_at, signature FROM key_transparency_heads WHERE tree_size = $1"
+    )
+    .bind(tree_size as i64)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.as_ref().map(row_to_tree_head))
+}
+
+/// Log entries in the range [start, end)
+pub async fn get_log_entries(pool: &PgPool, start: u64, end: u64) -> Result<Vec<LogLeaf>, Box<dyn Error>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT leaf_index, email, action, public_key, logged_at FROM key_transparency_log
+        WHERE leaf_index >= $1 AND leaf_index < $2
+        ORDER BY leaf_index
+        "#
+    )
+    // Indexes past i64::MAX cannot exist, so clamping them changes nothing
+    .bind(start.min(i64::MAX as u64) as i64)
+    .bind(end.min(i64::MAX as u64) as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.iter().map(row_to_leaf).collect())
+}
+
+// Leaf hashes of the first `tree_size` entries
+async fn get_leaf_hashes(pool: &PgPool, tree_size: u64) -> Result<Vec<Hash>, Box<dyn Error>> {
+    let rows = sqlx::query("SELECT leaf_hash FROM key_transparency_log WHERE leaf_index < $1 ORDER BY leaf_index")
+        .bind(tree_size as i64)
+        .fetch_all(pool)
+        .await?;
+
+    let leaves = rows
+        .iter()
+        .map(|row| decode_hash(row.get("leaf_hash")))
+        .collect::<Result<Vec<Hash>, _>>()?;
+
+    if (leaves.len() as u64) < tree_size {
+        return Err(format!("Log only has {} entries", leaves.len()).into());
+    }
+    Ok(leaves)
+}
+
+/// Inclusion proof for a leaf in the tree of the given size
+pub async fn get_inclusion_proof(pool: &PgPool, leaf_index: u64, tree_size: u64) -> Result<InclusionProof, Box<dyn Error>> {
+    if leaf_index >= tree_size {
+        return Err("Leaf index must be smaller than the tree size".into());
+    }
+
+    let leaves = get_leaf_hashes(pool, tree_size).await?;
+    Ok(InclusionProof {
+        leaf_index,
+        tree_size,
+        audit_path: inclusion_path(leaf_index as usize, &leaves).iter().map(encode_hash).collect(),
+    })
+}
+
+/// Consistency proof between two tree sizes
+pub async fn get_consistency_proof(pool: &PgPool, first_size: u64, second_size: u64) -> Result<ConsistencyProof, Box<dyn Error>> {
+    if first_size == 0 || first_size > second_size {
+        return Err("First tree size must be between 1 and the second tree size".into());
+    }
+
+    let leaves = get_leaf_hashes(pool, second_size).await?;
+    Ok(ConsistencyProof {
+        first_size,
+        second_size,
+        proof: consistency_path(first_size as usize, &leaves).iter().map(encode_hash).collect(),
+    })
+}
+
+/// A user's full key history with inclusion proofs against the latest tree head
+pub async fn get_key_history(pool: &PgPool, email: &str) -> Result<Option<KeyHistoryBundle>, Box<dyn Error>> {
+    let tree_head = match get_latest_tree_head(pool).await? {
+        Some(tree_head) => tree_head,
+        None => return Ok(None),
+    };
+
+    let email = email.to_lowercase();
+    let leaves = get_leaf_hashes(pool, tree_head.tree_size).await?;
+    let rows = sqlx::query(
+        r#"
+        SELECT leaf_index, email, action, public_key, logged_at FROM key_transparency_log
+        WHERE email = $1 AND leaf_index < $2
+        ORDER BY leaf_index
+        "#
+    )
+    .bind(&email)
+    .bind(tree_head.tree_size as i64)
+    .fetch_all(pool)
+    .await?;
+
+    let entries = rows
+        .iter()
+        .map(row_to_leaf)
+        .map(|leaf| {
+            let inclusion_proof = InclusionProof {
+                leaf_index: leaf.leaf_index,
+                tree_size: tree_head.tree_size,
+                audit_path: inclusion_path(leaf.leaf_index as usize, &leaves).iter().map(encode_hash).collect(),
+            };
+            KeyHistoryEntry { leaf, inclusion_proof }
+        })
+        .collect();
+
+    Ok(Some(KeyHistoryBundle { email, tree_head, entries }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Hash {
+        let bytes: Vec<u8> = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect();
+        bytes.try_into().unwrap()
+    }
+
+    fn tampered(hash: &Hash) -> Hash {
+        let mut hash = *hash;
+        hash[0] ^= 1;
+        hash
+    }
+
+    // The leaf inputs of the RFC 6962 reference test vectors
+    fn reference_leaves() -> Vec<Hash> {
+        let inputs: [&[u8]; 8] = [
+            b"",
+            b"\x00",
+            b"\x10",
+            b"\x20\x21",
+            b"\x30\x31",
+            b"\x40\x41\x42\x43",
+            b"\x50\x51\x52\x53\x54\x55\x56\x57",
+            b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f",
+        ];
+        inputs.iter().map(|input| leaf_hash(input)).collect()
+    }
+
+    fn leaves(n: usize) -> Vec<Hash> {
+        (0..n).map(|i| leaf_hash(format!("leaf {}", i).as_bytes())).collect()
+    }
+
+    #[test]
+    fn hashes_leaves_and_nodes_with_rfc_6962_prefixes() {
+        let mut leaf = Sha256::new();
+        leaf.update([0x00, b'a']);
+        assert_eq!(leaf_hash(b"a"), <Hash>::from(leaf.finalize()));
+
+        let (left, right) = (leaf_hash(b"l"), leaf_hash(b"r"));
+        let mut node = Sha256::new();
+        node.update([0x01]);
+        node.update(left);
+        node.update(right);
+        assert_eq!(node_hash(&left, &right), <Hash>::from(node.finalize()));
+        assert_ne!(node_hash(&left, &right), node_hash(&right, &left));
+    }
+
+    #[test]
+    fn matches_reference_roots() {
+        let leaves = reference_leaves();
+        let roots = [
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+            "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+            "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+            "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+        ];
+        assert_eq!(merkle_root(&[]), hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"));
+        for (n, root) in roots.iter().enumerate() {
+            assert_eq!(merkle_root(&leaves[..n + 1]), hex(root), "tree of {} leaves", n + 1);
+        }
+    }
+
+    #[test]
+    fn matches_reference_inclusion_proofs() {
+        let leaves = reference_leaves();
+        let cases: [(usize, usize, &[&str]); 5] = [
+            (0, 1, &[]),
+            (0, 8, &[
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ]),
+            (5, 8, &[
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ]),
+            (2, 3, &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"]),
+            (1, 5, &[
+                "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ]),
+        ];
+
+        for (index, size, expected) in cases {
+            let path = inclusion_path(index, &leaves[..size]);
+            assert_eq!(path, expected.iter().map(|h| hex(h)).collect::<Vec<_>>(), "leaf {} of {}", index, size);
+            assert!(verify_inclusion(index as u64, size as u64, &leaves[index], &path, &merkle_root(&leaves[..size])));
+        }
+    }
+
+    #[test]
+    fn matches_reference_consistency_proofs() {
+        let leaves = reference_leaves();
+        let cases: [(usize, usize, &[&str]); 4] = [
+            (1, 1, &[]),
+            (1, 8, &[
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ]),
+            (6, 8, &[
+                "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ]),
+            (2, 5, &[
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ]),
+        ];
+
+        for (first, second, expected) in cases {
+            let proof = consistency_path(first, &leaves[..second]);
+            assert_eq!(proof, expected.iter().map(|h| hex(h)).collect::<Vec<_>>(), "{} to {}", first, second);
+            let (first_root, second_root) = (merkle_root(&leaves[..first]), merkle_root(&leaves[..second]));
+            assert!(verify_consistency(first as u64, second as u64, &first_root, &second_root, &proof));
+        }
+    }
+
+    #[test]
+    fn inclusion_proofs_round_trip_and_reject_tampering() {
+        for n in 1..=10 {
+            let leaves = leaves(n);
+            let root = merkle_root(&leaves);
+            let size = n as u64;
+
+            for m in 0..n {
+                let index = m as u64;
+                let path = inclusion_path(m, &leaves);
+                assert!(verify_inclusion(index, size, &leaves[m], &path, &root), "leaf {} of {}", m, n);
+
+                assert!(!verify_inclusion(index, size, &tampered(&leaves[m]), &path, &root));
+                assert!(!verify_inclusion(index, size, &leaves[m], &path, &tampered(&root)));
+                for i in 0..path.len() {
+                    let mut bad_path = path.clone();
+                    bad_path[i] = tampered(&bad_path[i]);
+                    assert!(!verify_inclusion(index, size, &leaves[m], &bad_path, &root), "leaf {} of {}, node {}", m, n, i);
+                }
+                if !path.is_empty() {
+                    assert!(!verify_inclusion(index, size, &leaves[m], &path[..path.len() - 1], &root));
+                }
+                let mut long_path = path.clone();
+                long_path.push(root);
+                assert!(!verify_inclusion(index, size, &leaves[m], &long_path, &root));
+
+                // Wrong positions and sizes
+                if m + 1 < n {
+                    assert!(!verify_inclusion(index + 1, size, &leaves[m], &path, &root));
+                }
+                assert!(!verify_inclusion(index, index, &leaves[m], &path, &root));
+                assert!(!verify_inclusion(index, size * 2, &leaves[m], &path, &root));
+            }
+        }
+    }
+
+    #[test]
+    fn consistency_proofs_round_trip_and_reject_tampering() {
+        for n in 1..=10 {
+            let leaves = leaves(n);
+            let second_root = merkle_root(&leaves);
+            let second = n as u64;
+
+            for m in 1..=n {
+                let first_root = merkle_root(&leaves[..m]);
+                let first = m as u64;
+                let proof = consistency_path(m, &leaves);
+                assert!(verify_consistency(first, second, &first_root, &second_root, &proof), "{} to {}", m, n);
+
+                assert!(!verify_consistency(first, second, &tampered(&first_root), &second_root, &proof));
+                assert!(!verify_consistency(first, second, &first_root, &tampered(&second_root), &proof));
+                for i in 0..proof.len() {
+                    let mut bad_proof = proof.clone();
+                    bad_proof[i] = tampered(&bad_proof[i]);
+                    assert!(!verify_consistency(first, second, &first_root, &second_root, &bad_proof), "{} to {}, node {}", m, n, i);
+                }
+
+                if m < n {
+                    assert!(!verify_consistency(first, second, &first_root, &second_root, &proof[..proof.len() - 1]));
+                    let mut long_proof = proof.clone();
+                    long_proof.push(second_root);
+                    assert!(!verify_consistency(first, second, &first_root, &second_root, &long_proof));
+
+                    // Swapped or wrong sizes
+                    assert!(!verify_consistency(second, first, &first_root, &second_root, &proof));
+                    assert!(!verify_consistency(first, second, &second_root, &first_root, &proof));
+                    assert!(!verify_consistency(first, second * 2, &first_root, &second_root, &proof));
+                }
+            }
+            assert!(!verify_consistency(0, second, &second_root, &second_root, &[]));
+        }
+    }
+
+    #[test]
+    fn frontier_appends_reproduce_the_full_root() {
+        let leaves = leaves(33);
+        let mut frontier = Vec::new();
+        assert_eq!(frontier_root(&frontier), merkle_root(&[]));
+
+        for (size, leaf) in leaves.iter().enumerate() {
+            append_to_frontier(&mut frontier, size as u64, *leaf);
+            let n = size + 1;
+            assert_eq!(frontier.len(), n.count_ones() as usize, "tree of {} leaves", n);
+            assert_eq!(frontier_root(&frontier), merkle_root(&leaves[..n]), "tree of {} leaves", n);
+            assert_eq!(frontier, frontier_of(&leaves[..n]));
+            assert_eq!(decode_frontier(&encode_frontier(&frontier)).unwrap(), frontier);
+        }
+        assert!(decode_frontier("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn signed_tree_heads_verify_only_as_signed() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = encode_config(signing_key.verifying_key().as_bytes(), STANDARD);
+        let root = merkle_root(&leaves(3));
+
+        let tree_head = sign_tree_head(&signing_key, 3, &root);
+        assert!(tree_head.verify(&public_key).is_ok());
+        assert_eq!(tree_head.root().unwrap(), root);
+
+        let other_key = encode_config(SigningKey::from_bytes(&[8; 32]).verifying_key().as_bytes(), STANDARD);
+        assert!(tree_head.verify(&other_key).is_err());
+
+        let mut wrong_size = tree_head.clone();
+        wrong_size.tree_size = 4;
+        assert!(wrong_size.verify(&public_key).is_err());
+
+        let mut wrong_root = tree_head.clone();
+        wrong_root.root_hash = encode_hash(&tampered(&root));
+        assert!(wrong_root.verify(&public_key).is_err());
+
+        let mut wrong_time = tree_head.clone();
+        wrong_time.signed_at = "1970-01-01T00:00:00+00:00".to_string();
+        assert!(wrong_time.verify(&public_key).is_err());
+
+        assert!(tree_head.verify("not a key").is_err());
+    }
+}
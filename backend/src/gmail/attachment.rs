@@ -0,0 +1,64 @@
+use base64::{decode_config, URL_SAFE, URL_SAFE_NO_PAD};
+use serde::Deserialize;
+
+use super::{quota, GmailClient, GmailError, GmailMessage, GmailPart, GMAIL_API};
+
+// Answer to users.messages.attachments.get: the content, base64url encoded
+#[derive(Debug, Deserialize)]
+struct GmailAttachmentBody {
+    data: Option<String>,
+}
+
+impl GmailClient {
+    // Fetch the content of a part Gmail left out of the message, by the attachment id the payload gives it
+    pub async fn get_attachment(
+        &self,
+        user_id: &str,
+        access_token: &str,
+        message_id: &str,
+        attachment_id: &str,
+    ) -> Result<Vec<u8>, GmailError> {
+        let url = format!("{}/messages/{}/attachments/{}", GMAIL_API, message_id, attachment_id);
+
+        println!("Fetching attachment of Gmail message {}", message_id);
+
+        let request = self.http_client
+            .get(&url)
+            .bearer_auth(access_token);
+        let response = self.send(user_id, quota::ATTACHMENTS_GET, request).await?;
+        let status = response.status().as_u16();
+        let attachment: GmailAttachmentBody = Self::read_json(response).await?;
+
+        let data = decode_body_data(attachment.data.as_deref().unwrap_or_default())
+            .ok_or_else(|| GmailError::Parse { status, details: "attachment data is not base64url".to_string() })?;
+        println!("Fetched {} bytes of attachment for Gmail message {}", data.len(), message_id);
+        Ok(data)
+    }
+}
+
+// The content of a part that came with the message itself; Gmail only gives small parts no attachment id
+pub fn part_data(message: &GmailMessage, part_id: &str) -> Option<Vec<u8>> {
+    let parts = message.payload.as_ref()?.parts.as_deref()?;
+    find_part(parts, part_id)?
+        .body
+        .as_ref()
+        .and_then(|body| body.data.as_deref())
+        .and_then(decode_body_data)
+}
+
+fn find_part<'a>(parts: &'a [GmailPart], part_id: &str) -> Option<&'a GmailPart> {
+    parts.iter().find_map(|part| {
+        if part.part_id.as_deref() == Some(part_id) {
+            Some(part)
+        } else {
+            find_part(part.parts.as_deref().unwrap_or_default(), part_id)
+        }
+    })
+}
+
+// Gmail sends body data base64url encoded, with or without padding
+fn decode_body_data(data: &str) -> Option<Vec<u8>> {
+    decode_config(data, URL_SAFE)
+        .or_else(|_| decode_config(data.trim_end_matches('='), URL_SAFE_NO_PAD))
+        .ok()
+}
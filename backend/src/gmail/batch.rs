@@ -0,0 +1,185 @@
+use reqwest::{header::CONTENT_TYPE, StatusCode};
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::quota::{self, Call};
+use crate::mime::{parse_header_value, parse_headers, split_head, split_multipart, MimePart};
+use super::{GmailClient, GmailError, GmailMessage};
+
+const BATCH_URL: &str = "https://gmail.googleapis.com/batch/gmail/v1";
+
+// Most calls Google accepts in one batch request
+const MAX_BATCH_SIZE: usize = 100;
+
+// One message of a batch: its id and what Gmail answered for it
+#[derive(Debug)]
+pub struct BatchedMessage {
+    pub message_id: String,
+    pub result: Result<GmailMessage, GmailError>,
+}
+
+impl GmailClient {
+    // Fetch many messages with one multipart/mixed batch request per MAX_BATCH_SIZE ids.
+    // Each message gets its own result, so one missing or throttled message does not fail the others;
+    // Err is only returned when a whole batch request fails. Results come back in the order of message_ids.
+    pub async fn get_messages_batch(
+        &self,
+        user_id: &str,
+        access_token: &str,
+        message_ids: &[String],
+    ) -> Result<Vec<BatchedMessage>, GmailError> {
+        let mut results = Vec::with_capacity(message_ids.len());
+        for chunk in message_ids.chunks(MAX_BATCH_SIZE) {
+            results.extend(self.send_batch(user_id, access_token, chunk).await?);
+        }
+        Ok(results)
+    }
+
+    async fn send_batch(
+        &self,
+        user_id: &str,
+        access_token: &str,
+        message_ids: &[String],
+    ) -> Result<Vec<BatchedMessage>, GmailError> {
+        let boundary = format!("batch_{}", Uuid::new_v4().simple());
+        let mut body = String::new();
+        for (index, message_id) in message_ids.iter().enumerate() {
+            body.push_str(&format!(
+                "--{}\r\nContent-Type: application/http\r\nContent-ID: <item-{}>\r\n\r\nGET /gmail/v1/users/me/messages/{}\r\n\r\n",
+                boundary, index, message_id
+            ));
+        }
+        body.push_str(&format!("--{}--\r\n", boundary));
+
+        println!("Fetching {} Gmail messages in one batch", message_ids.len());
+
+        let request = self.http_client
+            .post(BATCH_URL)
+            .bearer_auth(access_token)
+            .header(CONTENT_TYPE, format!("multipart/mixed; boundary={}", boundary))
+            .body(body);
+        let response = self.send(user_id, Call::batch_of(quota::MESSAGES_GET, message_ids.len()), request).await?;
+
+        let status = response.status().as_u16();
+        let response_boundary = response.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_header_value(value).param("boundary").map(str::to_string))
+            .ok_or_else(|| GmailError::Parse { status, details: "batch response is not multipart".to_string() })?;
+        let bytes = response.bytes().await?;
+
+        // Parts may come back in any order; Content-ID ties each one to its request
+        let mut answers: Vec<Option<Result<GmailMessage, GmailError>>> = message_ids.iter().map(|_| None).collect();
+        for part in split_multipart(&bytes, &response_boundary) {
+            let (index, result) = parse_part(part);
+            if let Some(slot) = index.and_then(|index| answers.get_mut(index)) {
+                *slot = Some(result);
+            }
+        }
+
+        let results: Vec<BatchedMessage> = message_ids.iter()
+            .zip(answers)
+            .map(|(message_id, answer)| BatchedMessage {
+                message_id: message_id.clone(),
+                result: answer.unwrap_or_else(|| Err(GmailError::Parse {
+                    status,
+                    details: format!("no answer for message {} in batch response", message_id),
+                })),
+            })
+            .collect();
+
+        // Same as for a single call: a rejected token must not be reused
+        if results.iter().any(|r| matches!(r.result, Err(GmailError::AuthExpired(_)) | Err(GmailError::AuthRevoked(_)))) {
+            self.token_cache.write().await.remove(user_id);
+        }
+
+        Ok(results)
+    }
+}
+
+// One part holds an embedded HTTP response; its Content-ID is "response-item-N" for request "item-N"
+fn parse_part(part: &[u8]) -> (Option<usize>, Result<GmailMessage, GmailError>) {
+    let part = MimePart::parse(part);
+    let index = part.content_id()
+        .and_then(|id| id.rsplit('-').next().and_then(|index| index.parse().ok()));
+
+    let (response_head, body) = split_head(&part.body);
+    let response_headers = parse_headers(response_head);
+    let body = String::from_utf8_lossy(body);
+    let body = body.trim_end_matches(['\r', '\n']);
+    let status = String::from_utf8_lossy(response_head)
+        .lines()
+        .next()
+        .and_then(|status_line| status_line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok());
+
+    let result = match status {
+        Some(status) if status.is_success() => serde_json::from_str(body)
+            .map_err(|e| GmailError::Parse { status: status.as_u16(), details: e.to_string() }),
+        Some(status) => {
+            let retry_after = response_headers.iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("Retry-After"))
+                .and_then(|(_, value)| value.parse().ok())
+                .map(Duration::from_secs);
+            Err(GmailError::from_status(status, body, retry_after))
+        }
+        None => Err(GmailError::Parse { status: 0, details: "batch part without an HTTP status line".to_string() }),
+    };
+    (index, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(content_id: &str, response: &str) -> String {
+        format!("Content-Type: application/http\r\nContent-ID: <{}>\r\n\r\n{}", content_id, response)
+    }
+
+    #[test]
+    fn maps_content_ids_to_request_indexes() {
+        let body = format!(
+            "--batch_x\r\n{}\r\n--batch_x\r\n{}\r\n--batch_x--\r\n",
+            answer("response-item-1", "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n{\"id\":\"m1\",\"threadId\":\"t1\"}\r\n"),
+            answer("response-item-0", "HTTP/1.1 404 Not Found\r\n\r\n{\"error\":{\"code\":404,\"message\":\"Not Found\"}}\r\n"),
+        );
+        let parts: Vec<_> = split_multipart(body.as_bytes(), "batch_x").into_iter().map(parse_part).collect();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].0, Some(1));
+        assert_eq!(parts[0].1.as_ref().map(|message| message.id.as_str()).ok(), Some("m1"));
+        assert_eq!(parts[1].0, Some(0));
+        assert!(matches!(parts[1].1, Err(GmailError::NotFound(_))));
+    }
+
+    #[test]
+    fn reads_retry_after_of_a_throttled_part() {
+        let part = answer("response-item-7", "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 30\r\n\r\n{}");
+        let (index, result) = parse_part(part.as_bytes());
+        assert_eq!(index, Some(7));
+        assert!(matches!(
+            result,
+            Err(GmailError::QuotaExceeded { retry_after: Some(retry_after), .. }) if retry_after == Duration::from_secs(30)
+        ));
+    }
+
+    #[test]
+    fn reports_malformed_parts() {
+        // No Content-ID, so the answer cannot be tied to a request
+        let (index, _) = parse_part(b"Content-Type: application/http\r\n\r\nHTTP/1.1 200 OK\r\n\r\n{}");
+        assert_eq!(index, None);
+
+        let (index, result) = parse_part(answer("response-item-x", "HTTP/1.1 200 OK\r\n\r\n{}").as_bytes());
+        assert_eq!(index, None);
+        assert!(matches!(result, Err(GmailError::Parse { status: 200, .. })));
+
+        // Cut off before the JSON ends
+        let (index, result) = parse_part(answer("response-item-2", "HTTP/1.1 200 OK\r\n\r\n{\"id\":\"m").as_bytes());
+        assert_eq!(index, Some(2));
+        assert!(matches!(result, Err(GmailError::Parse { status: 200, .. })));
+
+        let (_, result) = parse_part(answer("response-item-3", "").as_bytes());
+        assert!(matches!(result, Err(GmailError::Parse { status: 0, .. })));
+    }
+}
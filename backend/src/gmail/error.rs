@@ -0,0 +1,197 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::time::Duration;
+
+// Where the frontend sends users whose Google grant no longer works
+pub const REAUTH_PATH: &str = "/auth/google/link";
+
+// Status and error details of a failed Google API call
+#[derive(Debug, Clone)]
+pub struct GoogleApiError {
+    // None when the call never reached Google, e.g. the stored grant could not be decrypted
+    pub status: Option<u16>,
+    // Google's machine-readable reason, e.g. "rateLimitExceeded" or "invalid_grant"
+    pub reason: Option<String>,
+    pub message: String,
+}
+
+impl GoogleApiError {
+    // Read Google's error body; the Gmail API and the OAuth token endpoint use different shapes
+    pub fn from_body(status: StatusCode, body: &str) -> Self {
+        #[derive(Deserialize)]
+        struct ApiErrorBody {
+            error: Value,
+            error_description: Option<String>,
+        }
+
+        let parsed = serde_json::from_str::<ApiErrorBody>(body).ok();
+        let (reason, message) = match parsed {
+            // {"error": {"code": 403, "message": "...", "errors": [{"reason": "..."}], "status": "..."}}
+            Some(ApiErrorBody { error: Value::Object(error), .. }) => {
+                let reason = error.get("errors")
+                    .and_then(|errors| errors.get(0))
+                    .and_then(|first| first.get("reason"))
+                    .or_else(|| error.get("status"))
+                    .and_then(Value::as_str)
+                    .map(str::to_string);
+                let message = error.get("message").and_then(Value::as_str).unwrap_or_default().to_string();
+                (reason, message)
+            }
+            // {"error": "invalid_grant", "error_description": "..."}
+            Some(ApiErrorBody { error: Value::String(error), error_description }) => {
+                (Some(error), error_description.unwrap_or_default())
+            }
+            _ => (None, body.chars().take(200).collect()),
+        };
+
+        GoogleApiError { status: Some(status.as_u16()), reason, message }
+    }
+
+    fn has_reason(&self, reasons: &[&str]) -> bool {
+        self.reason.as_deref().is_some_and(|reason| reasons.contains(&reason))
+    }
+}
+
+impl fmt::Display for GoogleApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(status) = self.status {
+            write!(f, "status {}", status)?;
+            if let Some(ref reason) = self.reason {
+                write!(f, " ({})", reason)?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum GmailError {
+    // The request never got an answer from Google
+    Transport(reqwest::Error),
+    // Google rejected the access token; it is dropped from the cache, so a retry refreshes it
+    AuthExpired(GoogleApiError),
+    // The refresh token was revoked or cannot be used; the user has to grant access again
+    AuthRevoked(GoogleApiError),
+    QuotaExceeded { error: GoogleApiError, retry_after: Option<Duration> },
+    NotFound(GoogleApiError),
+    InvalidRequest(GoogleApiError),
+    // Google failed on its side
+    Unavailable(GoogleApiError),
+    // Google answered with something we could not read
+    Parse { status: u16, details: String },
+}
+
+impl GmailError {
+    // Classify a non-success response from a Gmail API or OAuth endpoint
+    pub fn from_status(status: StatusCode, body: &str, retry_after: Option<Duration>) -> Self {
+        let error = GoogleApiError::from_body(status, body);
+        match status {
+            StatusCode::UNAUTHORIZED => GmailError::AuthExpired(error),
+            StatusCode::BAD_REQUEST if error.has_reason(&["invalid_grant"]) => GmailError::AuthRevoked(error),
+            StatusCode::FORBIDDEN if error.has_reason(&["insufficientPermissions", "ACCESS_TOKEN_SCOPE_INSUFFICIENT"]) => {
+                GmailError::AuthRevoked(error)
+            }
+            StatusCode::TOO_MANY_REQUESTS => GmailError::QuotaExceeded { error, retry_after },
+            StatusCode::FORBIDDEN if error.has_reason(&["rateLimitExceeded", "userRateLimitExceeded", "quotaExceeded", "dailyLimitExceeded"]) => {
+                GmailError::QuotaExceeded { error, retry_after }
+            }
+            StatusCode::NOT_FOUND => GmailError::NotFound(error),
+            status if status.is_server_error() => GmailError::Unavailable(error),
+            _ => GmailError::InvalidRequest(error),
+        }
+    }
+
+    // The stored grant is unusable without Google having said so, e.g. it cannot be decrypted
+    pub fn unusable_grant(details: impl fmt::Display) -> Self {
+        GmailError::AuthRevoked(GoogleApiError {
+            status: None,
+            reason: None,
+            message: details.to_string(),
+        })
+    }
+
+    // Short machine-readable reason for the frontend
+    pub fn reason(&self) -> &'static str {
+        match self {
+            GmailError::Transport(_) => "gmail_unreachable",
+            GmailError::AuthExpired(_) => "gmail_auth_expired",
+            GmailError::AuthRevoked(_) => "gmail_reauth_required",
+            GmailError::QuotaExceeded { .. } => "gmail_quota_exceeded",
+            GmailError::NotFound(_) => "gmail_not_found",
+            GmailError::InvalidRequest(_) => "gmail_invalid_request",
+            GmailError::Unavailable(_) => "gmail_unavailable",
+            GmailError::Parse { .. } => "gmail_bad_response",
+        }
+    }
+
+    // Google's status code, when Google answered at all
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            GmailError::Transport(e) => e.status().map(|s| s.as_u16()),
+            GmailError::AuthExpired(e)
+            | GmailError::AuthRevoked(e)
+            | GmailError::QuotaExceeded { error: e, .. }
+            | GmailError::NotFound(e)
+            | GmailError::InvalidRequest(e)
+            | GmailError::Unavailable(e) => e.status,
+            GmailError::Parse { status, .. } => Some(*status),
+        }
+    }
+}
+
+impl fmt::Display for GmailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GmailError::Transport(e) => write!(f, "Could not reach Gmail: {}", e),
+            GmailError::AuthExpired(e) => write!(f, "Gmail rejected the access token: {}", e),
+            GmailError::AuthRevoked(e) => write!(f, "Gmail access was revoked; sign in with Google again: {}", e),
+            GmailError::QuotaExceeded { error, .. } => write!(f, "Gmail quota exceeded: {}", error),
+            GmailError::NotFound(e) => write!(f, "Not found in Gmail: {}", e),
+            GmailError::InvalidRequest(e) => write!(f, "Gmail rejected the request: {}", e),
+            GmailError::Unavailable(e) => write!(f, "Gmail is unavailable: {}", e),
+            GmailError::Parse { status, details } => write!(f, "Unexpected Gmail response (status {}): {}", status, details),
+        }
+    }
+}
+
+impl From<reqwest::Error> for GmailError {
+    fn from(e: reqwest::Error) -> Self {
+        GmailError::Transport(e)
+    }
+}
+
+impl ResponseError for GmailError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            GmailError::AuthRevoked(_) => StatusCode::FORBIDDEN,
+            GmailError::QuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+            GmailError::NotFound(_) => StatusCode::NOT_FOUND,
+            GmailError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            GmailError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            GmailError::Transport(_) | GmailError::AuthExpired(_) | GmailError::Parse { .. } => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = json!({
+            "success": false,
+            "error": self.to_string(),
+            "reason": self.reason(),
+            "gmail_status": self.status(),
+        });
+        let mut response = HttpResponse::build(self.status_code());
+
+        match self {
+            GmailError::AuthRevoked(_) => body["reauth_url"] = json!(REAUTH_PATH),
+            GmailError::QuotaExceeded { retry_after: Some(retry_after), .. } => {
+                body["retry_after_seconds"] = json!(retry_after.as_secs());
+                response.insert_header(("Retry-After", retry_after.as_secs().to_string()));
+            }
+            _ => {}
+        }
+        response.json(body)
+    }
+}
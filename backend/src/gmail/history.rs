@@ -0,0 +1,138 @@
+use serde::Deserialize;
+
+use super::{quota, GmailClient, GmailError, GMAIL_API};
+
+// Largest page users.history.list hands out
+const HISTORY_PAGE_SIZE: usize = 500;
+
+// The mailbox changes incremental sync cares about
+const HISTORY_TYPES: [&str; 4] = ["messageAdded", "messageDeleted", "labelAdded", "labelRemoved"];
+
+// Gmail profile; its historyId marks the point a full sync is current as of
+#[derive(Debug, Deserialize)]
+pub struct GmailProfile {
+    #[serde(rename = "historyId")]
+    pub history_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryListResponse {
+    history: Option<Vec<HistoryRecord>>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+    #[serde(rename = "historyId")]
+    history_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryRecord {
+    #[serde(rename = "messagesAdded", default)]
+    messages_added: Vec<HistoryMessage>,
+    #[serde(rename = "messagesDeleted", default)]
+    messages_deleted: Vec<HistoryMessage>,
+    #[serde(rename = "labelsAdded", default)]
+    labels_added: Vec<HistoryLabels>,
+    #[serde(rename = "labelsRemoved", default)]
+    labels_removed: Vec<HistoryLabels>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryMessage {
+    message: HistoryMessageRef,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryLabels {
+    message: HistoryMessageRef,
+    #[serde(rename = "labelIds", default)]
+    label_ids: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryMessageRef {
+    id: String,
+}
+
+// One change to the mailbox, in the order Gmail recorded it
+#[derive(Debug, Clone)]
+pub enum MailboxChange {
+    MessageAdded(String),
+    MessageDeleted(String),
+    LabelsAdded { message_id: String, label_ids: Vec<String> },
+    LabelsRemoved { message_id: String, label_ids: Vec<String> },
+}
+
+// Everything that happened since a historyId, and the historyId it brings the mailbox to
+#[derive(Debug, Clone)]
+pub struct MailboxHistory {
+    pub changes: Vec<MailboxChange>,
+    pub history_id: String,
+}
+
+impl GmailClient {
+    // The user's Gmail profile, including the mailbox's current historyId
+    pub async fn get_profile(&self, user_id: &str, access_token: &str) -> Result<GmailProfile, GmailError> {
+        let url = format!("{}/profile", GMAIL_API);
+
+        let request = self.http_client
+            .get(&url)
+            .bearer_auth(access_token);
+        Self::read_json(self.send(user_id, quota::GET_PROFILE, request).await?).await
+    }
+
+    // Every change recorded after start_history_id, following nextPageToken to the end.
+    // Gmail only keeps about a week of history; an id older than that fails with GmailError::NotFound
+    // and the caller has to fall back to a full sync.
+    pub async fn list_history(
+        &self,
+        user_id: &str,
+        access_token: &str,
+        start_history_id: &str,
+    ) -> Result<MailboxHistory, GmailError> {
+        let url = format!("{}/history", GMAIL_API);
+
+        let mut changes = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut params = vec![
+                ("startHistoryId", start_history_id.to_string()),
+                ("maxResults", HISTORY_PAGE_SIZE.to_string()),
+            ];
+            for history_type in HISTORY_TYPES {
+                params.push(("historyTypes", history_type.to_string()));
+            }
+            if let Some(ref token) = page_token {
+                params.push(("pageToken", token.clone()));
+            }
+
+            println!("Fetching Gmail history: {} since {}", url, start_history_id);
+
+            let request = self.http_client
+                .get(&url)
+                .bearer_auth(access_token)
+                .query(&params);
+            let response: HistoryListResponse = Self::read_json(self.send(user_id, quota::HISTORY_LIST, request).await?).await?;
+
+            for record in response.history.unwrap_or_default() {
+                changes.extend(record.messages_added.into_iter()
+                    .map(|added| MailboxChange::MessageAdded(added.message.id)));
+                changes.extend(record.labels_added.into_iter()
+                    .map(|change| MailboxChange::LabelsAdded { message_id: change.message.id, label_ids: change.label_ids }));
+                changes.extend(record.labels_removed.into_iter()
+                    .map(|change| MailboxChange::LabelsRemoved { message_id: change.message.id, label_ids: change.label_ids }));
+                changes.extend(record.messages_deleted.into_iter()
+                    .map(|deleted| MailboxChange::MessageDeleted(deleted.message.id)));
+            }
+
+            match response.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => {
+                    return Ok(MailboxHistory {
+                        changes,
+                        history_id: response.history_id,
+                    });
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,35 @@
-use reqwest::{Client, Error as ReqwestError};
-use serde::{Deserialize, Serialize};
+use reqwest::{header::RETRY_AFTER, Client, RequestBuilder, Response};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::sync::RwLock;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use serde_json::json;
+use crate::config::GoogleConfig;
 use crate::models::GmailLabel;
+use crate::encryption::refresh_token::open_refresh_token;
+use crate::mime::{decode_encoded_words, MessageBody, MimePart};
+use base64::{decode_config, URL_SAFE};
+use quota::{Call, QuotaLimiter};
+
+mod attachment;
+mod batch;
+mod error;
+mod history;
+mod pagination;
+mod quota;
+mod thread;
+mod watch;
+
+pub use attachment::part_data;
+pub use error::{GmailError, GoogleApiError};
+pub use history::MailboxChange;
+pub use pagination::MessageListQuery;
+pub use quota::{in_background, QuotaMetricsSnapshot};
+
+// Every call addresses the mailbox the access token was granted for, which need not be the login
+// email; that email only keys the token cache and the quota
+const GMAIL_API: &str = "https://gmail.googleapis.com/gmail/v1/users/me";
 
 // Gmail API token response
 #[derive(Debug, Deserialize)]
@@ -114,19 +138,111 @@ pub struct TokenCache {
 pub struct GmailClient {
     http_client: Client,
     token_cache: RwLock<HashMap<String, TokenCache>>,
+    client_id: String,
+    client_secret: String,
+    // Upper bound on a listing that does not set its own
+    max_listed_messages: usize,
+    // Paces each user's calls to their share of the Gmail quota
+    quota: QuotaLimiter,
 }
 
 impl GmailClient {
-    // Create a new Gmail client
-    pub fn new() -> Self {
+    // Create a new Gmail client using the Google OAuth app's credentials
+    pub fn new(google: &GoogleConfig) -> Self {
         Self {
             http_client: Client::new(),
             token_cache: RwLock::new(HashMap::new()),
+            client_id: google.client_id.clone(),
+            client_secret: google.client_secret.expose().to_string(),
+            max_listed_messages: google.max_listed_messages,
+            quota: QuotaLimiter::new(google.quota_units_per_second),
         }
     }
 
-    // Get token for Gmail API
-    pub async fn get_token(&self, user_id: &str, refresh_token: &str) -> Result<String, ReqwestError> {
+    // Throttling and retry counters since startup
+    pub fn quota_metrics(&self) -> QuotaMetricsSnapshot {
+        self.quota.snapshot()
+    }
+
+    // Send a request to a Google endpoint once the user's quota allows it, retrying rate limits and
+    // transient failures with jittered exponential backoff. Calls made inside in_background() wait
+    // longer and leave part of the quota to interactive requests.
+    async fn send(&self, user_id: &str, call: Call, request: RequestBuilder) -> Result<Response, GmailError> {
+        let priority = quota::current_priority();
+        let mut attempt = 0;
+        let mut backed_off = Duration::ZERO;
+        loop {
+            self.quota.acquire(user_id, call, priority).await;
+            attempt += 1;
+
+            // Our bodies are always in memory; a streaming one could not be replayed and is sent once
+            let attempt_request = match request.try_clone() {
+                Some(attempt_request) => attempt_request,
+                None => return self.send_once(user_id, request).await,
+            };
+            let error = match self.send_once(user_id, attempt_request).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+
+            let delay = match quota::retry_delay(&error, call, priority, attempt, backed_off) {
+                Some(delay) => delay,
+                None => {
+                    if attempt > 1 {
+                        self.quota.record_retries_exhausted();
+                    }
+                    return Err(error);
+                }
+            };
+            if let GmailError::QuotaExceeded { .. } = error {
+                // Google throttles the whole user, so hold their other calls as well
+                self.quota.pause(user_id, delay);
+            }
+            self.quota.record_retry();
+            println!("Retrying Gmail call for {} in {} ms (attempt {})", user_id, delay.as_millis(), attempt + 1);
+            tokio::time::sleep(delay).await;
+            backed_off += delay;
+        }
+    }
+
+    // One attempt at a request, turning a failure status into a GmailError.
+    // A rejected access token is dropped from the cache so the next call refreshes it.
+    async fn send_once(&self, user_id: &str, request: RequestBuilder) -> Result<Response, GmailError> {
+        let response = request.send().await?;
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        let status = response.status();
+        let retry_after = response.headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs);
+        let body = response.text().await.unwrap_or_default();
+        let error = GmailError::from_status(status, &body, retry_after);
+
+        match error {
+            GmailError::AuthExpired(_) | GmailError::AuthRevoked(_) => {
+                self.token_cache.write().await.remove(user_id);
+            }
+            GmailError::QuotaExceeded { .. } => self.quota.record_rate_limited(),
+            _ => {}
+        }
+        println!("Gmail API error for {}: {}", user_id, error);
+        Err(error)
+    }
+
+    // Parse a successful response body
+    async fn read_json<T: DeserializeOwned>(response: Response) -> Result<T, GmailError> {
+        let status = response.status().as_u16();
+        let text = response.text().await?;
+        serde_json::from_str(&text).map_err(|e| GmailError::Parse { status, details: e.to_string() })
+    }
+
+    // Get token for Gmail API.
+    // Takes the sealed refresh token as stored in the database; it is only decrypted here.
+    pub async fn get_token(&self, user_id: &str, sealed_refresh_token: &str) -> Result<String, GmailError> {
         // Check cache first
         {
             let token_cache = self.token_cache.read().await;
@@ -138,205 +254,89 @@ impl GmailClient {
             }
         }
 
-        // Get new token
-        let client_id = std::env::var("GOOGLE_CLIENT_ID").expect("GOOGLE_CLIENT_ID must be set");
-        let client_secret = std::env::var("GOOGLE_CLIENT_SECRET").expect("GOOGLE_CLIENT_SECRET must be set");
+        let refresh_token = open_refresh_token(sealed_refresh_token, user_id).map_err(|e| {
+            println!("Could not decrypt stored refresh token for {}: {}", user_id, e);
+            GmailError::unusable_grant(e)
+        })?;
 
-        println!("Refreshing token for user {} with refresh_token {}", user_id, refresh_token);
+        // Get new token
+        println!("Refreshing access token for user {}", user_id);
 
         let params = [
-            ("client_id", client_id.clone()),
-            ("client_secret", client_secret.clone()),
-            ("refresh_token", refresh_token.to_string()),
+            ("client_id", self.client_id.clone()),
+            ("client_secret", self.client_secret.clone()),
+            ("refresh_token", refresh_token),
             ("grant_type", "refresh_token".to_string()),
         ];
 
-        // Debug output
-        println!("Token refresh request - URL: https://oauth2.googleapis.com/token");
-        println!("Token refresh params - client_id: {}, refresh_token: {}", client_id, refresh_token);
-
-        let response = self.http_client
+        let request = self.http_client
             .post("https://oauth2.googleapis.com/token")
-            .form(&params)
-            .send()
-            .await?;
-        
-        // Check for error status
-        if !response.status().is_success() {
-            let status = response.status();
-            let error = response.text().await.unwrap_or_else(|_| "Could not get error text".to_string());
-            println!("Token refresh error: Status {} - {}", status, error);
-            // Create a dummy request that will fail to generate a ReqwestError
-            return Err(self.http_client.get("error://example.com").send().await.unwrap_err());
-        } else {
-            let token_response = response.json::<TokenResponse>().await?;
-
-            // Cache the token
-            let expires_at = Instant::now() + Duration::from_secs(token_response.expires_in as u64 - 300); // 5 min buffer
-            let access_token = token_response.access_token.clone();
-
-            let mut token_cache = self.token_cache.write().await;
-            token_cache.insert(
-                user_id.to_string(),
-                TokenCache {
-                    access_token: access_token.clone(),
-                    expires_at,
-                },
-            );
-
-            println!("Successfully refreshed token for {}", user_id);
-            Ok(access_token)
-        }
-    }
-
-    // Get emails from Gmail
-    pub async fn get_messages(&self, user_id: &str, access_token: &str, query: Option<&str>) -> Result<Vec<GmailMessageId>, ReqwestError> {
-        let query_param = query.unwrap_or(""); 
-        let url = format!(
-            "https://gmail.googleapis.com/gmail/v1/users/{}/messages?q={}",
-            user_id, query_param
-        );
-        
-        println!("Fetching Gmail messages: {}", url);
-        
-        let response = match self.http_client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", access_token))
-            .send()
-            .await {
-                Ok(resp) => resp,
-                Err(e) => {
-                    println!("Error fetching messages: {}", e);
-                    return Err(e);
-                }
-            };
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Could not get error text".to_string());
-            println!("Gmail API error: Status {} - {}", status, error_text);
-            // Create a dummy request that will fail to generate a ReqwestError
-            return Err(self.http_client.get("error://example.com").send().await.unwrap_err());
-        }
-
-        // Get the response text first to debug
-        let response_text = response.text().await?;
-        println!("Gmail API response: {}", response_text);
-
-        // Then parse it
-        let response_data = match serde_json::from_str::<GmailMessageListResponse>(&response_text) {
-            Ok(data) => data,
-            Err(e) => {
-                println!("Error parsing message list response: {}", e);
-                // Create a dummy request that will fail to generate a ReqwestError
-                return Err(self.http_client.get("error://example.com").send().await.unwrap_err());
-            }
+            .form(&params);
+        let response = match self.send(user_id, quota::OAUTH, request).await {
+            Ok(response) => response,
+            // At the token endpoint a 401 means our client credentials are wrong, not the user's grant
+            Err(GmailError::AuthExpired(e)) => return Err(GmailError::InvalidRequest(e)),
+            Err(e) => return Err(e),
         };
+        let token_response: TokenResponse = Self::read_json(response).await?;
+
+        // Cache the token
+        let expires_at = Instant::now() + Duration::from_secs(token_response.expires_in as u64 - 300); // 5 min buffer
+        let access_token = token_response.access_token.clone();
+
+        let mut token_cache = self.token_cache.write().await;
+        token_cache.insert(
+            user_id.to_string(),
+            TokenCache {
+                access_token: access_token.clone(),
+                expires_at,
+            },
+        );
 
-        let messages = response_data.messages.unwrap_or_default();
-        println!("Successfully fetched {} Gmail messages", messages.len());
-        
-        Ok(messages)
+        println!("Successfully refreshed token for {}", user_id);
+        Ok(access_token)
     }
 
-    // Get emails from Gmail with a limit parameter
-    pub async fn get_messages_with_limit(
-        &self, 
-        user_id: &str, 
-        access_token: &str, 
-        query: Option<&str>, 
-        max_results: usize
-    ) -> Result<Vec<GmailMessageId>, ReqwestError> {
-        let query_param = query.unwrap_or(""); 
-        
-        // Build URL with maxResults parameter
-        let url = format!(
-            "https://gmail.googleapis.com/gmail/v1/users/{}/messages?q={}&maxResults={}",
-            user_id, query_param, max_results
-        );
-        
-        println!("Fetching Gmail messages with limit: {}", url);
-        
-        let response = match self.http_client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", access_token))
-            .send()
-            .await {
-                Ok(resp) => resp,
-                Err(e) => {
-                    println!("Error fetching messages: {}", e);
-                    return Err(e);
-                }
-            };
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Could not get error text".to_string());
-            println!("Gmail API error: Status {} - {}", status, error_text);
-            return Err(self.http_client.get("error://example.com").send().await.unwrap_err());
+    // Revoke the user's Google grant, so the refresh token and every access token from it stop working
+    pub async fn revoke_token(&self, user_id: &str, sealed_refresh_token: &str) -> Result<(), GmailError> {
+        self.token_cache.write().await.remove(user_id);
+
+        let refresh_token = open_refresh_token(sealed_refresh_token, user_id).map_err(|e| {
+            println!("Could not decrypt stored refresh token for {}: {}", user_id, e);
+            GmailError::unusable_grant(e)
+        })?;
+
+        let request = self.http_client
+            .post("https://oauth2.googleapis.com/revoke")
+            .form(&[("token", refresh_token)]);
+        match self.send(user_id, quota::OAUTH, request).await {
+            // Google answers 400 invalid_token when the grant is already gone
+            Ok(_) | Err(GmailError::InvalidRequest(GoogleApiError { status: Some(400), .. })) => {
+                println!("Revoked Google grant for {}", user_id);
+                Ok(())
+            }
+            Err(e) => Err(e),
         }
-
-        let response_data: GmailMessageListResponse = response.json().await?;
-        let messages = response_data.messages.unwrap_or_default();
-        
-        println!("Successfully fetched {} Gmail messages (limited to {})", messages.len(), max_results);
-        
-        Ok(messages)
     }
 
     // Get message details
-    pub async fn get_message_detail(&self, user_id: &str, access_token: &str, message_id: &str) -> Result<GmailMessage, ReqwestError> {
-        let url = format!(
-            "https://gmail.googleapis.com/gmail/v1/users/{}/messages/{}",
-            user_id, message_id
-        );
+    pub async fn get_message_detail(&self, user_id: &str, access_token: &str, message_id: &str) -> Result<GmailMessage, GmailError> {
+        let url = format!("{}/messages/{}", GMAIL_API, message_id);
         
         println!("Fetching Gmail message detail: {}", url);
 
-        let response = match self.http_client
+        let request = self.http_client
             .get(&url)
-            .header("Authorization", format!("Bearer {}", access_token))
-            .send()
-            .await {
-                Ok(resp) => resp,
-                Err(e) => {
-                    println!("Error fetching message detail: {}", e);
-                    return Err(e);
-                }
-            };
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Could not get error text".to_string());
-            println!("Gmail API error: Status {} - {}", status, error_text);
-            // Create a dummy request that will fail to generate a ReqwestError
-            return Err(self.http_client.get("error://example.com").send().await.unwrap_err());
-        }
-
-        // Get the response text first to debug
-        let response_text = response.text().await?;
-        println!("Gmail API message detail response: {}", response_text);
-
-        // Then parse it
-        let message = match serde_json::from_str::<GmailMessage>(&response_text) {
-            Ok(msg) => msg,
-            Err(e) => {
-                println!("Error parsing message detail response: {}", e);
-                // Create a dummy request that will fail to generate a ReqwestError
-                return Err(self.http_client.get("error://example.com").send().await.unwrap_err());
-            }
-        };
+            .bearer_auth(access_token);
+        let message: GmailMessage = Self::read_json(self.send(user_id, quota::MESSAGES_GET, request).await?).await?;
 
         println!("Successfully fetched Gmail message {}", message_id);
         Ok(message)
     }
 
     // Send a message
-    pub async fn send_message(&self, user_id: &str, access_token: &str, raw_message: String) -> Result<SendMessageResponse, ReqwestError> {
-        let url = format!(
-            "https://gmail.googleapis.com/gmail/v1/users/{}/messages/send",
-            user_id
-        );
+    pub async fn send_message(&self, user_id: &str, access_token: &str, raw_message: String) -> Result<SendMessageResponse, GmailError> {
+        let url = format!("{}/messages/send", GMAIL_API);
         
         println!("Sending Gmail message: {} with token length {}", url, access_token.len());
 
@@ -344,78 +344,26 @@ impl GmailClient {
             "raw": raw_message
         });
 
-        let response = match self.http_client
+        let request = self.http_client
             .post(&url)
-            .header("Authorization", format!("Bearer {}", access_token))
-            .json(&body)
-            .send()
-            .await {
-                Ok(resp) => resp,
-                Err(e) => {
-                    println!("Error sending message: {}", e);
-                    return Err(e);
-                }
-            };
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Could not get error text".to_string());
-            println!("Gmail API error when sending: Status {} - {}", status, error_text);
-            // Create a dummy request that will fail to generate a ReqwestError
-            return Err(self.http_client.get("error://example.com").send().await.unwrap_err());
-        }
-
-        let send_response = match response.json::<SendMessageResponse>().await {
-            Ok(resp) => resp,
-            Err(e) => {
-                println!("Error parsing send message response: {}", e);
-                return Err(e);
-            }
-        };
+            .bearer_auth(access_token)
+            .json(&body);
+        let send_response: SendMessageResponse = Self::read_json(self.send(user_id, quota::MESSAGES_SEND, request).await?).await?;
 
         println!("Successfully sent Gmail message with ID: {}", send_response.id);
         Ok(send_response)
     }
 
     // Get labels from Gmail
-    pub async fn get_labels(&self, user_id: &str, access_token: &str) -> Result<Vec<GmailLabel>, ReqwestError> {
-        let url = format!(
-            "https://gmail.googleapis.com/gmail/v1/users/{}/labels",
-            user_id
-        );
+    pub async fn get_labels(&self, user_id: &str, access_token: &str) -> Result<Vec<GmailLabel>, GmailError> {
+        let url = format!("{}/labels", GMAIL_API);
         
         println!("Fetching Gmail labels: {}", url);
         
-        let response = match self.http_client
+        let request = self.http_client
             .get(&url)
-            .header("Authorization", format!("Bearer {}", access_token))
-            .send()
-            .await {
-                Ok(resp) => resp,
-                Err(e) => {
-                    println!("Error fetching labels: {}", e);
-                    return Err(e);
-                }
-            };
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Could not get error text".to_string());
-            println!("Gmail API error: Status {} - {}", status, error_text);
-            // Create a dummy error to return
-            return Err(self.http_client.get("error://example.com").send().await.unwrap_err());
-        }
-        
-        let response_text = response.text().await?;
-        println!("Gmail API labels response: {}", response_text);
-        
-        let response_data = match serde_json::from_str::<GmailLabelListResponse>(&response_text) {
-            Ok(data) => data,
-            Err(e) => {
-                println!("Error parsing label list response: {}", e);
-                return Err(self.http_client.get("error://example.com").send().await.unwrap_err());
-            }
-        };
+            .bearer_auth(access_token);
+        let response_data: GmailLabelListResponse = Self::read_json(self.send(user_id, quota::LABELS_LIST, request).await?).await?;
         
         println!("Successfully fetched {} Gmail labels", response_data.labels.len());
         
@@ -430,11 +378,8 @@ impl GmailClient {
         message_id: &str,
         add_labels: &Vec<String>,
         remove_labels: &Vec<String>
-    ) -> Result<GmailMessage, ReqwestError> {
-        let url = format!(
-            "https://gmail.googleapis.com/gmail/v1/users/{}/messages/{}/modify",
-            user_id, message_id
-        );
+    ) -> Result<GmailMessage, GmailError> {
+        let url = format!("{}/messages/{}/modify", GMAIL_API, message_id);
         
         println!("Modifying Gmail message: {}", url);
         println!("Adding labels: {:?}, Removing labels: {:?}", add_labels, remove_labels);
@@ -444,36 +389,11 @@ impl GmailClient {
             "removeLabelIds": remove_labels
         });
         
-        let response = match self.http_client
+        let request = self.http_client
             .post(&url)
-            .header("Authorization", format!("Bearer {}", access_token))
-            .json(&body)
-            .send()
-            .await {
-                Ok(resp) => resp,
-                Err(e) => {
-                    println!("Error modifying message: {}", e);
-                    return Err(e);
-                }
-            };
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Could not get error text".to_string());
-            println!("Gmail API error: Status {} - {}", status, error_text);
-            return Err(self.http_client.get("error://example.com").send().await.unwrap_err());
-        }
-        
-        let response_text = response.text().await?;
-        println!("Gmail API modify message response: {}", response_text);
-        
-        let message = match serde_json::from_str::<GmailMessage>(&response_text) {
-            Ok(msg) => msg,
-            Err(e) => {
-                println!("Error parsing modify message response: {}", e);
-                return Err(self.http_client.get("error://example.com").send().await.unwrap_err());
-            }
-        };
+            .bearer_auth(access_token)
+            .json(&body);
+        let message: GmailMessage = Self::read_json(self.send(user_id, quota::MESSAGES_MODIFY, request).await?).await?;
         
         println!("Successfully modified Gmail message {}", message_id);
         Ok(message)
@@ -481,8 +401,8 @@ impl GmailClient {
 }
 
 // Create a shared Gmail client
-pub fn create_gmail_client() -> Arc<GmailClient> {
-    Arc::new(GmailClient::new())
+pub fn create_gmail_client(google: &GoogleConfig) -> Arc<GmailClient> {
+    Arc::new(GmailClient::new(google))
 }
 
 // Helper to extract email address from a header value
@@ -511,87 +431,69 @@ pub fn extract_sender_name(header_value: &str) -> String {
     "".to_string()
 }
 
-// Parse Gmail message to extract useful parts
-pub fn parse_gmail_message(message: &GmailMessage) -> (String, String, String, String, String) {
+// Parse Gmail message to extract useful parts: subject, sender address and name, recipient and body
+pub fn parse_gmail_message(message: &GmailMessage) -> (String, String, String, String, MessageBody) {
     // Default values
     let mut subject = String::new();
     let mut sender = String::new();
     let mut sender_name = String::new();
     let mut recipient = String::new();
-    let mut body = String::new();
+    let mut body = MessageBody::default();
     
-    // Extract headers
     if let Some(payload) = &message.payload {
-        if let Some(headers) = &payload.headers {
-            for header in headers {
-                match header.name.as_str() {
-                    "Subject" => subject = header.value.clone(),
-                    "From" => {
-                        sender = extract_email_address(&header.value);
-                        sender_name = extract_sender_name(&header.value);
-                    },
-                    "To" => recipient = extract_email_address(&header.value),
-                    _ => {}
-                }
+        // Extract headers, decoding RFC 2047 encoded-words such as =?UTF-8?B?...?=
+        for header in payload.headers.iter().flatten() {
+            let value = decode_encoded_words(&header.value);
+            match header.name.to_ascii_lowercase().as_str() {
+                "subject" => subject = value,
+                "from" => {
+                    sender = extract_email_address(&value);
+                    sender_name = extract_sender_name(&value);
+                },
+                "to" => recipient = extract_email_address(&value),
+                _ => {}
             }
         }
         
-        // Extract body
-        body = extract_message_body(payload);
+        body = MessageBody::from_part(&mime_tree(payload));
     }
     
     (subject, sender, sender_name, recipient, body)
 }
 
-// Recursively extract message body from Gmail message parts
-fn extract_message_body(payload: &GmailPayload) -> String {
-    // Check for body in the current payload
-    if let Some(body) = &payload.body {
-        if let Some(data) = &body.data {
-            if let Ok(decoded) = base64::decode(data.replace('-', "+").replace('_', "/")) {
-                if let Ok(text) = String::from_utf8(decoded) {
-                    return text;
-                }
-            }
-        }
+// Gmail's parsed payload as a MIME tree. Gmail has already undone the transfer encoding;
+// body data only needs its base64url wrapping removed.
+fn mime_tree(payload: &GmailPayload) -> MimePart {
+    mime_part(None, payload.mime_type.as_deref(), &payload.headers, payload.body.as_ref(), &payload.parts)
+}
+
+fn mime_part(
+    part_id: Option<String>,
+    mime_type: Option<&str>,
+    headers: &Option<Vec<GmailHeader>>,
+    body: Option<&GmailBody>,
+    parts: &Option<Vec<GmailPart>>,
+) -> MimePart {
+    let mut headers: Vec<(String, String)> = headers.iter().flatten()
+        .map(|header| (header.name.clone(), header.value.clone()))
+        .collect();
+    // Nested parts sometimes come without headers; Gmail still reports their type
+    if let Some(mime_type) = mime_type.filter(|_| !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))) {
+        headers.push(("Content-Type".to_string(), mime_type.to_string()));
     }
-    
-    // Check parts recursively
-    if let Some(parts) = &payload.parts {
-        for part in parts {
-            if let Some(mime_type) = &part.mime_type {
-                if mime_type == "text/plain" {
-                    if let Some(body) = &part.body {
-                        if let Some(data) = &body.data {
-                            if let Ok(decoded) = base64::decode(data.replace('-', "+").replace('_', "/")) {
-                                if let Ok(text) = String::from_utf8(decoded) {
-                                    return text;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            
-            // Recursively check nested parts
-            if let Some(nested_parts) = &part.parts {
-                if !nested_parts.is_empty() {
-                    let nested_payload = GmailPayload {
-                        headers: part.headers.clone(),
-                        parts: Some(nested_parts.clone()),
-                        body: part.body.clone(),
-                        mime_type: part.mime_type.clone(),
-                    };
-                    let body = extract_message_body(&nested_payload);
-                    if !body.is_empty() {
-                        return body;
-                    }
-                }
-            }
-        }
+
+    MimePart {
+        headers,
+        body: body.and_then(|b| b.data.as_deref())
+            .and_then(|data| decode_config(data, URL_SAFE).ok())
+            .unwrap_or_default(),
+        parts: parts.iter().flatten()
+            .map(|part| mime_part(part.part_id.clone(), part.mime_type.as_deref(), &part.headers, part.body.as_ref(), &part.parts))
+            .collect(),
+        part_id: part_id.filter(|id| !id.is_empty()),
+        attachment_id: body.and_then(|b| b.attachment_id.clone()),
+        size: body.and_then(|b| b.size),
     }
-    
-    String::new()
 }
 
 // Process a SendMessageResponse into basic components for creating Email objects
@@ -606,5 +508,3 @@ pub fn process_send_message_response(_message: &SendMessageResponse, from_email:
         body.to_string()
     )
 }
-
-// A simple function that returns a reqwest error with the given message
@@ -0,0 +1,119 @@
+use futures::stream::{self, Stream, TryStreamExt};
+
+use super::{quota, GmailClient, GmailError, GmailMessageId, GmailMessageListResponse, GMAIL_API};
+
+// Gmail's own default and maximum for maxResults
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 500;
+
+// Which messages to list and how far to follow nextPageToken
+#[derive(Debug, Clone, Default)]
+pub struct MessageListQuery {
+    // Gmail search syntax, e.g. "in:sent" or "newer_than:7d"
+    pub query: Option<String>,
+    // Only messages carrying every one of these labels
+    pub label_ids: Vec<String>,
+    pub include_spam_trash: bool,
+    // Messages per request; defaults to 100, capped at 500
+    pub page_size: Option<usize>,
+    // Stop after this many messages across all pages; defaults to google.max_listed_messages
+    pub max_messages: Option<usize>,
+    // Where to start; a token saved from an earlier page resumes an interrupted listing
+    pub page_token: Option<String>,
+}
+
+// One page of a listing, with the token that continues after it
+#[derive(Debug, Clone)]
+pub struct MessagePage {
+    pub messages: Vec<GmailMessageId>,
+    // Save this to resume after an interruption; None on the last page
+    pub next_page_token: Option<String>,
+}
+
+impl GmailClient {
+    // Fetch the page of a listing that starts at query.page_token
+    pub async fn list_messages_page(
+        &self,
+        user_id: &str,
+        access_token: &str,
+        query: &MessageListQuery,
+        page_size: usize,
+    ) -> Result<MessagePage, GmailError> {
+        let url = format!("{}/messages", GMAIL_API);
+
+        let mut params = vec![
+            ("maxResults", page_size.clamp(1, MAX_PAGE_SIZE).to_string()),
+            ("includeSpamTrash", query.include_spam_trash.to_string()),
+        ];
+        if let Some(ref q) = query.query {
+            params.push(("q", q.clone()));
+        }
+        for label_id in &query.label_ids {
+            params.push(("labelIds", label_id.clone()));
+        }
+        if let Some(ref page_token) = query.page_token {
+            params.push(("pageToken", page_token.clone()));
+        }
+
+        println!("Fetching Gmail message page: {} q={:?} labels={:?}", url, query.query, query.label_ids);
+
+        let request = self.http_client
+            .get(&url)
+            .bearer_auth(access_token)
+            .query(&params);
+        let response: GmailMessageListResponse = Self::read_json(self.send(user_id, quota::MESSAGES_LIST, request).await?).await?;
+
+        Ok(MessagePage {
+            messages: response.messages.unwrap_or_default(),
+            next_page_token: response.next_page_token,
+        })
+    }
+
+    // Stream the pages of a listing, following nextPageToken until Google runs out or the
+    // message bound is reached. The stream ends after the first error; the last page's
+    // next_page_token is where to resume.
+    pub fn message_pages<'a>(
+        &'a self,
+        user_id: &'a str,
+        access_token: &'a str,
+        query: MessageListQuery,
+    ) -> impl Stream<Item = Result<MessagePage, GmailError>> + 'a {
+        let max_messages = query.max_messages.unwrap_or(self.max_listed_messages);
+        stream::unfold(Some((query, 0usize)), move |state| async move {
+            let (mut query, fetched) = state?;
+
+            // Shrink the last request instead of truncating a page, so its token stays a valid resume point
+            let remaining = max_messages.saturating_sub(fetched);
+            if remaining == 0 {
+                return None;
+            }
+            let page_size = query.page_size
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .min(remaining)
+                .min(MAX_PAGE_SIZE);
+
+            match self.list_messages_page(user_id, access_token, &query, page_size).await {
+                Ok(page) => {
+                    let fetched = fetched + page.messages.len();
+                    let next_state = page.next_page_token.clone().map(|token| {
+                        query.page_token = Some(token);
+                        (query, fetched)
+                    });
+                    Some((Ok(page), next_state))
+                }
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+
+    // Every message id of a listing, up to the message bound
+    pub async fn collect_message_ids(
+        &self,
+        user_id: &str,
+        access_token: &str,
+        query: MessageListQuery,
+    ) -> Result<Vec<GmailMessageId>, GmailError> {
+        let pages: Vec<MessagePage> = self.message_pages(user_id, access_token, query).try_collect().await?;
+        Ok(pages.into_iter().flat_map(|page| page.messages).collect())
+    }
+}
@@ -0,0 +1,256 @@
+use rand::Rng;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::GmailError;
+
+// Share of each user's bucket that background work leaves for interactive requests
+const BACKGROUND_RESERVE: f64 = 0.2;
+
+// First retry delay; doubled for every further attempt up to MAX_BACKOFF
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(32);
+
+// A Gmail API call: what it costs in quota units and whether repeating it is harmless
+#[derive(Debug, Clone, Copy)]
+pub struct Call {
+    pub units: u32,
+    // A non-idempotent call is only retried when Google certainly did not process it
+    pub idempotent: bool,
+}
+
+// Quota units per method, from Google's Gmail API usage limits
+pub const MESSAGES_GET: Call = Call { units: 5, idempotent: true };
+pub const MESSAGES_LIST: Call = Call { units: 5, idempotent: true };
+pub const MESSAGES_SEND: Call = Call { units: 100, idempotent: false };
+pub const MESSAGES_MODIFY: Call = Call { units: 5, idempotent: true };
+pub const THREADS_GET: Call = Call { units: 10, idempotent: true };
+pub const ATTACHMENTS_GET: Call = Call { units: 5, idempotent: true };
+pub const LABELS_LIST: Call = Call { units: 1, idempotent: true };
+pub const HISTORY_LIST: Call = Call { units: 2, idempotent: true };
+pub const GET_PROFILE: Call = Call { units: 1, idempotent: true };
+pub const WATCH: Call = Call { units: 100, idempotent: true };
+// OAuth endpoints are not metered by the Gmail quota
+pub const OAUTH: Call = Call { units: 0, idempotent: true };
+
+impl Call {
+    // A batch is charged for every call inside it
+    pub fn batch_of(call: Call, count: usize) -> Call {
+        Call { units: call.units * count as u32, idempotent: call.idempotent }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    // A user is waiting on the answer
+    Interactive,
+    // Syncs and renewals nobody is waiting on
+    Background,
+}
+
+impl Priority {
+    fn max_attempts(&self) -> u32 {
+        match self {
+            Priority::Interactive => 3,
+            Priority::Background => 6,
+        }
+    }
+
+    // Longest total time spent backing off before giving up; a user would rather see the error
+    fn max_total_backoff(&self) -> Duration {
+        match self {
+            Priority::Interactive => Duration::from_secs(8),
+            Priority::Background => Duration::from_secs(120),
+        }
+    }
+}
+
+tokio::task_local! {
+    static PRIORITY: Priority;
+}
+
+// Run Gmail calls made by `work` at background priority
+pub async fn in_background<F: Future>(work: F) -> F::Output {
+    PRIORITY.scope(Priority::Background, work).await
+}
+
+pub fn current_priority() -> Priority {
+    PRIORITY.try_with(|priority| *priority).unwrap_or(Priority::Interactive)
+}
+
+// Throttling counters since startup
+#[derive(Debug, Default)]
+struct QuotaMetrics {
+    requests: AtomicU64,
+    quota_units: AtomicU64,
+    throttled_interactive: AtomicU64,
+    throttled_background: AtomicU64,
+    throttle_wait_ms: AtomicU64,
+    rate_limited_responses: AtomicU64,
+    retries: AtomicU64,
+    retries_exhausted: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuotaMetricsSnapshot {
+    pub requests: u64,
+    pub quota_units: u64,
+    // Requests that waited for the user's bucket to refill
+    pub throttled_interactive: u64,
+    pub throttled_background: u64,
+    pub throttle_wait_ms: u64,
+    // 429s and rate-limit 403s from Google
+    pub rate_limited_responses: u64,
+    pub retries: u64,
+    // Calls that failed after their retry budget ran out
+    pub retries_exhausted: u64,
+}
+
+struct Bucket {
+    units: f64,
+    refilled_at: Instant,
+    // Google told us to back off; nothing is sent for this user before then
+    paused_until: Option<Instant>,
+}
+
+// Per-user token buckets holding Gmail quota units
+pub struct QuotaLimiter {
+    units_per_second: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    metrics: QuotaMetrics,
+}
+
+impl QuotaLimiter {
+    pub fn new(units_per_second: u32) -> Self {
+        QuotaLimiter {
+            units_per_second: units_per_second as f64,
+            buckets: Mutex::new(HashMap::new()),
+            metrics: QuotaMetrics::default(),
+        }
+    }
+
+    // How long to wait before the call may go out, or None after taking its units
+    fn try_take(&self, user_id: &str, call: Call, priority: Priority) -> Option<Duration> {
+        let capacity = self.units_per_second;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        let bucket = buckets.entry(user_id.to_string()).or_insert(Bucket {
+            units: capacity,
+            refilled_at: now,
+            paused_until: None,
+        });
+
+        if let Some(paused_until) = bucket.paused_until {
+            if paused_until > now {
+                return Some(paused_until - now);
+            }
+            bucket.paused_until = None;
+        }
+
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.units = (bucket.units + elapsed * self.units_per_second).min(capacity);
+        bucket.refilled_at = now;
+
+        let reserve = match priority {
+            Priority::Interactive => 0.0,
+            Priority::Background => capacity * BACKGROUND_RESERVE,
+        };
+        // A batch may cost more than the bucket holds; it goes out once the bucket is full and leaves a debt
+        let needed = (call.units as f64).min(capacity - reserve) + reserve;
+        if bucket.units >= needed {
+            bucket.units -= call.units as f64;
+            None
+        } else {
+            Some(Duration::from_secs_f64((needed - bucket.units) / self.units_per_second))
+        }
+    }
+
+    // Wait until the user's bucket can pay for the call
+    pub async fn acquire(&self, user_id: &str, call: Call, priority: Priority) {
+        self.metrics.requests.fetch_add(1, Ordering::Relaxed);
+        self.metrics.quota_units.fetch_add(call.units as u64, Ordering::Relaxed);
+        if call.units == 0 {
+            return;
+        }
+
+        let mut waited = Duration::ZERO;
+        while let Some(wait) = self.try_take(user_id, call, priority) {
+            tokio::time::sleep(wait).await;
+            waited += wait;
+        }
+
+        if !waited.is_zero() {
+            let counter = match priority {
+                Priority::Interactive => &self.metrics.throttled_interactive,
+                Priority::Background => &self.metrics.throttled_background,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            self.metrics.throttle_wait_ms.fetch_add(waited.as_millis() as u64, Ordering::Relaxed);
+        }
+    }
+
+    // Hold every call for the user until Google's back-off has passed
+    pub fn pause(&self, user_id: &str, delay: Duration) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(bucket) = buckets.get_mut(user_id) {
+            let until = Instant::now() + delay;
+            bucket.paused_until = Some(bucket.paused_until.map_or(until, |current| current.max(until)));
+        }
+    }
+
+    pub fn record_rate_limited(&self) {
+        self.metrics.rate_limited_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_retry(&self) {
+        self.metrics.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_retries_exhausted(&self) {
+        self.metrics.retries_exhausted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> QuotaMetricsSnapshot {
+        let m = &self.metrics;
+        QuotaMetricsSnapshot {
+            requests: m.requests.load(Ordering::Relaxed),
+            quota_units: m.quota_units.load(Ordering::Relaxed),
+            throttled_interactive: m.throttled_interactive.load(Ordering::Relaxed),
+            throttled_background: m.throttled_background.load(Ordering::Relaxed),
+            throttle_wait_ms: m.throttle_wait_ms.load(Ordering::Relaxed),
+            rate_limited_responses: m.rate_limited_responses.load(Ordering::Relaxed),
+            retries: m.retries.load(Ordering::Relaxed),
+            retries_exhausted: m.retries_exhausted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// How long to wait before retrying a failed call, or None if it should fail now.
+// `attempt` counts the attempts made so far; `backed_off` is the time already spent waiting.
+pub fn retry_delay(error: &GmailError, call: Call, priority: Priority, attempt: u32, backed_off: Duration) -> Option<Duration> {
+    if attempt >= priority.max_attempts() {
+        return None;
+    }
+
+    let (retryable, retry_after) = match error {
+        GmailError::QuotaExceeded { retry_after, .. } => (true, *retry_after),
+        GmailError::Unavailable(_) => (call.idempotent, None),
+        // A refused connection never reached Google; a timeout may have
+        GmailError::Transport(e) => (e.is_connect() || (call.idempotent && e.is_timeout()), None),
+        _ => (false, None),
+    };
+    if !retryable {
+        return None;
+    }
+
+    // Exponential backoff with jitter in its upper half, so throttled callers do not retry in lockstep
+    let backoff = BASE_BACKOFF.saturating_mul(1 << (attempt - 1).min(16)).min(MAX_BACKOFF);
+    let jittered = backoff / 2 + backoff.mul_f64(rand::thread_rng().gen_range(0.0..0.5));
+    let delay = retry_after.map_or(jittered, |retry_after| retry_after.max(jittered));
+
+    (backed_off + delay <= priority.max_total_backoff()).then_some(delay)
+}
@@ -0,0 +1,28 @@
+use serde::Deserialize;
+
+use super::{quota, GmailClient, GmailError, GmailMessage, GMAIL_API};
+
+// Answer to users.threads.get: the conversation's messages, oldest first
+#[derive(Debug, Deserialize)]
+pub struct GmailThread {
+    pub id: String,
+    #[serde(default)]
+    pub messages: Vec<GmailMessage>,
+}
+
+impl GmailClient {
+    // Fetch every message of a Gmail thread in full
+    pub async fn get_thread(&self, user_id: &str, access_token: &str, thread_id: &str) -> Result<GmailThread, GmailError> {
+        let url = format!("{}/threads/{}", GMAIL_API, thread_id);
+
+        println!("Fetching Gmail thread {}", thread_id);
+
+        let request = self.http_client
+            .get(&url)
+            .bearer_auth(access_token);
+        let thread: GmailThread = Self::read_json(self.send(user_id, quota::THREADS_GET, request).await?).await?;
+
+        println!("Successfully fetched {} messages of Gmail thread {}", thread.messages.len(), thread.id);
+        Ok(thread)
+    }
+}
@@ -0,0 +1,45 @@
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{quota, GmailClient, GmailError, GMAIL_API};
+
+// Answer to users.watch
+#[derive(Debug, Deserialize)]
+pub struct WatchResponse {
+    // Milliseconds since the epoch, as a string
+    pub expiration: String,
+}
+
+impl WatchResponse {
+    // When Gmail stops publishing unless the watch is renewed
+    pub fn expires_at_millis(&self) -> i64 {
+        self.expiration.parse().unwrap_or(0)
+    }
+}
+
+impl GmailClient {
+    // Ask Gmail to publish the mailbox's changes to a Pub/Sub topic. Calling it again renews the watch.
+    pub async fn watch(
+        &self,
+        user_id: &str,
+        access_token: &str,
+        topic: &str,
+        label_ids: &[String],
+    ) -> Result<WatchResponse, GmailError> {
+        let url = format!("{}/watch", GMAIL_API);
+
+        let mut body = json!({ "topicName": topic });
+        if !label_ids.is_empty() {
+            body["labelIds"] = json!(label_ids);
+            body["labelFilterBehavior"] = json!("include");
+        }
+
+        println!("Registering Gmail watch for {} on {}", user_id, topic);
+
+        let request = self.http_client
+            .post(&url)
+            .bearer_auth(access_token)
+            .json(&body);
+        Self::read_json(self.send(user_id, quota::WATCH, request).await?).await
+    }
+}
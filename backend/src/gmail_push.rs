@@ -0,0 +1,16 @@
+use base64::{encode_config, STANDARD};
+use serde_json::json;
+
+// The body of a Pub/Sub push: the Gmail notification travels base64-encoded in message.data.
+// push_stub sends it, and the push endpoint's tests post it.
+pub fn push_envelope(email: &str, history_id: u64, sequence: u64) -> serde_json::Value {
+    let notification = json!({ "emailAddress": email, "historyId": history_id });
+    json!({
+        "message": {
+            "data": encode_config(notification.to_string(), STANDARD),
+            "messageId": format!("push-stub-{}-{}", history_id, sequence),
+            "publishTime": chrono::Utc::now().to_rfc3339(),
+        },
+        "subscription": "projects/push-stub/subscriptions/gmail-push",
+    })
+}
@@ -0,0 +1,129 @@
+use actix_web::{web, HttpResponse, Responder, ResponseError};
+use serde_json::json;
+use log::{info, error};
+
+use crate::auth::{self, AuthenticatedUser};
+use crate::db;
+use crate::models::CreateAccessTokenRequest;
+
+type DbPool = web::Data<sqlx::PgPool>;
+
+// Longest lifetime a token may be created with
+const MAX_TOKEN_LIFETIME_DAYS: i32 = 365;
+
+// List the signed-in user's personal access tokens
+pub async fn list_access_tokens(user: AuthenticatedUser, db_pool: DbPool) -> impl Responder {
+    if let Err(e) = user.require_session() {
+        return e.error_response();
+    }
+
+    match db::access_tokens::list_access_tokens(db_pool.get_ref(), &user.email).await {
+        Ok(tokens) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "tokens": tokens
+        })),
+        Err(e) => {
+            error!("Failed to list access tokens: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to list access tokens",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
+
+// Create a scoped token for scripts; the token itself is only returned once
+pub async fn create_access_token(
+    user: AuthenticatedUser,
+    body: web::Json<CreateAccessTokenRequest>,
+    db_pool: DbPool,
+) -> impl Responder {
+    if let Err(e) = user.require_session() {
+        return e.error_response();
+    }
+
+    let name = body.name.trim();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": "Token name cannot be empty"
+        }));
+    }
+    if body.scopes.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": "A token needs at least one scope"
+        }));
+    }
+    if let Some(days) = body.expires_in_days {
+        if !(1..=MAX_TOKEN_LIFETIME_DAYS).contains(&days) {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": format!("Tokens must expire within 1 to {} days", MAX_TOKEN_LIFETIME_DAYS)
+            }));
+        }
+    }
+
+    let mut scopes = Vec::new();
+    for scope in &body.scopes {
+        if !scopes.contains(scope) {
+            scopes.push(*scope);
+        }
+    }
+
+    let token = auth::generate_access_token();
+    match db::access_tokens::create_access_token(db_pool.get_ref(), &user.email, name, &token, &scopes, body.expires_in_days).await {
+        Ok(info) => {
+            info!("Access token {} created by {}", info.id, user.email);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "token": token,
+                "info": info
+            }))
+        }
+        Err(e) => {
+            error!("Failed to create access token: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to create access token",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
+
+// Revoke one of the user's tokens
+pub async fn revoke_access_token(
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    db_pool: DbPool,
+) -> impl Responder {
+    let token_id = path.into_inner();
+
+    if let Err(e) = user.require_session() {
+        return e.error_response();
+    }
+
+    match db::access_tokens::revoke_access_token(db_pool.get_ref(), &user.email, &token_id).await {
+        Ok(true) => {
+            info!("Access token {} revoked by {}", token_id, user.email);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Access token revoked"
+            }))
+        }
+        Ok(false) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "error": "Access token not found"
+        })),
+        Err(e) => {
+            error!("Failed to revoke access token: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to revoke access token",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use actix_web::{web, HttpResponse, Responder, ResponseError};
+use serde_json::json;
+use uuid::Uuid;
+use log::{info, error};
+
+use crate::auth::{self, AuthenticatedUser};
+use crate::db;
+use crate::gmail::GmailClient;
+use crate::cache::{AttachmentCache, RedisCache};
+use crate::models::{AccountDeletionReceipt, DeleteAccountRequest, UndoAccountDeletionRequest};
+
+type DbPool = web::Data<sqlx::PgPool>;
+type GmailClientData = web::Data<std::sync::Arc<GmailClient>>;
+type RedisCacheData = web::Data<std::sync::Arc<RedisCache>>;
+type AttachmentCacheData = web::Data<std::sync::Arc<AttachmentCache>>;
+type ConfigData = web::Data<crate::config::AppConfig>;
+
+// Delete an account for good: revoke the Google grant, revoke the published key,
+// then remove the user's rows, cache entries and cached attachments
+pub async fn purge_account(
+    pool: &sqlx::PgPool,
+    gmail_client: &GmailClient,
+    redis_cache: &RedisCache,
+    attachment_cache: &AttachmentCache,
+    receipt_id: &str,
+    email: &str,
+) -> Result<Option<AccountDeletionReceipt>, sqlx::Error> {
+    let google_grant_revoked = match db::get_user_refresh_token(pool, email).await? {
+        Some(refresh_token) => match gmail_client.revoke_token(email, &refresh_token).await {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Failed to revoke Google grant for {}: {}", email, e);
+                false
+            }
+        },
+        // Nothing was granted, so nothing is left to revoke
+        None => true,
+    };
+
+    // Record the revocation in the transparency log before the key disappears. If that fails the
+    // account is left as it is, and the next purge run tries again, rather than drop the key unlogged.
+    crate::encryption::keys::revoke_keypair(pool, email)
+        .await
+        .map_err(|e| sqlx::Error::Configuration(format!("failed to revoke encryption keys: {}", e).into()))?;
+
+    db::account_deletion::delete_user_data(pool, email).await?;
+
+    if let Err(e) = redis_cache.delete_user_keys(email).await {
+        error!("Failed to clear cache for deleted account {}: {}", email, e);
+    }
+    if let Err(e) = attachment_cache.remove_user(email).await {
+        error!("Failed to remove cached attachments for deleted account {}: {}", email, e);
+    }
+
+    info!("Account deletion {} completed", receipt_id);
+    db::account_deletion::complete_account_deletion(pool, receipt_id, google_grant_revoked).await
+}
+
+// Purge every account whose grace period has ended
+pub async fn purge_due_accounts(
+    pool: &sqlx::PgPool,
+    gmail_client: &GmailClient,
+    redis_cache: &RedisCache,
+    attachment_cache: &AttachmentCache,
+) {
+    let due = match db::account_deletion::get_due_deletions(pool).await {
+        Ok(due) => due,
+        Err(e) => {
+            error!("Failed to load due account deletions: {}", e);
+            return;
+        }
+    };
+
+    for (receipt_id, email) in due {
+        if let Err(e) = purge_account(pool, gmail_client, redis_cache, attachment_cache, &receipt_id, &email).await {
+            error!("Failed to complete account deletion {}: {}", receipt_id, e);
+        }
+    }
+}
+
+// Request deletion of the signed-in account
+pub async fn delete_account(
+    user: AuthenticatedUser,
+    body: web::Json<DeleteAccountRequest>,
+    db_pool: DbPool,
+    gmail_client: GmailClientData,
+    redis_cache: RedisCacheData,
+    attachment_cache: AttachmentCacheData,
+    config: ConfigData,
+) -> impl Responder {
+    if let Err(e) = user.require_session() {
+        return e.error_response();
+    }
+
+    if !body.confirm_email.trim().eq_ignore_ascii_case(&user.email) {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": "Confirmation email does not match the signed-in account"
+        }));
+    }
+
+    match db::account_deletion::get_pending_deletion(db_pool.get_ref(), &user.email).await {
+        Ok(None) => {}
+        Ok(Some(receipt)) => {
+            return HttpResponse::Conflict().json(json!({
+                "success": false,
+                "error": "Account deletion is already scheduled",
+                "receipt": receipt
+            }));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "error": "Database error",
+                "details": format!("{}", e)
+            }));
+        }
+    }
+
+    let grace_days = config.accounts.deletion_grace_days;
+    let undo_token = Uuid::new_v4().to_string();
+    let mut receipt = match db::account_deletion::schedule_account_deletion(db_pool.get_ref(), &user.email, grace_days, &undo_token).await {
+        Ok(receipt) => receipt,
+        Err(e) => {
+            error!("Failed to schedule account deletion: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to schedule account deletion",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+
+    if grace_days == 0 {
+        receipt = match purge_account(db_pool.get_ref(), &gmail_client, &redis_cache, &attachment_cache, &receipt.receipt_id, &user.email).await {
+            Ok(Some(receipt)) => receipt,
+            Ok(None) => receipt,
+            Err(e) => {
+                error!("Failed to delete account {}: {}", receipt.receipt_id, e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "error": "Failed to delete account",
+                    "details": format!("{}", e),
+                    "receipt_id": receipt.receipt_id
+                }));
+            }
+        };
+    } else {
+        // Sign out everywhere; the account stays locked until it is purged or the deletion is undone
+        if let Err(e) = db::sessions::revoke_all_sessions(db_pool.get_ref(), &user.email, None).await {
+            error!("Failed to revoke sessions for {}: {}", user.email, e);
+        }
+        if let Err(e) = db::access_tokens::revoke_all_access_tokens(db_pool.get_ref(), &user.email).await {
+            error!("Failed to revoke access tokens for {}: {}", user.email, e);
+        }
+        receipt.undo_token = Some(undo_token);
+        info!("Account deletion {} scheduled for {}", receipt.receipt_id, receipt.scheduled_for);
+    }
+
+    HttpResponse::Ok()
+        .cookie(auth::create_logout_cookie(config.server.cookies_secure()))
+        .json(json!({
+            "success": true,
+            "receipt": receipt
+        }))
+}
+
+// Cancel a scheduled deletion with the undo token from the receipt
+pub async fn undo_account_deletion(
+    body: web::Json<UndoAccountDeletionRequest>,
+    db_pool: DbPool,
+) -> impl Responder {
+    match db::account_deletion::cancel_account_deletion(db_pool.get_ref(), &body.receipt_id, &body.undo_token).await {
+        Ok(Some(receipt)) => {
+            info!("Account deletion {} cancelled", receipt.receipt_id);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Account deletion cancelled, you can sign in again",
+                "receipt": receipt
+            }))
+        }
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "error": "No pending deletion matches this receipt and undo token"
+        })),
+        Err(e) => {
+            error!("Failed to cancel account deletion: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to cancel account deletion",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
+
+// Check the state of a deletion by its receipt id
+pub async fn get_account_deletion(path: web::Path<String>, db_pool: DbPool) -> impl Responder {
+    match db::account_deletion::get_deletion_receipt(db_pool.get_ref(), &path.into_inner()).await {
+        Ok(Some(receipt)) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "receipt": receipt
+        })),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "error": "Receipt not found"
+        })),
+        Err(e) => {
+            error!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "error": "Database error",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
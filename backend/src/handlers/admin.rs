@@ -1,9 +1,17 @@
 use actix_web::{web, HttpResponse, Responder};
 use serde_json::json;
+use log::info;
 
-use crate::db;
+use crate::auth::AdminActor;
+use crate::db::{self, roles::RoleChange};
+use crate::gmail::GmailClient;
+use crate::models::{GrantRoleRequest, Role};
 
 type DbPool = web::Data<sqlx::PgPool>;
+type GmailClientData = web::Data<std::sync::Arc<GmailClient>>;
+
+// Most audit entries returned in one request
+const AUDIT_LOG_LIMIT: i64 = 500;
 
 // Admin endpoints for debugging
 pub async fn list_users(db_pool: DbPool) -> impl Responder {
@@ -17,3 +25,72 @@ pub async fn list_users(db_pool: DbPool) -> impl Responder {
         }
     }
 }
+
+// Grant a role to a user
+pub async fn grant_role(
+    actor: web::ReqData<AdminActor>,
+    path: web::Path<String>,
+    body: web::Json<GrantRoleRequest>,
+    db_pool: DbPool,
+) -> impl Responder {
+    let target = path.into_inner();
+    change_role(db_pool.get_ref(), &actor, &target, body.role).await
+}
+
+// Revoke a user's role, returning them to a regular user
+pub async fn revoke_role(
+    actor: web::ReqData<AdminActor>,
+    path: web::Path<String>,
+    db_pool: DbPool,
+) -> impl Responder {
+    let target = path.into_inner();
+    change_role(db_pool.get_ref(), &actor, &target, Role::User).await
+}
+
+async fn change_role(pool: &sqlx::PgPool, actor: &AdminActor, target: &str, role: Role) -> HttpResponse {
+    // Never leave the deployment without an admin
+    match db::roles::set_user_role(pool, target, role).await {
+        Ok(RoleChange::Changed) => {
+            info!("{} set role of {} to {}", actor.email, target, role.as_str());
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "email": target,
+                "role": role
+            }))
+        }
+        Ok(RoleChange::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "error": "User not found"
+        })),
+        Ok(RoleChange::LastAdmin) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "error": "Cannot remove the last admin"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": "Database error",
+            "details": format!("{}", e)
+        })),
+    }
+}
+
+// Recent admin access attempts, including denied ones
+pub async fn get_admin_audit_log(db_pool: DbPool) -> impl Responder {
+    match db::roles::list_admin_audit_log(db_pool.get_ref(), AUDIT_LOG_LIMIT).await {
+        Ok(entries) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "entries": entries
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": "Database error",
+            "details": format!("{}", e)
+        })),
+    }
+}
+
+// Gmail throttling since startup: waits on the per-user quota, Google's rate limits and retries
+pub async fn get_gmail_quota_metrics(gmail_client: GmailClientData) -> impl Responder {
+    HttpResponse::Ok().json(json!({
+        "success": true,
+        "quota": gmail_client.quota_metrics()
+    }))
+}
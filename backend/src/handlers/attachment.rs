@@ -0,0 +1,239 @@
+use actix_web::body::SizedStream;
+use actix_web::http::header::{
+    Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue, CACHE_CONTROL,
+    CONTENT_SECURITY_POLICY, CONTENT_TYPE,
+};
+use actix_web::{web, HttpResponse, Responder, ResponseError};
+use futures::Stream;
+use serde_json::json;
+use log::{error, warn};
+use tokio::io::AsyncReadExt;
+
+use crate::auth::AuthenticatedUser;
+use crate::cache::{AttachmentCache, CachedAttachment};
+use crate::db;
+use crate::gmail::{self, parse_gmail_message, GmailClient, GmailError};
+use crate::models::{AttachmentInfo, TokenScope};
+
+type DbPool = web::Data<sqlx::PgPool>;
+type GmailClientData = web::Data<std::sync::Arc<GmailClient>>;
+type AttachmentCacheData = web::Data<std::sync::Arc<AttachmentCache>>;
+
+// Types a browser may be told as they are; they render inertly. Anything else is sent as
+// application/octet-stream, so a hostile attachment cannot run as a page on this origin.
+const SAFE_CONTENT_TYPES: [&str; 10] = [
+    "application/pdf",
+    "application/zip",
+    "audio/mpeg",
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+    "text/csv",
+    "text/plain",
+    "video/mp4",
+];
+
+// Bytes read from the cache per chunk of a download
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+// Download one attachment of a Gmail message, by the part id listed in the email's `attachments`.
+// Content is fetched through users.messages.attachments.get and kept in the on-disk cache.
+pub async fn download_attachment(
+    user: AuthenticatedUser,
+    path: web::Path<(String, String)>,
+    db_pool: DbPool,
+    gmail_client: GmailClientData,
+    attachment_cache: AttachmentCacheData,
+) -> impl Responder {
+    if let Err(e) = user.require_scope(TokenScope::ReadMail) {
+        return e.error_response();
+    }
+    let (email_id, part_id) = path.into_inner();
+    let message_id = email_id.strip_prefix("gmail_").unwrap_or(&email_id);
+    let email = user.email;
+
+    // Gmail ids are hex and part ids dotted numbers; nothing else can name an attachment
+    if message_id.is_empty()
+        || !message_id.chars().all(|c| c.is_ascii_alphanumeric())
+        || part_id.is_empty()
+        || !part_id.chars().all(|c| c.is_ascii_digit() || c == '.')
+    {
+        return attachment_not_found();
+    }
+
+    let refresh_token = match user.refresh_token {
+        Some(refresh_token) => refresh_token,
+        None => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "No Gmail refresh token found"
+            }));
+        }
+    };
+
+    // Cached content belongs to the mailbox it came from, so a cache hit needs that mailbox linked still
+    let mailbox = match db::identities::get_mailbox_email(db_pool.get_ref(), &email).await {
+        Ok(Some(mailbox)) => mailbox,
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "No Gmail mailbox is linked to this account"
+            }));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to look up the linked mailbox",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+
+    if let Some(cached) = attachment_cache.get(&email, &mailbox, message_id, &part_id).await {
+        return match stream_cached(&cached).await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to read cached attachment {}: {}", cached.path.display(), e);
+                HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "error": "Failed to read attachment"
+                }))
+            }
+        };
+    }
+
+    let access_token = match gmail_client.get_token(&email, &refresh_token).await {
+        Ok(access_token) => access_token,
+        Err(e) => {
+            error!("Gmail token error: {}", e);
+            return e.error_response();
+        }
+    };
+
+    // The message is read first: it proves the attachment belongs to this mailbox and describes it
+    let message = match gmail_client.get_message_detail(&email, &access_token, message_id).await {
+        Ok(message) => message,
+        Err(GmailError::NotFound(_)) => return attachment_not_found(),
+        Err(e) => {
+            error!("Failed to fetch Gmail message {}: {}", message_id, e);
+            return e.error_response();
+        }
+    };
+    let (_, _, _, _, body) = parse_gmail_message(&message);
+    let info = match body.all_attachments().into_iter().find(|a| a.part_id.as_deref() == Some(part_id.as_str())) {
+        Some(info) => info,
+        None => return attachment_not_found(),
+    };
+    if info.size > attachment_cache.max_size() as i64 {
+        return attachment_too_large(attachment_cache.max_size());
+    }
+
+    let data = match info.attachment_id {
+        Some(ref attachment_id) => {
+            match gmail_client.get_attachment(&email, &access_token, message_id, attachment_id).await {
+                Ok(data) => data,
+                Err(GmailError::NotFound(_)) => return attachment_not_found(),
+                Err(e) => {
+                    error!("Failed to fetch attachment {} of Gmail message {}: {}", part_id, message_id, e);
+                    return e.error_response();
+                }
+            }
+        }
+        // Small parts come with the message itself
+        None => match gmail::part_data(&message, &part_id) {
+            Some(data) => data,
+            None => return attachment_not_found(),
+        },
+    };
+    // Gmail's reported size is only an estimate
+    if data.len() as u64 > attachment_cache.max_size() {
+        return attachment_too_large(attachment_cache.max_size());
+    }
+
+    match attachment_cache.put(&email, &mailbox, message_id, &part_id, &info, &data).await {
+        Ok(_) => {}
+        Err(e) => warn!("Failed to cache attachment {} of Gmail message {}: {}", part_id, message_id, e),
+    }
+    download_response(&info).body(data)
+}
+
+async fn stream_cached(cached: &CachedAttachment) -> std::io::Result<HttpResponse> {
+    let file = tokio::fs::File::open(&cached.path).await?;
+    Ok(download_response(&cached.info).body(SizedStream::new(cached.size, file_chunks(file))))
+}
+
+fn file_chunks(file: tokio::fs::File) -> impl Stream<Item = Result<web::Bytes, std::io::Error>> {
+    futures::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut chunk = vec![0; STREAM_CHUNK_SIZE];
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(read) => {
+                chunk.truncate(read);
+                Some((Ok(web::Bytes::from(chunk)), Some(file)))
+            }
+            // The error ends the download
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+// Always a download, never rendered in place; the sandbox covers a browser that opens it anyway
+fn download_response(info: &AttachmentInfo) -> actix_web::HttpResponseBuilder {
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((CONTENT_TYPE, safe_content_type(&info.mime_type)))
+        .insert_header(content_disposition(&info.filename))
+        .insert_header((CONTENT_SECURITY_POLICY, "sandbox; default-src 'none'"))
+        .insert_header((CACHE_CONTROL, "private, no-cache"));
+    response
+}
+
+fn safe_content_type(mime_type: &str) -> &'static str {
+    let mime_type = mime_type.trim().to_ascii_lowercase();
+    SAFE_CONTENT_TYPES.iter()
+        .find(|safe| **safe == mime_type)
+        .copied()
+        .unwrap_or("application/octet-stream")
+}
+
+// An ASCII filename for old clients and the exact one as RFC 5987 UTF-8
+fn content_disposition(filename: &str) -> ContentDisposition {
+    // Path separators and control characters have no place in a saved file's name
+    let filename: String = filename.chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if matches!(c, '/' | '\\') { '_' } else { c })
+        .collect();
+    let fallback: String = filename.chars()
+        .map(|c| if c.is_ascii() && c != '"' { c } else { '_' })
+        .collect();
+
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![
+            DispositionParam::Filename(fallback),
+            DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_string()),
+                language_tag: None,
+                value: filename.into_bytes(),
+            }),
+        ],
+    }
+}
+
+fn attachment_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({
+        "success": false,
+        "error": "Attachment not found"
+    }))
+}
+
+fn attachment_too_large(max_size: u64) -> HttpResponse {
+    HttpResponse::PayloadTooLarge().json(json!({
+        "success": false,
+        "error": "Attachment is too large to download",
+        "details": format!("Attachments are limited to {} bytes", max_size)
+    }))
+}
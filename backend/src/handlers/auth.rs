@@ -1,181 +1,392 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder, ResponseError};
 use serde_json::json;
-use oauth2::{AuthorizationCode, CsrfToken, Scope, TokenResponse};
-use oauth2::reqwest::async_http_client;
-use reqwest;
+use oauth2::{CsrfToken, PkceCodeChallenge};
 use uuid;
-use actix_web::cookie::Cookie;
 
+use crate::config::AppConfig;
 use crate::db;
-use crate::auth;
+use crate::auth::{self, AuthenticatedUser, IdentityProviderConfig, IdentityProviders};
+use crate::gmail::GmailClient;
 use crate::models::*;
 
 type DbPool = web::Data<sqlx::PgPool>;
+type GmailClientData = web::Data<std::sync::Arc<GmailClient>>;
+type RedisCacheData = web::Data<std::sync::Arc<crate::cache::RedisCache>>;
+type AttachmentCacheData = web::Data<std::sync::Arc<crate::cache::AttachmentCache>>;
+type IdentityProvidersData = web::Data<std::sync::Arc<IdentityProviders>>;
+type ConfigData = web::Data<AppConfig>;
 
-pub async fn auth_google() -> impl Responder {
-    let client = auth::create_oauth_client();
+// Sign-in options for the welcome page
+pub async fn list_identity_providers(providers: IdentityProvidersData) -> impl Responder {
+    let providers: Vec<IdentityProviderInfo> = providers
+        .list()
+        .iter()
+        .map(|p| IdentityProviderInfo { id: p.id.clone(), name: p.display_name.clone() })
+        .collect();
 
-    let (auth_url, _csrf_token) = client
-        .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new("profile".to_string()))
-        .add_scope(Scope::new("email".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/gmail.readonly".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/gmail.send".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/gmail.modify".to_string()))
-        .add_extra_param("access_type", "offline")
-        .add_extra_param("prompt", "consent")
-        .url();
+    HttpResponse::Ok().json(json!({ "providers": providers }))
+}
+
+pub async fn auth_login(
+    path: web::Path<String>,
+    providers: IdentityProvidersData,
+    redis_cache: RedisCacheData,
+    config: ConfigData,
+) -> impl Responder {
+    let provider = match providers.get(&path.into_inner()) {
+        Ok(provider) => provider,
+        Err(e) => return auth_error_redirect(&config, e.reason()),
+    };
+
+    start_login(&config, &providers, provider, &redis_cache, None).await
+}
+
+// Attach a Gmail mailbox to the signed-in user, whichever provider they logged in with
+pub async fn auth_google_link(
+    user: AuthenticatedUser,
+    providers: IdentityProvidersData,
+    redis_cache: RedisCacheData,
+    config: ConfigData,
+) -> impl Responder {
+    if let Err(e) = user.require_session() {
+        return e.error_response();
+    }
+
+    let provider = match providers.get(auth::GOOGLE_PROVIDER_ID) {
+        Ok(provider) => provider,
+        Err(e) => return auth_error_redirect(&config, e.reason()),
+    };
+
+    start_login(&config, &providers, provider, &redis_cache, Some(user.email)).await
+}
+
+// Send the browser to the provider, remembering what the callback needs to finish
+async fn start_login(
+    config: &AppConfig,
+    providers: &IdentityProviders,
+    provider: &IdentityProviderConfig,
+    redis_cache: &crate::cache::RedisCache,
+    link_user: Option<String>,
+) -> HttpResponse {
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+    let state = CsrfToken::new_random();
+    let nonce = CsrfToken::new_random();
+
+    // Google only issues a refresh token for Gmail when asked for offline access
+    let extra_params: &[(&str, &str)] = if provider.links_gmail {
+        &[("access_type", "offline"), ("prompt", "consent")]
+    } else {
+        &[]
+    };
+
+    let auth_url = match providers
+        .authorization_url(provider, state.secret(), nonce.secret(), pkce_challenge.as_str(), extra_params)
+        .await
+    {
+        Ok(url) => url,
+        Err(e) => {
+            println!("Error starting {} login: {}", provider.id, e);
+            return auth_error_redirect(config, e.reason());
+        }
+    };
+
+    let login_state = OAuthLoginState {
+        provider: provider.id.clone(),
+        pkce_verifier: pkce_verifier.secret().clone(),
+        nonce: nonce.secret().clone(),
+        link_user,
+    };
+
+    // The verifier and nonce stay server-side; the browser only gets the state in a cookie
+    if let Err(e) = redis_cache
+        .store_oauth_state(
+            state.secret(),
+            &serde_json::to_string(&login_state).unwrap_or_default(),
+            auth::OAUTH_STATE_TTL_SECONDS as usize,
+        )
+        .await
+    {
+        println!("Error storing OAuth state: {}", e);
+        return auth_error_redirect(config, "unavailable");
+    }
 
     println!("Auth URL: {}", auth_url);
-    HttpResponse::Found().append_header(("Location", auth_url.to_string())).finish()
+    HttpResponse::Found()
+        .cookie(auth::create_oauth_state_cookie(state.secret(), config.server.cookies_secure()))
+        .append_header(("Location", auth_url))
+        .finish()
 }
 
-pub async fn auth_google_callback(
+// Send the browser back to the frontend with a reason it can show before restarting the login
+fn auth_error_redirect(config: &AppConfig, reason: &str) -> HttpResponse {
+    HttpResponse::Found()
+        .cookie(auth::clear_oauth_state_cookie(config.server.cookies_secure()))
+        .append_header(("Location", format!("{}/?auth_error={}", config.server.frontend_url, reason)))
+        .finish()
+}
+
+// Google sometimes returns a small image over plain HTTP
+fn normalize_picture(picture: Option<String>) -> Option<String> {
+    picture.map(|pic_url| pic_url.replace("http://", "https://").replace("=s96-c", "=s256-c"))
+}
+
+pub async fn auth_callback(
+    req: HttpRequest,
+    path: web::Path<String>,
     query: web::Query<AuthQuery>,
     db_pool: DbPool,
+    providers: IdentityProvidersData,
+    redis_cache: RedisCacheData,
+    config: ConfigData,
 ) -> impl Responder {
-    let client = auth::create_oauth_client();
-    
-    // Exchange the code for a token
-    let code = AuthorizationCode::new(query.code.clone());
-    
-    match client
-        .exchange_code(code)
-        .request_async(async_http_client)
-        .await
-    {
-        Ok(token) => {
-            println!("Successfully exchanged auth code for token");
-            
-            // Get refresh token
-            let refresh_token = token.refresh_token().map(|t| t.secret().clone());
-            
-            if let Some(ref refresh) = refresh_token {
-                println!("Received refresh token, length: {}", refresh.len());
-            } else {
-                println!("No refresh token received! User won't be able to use Gmail API");
+    let provider = match providers.get(&path.into_inner()) {
+        Ok(provider) => provider,
+        Err(e) => return auth_error_redirect(&config, e.reason()),
+    };
+
+    if let Some(ref error) = query.error {
+        println!("{} returned an OAuth error: {}", provider.display_name, error);
+        return auth_error_redirect(&config, if error == "access_denied" { "access_denied" } else { "provider_error" });
+    }
+
+    // The state must match the one issued to this browser
+    let expected_state = match req.cookie("oauth_state") {
+        Some(cookie) => cookie.value().to_string(),
+        None => return auth_error_redirect(&config, "missing_state"),
+    };
+    let state = match query.state {
+        Some(ref state) if *state == expected_state => state,
+        _ => {
+            println!("OAuth state mismatch in callback");
+            return auth_error_redirect(&config, "state_mismatch");
+        }
+    };
+
+    // Each state is single-use and expires with its PKCE verifier
+    let login_state = match redis_cache.take_oauth_state(state).await {
+        Ok(Some(payload)) => match serde_json::from_str::<OAuthLoginState>(&payload) {
+            Ok(login_state) => login_state,
+            Err(_) => return auth_error_redirect(&config, "state_expired"),
+        },
+        Ok(None) => return auth_error_redirect(&config, "state_expired"),
+        Err(e) => {
+            println!("Error loading OAuth state: {}", e);
+            return auth_error_redirect(&config, "unavailable");
+        }
+    };
+
+    // A state issued for one provider cannot complete a login at another
+    if login_state.provider != provider.id {
+        println!("OAuth state for {} used at {} callback", login_state.provider, provider.id);
+        return auth_error_redirect(&config, "state_mismatch");
+    }
+
+    let code = match query.code {
+        Some(ref code) => code,
+        None => return auth_error_redirect(&config, "missing_code"),
+    };
+
+    let tokens = match providers.exchange_code(provider, code, &login_state.pkce_verifier).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            println!("Error exchanging code with {}: {}", provider.id, e);
+            return auth_error_redirect(&config, e.reason());
+        }
+    };
+
+    let identity = match providers.validate_id_token(provider, &tokens.id_token, &login_state.nonce).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            println!("Rejected {} ID token: {}", provider.id, e);
+            return auth_error_redirect(&config, e.reason());
+        }
+    };
+    println!("Verified {} identity for: {}", provider.display_name, identity.profile.email);
+
+    // Only the Gmail grant needs a refresh token; other providers are used for sign-in alone
+    let refresh_token = if provider.links_gmail { tokens.refresh_token } else { None };
+    if provider.links_gmail && refresh_token.is_none() {
+        println!("No refresh token received! User won't be able to use Gmail API");
+    }
+
+    // Linking a mailbox leaves the signed-in user and their session as they are
+    if let Some(ref user_email) = login_state.link_user {
+        return match db::identities::link_mailbox(db_pool.get_ref(), user_email, &identity.profile.email, &refresh_token).await {
+            Ok(db::identities::MailboxLink::InUse) => {
+                println!("Mailbox {} is already linked to another user", identity.profile.email);
+                auth_error_redirect(&config, "mailbox_in_use")
             }
-            
-            // Get the access token
-            let access_token = token.access_token().secret();
-            println!("Received access token, length: {}", access_token.len());
-            
-            // Get user info from Google
-            let user_info_url = "https://www.googleapis.com/oauth2/v2/userinfo";
-            let user_info_response = reqwest::Client::new()
-                .get(user_info_url)
-                .header("Authorization", format!("Bearer {}", access_token))
-                .send()
-                .await;
-                
-            match user_info_response {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        match response.json::<GoogleUserInfo>().await {
-                            Ok(mut user_info) => {
-                                println!("Retrieved user info for: {}", user_info.email);
-                                
-                                // Add the refresh token to the user info
-                                user_info.refresh_token = refresh_token.clone();
-                                
-                                // Fix profile picture URL - ensure it's using HTTPS
-                                if let Some(ref pic_url) = user_info.picture {
-                                    if pic_url.starts_with("http://") {
-                                        user_info.picture = Some(pic_url.replace("http://", "https://"));
-                                    }
-                                    
-                                    // Ensure the picture URL doesn't have size restrictions that are too small
-                                    // Google sometimes returns a small sized image by default
-                                    if let Some(ref mut pic_url) = user_info.picture {
-                                        if pic_url.contains("=s") {
-                                            // Replace the size parameter with a larger one (s256-c instead of s96-c)
-                                            *pic_url = pic_url.replace("=s96-c", "=s256-c");
-                                        }
-                                    }
-                                }
-                                
-                                // Generate a session token
-                                let session_token = uuid::Uuid::new_v4().to_string();
-                                
-                                // Store the user and session in the database
-                                match db::store_user(
-                                    db_pool.get_ref(),
-                                    &user_info.email,
-                                    &user_info.name,
-                                    &user_info.picture,
-                                    &session_token,
-                                    &refresh_token,
-                                ).await {
-                                    Ok(_) => {
-                                        println!("Stored user session for: {}", user_info.email);
-                                        
-                                        // Create a cookie with the session token
-                                        let cookie = Cookie::build("session", session_token)
-                                            .path("/")
-                                            .max_age(actix_web::cookie::time::Duration::days(7))
-                                            .http_only(true)
-                                            .finish();
-                                            
-                                        // Redirect to the frontend with the cookie
-                                        println!("Redirecting to frontend: {}", auth::FRONTEND_URL);
-                                        HttpResponse::Found()
-                                            .cookie(cookie)
-                                            .append_header(("Location", auth::FRONTEND_URL))
-                                            .finish()
-                                    },
-                                    Err(e) => {
-                                        println!("Error storing user session: {}", e);
-                                        HttpResponse::InternalServerError().json(json!({
-                                            "error": "Database error",
-                                            "details": format!("{}", e)
-                                        }))
-                                    }
-                                }
-                            },
-                            Err(e) => {
-                                println!("Error parsing user info: {}", e);
-                                HttpResponse::InternalServerError().json(json!({
-                                    "error": "Failed to parse user info",
-                                    "details": format!("{}", e)
-                                }))
-                            }
-                        }
-                    } else {
-                        println!("Error response from Google API: {}", response.status());
-                        HttpResponse::InternalServerError().json(json!({
-                            "error": "Failed to get user info",
-                            "details": format!("Status: {}", response.status())
-                        }))
+            Ok(db::identities::MailboxLink::Linked) => {
+                println!("Linked Gmail mailbox {} to {}", identity.profile.email, user_email);
+                HttpResponse::Found()
+                    .cookie(auth::clear_oauth_state_cookie(config.server.cookies_secure()))
+                    .append_header(("Location", format!("{}/?mailbox_linked=1", config.server.frontend_url)))
+                    .finish()
+            }
+            Err(e) => {
+                println!("Error linking mailbox: {}", e);
+                auth_error_redirect(&config, "unavailable")
+            }
+        };
+    }
+
+    // Known provider accounts sign in as the user they were first linked to,
+    // even if their email has changed since
+    let linked_email = match db::identities::find_user_by_identity(db_pool.get_ref(), &provider.id, &identity.subject).await {
+        Ok(linked_email) => linked_email,
+        Err(e) => {
+            println!("Error looking up identity: {}", e);
+            return auth_error_redirect(&config, "unavailable");
+        }
+    };
+
+    let user_email = match linked_email {
+        Some(email) => email,
+        None => {
+            if !identity.may_create_account() {
+                let existing = match db::get_user_info(db_pool.get_ref(), &identity.profile.email).await {
+                    Ok(existing) => existing,
+                    Err(e) => {
+                        println!("Error looking up user: {}", e);
+                        return auth_error_redirect(&config, "unavailable");
                     }
-                },
-                Err(e) => {
-                    println!("Error requesting user info: {}", e);
-                    HttpResponse::InternalServerError().json(json!({
-                        "error": "Failed to request user info",
-                        "details": format!("{}", e)
-                    }))
-                }
+                };
+                println!("Refusing unverified {} identity for {}", provider.id, identity.profile.email);
+                let reason = if existing.is_some() { "identity_conflict" } else { "email_unverified" };
+                return auth_error_redirect(&config, reason);
             }
-        },
+            identity.profile.email.clone()
+        }
+    };
+
+    // Accounts waiting to be deleted stay locked until the deletion is undone
+    match db::account_deletion::get_pending_deletion(db_pool.get_ref(), &user_email).await {
+        Ok(None) => {}
+        Ok(Some(_)) => return auth_error_redirect(&config, "deletion_pending"),
+        Err(e) => {
+            println!("Error checking pending account deletion: {}", e);
+            return auth_error_redirect(&config, "unavailable");
+        }
+    }
+
+    // Generate a session token
+    let session_token = uuid::Uuid::new_v4().to_string();
+
+    // Device details shown in the session list
+    let user_agent = req.headers()
+        .get("User-Agent")
+        .and_then(|ua| ua.to_str().ok())
+        .map(|ua| ua.to_string());
+    let ip_address = req.connection_info().realip_remote_addr().map(|ip| ip.to_string());
+
+    // Store the user and their identity, link Google's mailbox, then start a session for this device only
+    let pool = db_pool.get_ref();
+    let picture = normalize_picture(identity.profile.picture.clone());
+    let stored = async {
+        db::store_user(pool, &user_email, &identity.profile.name, &picture, &None).await?;
+        db::identities::link_identity(pool, &provider.id, &identity.subject, &user_email).await?;
+        // Signing in still works when another user holds the mailbox; it just stays with them
+        if provider.links_gmail {
+            let link = db::identities::link_mailbox(pool, &user_email, &identity.profile.email, &refresh_token).await?;
+            if link == db::identities::MailboxLink::InUse {
+                println!("Mailbox {} is already linked to another user; not linking it to {}", identity.profile.email, user_email);
+            }
+        }
+        db::sessions::create_session(pool, &user_email, &session_token, user_agent.as_deref(), ip_address.as_deref()).await
+    }.await;
+
+    if let Err(e) = stored {
+        println!("Error storing user session: {}", e);
+        return HttpResponse::InternalServerError().json(json!({
+            "error": "Database error",
+            "details": format!("{}", e)
+        }));
+    }
+    println!("Stored user session for: {}", user_email);
+
+    // The configured first admin may only sign in after startup, and only through Google,
+    // which verifies the address the role is granted to
+    let verified_google = provider.id == auth::GOOGLE_PROVIDER_ID && identity.email_verified == Some(true);
+    let admin_email = config.bootstrap_admin_email()
+        .filter(|admin| verified_google && user_email.eq_ignore_ascii_case(admin));
+    if let Some(admin_email) = admin_email {
+        if let Ok(true) = db::roles::bootstrap_admin(pool, admin_email).await {
+            println!("Bootstrapped {} as the first admin", admin_email);
+        }
+    }
+
+    // Redirect to the frontend with the session cookie
+    println!("Redirecting to frontend: {}", config.server.frontend_url);
+    HttpResponse::Found()
+        .cookie(auth::create_session_cookie(&session_token, config.server.cookies_secure()))
+        .cookie(auth::clear_oauth_state_cookie(config.server.cookies_secure()))
+        .append_header(("Location", config.server.frontend_url.as_str()))
+        .finish()
+}
+
+// Detach the user's Gmail mailbox and revoke its grant; signing in is unaffected
+pub async fn unlink_mailbox(
+    user: AuthenticatedUser,
+    db_pool: DbPool,
+    gmail_client: GmailClientData,
+    attachment_cache: AttachmentCacheData,
+) -> impl Responder {
+    if let Err(e) = user.require_session() {
+        return e.error_response();
+    }
+
+    if let Some(ref refresh_token) = user.refresh_token {
+        if let Err(e) = gmail_client.revoke_token(&user.email, refresh_token).await {
+            println!("Error revoking Google grant for {}: {}", user.email, e);
+            return HttpResponse::BadGateway().json(json!({
+                "success": false,
+                "error": "Failed to revoke Google access",
+                "details": format!("{}", e)
+            }));
+        }
+    }
+
+    match db::identities::unlink_mailbox(db_pool.get_ref(), &user.email).await {
+        Ok(_) => {
+            // Attachments downloaded from the mailbox go with it
+            if let Err(e) = attachment_cache.remove_user(&user.email).await {
+                println!("Error clearing cached attachments for {}: {}", user.email, e);
+            }
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Mailbox unlinked"
+            }))
+        }
         Err(e) => {
-            println!("Error exchanging code for token: {}", e);
+            println!("Error unlinking mailbox: {}", e);
             HttpResponse::InternalServerError().json(json!({
-                "error": "Failed to exchange code for token",
+                "error": "Database error",
                 "details": format!("{}", e)
             }))
         }
     }
 }
 
-pub async fn logout() -> impl Responder {
+pub async fn logout(
+    user: Result<auth::AuthenticatedUser, auth::AuthError>,
+    db_pool: DbPool,
+    config: ConfigData,
+) -> impl Responder {
+    // End only this device's session; access tokens are revoked separately
+    if let Some(session_token) = user.as_ref().ok().and_then(|user| user.require_session().ok()) {
+        if let Err(e) = db::sessions::revoke_session_by_token(db_pool.get_ref(), session_token).await {
+            println!("Error ending session: {}", e);
+        }
+    }
+
     // Create an expired cookie to clear the session
-    let expired_cookie = auth::create_logout_cookie();
-    
+    let expired_cookie = auth::create_logout_cookie(config.server.cookies_secure());
+
     println!("User logged out");
-    
+
     // Return a response with the expired cookie and redirect to welcome page
     HttpResponse::Found()
-        .append_header(("Location", format!("{}", auth::FRONTEND_URL)))
+        .append_header(("Location", config.server.frontend_url.as_str()))
         .cookie(expired_cookie)
         .json(json!({
             "success": true,
@@ -1,221 +1,340 @@
-use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use actix_web::{web, HttpResponse, Responder, ResponseError};
 use serde_json::json;
 use uuid::Uuid;
-use base64::{encode_config, STANDARD};
+use base64::{decode_config, STANDARD};
 use log::{info, error, warn};
 
+use crate::auth::AuthenticatedUser;
 use crate::db;
-use crate::models::SendEmailRequest;
-use crate::gmail::{GmailClient, parse_gmail_message, GmailMessage};
+use crate::models::{SendEmailRequest, ForwardEmailRequest, TokenScope};
+use crate::mime::{Address, Attachment, MessageBuilder, MessageError};
+use crate::gmail::{GmailClient, GmailError, parse_gmail_message};
 use crate::cache::RedisCache;
+use crate::encryption::portal;
+use crate::sync;
 
 type DbPool = web::Data<sqlx::PgPool>;
 type GmailClientData = web::Data<std::sync::Arc<GmailClient>>;
 type RedisCacheData = web::Data<std::sync::Arc<RedisCache>>;
+type ConfigData = web::Data<crate::config::AppConfig>;
 
 // Send a new email
 pub async fn send_email(
-    req: HttpRequest,
-    email_req: web::Json<SendEmailRequest>,
+    user: AuthenticatedUser,
+    mut email_req: web::Json<SendEmailRequest>,
     db_pool: DbPool,
     gmail_client: GmailClientData,
     redis_cache: RedisCacheData,
+    config: ConfigData,
 ) -> impl Responder {
-    if let Some(cookie) = req.cookie("session") {
-        let session_token = cookie.value().to_string();
+    if let Err(e) = user.require_scope(TokenScope::SendMail) {
+        return e.error_response();
+    }
+    let email = user.email;
+    let refresh_token = user.refresh_token;
+    
+    // From here on the recipient is the bare address, lowercased like every stored address; a
+    // display name is only kept for the To header of a direct message
+    let recipient = match Address::parse(&email_req.recipient_email) {
+        Ok(recipient) => Address { name: recipient.name, email: recipient.email.to_lowercase() },
+        Err(e) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "Invalid recipient address",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    email_req.recipient_email = recipient.email.clone();
+    
+    // A Quant message only sends a notification; whatever has to travel in the email itself needs direct delivery
+    let direct = email_req.direct.unwrap_or(false);
+    let has_direct_fields = !email_req.cc.is_empty()
+        || !email_req.bcc.is_empty()
+        || email_req.reply_to.is_some()
+        || email_req.html_body.is_some()
+        || !email_req.attachments.is_empty();
+    let direct_problem = if direct && email_req.encrypt.unwrap_or(false) {
+        Some("Direct messages are sent as ordinary email and cannot be encrypted")
+    } else if direct && email_req.in_reply_to.is_some() {
+        Some("Replies to Quant messages are sent through Quant, not directly")
+    } else if !direct && has_direct_fields {
+        Some("Cc, Bcc, Reply-To, HTML and attachments can only be sent with direct delivery")
+    } else {
+        None
+    };
+    if let Some(problem) = direct_problem {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": problem
+        }));
+    }
+    
+    // A reply must answer a message the user sent or received
+    let parent = match email_req.in_reply_to.as_deref() {
+        Some(parent_id) => match db::get_email(db_pool.get_ref(), parent_id).await {
+            Ok(Some(parent)) if parent.sender_email == email || parent.recipient_email == email => Some(parent),
+            Ok(_) => {
+                return HttpResponse::NotFound().json(json!({
+                    "success": false,
+                    "error": "Email being replied to not found"
+                }));
+            }
+            Err(e) => {
+                error!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "error": "Failed to look up the email being replied to",
+                    "details": format!("{}", e)
+                }));
+            }
+        },
+        None => None,
+    };
+    
+    if let Some(refresh_token) = refresh_token {
+        if direct {
+            return send_direct_email(&email, &refresh_token, &email_req, recipient, db_pool.get_ref(), &gmail_client, &config).await;
+        }
         
-        match db::get_user_by_session(db_pool.get_ref(), &session_token).await {
-            Ok(Some((email, _, _, refresh_token))) => {
-                if let Some(refresh_token) = refresh_token {
-                    // Check if encryption is requested
-                    let should_encrypt = email_req.encrypt.unwrap_or(false);
-                    let (_, _, raw_encrypted_content) = if should_encrypt {
-                        // Get recipient's public key
-                        match crate::encryption::keys::get_public_key(db_pool.get_ref(), &email_req.recipient_email).await {
-                            Ok(Some(public_key)) => {
-                                // Encrypt the message
-                                match crate::encryption::encrypt_message(&email_req.body, &public_key) {
-                                    Ok(encrypted_msg) => {
-                                        // Serialize the encrypted message
-                                        match crate::encryption::serialize_encrypted_message(&encrypted_msg) {
-                                            Ok(content) => {
-                                                let encrypted_subject = crate::encryption::format_encrypted_subject(&email_req.subject);
-                                                let encrypted_body = crate::encryption::format_encrypted_body();
-                                                (encrypted_subject, encrypted_body, Some(content))
-                                            },
-                                            Err(e) => {
-                                                error!("Failed to serialize encrypted message: {}", e);
-                                                return HttpResponse::InternalServerError().json(json!({
-                                                    "success": false,
-                                                    "error": "Failed to encrypt message",
-                                                    "details": format!("{}", e)
-                                                }));
-                                            }
-                                        }
-                                    },
-                                    Err(e) => {
-                                        error!("Failed to encrypt message: {}", e);
-                                        return HttpResponse::InternalServerError().json(json!({
-                                            "success": false,
-                                            "error": "Failed to encrypt message",
-                                            "details": format!("{}", e)
-                                        }));
-                                    }
-                                }
-                            },
-                            Ok(None) => {
-                                // No public key found for recipient
-                                error!("No public key found for recipient: {}", email_req.recipient_email);
-                                // Generate a key pair for the recipient
-                                match crate::encryption::generate_keypair() {
-                                    Ok(keypair) => {
-                                        // Store the key pair
-                                        if let Err(e) = crate::encryption::keys::store_keypair(db_pool.get_ref(), &email_req.recipient_email, &keypair).await {
-                                            error!("Failed to store key pair: {}", e);
-                                        }
-                                        // Send without encryption this time
-                                        (email_req.subject.clone(), email_req.body.clone(), None)
-                                    },
-                                    Err(e) => {
-                                        error!("Failed to generate key pair: {}", e);
-                                        (email_req.subject.clone(), email_req.body.clone(), None)
-                                    }
+        let mailbox_email = match sending_mailbox(db_pool.get_ref(), &email).await {
+            Ok(mailbox_email) => mailbox_email,
+            Err(response) => return response,
+        };
+        
+        // Check if encryption is requested
+        let should_encrypt = email_req.encrypt.unwrap_or(false);
+        let mut portal_passcode: Option<String> = None;
+        let (_, _, raw_encrypted_content) = if should_encrypt {
+            // Get recipient's public key
+            match crate::encryption::keys::get_public_key(db_pool.get_ref(), &email_req.recipient_email).await {
+                Ok(Some(public_key)) => {
+                    // Encrypt the message
+                    match crate::encryption::encrypt_message(&email_req.body, &public_key) {
+                        Ok(encrypted_msg) => {
+                            // Serialize the encrypted message
+                            match crate::encryption::serialize_encrypted_message(&encrypted_msg) {
+                                Ok(content) => {
+                                    let encrypted_subject = crate::encryption::format_encrypted_subject(&email_req.subject);
+                                    let encrypted_body = crate::encryption::format_encrypted_body();
+                                    (encrypted_subject, encrypted_body, Some(content))
+                                },
+                                Err(e) => {
+                                    error!("Failed to serialize encrypted message: {}", e);
+                                    return HttpResponse::InternalServerError().json(json!({
+                                        "success": false,
+                                        "error": "Failed to encrypt message",
+                                        "details": format!("{}", e)
+                                    }));
                                 }
-                            },
-                            Err(e) => {
-                                error!("Failed to get public key: {}", e);
-                                return HttpResponse::InternalServerError().json(json!({
-                                    "success": false,
-                                    "error": "Failed to get recipient's public key",
-                                    "details": format!("{}", e)
-                                }));
                             }
-                        }
-                    } else {
-                        // No encryption requested
-                        (email_req.subject.clone(), email_req.body.clone(), None)
-                    };
-                    
-                    // Store original message in database
-                    let email_id = match db::store_email(
-                        db_pool.get_ref(),
-                        &email,
-                        &email,
-                        &email_req.recipient_email,
-                        &email_req.subject,
-                        &email_req.body,
-                        should_encrypt,
-                        raw_encrypted_content.as_deref(),
-                    ).await {
-                        Ok(id) => id,
+                        },
                         Err(e) => {
-                            error!("Database error: {}", e);
+                            error!("Failed to encrypt message: {}", e);
                             return HttpResponse::InternalServerError().json(json!({
                                 "success": false,
-                                "error": "Failed to store email in database",
+                                "error": "Failed to encrypt message",
                                 "details": format!("{}", e)
                             }));
                         }
+                    }
+                },
+                Ok(None) => {
+                    // Recipient has no account, encrypt under a one-time passcode for the secure portal
+                    info!("No public key found for {}, using secure portal delivery", email_req.recipient_email);
+                    let passcode = match email_req.passcode.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
+                        Some(passcode) if passcode.chars().filter(|c| c.is_ascii_alphanumeric()).count() < 8 => {
+                            return HttpResponse::BadRequest().json(json!({
+                                "success": false,
+                                "error": "Passcode must contain at least 8 letters or digits"
+                            }));
+                        }
+                        Some(passcode) => passcode.to_string(),
+                        None => portal::generate_passcode(),
                     };
                     
-                    // Generate view link for the notification email
-                    let view_link = format!("{}/?view={}", crate::auth::FRONTEND_URL, email_id);
-                    
-                    // Get sender's name from database
-                    let sender_name = match db::get_user_info(db_pool.get_ref(), &email).await {
-                        Ok(Some(user_info)) => user_info.name.unwrap_or_else(|| email.clone()),
-                        _ => email.clone(), // Fallback to email if user info not available
-                    };
-                    
-                    // Create placeholder message for Gmail notification
-                    let placeholder_subject = format!("[Quant Client] New secure message from {}", sender_name);
-                    let placeholder_body = format!(
-                        "You've received a new message from **{}** via Quant Client.\n\n\
-                        To view the full message, please click here: [Quant Client]({})\n\n\
-                        This is a notification email. The actual message content is securely stored in Quant Client.",
-                        sender_name, view_link
-                    );
-                    
-                    let raw_message = encode_config(
-                        format!(
-                            "From: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=UTF-8\r\nMIME-Version: 1.0\r\n\r\n{}",
-                            email,
-                            email_req.recipient_email,
-                            placeholder_subject,
-                            placeholder_body
-                        ),
-                        STANDARD
-                    );
-                    
-                    match gmail_client.get_token(&email, &refresh_token).await {
-                        Ok(access_token) => {
-                            match gmail_client.send_message(&email, &access_token, raw_message).await {
-                                Ok(message) => {
-                                    // Create email object based on our stored message
-                                    let email_obj = crate::models::Email {
-                                        id: email_id.clone(),
-                                        sender_id: email.clone(),
-                                        sender_email: email.clone(),
-                                        sender_name: None, // We could fetch this from user profile
-                                        recipient_email: email_req.recipient_email.clone(),
-                                        subject: email_req.subject.clone(),
-                                        body: email_req.body.clone(),
-                                        sent_at: chrono::Utc::now().to_rfc3339(),
-                                        read_at: None,
-                                        gmail_id: Some(message.id.clone()), // Store reference to notification email
-                                        label_ids: Some(vec!["SENT".to_string()]),
-                                        is_encrypted: should_encrypt,
-                                        raw_encrypted_content: raw_encrypted_content,
-                                    };
-
-                                    // Update cache with our email object
-                                    if let Err(e) = redis_cache.update_email_lists(&email, &email_obj, true).await {
-                                        error!("Failed to update cache: {}", e);
-                                    }
-
-                                    info!("Email sent and stored in database: {} -> {}", email, email_req.recipient_email);
-                                    
-                                    return HttpResponse::Ok().json(json!({
-                                        "success": true,
-                                        "email": email_obj,
-                                        "message": "Email sent successfully"
-                                    }));
-                                }
-                                Err(e) => {
-                                    error!("Gmail API error: {}", e);
-                                    return HttpResponse::InternalServerError().json(json!({
-                                        "success": false,
-                                        "error": "Failed to send notification email",
-                                        "details": format!("{}", e)
-                                    }));
-                                }
-                            }
-                        }
+                    // Deriving the key is deliberately slow, so it runs off the async workers
+                    let (body, key_passcode) = (email_req.body.clone(), passcode.clone());
+                    let encrypted = web::block(move || {
+                        portal::encrypt_with_passcode(&body, &key_passcode)
+                            .and_then(|envelope| portal::serialize_passcode_envelope(&envelope))
+                            .map_err(|e| e.to_string())
+                    }).await;
+                    match encrypted.map_err(|e| e.to_string()).and_then(|content| content) {
+                        Ok(content) => {
+                            portal_passcode = Some(passcode);
+                            (email_req.subject.clone(), portal::PORTAL_PLACEHOLDER_BODY.to_string(), Some(content))
+                        },
                         Err(e) => {
-                            error!("Gmail token error: {}", e);
+                            error!("Failed to encrypt message with passcode: {}", e);
                             return HttpResponse::InternalServerError().json(json!({
                                 "success": false,
-                                "error": "Failed to get Gmail token",
+                                "error": "Failed to encrypt message",
                                 "details": format!("{}", e)
                             }));
                         }
                     }
+                },
+                Err(e) => {
+                    error!("Failed to get public key: {}", e);
+                    return HttpResponse::InternalServerError().json(json!({
+                        "success": false,
+                        "error": "Failed to get recipient's public key",
+                        "details": format!("{}", e)
+                    }));
                 }
             }
-            Ok(None) => {
-                error!("Invalid session");
-                return HttpResponse::Unauthorized().json(json!({
+        } else {
+            // No encryption requested
+            (email_req.subject.clone(), email_req.body.clone(), None)
+        };
+        
+        // Portal messages are only kept encrypted, never as plaintext, in the database, the cache
+        // or the response
+        let stored_body = if portal_passcode.is_some() {
+            portal::PORTAL_PLACEHOLDER_BODY
+        } else {
+            email_req.body.as_str()
+        };
+        
+        // Store original message in database
+        let email_id = match db::store_email(
+            db_pool.get_ref(),
+            &email,
+            &email,
+            &email_req.recipient_email,
+            &email_req.subject,
+            stored_body,
+            should_encrypt,
+            raw_encrypted_content.as_deref(),
+        ).await {
+            Ok(id) => id,
+            Err(e) => {
+                error!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "error": "Failed to store email in database",
+                    "details": format!("{}", e)
+                }));
+            }
+        };
+        
+        // A reply joins its parent's thread; if that fails it is still delivered, as a thread of its own
+        let thread_id = match parent {
+            Some(ref parent) => match db::threads::link_reply(db_pool.get_ref(), &email_id, &parent.id).await {
+                Ok(thread_id) => thread_id,
+                Err(e) => {
+                    error!("Failed to thread reply {} under {}: {}", email_id, parent.id, e);
+                    Some(email_id.clone())
+                }
+            },
+            None => Some(email_id.clone()),
+        };
+        
+        // Get sender's name from database
+        let sender_name = match db::get_user_info(db_pool.get_ref(), &email).await {
+            Ok(Some(user_info)) => user_info.name.unwrap_or_else(|| email.clone()),
+            _ => email.clone(), // Fallback to email if user info not available
+        };
+        
+        let raw_message = match if portal_passcode.is_some() {
+            // External recipients get a link to the passcode-protected portal
+            let access_token = portal::generate_portal_token();
+            if let Err(e) = db::portal::create_portal_access(db_pool.get_ref(), &email_id, &access_token).await {
+                error!("Failed to create portal access: {}", e);
+                discard_unsent_email(db_pool.get_ref(), &email_id).await;
+                return HttpResponse::InternalServerError().json(json!({
                     "success": false,
-                    "error": "Not authenticated"
+                    "error": "Failed to create secure portal link",
+                    "details": format!("{}", e)
                 }));
             }
+            let portal_link = format!("{}/portal/{}", config.server.frontend_url, access_token);
+            build_portal_notification_message(&mailbox_email, &sender_name, &email_req.recipient_email, &portal_link)
+        } else {
+            // Generate a signed, expiring view link for the notification email
+            let view_link = crate::auth::create_view_link(&config.server.frontend_url, &email_id, &email_req.recipient_email);
+            build_notification_message(&mailbox_email, &sender_name, &email_req.recipient_email, &view_link)
+        } {
+            Ok(raw_message) => raw_message,
             Err(e) => {
-                error!("Database error: {}", e);
+                error!("Failed to build notification email: {}", e);
+                discard_unsent_email(db_pool.get_ref(), &email_id).await;
                 return HttpResponse::InternalServerError().json(json!({
-                    "error": "Database error",
+                    "success": false,
+                    "error": "Failed to build notification email",
                     "details": format!("{}", e)
                 }));
             }
+        };
+        
+        match gmail_client.get_token(&email, &refresh_token).await {
+            Ok(access_token) => {
+                match gmail_client.send_message(&email, &access_token, raw_message).await {
+                    Ok(message) => {
+                        // Optionally send the passcode in its own message
+                        if let Some(ref passcode) = portal_passcode {
+                            if email_req.send_passcode_separately.unwrap_or(false) {
+                                let sent = match build_passcode_message(&mailbox_email, &sender_name, &email_req.recipient_email, passcode) {
+                                    Ok(passcode_message) => gmail_client.send_message(&email, &access_token, passcode_message).await
+                                        .map(|_| ())
+                                        .map_err(|e| e.to_string()),
+                                    Err(e) => Err(e.to_string()),
+                                };
+                                if let Err(e) = sent {
+                                    warn!("Failed to send separate passcode email: {}", e);
+                                }
+                            }
+                        }
+                        
+                        // Create email object based on our stored message
+                        let email_obj = crate::models::Email {
+                            id: email_id.clone(),
+                            sender_id: email.clone(),
+                            sender_email: email.clone(),
+                            sender_name: None, // We could fetch this from user profile
+                            recipient_email: email_req.recipient_email.clone(),
+                            subject: email_req.subject.clone(),
+                            body: stored_body.to_string(),
+                            html_body: None,
+                            attachments: Vec::new(),
+                            sent_at: chrono::Utc::now().to_rfc3339(),
+                            read_at: None,
+                            gmail_id: Some(message.id.clone()), // Store reference to notification email
+                            label_ids: Some(vec!["SENT".to_string()]),
+                            is_encrypted: should_encrypt,
+                            raw_encrypted_content: raw_encrypted_content,
+                            recalled_at: None,
+                            thread_id,
+                        };
+
+                        // Update cache with our email object
+                        if let Err(e) = redis_cache.update_email_lists(&email, &email_obj, true).await {
+                            error!("Failed to update cache: {}", e);
+                        }
+
+                        info!("Email sent and stored in database: {} -> {}", email, email_req.recipient_email);
+                        
+                        return HttpResponse::Ok().json(json!({
+                            "success": true,
+                            "email": email_obj,
+                            "delivery": if portal_passcode.is_some() { "portal" } else { "internal" },
+                            "passcode": portal_passcode,
+                            "message": "Email sent successfully"
+                        }));
+                    }
+                    Err(e) => {
+                        error!("Failed to send notification email: {}", e);
+                        discard_unsent_email(db_pool.get_ref(), &email_id).await;
+                        return e.error_response();
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Gmail token error: {}", e);
+                discard_unsent_email(db_pool.get_ref(), &email_id).await;
+                return e.error_response();
+            }
         }
     }
     
@@ -225,94 +344,278 @@ pub async fn send_email(
     }))
 }
 
+// A message whose notification never went out must not stay readable, through the portal or otherwise
+async fn discard_unsent_email(pool: &sqlx::PgPool, email_id: &str) {
+    if let Err(e) = db::delete_unsent_email(pool, email_id).await {
+        error!("Failed to remove unsent email {}: {}", email_id, e);
+    }
+}
+
+// Send an ordinary email through the user's Gmail, content, Cc, Bcc and attachments included.
+// Nothing is stored in Quant; Gmail keeps the sent copy, and the next sync lists it.
+async fn send_direct_email(
+    email: &str,
+    refresh_token: &str,
+    email_req: &SendEmailRequest,
+    recipient: Address,
+    pool: &sqlx::PgPool,
+    gmail_client: &GmailClient,
+    config: &crate::config::AppConfig,
+) -> HttpResponse {
+    let mut attachments = Vec::with_capacity(email_req.attachments.len());
+    let mut total_size = 0;
+    for attachment in &email_req.attachments {
+        let compact: String = attachment.data.chars().filter(|c| !c.is_whitespace()).collect();
+        let data = match decode_config(&compact, STANDARD) {
+            Ok(data) => data,
+            Err(e) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "success": false,
+                    "error": format!("Attachment {} is not valid base64", attachment.filename),
+                    "details": format!("{}", e)
+                }));
+            }
+        };
+        total_size += data.len() as u64;
+        attachments.push(Attachment {
+            filename: attachment.filename.clone(),
+            mime_type: attachment.mime_type.clone().unwrap_or_else(|| "application/octet-stream".to_string()),
+            data,
+        });
+    }
+    if total_size > config.attachments.max_size_bytes {
+        return HttpResponse::PayloadTooLarge().json(json!({
+            "success": false,
+            "error": "Attachments are too large to send",
+            "details": format!("Attachments are limited to {} bytes in total", config.attachments.max_size_bytes)
+        }));
+    }
+    
+    let mailbox_email = match sending_mailbox(pool, email).await {
+        Ok(mailbox_email) => mailbox_email,
+        Err(response) => return response,
+    };
+    let sender_name = match db::get_user_info(pool, email).await {
+        Ok(Some(user_info)) => user_info.name.unwrap_or_else(|| mailbox_email.clone()),
+        _ => mailbox_email.clone(),
+    };
+    let raw_message = match direct_message(email_req, sender_address(&mailbox_email, &sender_name), recipient, attachments)
+        .and_then(|message| message.build_raw())
+    {
+        Ok(raw_message) => raw_message,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "Invalid email address",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    
+    let access_token = match gmail_client.get_token(email, refresh_token).await {
+        Ok(access_token) => access_token,
+        Err(e) => {
+            error!("Gmail token error: {}", e);
+            return e.error_response();
+        }
+    };
+    match gmail_client.send_message(email, &access_token, raw_message).await {
+        Ok(message) => {
+            info!("Direct email sent through Gmail: {} -> {}", email, email_req.recipient_email);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "delivery": "direct",
+                "gmail_id": message.id,
+                "message": "Email sent successfully"
+            }))
+        }
+        Err(e) => {
+            error!("Failed to send direct email: {}", e);
+            e.error_response()
+        }
+    }
+}
+
+// The message a direct send delivers; any address that does not parse fails it
+fn direct_message(email_req: &SendEmailRequest, from: Address, to: Address, attachments: Vec<Attachment>) -> Result<MessageBuilder, MessageError> {
+    let addresses = |list: &[String]| list.iter().map(|address| Address::parse(address)).collect::<Result<Vec<_>, _>>();
+    let mut message = MessageBuilder::new(from, to, &email_req.subject, &email_req.body);
+    message.cc = addresses(&email_req.cc)?;
+    message.bcc = addresses(&email_req.bcc)?;
+    message.reply_to = email_req.reply_to.as_deref().map(Address::parse).transpose()?;
+    message.html = email_req.html_body.clone();
+    message.attachments = attachments;
+    Ok(message)
+}
+
+// Mail goes out through the linked Gmail mailbox, so it is sent from that address; the login
+// email may belong to another provider altogether
+pub async fn sending_mailbox(pool: &sqlx::PgPool, email: &str) -> Result<String, HttpResponse> {
+    match db::identities::get_mailbox_email(pool, email).await {
+        Ok(Some(mailbox_email)) => Ok(mailbox_email),
+        Ok(None) => Err(HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": "No Gmail mailbox is linked to this account"
+        }))),
+        Err(e) => {
+            error!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to look up the linked mailbox",
+                "details": format!("{}", e)
+            })))
+        }
+    }
+}
+
+// The sender as notifications show them: by name, when the user has one
+fn sender_address(sender_email: &str, sender_name: &str) -> Address {
+    if sender_name == sender_email {
+        Address { name: None, email: sender_email.to_string() }
+    } else {
+        Address::named(sender_name, sender_email)
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Build the raw Gmail notification that points the recipient at Quant Client
+pub fn build_notification_message(mailbox_email: &str, sender_name: &str, recipient_email: &str, view_link: &str) -> Result<String, MessageError> {
+    let placeholder_subject = format!("[Quant Client] New secure message from {}", sender_name);
+    let placeholder_body = format!(
+        "You've received a new message from **{}** via Quant Client.\n\n\
+        To view the full message, please click here: [Quant Client]({})\n\n\
+        This is a notification email. The actual message content is securely stored in Quant Client.",
+        sender_name, view_link
+    );
+    let placeholder_html = format!(
+        "<p>You've received a new message from <strong>{}</strong> via Quant Client.</p>\n\
+        <p>To view the full message, please click here: <a href=\"{}\">Quant Client</a></p>\n\
+        <p>This is a notification email. The actual message content is securely stored in Quant Client.</p>",
+        escape_html(sender_name), escape_html(view_link)
+    );
+    
+    let mut message = MessageBuilder::new(
+        sender_address(mailbox_email, sender_name),
+        Address::parse(recipient_email)?,
+        &placeholder_subject,
+        &placeholder_body,
+    );
+    message.html = Some(placeholder_html);
+    message.build_raw()
+}
+
+// Build the notification for a recipient without an account
+fn build_portal_notification_message(mailbox_email: &str, sender_name: &str, recipient_email: &str, portal_link: &str) -> Result<String, MessageError> {
+    let placeholder_subject = format!("[Quant Client] New secure message from {}", sender_name);
+    let placeholder_body = format!(
+        "You've received a protected message from **{}** via Quant Client.\n\n\
+        To read it, open the secure portal: [Quant Client Secure Portal]({})\n\n\
+        You will need the passcode {} shared with you separately. No account is required.",
+        sender_name, portal_link, sender_name
+    );
+    let placeholder_html = format!(
+        "<p>You've received a protected message from <strong>{}</strong> via Quant Client.</p>\n\
+        <p>To read it, open the secure portal: <a href=\"{}\">Quant Client Secure Portal</a></p>\n\
+        <p>You will need the passcode {} shared with you separately. No account is required.</p>",
+        escape_html(sender_name), escape_html(portal_link), escape_html(sender_name)
+    );
+    
+    let mut message = MessageBuilder::new(
+        sender_address(mailbox_email, sender_name),
+        Address::parse(recipient_email)?,
+        &placeholder_subject,
+        &placeholder_body,
+    );
+    message.html = Some(placeholder_html);
+    message.build_raw()
+}
+
+// Build the separate message carrying a portal passcode
+fn build_passcode_message(mailbox_email: &str, sender_name: &str, recipient_email: &str, passcode: &str) -> Result<String, MessageError> {
+    let subject = format!("[Quant Client] Passcode for your message from {}", sender_name);
+    let body = format!(
+        "Use this one-time passcode to open the protected message from {}:\n\n{}\n\n\
+        The link to the message was sent in a separate email.",
+        sender_name, passcode
+    );
+    
+    MessageBuilder::new(sender_address(mailbox_email, sender_name), Address::parse(recipient_email)?, &subject, &body)
+        .build_raw()
+}
+
 // Get all emails for the current user (both sent and received)
 pub async fn get_emails(
-    req: HttpRequest,
+    user: AuthenticatedUser,
     query: web::Query<crate::models::EmailFilter>,
     db_pool: DbPool,
     _gmail_client: GmailClientData,
     _redis_cache: RedisCacheData,
 ) -> impl Responder {
-    if let Some(cookie) = req.cookie("session") {
-        let session_token = cookie.value().to_string();
-        
-        match db::get_user_by_session(db_pool.get_ref(), &session_token).await {
-            Ok(Some((email, _, _, _))) => {
-                let filter = query.into_inner();
-                let force_refresh = filter.force_refresh.unwrap_or(false);
-                
-                // Get emails directly from our database
-                let mut all_emails = Vec::new();
-                
-                // Get sent emails
-                match db::email::get_emails_for_user(db_pool.get_ref(), &email, true, Some(&filter)).await {
-                    Ok(sent) => {
-                        all_emails.extend(sent);
-                    },
-                    Err(e) => {
-                        error!("Database error retrieving sent emails: {}", e);
-                    }
-                }
-                
-                // Get received emails
-                match db::email::get_emails_for_user(db_pool.get_ref(), &email, false, Some(&filter)).await {
-                    Ok(received) => {
-                        all_emails.extend(received);
-                    },
-                    Err(e) => {
-                        error!("Database error retrieving received emails: {}", e);
-                    }
-                }
-                
-                // Apply any additional filters from the request
-                let filtered_emails = apply_filters_to_emails(all_emails, &filter);
-                
-                // Create paginated response
-                let page = filter.page.unwrap_or(0);
-                let page_size = filter.page_size.unwrap_or(50);
-                
-                let total_items = filtered_emails.len();
-                let total_pages = (total_items as f64 / page_size as f64).ceil() as i32;
-                
-                let start = (page * page_size) as usize;
-                let end = (start + page_size as usize).min(filtered_emails.len());
-                
-                let emails_page = if start < filtered_emails.len() {
-                    filtered_emails[start..end].to_vec()
-                } else {
-                    Vec::new()
-                };
-                
-                return HttpResponse::Ok().json(json!({
-                    "success": true,
-                    "emails": emails_page,
-                    "totalPages": total_pages,
-                    "currentPage": page,
-                    "cached": !force_refresh,
-                    "message": "Emails retrieved from database",
-                }));
-            }
-            Ok(None) => {
-                return HttpResponse::Unauthorized().json(json!({
-                    "success": false,
-                    "error": "Invalid session"
-                }));
-            }
-            Err(e) => {
-                error!("Database error: {}", e);
-                return HttpResponse::InternalServerError().json(json!({
-                    "success": false,
-                    "error": "Database error",
-                    "details": format!("{}", e)
-                }));
-            }
+    if let Err(e) = user.require_scope(TokenScope::ReadMail) {
+        return e.error_response();
+    }
+    let email = user.email;
+    
+    let filter = query.into_inner();
+    let force_refresh = filter.force_refresh.unwrap_or(false);
+    
+    // Get emails directly from our database
+    let mut all_emails = Vec::new();
+    
+    // Get sent emails
+    match db::email::get_emails_for_user(db_pool.get_ref(), &email, true, Some(&filter)).await {
+        Ok(sent) => {
+            all_emails.extend(sent);
+        },
+        Err(e) => {
+            error!("Database error retrieving sent emails: {}", e);
+        }
+    }
+    
+    // Get received emails
+    match db::email::get_emails_for_user(db_pool.get_ref(), &email, false, Some(&filter)).await {
+        Ok(received) => {
+            all_emails.extend(received);
+        },
+        Err(e) => {
+            error!("Database error retrieving received emails: {}", e);
         }
-    } else {
-        return HttpResponse::Unauthorized().json(json!({
-            "success": false,
-            "error": "Not authenticated"
-        }));
     }
+    
+    // Apply any additional filters from the request
+    let filtered_emails = apply_filters_to_emails(all_emails, &filter);
+    
+    // Create paginated response
+    let page = filter.page.unwrap_or(0);
+    let page_size = filter.page_size.unwrap_or(50);
+    
+    let total_items = filtered_emails.len();
+    let total_pages = (total_items as f64 / page_size as f64).ceil() as i32;
+    
+    let start = (page * page_size) as usize;
+    let end = (start + page_size as usize).min(filtered_emails.len());
+    
+    let emails_page = if start < filtered_emails.len() {
+        filtered_emails[start..end].to_vec()
+    } else {
+        Vec::new()
+    };
+    
+    HttpResponse::Ok().json(json!({
+        "success": true,
+        "emails": emails_page,
+        "totalPages": total_pages,
+        "currentPage": page,
+        "cached": !force_refresh,
+        "message": "Emails retrieved from database",
+    }))
 }
 
 // Apply filters directly to a list of emails (for cached results)
@@ -404,242 +707,65 @@ fn apply_filters_to_emails(emails: Vec<crate::models::Email>, filter: &crate::mo
     filtered_emails
 }
 
-// Force refresh emails from Gmail API
+// Sync the mailbox with Gmail: apply what changed since the last sync, or copy it again when that is not possible
 pub async fn refresh_emails(
-    req: HttpRequest,
+    user: AuthenticatedUser,
     db_pool: DbPool,
     gmail_client: GmailClientData,
     redis_cache: RedisCacheData,
 ) -> impl Responder {
-    if let Some(cookie) = req.cookie("session") {
-        let session_token = cookie.value().to_string();
-        
-        match db::get_user_by_session(db_pool.get_ref(), &session_token).await {
-            Ok(Some((email, _, _, refresh_token))) => {
-                info!("Manual refresh requested for user: {}", email);
-                
-                // If refresh token exists, get emails from Gmail
-                if let Some(refresh_token) = refresh_token.clone() {
-                    match gmail_client.get_token(&email, &refresh_token).await {
-                        Ok(access_token) => {
-                            // We only need to fetch the most recent emails
-                            // For a refresh, we limit to the most recent 20 emails
-                            // This makes refresh much faster than a full sync
-                            const REFRESH_LIMIT: usize = 20;
-                            
-                            // Get the timestamp of last sync to optimize refresh
-                            let _last_sync_timestamp = redis_cache.get_last_sync(&email).await.unwrap_or(None);
-                            
-                            // Fetch inbox messages (most recent only)
-                            let received_future = async {
-                                if let Ok(messages) = gmail_client.get_messages_with_limit(&email, &access_token, None, REFRESH_LIMIT).await {
-                                let mut received_emails = Vec::new();
-                                    
-                                    // Use futures to process messages concurrently
-                                    use futures::{stream, StreamExt};
-                                    const CONCURRENT_REQUESTS: usize = 5;
-                                    
-                                    let message_stream = stream::iter(messages)
-                                        .map(|msg_id| {
-                                            let email_clone = email.clone();
-                                            let access_token_clone = access_token.clone();
-                                            let gmail_client_clone = gmail_client.clone();
-                                            
-                                            async move {
-                                                if let Ok(message) = gmail_client_clone.get_message_detail(&email_clone, &access_token_clone, &msg_id.id).await {
-                                                    if let Some(email_obj) = process_gmail_message(&message, &email_clone) {
-                                                        // Only include emails addressed to the user
-                                                        if email_obj.recipient_email == email_clone {
-                                                            Some(email_obj)
-                                                        } else {
-                                                            None
-                                                        }
-                                                    } else {
-                                                        None
-                                                    }
-                                                } else {
-                                                    None
-                                                }
-                                            }
-                                        })
-                                        .buffer_unordered(CONCURRENT_REQUESTS);
-                                    
-                                    let mut results = message_stream.collect::<Vec<_>>().await;
-                                    for result in results.drain(..) {
-                                        if let Some(email_obj) = result {
-                                            received_emails.push(email_obj);
-                                        }
-                                    }
-                                    
-                                    Some(received_emails)
-                                } else {
-                                    None
-                                }
-                            };
-                            
-                            // Fetch sent emails (most recent only)
-                            let sent_future = async {
-                                if let Ok(messages) = gmail_client.get_messages_with_limit(&email, &access_token, Some("in:sent"), REFRESH_LIMIT).await {
-                                let mut sent_emails = Vec::new();
-                                    
-                                    // Use futures to process messages concurrently
-                                    use futures::{stream, StreamExt};
-                                    const CONCURRENT_REQUESTS: usize = 5;
-                                    
-                                    let message_stream = stream::iter(messages)
-                                        .map(|msg_id| {
-                                            let email_clone = email.clone();
-                                            let access_token_clone = access_token.clone();
-                                            let gmail_client_clone = gmail_client.clone();
-                                            
-                                            async move {
-                                                if let Ok(message) = gmail_client_clone.get_message_detail(&email_clone, &access_token_clone, &msg_id.id).await {
-                                                    if let Some(email_obj) = process_gmail_message(&message, &email_clone) {
-                                                        // Only include emails where user is the sender
-                                                        if email_obj.sender_email == email_clone {
-                                                            Some(email_obj)
-                                                        } else {
-                                                            None
-                                                        }
-                                                    } else {
-                                                        None
-                                                    }
-                                                } else {
-                                                    None
-                                                }
-                                            }
-                                        })
-                                        .buffer_unordered(CONCURRENT_REQUESTS);
-                                    
-                                    let mut results = message_stream.collect::<Vec<_>>().await;
-                                    for result in results.drain(..) {
-                                        if let Some(email_obj) = result {
-                                            sent_emails.push(email_obj);
-                                        }
-                                    }
-                                    
-                                    Some(sent_emails)
-                                } else {
-                                    None
-                                }
-                            };
-                            
-                            // Execute both futures concurrently
-                            let (received_result, sent_result) = tokio::join!(received_future, sent_future);
-                            
-                            // Update cache with new emails
-                            let mut new_emails = Vec::new();
-                            
-                            if let Some(received) = received_result {
-                                // Get the existing cache
-                                if let Ok(Some((mut cached_received, _, _))) = redis_cache.get_cached_emails_paginated(&email, "received", 0, None).await {
-                                    // Create a set of existing ids for fast lookup
-                                    let existing_ids: std::collections::HashSet<String> = cached_received.iter()
-                                        .map(|e| e.id.clone())
-                                        .collect();
-                                    
-                                    // Add new emails to the beginning
-                                    for new_email in &received {
-                                        if !existing_ids.contains(&new_email.id) {
-                                            cached_received.insert(0, new_email.clone());
-                                            new_emails.push(new_email.clone());
-                                        }
-                                    }
-                                    
-                                    // Update the cache with a reasonable TTL (4 hours)
-                                    let cache_ttl = Some(4 * 60 * 60); // 4 hours in seconds
-                                    redis_cache.cache_emails_paginated(&email, "received", &cached_received, cache_ttl).await
-                                        .unwrap_or_else(|e| error!("Failed to update received emails cache: {}", e));
-                                } else {
-                                    // No existing cache, just cache the fetched emails
-                                    redis_cache.cache_emails_paginated(&email, "received", &received, None).await
-                                        .unwrap_or_else(|e| error!("Failed to cache received emails: {}", e));
-                                    new_emails.extend(received.clone());
-                                }
-                            }
-                            
-                            if let Some(sent) = sent_result {
-                                // Get the existing cache
-                                if let Ok(Some((mut cached_sent, _, _))) = redis_cache.get_cached_emails_paginated(&email, "sent", 0, None).await {
-                                    // Create a set of existing ids for fast lookup
-                                    let existing_ids: std::collections::HashSet<String> = cached_sent.iter()
-                                        .map(|e| e.id.clone())
-                                        .collect();
-                                    
-                                    // Add new emails to the beginning
-                                    for new_email in &sent {
-                                        if !existing_ids.contains(&new_email.id) {
-                                            cached_sent.insert(0, new_email.clone());
-                                            new_emails.push(new_email.clone());
-                                        }
-                                    }
-                                    
-                                    // Update the cache with a reasonable TTL (4 hours)
-                                    let cache_ttl = Some(4 * 60 * 60); // 4 hours in seconds
-                                    redis_cache.cache_emails_paginated(&email, "sent", &cached_sent, cache_ttl).await
-                                        .unwrap_or_else(|e| error!("Failed to update sent emails cache: {}", e));
-                                } else {
-                                    // No existing cache, just cache the fetched emails
-                                    redis_cache.cache_emails_paginated(&email, "sent", &sent, None).await
-                                        .unwrap_or_else(|e| error!("Failed to cache sent emails: {}", e));
-                                    new_emails.extend(sent.clone());
-                                }
-                            }
-                            
-                            // Update last sync timestamp
-                            let current_time = chrono::Utc::now().timestamp();
-                            redis_cache.set_last_sync(&email).await
-                                .unwrap_or_else(|e| error!("Failed to update last sync timestamp: {}", e));
-                            
-                            return HttpResponse::Ok().json(json!({
-                                "success": true,
-                                "message": "Emails refreshed successfully",
-                                "new_emails": new_emails.len(),
-                                "last_sync": current_time
-                            }));
-                        }
-                        Err(e) => {
-                            error!("Gmail token error: {}", e);
-                        return HttpResponse::InternalServerError().json(json!({
-                            "success": false,
-                                "error": "Failed to get Gmail token",
-                            "details": format!("{}", e)
-                        }));
-                        }
+    if let Err(e) = user.require_scope(TokenScope::ReadMail) {
+        return e.error_response();
+    }
+    let email = user.email;
+    let refresh_token = user.refresh_token;
+    
+    info!("Manual refresh requested for user: {}", email);
+    
+    // If refresh token exists, sync with Gmail
+    if let Some(refresh_token) = refresh_token {
+        match gmail_client.get_token(&email, &refresh_token).await {
+            Ok(access_token) => {
+                let outcome = match sync::sync_mailbox(db_pool.get_ref(), &gmail_client, &redis_cache, &email, &access_token).await {
+                    Ok(outcome) => outcome,
+                    Err(e) => {
+                        error!("Failed to sync mailbox for {}: {}", email, e);
+                        return e.error_response();
                     }
-                } else {
-                    return HttpResponse::BadRequest().json(json!({
-                        "success": false,
-                        "error": "No Gmail refresh token found"
-                    }));
-                }
-            }
-            Ok(None) => {
-                error!("Invalid session");
-                HttpResponse::Unauthorized().json(json!({
-                    "success": false,
-                    "error": "Not authenticated"
+                };
+                
+                // Update last sync timestamp
+                let current_time = chrono::Utc::now().timestamp();
+                redis_cache.set_last_sync(&email).await
+                    .unwrap_or_else(|e| error!("Failed to update last sync timestamp: {}", e));
+                
+                HttpResponse::Ok().json(json!({
+                    "success": true,
+                    "message": "Emails refreshed successfully",
+                    "sync": outcome.mode.as_str(),
+                    "new_emails": outcome.added,
+                    "deleted_emails": outcome.deleted,
+                    "relabeled_emails": outcome.relabeled,
+                    "history_id": outcome.history_id,
+                    "last_sync": current_time
                 }))
             }
             Err(e) => {
-                error!("Database error: {}", e);
-                HttpResponse::InternalServerError().json(json!({
-                    "error": "Database error",
-                    "details": format!("{}", e)
-                }))
+                error!("Gmail token error: {}", e);
+                e.error_response()
             }
         }
     } else {
-    HttpResponse::Unauthorized().json(json!({
-        "success": false,
-        "error": "Not authenticated"
-    }))
+        HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": "No Gmail refresh token found"
+        }))
     }
 }
 
 // Get a specific email by ID
 pub async fn get_email(
-    req: HttpRequest,
+    user: AuthenticatedUser,
     path: web::Path<String>,
     db_pool: DbPool,
     gmail_client: GmailClientData,
@@ -647,222 +773,191 @@ pub async fn get_email(
 ) -> impl Responder {
     let email_id = path.into_inner();
     
-    // Check if user is authenticated
-    if let Some(cookie) = req.cookie("session") {
-        let session_token = cookie.value().to_string();
-        
-        // Look up user by session token
-        match db::get_user_by_session(db_pool.get_ref(), &session_token).await {
-            Ok(Some((email, _, _, refresh_token))) => {
-                // First try to get from cache if it's a Gmail ID
-                if let Some(gmail_id) = email_id.strip_prefix("gmail_") {
-                    match redis_cache.get_cached_email(&email, gmail_id).await {
-                        Ok(Some(cached_email)) => {
-                            println!("Retrieved email {} from cache", gmail_id);
-                            return HttpResponse::Ok().json(json!({
-                                "success": true,
-                                "email": cached_email,
-                                "source": "cache"
-                            }));
-                        },
-                        _ => {
-                            println!("Email {} not found in cache", gmail_id);
-                            // Continue to try other methods
-                        }
-                    }
-                }
-                
-                // Store a clone of refresh_token to avoid ownership issues
-                let refresh_token_clone = refresh_token.clone();
-                
-                // Check if this is a Gmail ID (starts with numbers/letters, not UUID format)
-                if let Some(refresh_token) = refresh_token {
-                    // Check if this looks like a Gmail ID (not a UUID)
-                    if !email_id.contains('-') {
-                        // Try to get the email from Gmail API
-                        match gmail_client.get_token(&email, &refresh_token).await {
-                            Ok(access_token) => {
-                                match gmail_client.get_message_detail(&email, &access_token, &email_id).await {
-                                    Ok(message) => {
-                                        let (subject, sender, sender_name, recipient, body) = parse_gmail_message(&message);
-                                        
-                                        if !sender.is_empty() && !recipient.is_empty() {
-                                            // Create a database-style email object
-                                            let is_encrypted = subject.contains("[Q-ENCRYPTED]");
-                                            let email_obj = crate::models::Email {
-                                                id: Uuid::new_v4().to_string(),
-                                                sender_id: sender.clone(),
-                                                sender_email: sender,
-                                                sender_name: Some(sender_name),
-                                                recipient_email: recipient,
-                                                subject,
-                                                body,
-                                                sent_at: message.internal_date.unwrap_or_else(|| "".to_string()),
-                                                read_at: None,
-                                                gmail_id: Some(message.id.clone()),
-                                                label_ids: message.label_ids.clone(),
-                                                is_encrypted,
-                                                raw_encrypted_content: None,
-                                            };
-                                            
-                                            // Cache the email
-                                            let _ = redis_cache.cache_email(&email, &message.id, &email_obj).await;
-                                            
-                                            return HttpResponse::Ok().json(json!({
-                                                "success": true,
-                                                "email": email_obj,
-                                                "source": "gmail"
-                                            }));
-                                        }
-                                    }
-                                    Err(e) => {
-                                        println!("Error fetching email from Gmail API: {}", e);
-                                        // Fall through to database lookup
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                println!("Error getting Gmail access token: {}", e);
-                                // Fall through to database lookup
-                            }
-                        }
-                    }
-                }
-                
-                // Get the email from database
-                match db::get_email(db_pool.get_ref(), &email_id).await {
-                    Ok(Some(found_email)) => {
-                        // Check if user is either sender or recipient
-                        if found_email.sender_email == email || found_email.recipient_email == email {
-                            // Mark as read if user is recipient and email is not read yet
-                            if found_email.recipient_email == email && found_email.read_at.is_none() {
-                                // Mark email as read in database and update label_ids
-                                let now = chrono::Utc::now().to_rfc3339();
-                                let mut updated_email = found_email.clone();
-                                updated_email.read_at = Some(now.clone());
-                                
-                                // Remove UNREAD label if it exists
-                                if let Some(ref mut labels) = updated_email.label_ids {
-                                    if let Some(pos) = labels.iter().position(|label| label == "UNREAD") {
-                                        labels.remove(pos);
-                                        info!("Removed UNREAD label for email {}", email_id);
-                                    }
-                                }
-                                
-                                // Update in database
-                                if let Some(gmail_id) = &updated_email.gmail_id {
-                                    if let Some(refresh_token) = &refresh_token_clone {
-                                        // Update read status in Gmail via API
-                                        if let Ok(access_token) = gmail_client.get_token(&email, refresh_token).await {
-                                            let _ = gmail_client.modify_message(
-                                                &email, 
-                                                &access_token, 
-                                                gmail_id, 
-                                                &vec![], // add labels (none)
-                                                &vec!["UNREAD".to_string()] // remove labels (UNREAD)
-                                            ).await;
-                                            info!("Updated read status in Gmail for email {}", gmail_id);
-                                        }
-                                    }
-                                }
+    if let Err(e) = user.require_scope(TokenScope::ReadMail) {
+        return e.error_response();
+    }
+    let email = user.email;
+    let refresh_token = user.refresh_token;
+    
+    // First try to get from cache if it's a Gmail ID
+    if let Some(gmail_id) = email_id.strip_prefix("gmail_") {
+        match redis_cache.get_cached_email(&email, gmail_id).await {
+            Ok(Some(cached_email)) => {
+                println!("Retrieved email {} from cache", gmail_id);
+                return HttpResponse::Ok().json(json!({
+                    "success": true,
+                    "email": cached_email,
+                    "source": "cache"
+                }));
+            },
+            _ => {
+                println!("Email {} not found in cache", gmail_id);
+                // Continue to try other methods
+            }
+        }
+    }
+    
+    // Store a clone of refresh_token to avoid ownership issues
+    let refresh_token_clone = refresh_token.clone();
+    
+    // Check if this is a Gmail ID (starts with numbers/letters, not UUID format)
+    if let Some(refresh_token) = refresh_token {
+        // Check if this looks like a Gmail ID (not a UUID)
+        if !email_id.contains('-') {
+            // Try to get the email from Gmail API
+            match gmail_client.get_token(&email, &refresh_token).await {
+                Ok(access_token) => {
+                    match gmail_client.get_message_detail(&email, &access_token, &email_id).await {
+                        Ok(message) => {
+                            let (subject, sender, sender_name, recipient, body) = parse_gmail_message(&message);
+                            
+                            if !sender.is_empty() && !recipient.is_empty() {
+                                // Create a database-style email object
+                                let is_encrypted = subject.contains("[Q-ENCRYPTED]");
+                                let email_obj = crate::models::Email {
+                                    id: Uuid::new_v4().to_string(),
+                                    sender_id: sender.clone(),
+                                    sender_email: sender,
+                                    sender_name: Some(sender_name),
+                                    recipient_email: recipient,
+                                    subject,
+                                    attachments: body.all_attachments(),
+                                    html_body: body.html,
+                                    body: body.text,
+                                    sent_at: message.internal_date.unwrap_or_else(|| "".to_string()),
+                                    read_at: None,
+                                    gmail_id: Some(message.id.clone()),
+                                    label_ids: message.label_ids.clone(),
+                                    is_encrypted,
+                                    raw_encrypted_content: None,
+                                    recalled_at: None,
+                                    thread_id: Some(format!("gmail_{}", message.thread_id)),
+                                };
                                 
-                                // Update cache with new read status
-                                if let Some(ref gmail_id) = updated_email.gmail_id {
-                                    let _ = redis_cache.cache_email(&email, gmail_id, &updated_email).await;
-                                    info!("Updated cache with read status for email {}", gmail_id);
-                                }
+                                // Cache the email
+                                let _ = redis_cache.cache_email(&email, &message.id, &email_obj).await;
                                 
                                 return HttpResponse::Ok().json(json!({
                                     "success": true,
-                                    "email": updated_email,
-                                    "source": "database",
-                                    "read_updated": true
+                                    "email": email_obj,
+                                    "source": "gmail"
                                 }));
                             }
-                            
-                            // Cache the email if it has a Gmail ID
-                            if let Some(ref gmail_id) = found_email.gmail_id {
-                                let _ = redis_cache.cache_email(&email, gmail_id, &found_email).await;
-                            }
-                            
-                            return HttpResponse::Ok().json(json!({
-                                "success": true,
-                                "email": found_email,
-                                "source": "database"
-                            }));
-                        } else {
-                            return HttpResponse::Forbidden().json(json!({
-                                "success": false,
-                                "error": "You don't have permission to view this email"
-                            }));
                         }
-                    }
-                    Ok(None) => {
-                        return HttpResponse::NotFound().json(json!({
-                            "success": false,
-                            "error": "Email not found"
-                        }));
-                    }
-                    Err(e) => {
-                        println!("Database error when fetching email: {}", e);
-                        return HttpResponse::InternalServerError().json(json!({
-                            "success": false,
-                            "error": "Failed to fetch email",
-                            "details": format!("{}", e)
-                        }));
+                        Err(GmailError::NotFound(e)) => {
+                            println!("Email {} not found in Gmail: {}", email_id, e);
+                            // Fall through to database lookup
+                        }
+                        Err(e) => {
+                            println!("Error fetching email from Gmail API: {}", e);
+                            return e.error_response();
+                        }
                     }
                 }
+                Err(e) => {
+                    println!("Error getting Gmail access token: {}", e);
+                    return e.error_response();
+                }
             }
-            Ok(None) => {
-                println!("Invalid session token when fetching email");
-                return HttpResponse::Unauthorized().json(json!({
-                    "success": false,
-                    "error": "Not authenticated"
-                }));
+        }
+    }
+    
+    // A received message is marked read by the same statement that returns it, so a recall
+    // either lands before the body is read or fails
+    let opened = match db::mark_email_read(db_pool.get_ref(), &email_id, &email).await {
+        Ok(opened) => opened,
+        Err(e) => {
+            println!("Database error when opening email: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to fetch email",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    if let Some((mut opened_email, newly_read)) = opened {
+        if newly_read {
+            // Remove UNREAD label if it exists
+            if let Some(ref mut labels) = opened_email.label_ids {
+                if let Some(pos) = labels.iter().position(|label| label == "UNREAD") {
+                    labels.remove(pos);
+                    info!("Removed UNREAD label for email {}", email_id);
+                }
             }
-            Err(e) => {
-                println!("Database error during authentication check: {}", e);
-                return HttpResponse::InternalServerError().json(json!({
-                    "error": "Database error",
-                    "details": format!("{}", e)
-                }));
+            
+            if let Some(gmail_id) = &opened_email.gmail_id {
+                if let Some(refresh_token) = &refresh_token_clone {
+                    // Update read status in Gmail via API
+                    if let Ok(access_token) = gmail_client.get_token(&email, refresh_token).await {
+                        let _ = gmail_client.modify_message(
+                            &email, 
+                            &access_token, 
+                            gmail_id, 
+                            &vec![], // add labels (none)
+                            &vec!["UNREAD".to_string()] // remove labels (UNREAD)
+                        ).await;
+                        info!("Updated read status in Gmail for email {}", gmail_id);
+                    }
+                }
             }
         }
+        
+        // Update cache with the read status
+        if let Some(ref gmail_id) = opened_email.gmail_id {
+            let _ = redis_cache.cache_email(&email, gmail_id, &opened_email).await;
+        }
+        
+        return HttpResponse::Ok().json(json!({
+            "success": true,
+            "email": opened_email,
+            "source": "database",
+            "read_updated": newly_read
+        }));
     }
     
-    // No session cookie found
-    HttpResponse::Unauthorized().json(json!({
-        "success": false,
-        "error": "Not authenticated"
-    }))
+    // Get the email from database
+    match db::get_email(db_pool.get_ref(), &email_id).await {
+        Ok(Some(found_email)) => {
+            // Check if user is either sender or recipient
+            if found_email.sender_email == email || found_email.recipient_email == email {
+                // Cache the email if it has a Gmail ID
+                if let Some(ref gmail_id) = found_email.gmail_id {
+                    let _ = redis_cache.cache_email(&email, gmail_id, &found_email).await;
+                }
+                
+                HttpResponse::Ok().json(json!({
+                    "success": true,
+                    "email": found_email,
+                    "source": "database"
+                }))
+            } else {
+                HttpResponse::Forbidden().json(json!({
+                    "success": false,
+                    "error": "You don't have permission to view this email"
+                }))
+            }
+        }
+        Ok(None) => {
+            HttpResponse::NotFound().json(json!({
+                "success": false,
+                "error": "Email not found"
+            }))
+        }
+        Err(e) => {
+            println!("Database error when fetching email: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to fetch email",
+                "details": format!("{}", e)
+            }))
+        }
+    }
 }
 
 // Helper function to process a Gmail message into our Email model
-fn process_gmail_message(message: &GmailMessage, _user_email: &str) -> Option<crate::models::Email> {
-    let (subject, from_email, from_name, to_email, body) = parse_gmail_message(message);
-    
-    let date = message.internal_date.clone().unwrap_or_else(|| "".to_string());
-    
-    Some(crate::models::Email {
-        id: Uuid::new_v4().to_string(),
-        sender_id: from_email.clone(),
-        sender_email: from_email,
-        sender_name: Some(from_name),
-        recipient_email: to_email,
-        subject: subject.clone(),
-        body: body,
-        sent_at: date,
-        read_at: None,
-        gmail_id: Some(message.id.clone()),
-        label_ids: message.label_ids.clone(),
-        is_encrypted: subject.contains("[Q-ENCRYPTED]"),
-        raw_encrypted_content: None,
-    })
-}
-
 // Mark an email as read
 pub async fn mark_email_as_read(
-    req: HttpRequest,
+    user: AuthenticatedUser,
     path: web::Path<String>,
     db_pool: DbPool,
     gmail_client: GmailClientData,
@@ -870,326 +965,699 @@ pub async fn mark_email_as_read(
 ) -> impl Responder {
     let email_id = path.into_inner();
     
-    // Check if user is authenticated
-    if let Some(cookie) = req.cookie("session") {
-        let session_token = cookie.value().to_string();
-        
-        // Look up user by session token
-        match db::get_user_by_session(db_pool.get_ref(), &session_token).await {
-            Ok(Some((email, _, _, refresh_token))) => {
-                info!("Marking email {} as read for user {}", email_id, email);
-                
-                // Get the email to check ownership and current read status
-                match db::get_email(db_pool.get_ref(), &email_id).await {
-                    Ok(Some(found_email)) => {
-                        // Check if user is recipient (only recipients can mark as read)
-                        if found_email.recipient_email != email {
-                            return HttpResponse::Forbidden().json(json!({
-                                "success": false,
-                                "error": "You can only mark emails where you are the recipient as read"
-                            }));
-                        }
-                        
-                        // Check if already read
-                        if found_email.read_at.is_some() {
-                            return HttpResponse::Ok().json(json!({
-                                "success": true,
-                                "email": found_email,
-                                "message": "Email already marked as read"
-                            }));
-                        }
-                        
-                        // Mark email as read in database and update label_ids
-                        let now = chrono::Utc::now().to_rfc3339();
-                        let mut updated_email = found_email.clone();
-                        updated_email.read_at = Some(now.clone());
-                        
-                        // Remove UNREAD label if it exists
-                        if let Some(ref mut labels) = updated_email.label_ids {
-                            if let Some(pos) = labels.iter().position(|label| label == "UNREAD") {
-                                labels.remove(pos);
-                                info!("Removed UNREAD label for email {}", email_id);
-                            }
-                        }
-                        
-                        // If it has a Gmail ID, update in Gmail
-                        if let Some(gmail_id) = &updated_email.gmail_id {
-                            if let Some(refresh_token) = &refresh_token {
-                                // Update read status in Gmail via API
-                                if let Ok(access_token) = gmail_client.get_token(&email, refresh_token).await {
-                                    match gmail_client.modify_message(
-                                        &email, 
-                                        &access_token, 
-                                        gmail_id, 
-                                        &vec![], // add labels (none)
-                                        &vec!["UNREAD".to_string()] // remove labels (UNREAD)
-                                    ).await {
-                                        Ok(_) => info!("Updated read status in Gmail for email {}", gmail_id),
-                                        Err(e) => warn!("Failed to update Gmail labels: {}", e),
-                                    }
-                                }
-                            }
-                        }
-                        
-                        // Update cache with new read status
-                        if let Some(ref gmail_id) = updated_email.gmail_id {
-                            let _ = redis_cache.cache_email(&email, gmail_id, &updated_email).await;
-                            info!("Updated cache with read status for email {}", gmail_id);
+    if let Err(e) = user.require_scope(TokenScope::ReadMail) {
+        return e.error_response();
+    }
+    let email = user.email;
+    let refresh_token = user.refresh_token;
+    
+    info!("Marking email {} as read for user {}", email_id, email);
+    
+    // Get the email to check ownership
+    match db::get_email(db_pool.get_ref(), &email_id).await {
+        Ok(Some(found_email)) => {
+            // Check if user is recipient (only recipients can mark as read)
+            if found_email.recipient_email != email {
+                return HttpResponse::Forbidden().json(json!({
+                    "success": false,
+                    "error": "You can only mark emails where you are the recipient as read"
+                }));
+            }
+            
+            // Marked read in one statement, so a concurrent recall either lands first or fails
+            let mut updated_email = match db::mark_email_read(db_pool.get_ref(), &email_id, &email).await {
+                Ok(Some((read_email, false))) => {
+                    return HttpResponse::Ok().json(json!({
+                        "success": true,
+                        "email": read_email,
+                        "message": "Email already marked as read"
+                    }));
+                }
+                Ok(Some((updated_email, true))) => updated_email,
+                Ok(None) => {
+                    return HttpResponse::Gone().json(json!({
+                        "success": false,
+                        "error": "This email was recalled by the sender"
+                    }));
+                }
+                Err(e) => {
+                    error!("Failed to persist read status for email {}: {}", email_id, e);
+                    return HttpResponse::InternalServerError().json(json!({
+                        "success": false,
+                        "error": "Internal server error"
+                    }));
+                }
+            };
+            
+            // Remove UNREAD label if it exists
+            if let Some(ref mut labels) = updated_email.label_ids {
+                if let Some(pos) = labels.iter().position(|label| label == "UNREAD") {
+                    labels.remove(pos);
+                    info!("Removed UNREAD label for email {}", email_id);
+                }
+            }
+            
+            // If it has a Gmail ID, update in Gmail
+            if let Some(gmail_id) = &updated_email.gmail_id {
+                if let Some(refresh_token) = &refresh_token {
+                    // Update read status in Gmail via API
+                    if let Ok(access_token) = gmail_client.get_token(&email, refresh_token).await {
+                        match gmail_client.modify_message(
+                            &email, 
+                            &access_token, 
+                            gmail_id, 
+                            &vec![], // add labels (none)
+                            &vec!["UNREAD".to_string()] // remove labels (UNREAD)
+                        ).await {
+                            Ok(_) => info!("Updated read status in Gmail for email {}", gmail_id),
+                            Err(e) => warn!("Failed to update Gmail labels: {}", e),
                         }
-                        
-                        return HttpResponse::Ok().json(json!({
-                            "success": true,
-                            "email": updated_email,
-                            "message": "Email marked as read"
-                        }));
-                    }
-                    Ok(None) => {
-                        return HttpResponse::NotFound().json(json!({
-                            "success": false,
-                            "error": "Email not found"
-                        }));
-                    }
-                    Err(e) => {
-                        error!("Database error when getting email: {}", e);
-                        return HttpResponse::InternalServerError().json(json!({
-                            "success": false,
-                            "error": "Internal server error"
-                        }));
                     }
                 }
             }
-            Ok(None) => {
-                info!("Session not found: {}", session_token);
-                return HttpResponse::Unauthorized().json(json!({
-                    "success": false,
-                    "error": "Not authenticated"
-                }));
+            
+            // Update cache with new read status
+            if let Some(ref gmail_id) = updated_email.gmail_id {
+                let _ = redis_cache.cache_email(&email, gmail_id, &updated_email).await;
+                info!("Updated cache with read status for email {}", gmail_id);
             }
-            Err(e) => {
-                error!("Database error: {}", e);
-                return HttpResponse::InternalServerError().json(json!({
-                    "success": false,
-                    "error": "Internal server error"
-                }));
+            
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "email": updated_email,
+                "message": "Email marked as read"
+            }))
+        }
+        Ok(None) => {
+            HttpResponse::NotFound().json(json!({
+                "success": false,
+                "error": "Email not found"
+            }))
+        }
+        Err(e) => {
+            error!("Database error when getting email: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Internal server error"
+            }))
+        }
+    }
+}
+
+// Recall a sent email that the recipient has not opened yet
+pub async fn recall_email(
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    db_pool: DbPool,
+    redis_cache: RedisCacheData,
+) -> impl Responder {
+    let email_id = path.into_inner();
+    
+    if let Err(e) = user.require_scope(TokenScope::SendMail) {
+        return e.error_response();
+    }
+    let email = user.email;
+    
+    match db::recall_email(db_pool.get_ref(), &email_id, &email).await {
+        Ok(db::RecallOutcome::Recalled(recalled_email)) => {
+            info!("Email {} recalled by {}", email_id, email);
+            
+            // Replace any cached copies so neither side sees the old body
+            if let Err(e) = redis_cache.update_email_lists(&email, &recalled_email, true).await {
+                error!("Failed to update sender cache after recall: {}", e);
+            }
+            if let Some(ref gmail_id) = recalled_email.gmail_id {
+                let _ = redis_cache.cache_email(&recalled_email.recipient_email, gmail_id, &recalled_email).await;
             }
+            
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "email": recalled_email,
+                "message": "Message recalled before it was read"
+            }))
         }
-    } else {
-        info!("No session cookie found");
-        return HttpResponse::Unauthorized().json(json!({
+        Ok(db::RecallOutcome::AlreadyRead(read_at)) => {
+            HttpResponse::Conflict().json(json!({
+                "success": false,
+                "error": "Message has already been read and can no longer be recalled",
+                "read_at": read_at
+            }))
+        }
+        Ok(db::RecallOutcome::AlreadyRecalled(recalled_at)) => {
+            HttpResponse::Conflict().json(json!({
+                "success": false,
+                "error": "Message has already been recalled",
+                "recalled_at": recalled_at
+            }))
+        }
+        Ok(db::RecallOutcome::NotSender) => {
+            HttpResponse::Forbidden().json(json!({
+                "success": false,
+                "error": "Only the sender can recall this email"
+            }))
+        }
+        Ok(db::RecallOutcome::NotFound) => {
+            HttpResponse::NotFound().json(json!({
+                "success": false,
+                "error": "Email not found"
+            }))
+        }
+        Err(e) => {
+            error!("Database error when recalling email: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to recall email",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
+
+// Forward an encrypted email by re-wrapping its content key for the new recipient.
+// The body ciphertext is never decrypted on the server.
+pub async fn forward_email(
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    forward_req: web::Json<ForwardEmailRequest>,
+    db_pool: DbPool,
+    gmail_client: GmailClientData,
+    redis_cache: RedisCacheData,
+    config: ConfigData,
+) -> impl Responder {
+    let email_id = path.into_inner();
+    
+    if let Err(e) = user.require_scope(TokenScope::SendMail) {
+        return e.error_response();
+    }
+    // Only the bare address is kept, lowercased like every stored address
+    let recipient_email = match Address::parse(&forward_req.recipient_email) {
+        Ok(recipient) => recipient.email.to_lowercase(),
+        Err(e) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "Invalid recipient address",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    let email = user.email;
+    let refresh_token = user.refresh_token;
+    
+    let refresh_token = match refresh_token {
+        Some(token) => token,
+        None => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "No Gmail authorization found for this account"
+            }));
+        }
+    };
+    let mailbox_email = match sending_mailbox(db_pool.get_ref(), &email).await {
+        Ok(mailbox_email) => mailbox_email,
+        Err(response) => return response,
+    };
+    
+    let original = match db::get_email(db_pool.get_ref(), &email_id).await {
+        Ok(Some(original)) => original,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "error": "Email not found"
+            }));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "error": "Database error",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    
+    if !original.recipient_email.eq_ignore_ascii_case(&email) {
+        return HttpResponse::Forbidden().json(json!({
             "success": false,
-            "error": "Not authenticated"
+            "error": "Only the recipient of an encrypted email can forward it"
         }));
     }
+    
+    let raw_content = match (original.is_encrypted, original.raw_encrypted_content.as_deref()) {
+        (true, Some(raw_content)) => raw_content,
+        _ => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "Email is not encrypted"
+            }));
+        }
+    };
+    
+    let encrypted_msg = match crate::encryption::deserialize_encrypted_message(raw_content) {
+        Ok(encrypted_msg) => encrypted_msg,
+        Err(e) => {
+            // Portal messages use a passcode envelope and have no key slot to forward from
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "Email cannot be forwarded",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    
+    let keypair = match crate::encryption::keys::get_keypair(db_pool.get_ref(), &email).await {
+        Ok(Some(keypair)) => keypair,
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "No encryption keys found"
+            }));
+        }
+        Err(e) => {
+            error!("Failed to get encryption keys: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to get encryption keys",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    
+    let recipient_public_key = match crate::encryption::keys::get_public_key(db_pool.get_ref(), &recipient_email).await {
+        Ok(Some(public_key)) => public_key,
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "Recipient has no encryption keys"
+            }));
+        }
+        Err(e) => {
+            error!("Failed to get public key: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to get recipient's public key",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    
+    // Their encapsulated key is a bare shared secret; re-sending it would give it away
+    if crate::encryption::forward::is_legacy_envelope(&encrypted_msg) {
+        return HttpResponse::UnprocessableEntity().json(json!({
+            "success": false,
+            "error": "Email uses a legacy encryption format and cannot be forwarded"
+        }));
+    }
+    
+    let forwarded_msg = match crate::encryption::forward::rewrap_for_recipient(
+        &encrypted_msg,
+        &keypair.secret_key,
+        &recipient_public_key,
+        &email,
+        &recipient_email,
+    ) {
+        Ok(forwarded_msg) => forwarded_msg,
+        Err(e) => {
+            warn!("Failed to re-wrap email {} for forwarding: {}", email_id, e);
+            return HttpResponse::UnprocessableEntity().json(json!({
+                "success": false,
+                "error": "Email cannot be decrypted with your key and cannot be forwarded",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    
+    let forwarded_content = match crate::encryption::serialize_encrypted_message(&forwarded_msg) {
+        Ok(content) => content,
+        Err(e) => {
+            error!("Failed to serialize forwarded message: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to forward email",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    
+    let subject = format!("Fwd: {}", crate::encryption::extract_original_subject(&original.subject));
+    let body = crate::encryption::format_encrypted_body();
+    
+    let forwarded_id = match db::store_email(
+        db_pool.get_ref(),
+        &email,
+        &email,
+        &recipient_email,
+        &subject,
+        &body,
+        true,
+        Some(&forwarded_content),
+    ).await {
+        Ok(id) => id,
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to store email in database",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    
+    let sender_name = match db::get_user_info(db_pool.get_ref(), &email).await {
+        Ok(Some(user_info)) => user_info.name.unwrap_or_else(|| email.clone()),
+        _ => email.clone(),
+    };
+    
+    let view_link = crate::auth::create_view_link(&config.server.frontend_url, &forwarded_id, &recipient_email);
+    let raw_message = match build_notification_message(&mailbox_email, &sender_name, &recipient_email, &view_link) {
+        Ok(raw_message) => raw_message,
+        Err(e) => {
+            error!("Failed to build notification email: {}", e);
+            discard_unsent_email(db_pool.get_ref(), &forwarded_id).await;
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to build notification email",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    
+    match gmail_client.get_token(&email, &refresh_token).await {
+        Ok(access_token) => {
+            match gmail_client.send_message(&email, &access_token, raw_message).await {
+                Ok(message) => {
+                    let email_obj = crate::models::Email {
+                        id: forwarded_id.clone(),
+                        sender_id: email.clone(),
+                        sender_email: email.clone(),
+                        sender_name: None,
+                        recipient_email: recipient_email.clone(),
+                        subject,
+                        body,
+                        html_body: None,
+                        attachments: Vec::new(),
+                        sent_at: chrono::Utc::now().to_rfc3339(),
+                        read_at: None,
+                        gmail_id: Some(message.id.clone()),
+                        label_ids: Some(vec!["SENT".to_string()]),
+                        is_encrypted: true,
+                        raw_encrypted_content: Some(forwarded_content),
+                        recalled_at: None,
+                        thread_id: Some(forwarded_id.clone()),
+                    };
+                    
+                    if let Err(e) = redis_cache.update_email_lists(&email, &email_obj, true).await {
+                        error!("Failed to update cache: {}", e);
+                    }
+                    
+                    info!("Email {} forwarded by {} to {}", email_id, email, recipient_email);
+                    
+                    HttpResponse::Ok().json(json!({
+                        "success": true,
+                        "email": email_obj,
+                        "forwarding_chain": forwarded_msg.forwarding_chain,
+                        "message": "Email forwarded successfully"
+                    }))
+                }
+                Err(e) => {
+                    error!("Failed to send notification email: {}", e);
+                    discard_unsent_email(db_pool.get_ref(), &forwarded_id).await;
+                    e.error_response()
+                }
+            }
+        }
+        Err(e) => {
+            error!("Gmail token error: {}", e);
+            discard_unsent_email(db_pool.get_ref(), &forwarded_id).await;
+            e.error_response()
+        }
+    }
 }
 
 // Generate encryption keys for a user
 pub async fn generate_encryption_keys(
-    req: HttpRequest,
+    user: AuthenticatedUser,
     db_pool: DbPool,
 ) -> impl Responder {
-    if let Some(cookie) = req.cookie("session") {
-        let session_token = cookie.value().to_string();
-        
-        match db::get_user_by_session(db_pool.get_ref(), &session_token).await {
-            Ok(Some((email, _, _, _))) => {
-                // Check if user already has keys
-                match crate::encryption::keys::get_keypair(db_pool.get_ref(), &email).await {
-                    Ok(Some(_)) => {
-                        // User already has keys
-                        return HttpResponse::Ok().json(json!({
-                            "success": true,
-                            "message": "Encryption keys already exist"
-                        }));
-                    },
-                    Ok(None) => {
-                        // Generate new key pair
-                        match crate::encryption::generate_keypair() {
-                            Ok(keypair) => {
-                                // Store the key pair
-                                match crate::encryption::keys::store_keypair(db_pool.get_ref(), &email, &keypair).await {
-                                    Ok(_) => {
-                                        info!("Generated and stored encryption keys for user: {}", email);
-                                        return HttpResponse::Ok().json(json!({
-                                            "success": true,
-                                            "message": "Encryption keys generated successfully"
-                                        }));
-                                    },
-                                    Err(e) => {
-                                        error!("Failed to store key pair: {}", e);
-                                        return HttpResponse::InternalServerError().json(json!({
-                                            "success": false,
-                                            "error": "Failed to store encryption keys",
-                                            "details": format!("{}", e)
-                                        }));
-                                    }
-                                }
-                            },
-                            Err(e) => {
-                                error!("Failed to generate key pair: {}", e);
-                                return HttpResponse::InternalServerError().json(json!({
-                                    "success": false,
-                                    "error": "Failed to generate encryption keys",
-                                    "details": format!("{}", e)
-                                }));
-                            }
+    if let Err(e) = user.require_scope(TokenScope::ManageKeys) {
+        return e.error_response();
+    }
+    let email = user.email;
+    
+    // Check if user already has keys
+    match crate::encryption::keys::get_keypair(db_pool.get_ref(), &email).await {
+        Ok(Some(_)) => {
+            // User already has keys
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Encryption keys already exist"
+            }))
+        },
+        Ok(None) => {
+            // Generate new key pair
+            match crate::encryption::generate_keypair() {
+                Ok(keypair) => {
+                    // Store the key pair
+                    match crate::encryption::keys::store_keypair(db_pool.get_ref(), &email, &keypair).await {
+                        Ok(_) => {
+                            info!("Generated and stored encryption keys for user: {}", email);
+                            HttpResponse::Ok().json(json!({
+                                "success": true,
+                                "message": "Encryption keys generated successfully"
+                            }))
+                        },
+                        Err(e) => {
+                            error!("Failed to store key pair: {}", e);
+                            HttpResponse::InternalServerError().json(json!({
+                                "success": false,
+                                "error": "Failed to store encryption keys",
+                                "details": format!("{}", e)
+                            }))
                         }
-                    },
-                    Err(e) => {
-                        error!("Failed to check for existing keys: {}", e);
-                        return HttpResponse::InternalServerError().json(json!({
-                            "success": false,
-                            "error": "Failed to check for existing keys",
-                            "details": format!("{}", e)
-                        }));
                     }
+                },
+                Err(e) => {
+                    error!("Failed to generate key pair: {}", e);
+                    HttpResponse::InternalServerError().json(json!({
+                        "success": false,
+                        "error": "Failed to generate encryption keys",
+                        "details": format!("{}", e)
+                    }))
                 }
-            },
-            Ok(None) => {
-                error!("Invalid session");
-                return HttpResponse::Unauthorized().json(json!({
-                    "success": false,
-                    "error": "Not authenticated"
-                }));
-            },
-            Err(e) => {
-                error!("Database error: {}", e);
-                return HttpResponse::InternalServerError().json(json!({
-                    "error": "Database error",
-                    "details": format!("{}", e)
-                }));
             }
+        },
+        Err(e) => {
+            error!("Failed to check for existing keys: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to check for existing keys",
+                "details": format!("{}", e)
+            }))
         }
     }
+}
+
+// Revoke a user's encryption keys; the revocation is recorded in the transparency log
+pub async fn revoke_encryption_keys(
+    user: AuthenticatedUser,
+    db_pool: DbPool,
+) -> impl Responder {
+    if let Err(e) = user.require_scope(TokenScope::ManageKeys) {
+        return e.error_response();
+    }
+    let email = user.email;
     
-    HttpResponse::Unauthorized().json(json!({
-        "success": false,
-        "error": "Not authenticated"
-    }))
+    match crate::encryption::keys::revoke_keypair(db_pool.get_ref(), &email).await {
+        Ok(true) => {
+            info!("Revoked encryption keys for user: {}", email);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Encryption keys revoked"
+            }))
+        },
+        Ok(false) => {
+            HttpResponse::NotFound().json(json!({
+                "success": false,
+                "error": "No encryption keys found"
+            }))
+        },
+        Err(e) => {
+            error!("Failed to revoke key pair: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to revoke encryption keys",
+                "details": format!("{}", e)
+            }))
+        }
+    }
 }
 
 // Decrypt an email message
 pub async fn decrypt_email(
-    req: HttpRequest,
+    user: AuthenticatedUser,
     path: web::Path<String>,
     db_pool: DbPool,
 ) -> impl Responder {
     let email_id = path.into_inner();
     
-    if let Some(cookie) = req.cookie("session") {
-        let session_token = cookie.value().to_string();
-        
-        match db::get_user_by_session(db_pool.get_ref(), &session_token).await {
-            Ok(Some((email, _, _, _))) => {
-                // Get the email from the database
-                match db::get_email(db_pool.get_ref(), &email_id).await {
-                    Ok(Some(email_obj)) => {
-                        // Check if the email is encrypted and has raw content
-                        if email_obj.is_encrypted {
-                            if let Some(ref raw_content) = email_obj.raw_encrypted_content {
-                                // Get the user's private key
-                                match crate::encryption::keys::get_keypair(db_pool.get_ref(), &email).await {
-                                    Ok(Some(keypair)) => {
-                                        // Parse the encrypted content
-                                        match crate::encryption::deserialize_encrypted_message(&raw_content) {
-                                            Ok(encrypted_msg) => {
-                                                // Decrypt the message
-                                                match crate::encryption::decrypt_message(&encrypted_msg, &keypair.secret_key) {
-                                                    Ok(decrypted_body) => {
-                                                        // Create a new email object with the decrypted body
-                                                        let decrypted_subject = crate::encryption::extract_original_subject(&email_obj.subject);
-                                                        
-                                                        let mut decrypted_email = email_obj.clone();
-                                                        decrypted_email.subject = decrypted_subject;
-                                                        decrypted_email.body = decrypted_body;
-                                                        
-                                                        return HttpResponse::Ok().json(json!({
-                                                            "success": true,
-                                                            "email": decrypted_email
-                                                        }));
-                                                    },
-                                                    Err(e) => {
-                                                        error!("Failed to decrypt message: {}", e);
-                                                        return HttpResponse::InternalServerError().json(json!({
-                                                            "success": false,
-                                                            "error": "Failed to decrypt message",
-                                                            "details": format!("{}", e)
-                                                        }));
-                                                    }
-                                                }
-                                            },
-                                            Err(e) => {
-                                                error!("Failed to parse encrypted message: {}", e);
-                                                return HttpResponse::InternalServerError().json(json!({
-                                                    "success": false,
-                                                    "error": "Failed to parse encrypted message",
-                                                    "details": format!("{}", e)
-                                                }));
-                                            }
-                                        }
-                                    },
-                                    Ok(None) => {
-                                        error!("No encryption keys found for user: {}", email);
-                                        return HttpResponse::BadRequest().json(json!({
-                                            "success": false,
-                                            "error": "No encryption keys found"
-                                        }));
-                                    },
-                                    Err(e) => {
-                                        error!("Failed to get encryption keys: {}", e);
-                                        return HttpResponse::InternalServerError().json(json!({
-                                            "success": false,
-                                            "error": "Failed to get encryption keys",
-                                            "details": format!("{}", e)
-                                        }));
-                                    }
+    if let Err(e) = user.require_scope(TokenScope::ReadMail) {
+        return e.error_response();
+    }
+    let email = user.email;
+    
+    let email_obj = match open_for_decryption(db_pool.get_ref(), &email_id, &email).await {
+        Ok(email_obj) => email_obj,
+        Err(response) => return response,
+    };
+    
+    // Check if the email is encrypted and has raw content
+    if email_obj.is_encrypted {
+        if let Some(ref raw_content) = email_obj.raw_encrypted_content {
+            // Get the user's private key
+            match crate::encryption::keys::get_keypair(db_pool.get_ref(), &email).await {
+                Ok(Some(keypair)) => {
+                    // Parse the encrypted content
+                    match crate::encryption::deserialize_encrypted_message(raw_content) {
+                        Ok(encrypted_msg) => {
+                            // Decrypt the message
+                            match crate::encryption::decrypt_message(&encrypted_msg, &keypair.secret_key) {
+                                Ok(decrypted_body) => {
+                                    // Create a new email object with the decrypted body
+                                    let decrypted_subject = crate::encryption::extract_original_subject(&email_obj.subject);
+                                    
+                                    let mut decrypted_email = email_obj.clone();
+                                    decrypted_email.subject = decrypted_subject;
+                                    decrypted_email.body = decrypted_body;
+                                    
+                                    HttpResponse::Ok().json(json!({
+                                        "success": true,
+                                        "email": decrypted_email
+                                    }))
+                                },
+                                Err(e) => {
+                                    error!("Failed to decrypt message: {}", e);
+                                    HttpResponse::InternalServerError().json(json!({
+                                        "success": false,
+                                        "error": "Failed to decrypt message",
+                                        "details": format!("{}", e)
+                                    }))
                                 }
-                            } else {
-                                return HttpResponse::BadRequest().json(json!({
-                                    "success": false,
-                                    "error": "Email is marked as encrypted but has no encrypted content"
-                                }));
                             }
-                        } else {
-                            return HttpResponse::BadRequest().json(json!({
+                        },
+                        Err(e) => {
+                            error!("Failed to parse encrypted message: {}", e);
+                            HttpResponse::InternalServerError().json(json!({
                                 "success": false,
-                                "error": "Email is not encrypted"
-                            }));
+                                "error": "Failed to parse encrypted message",
+                                "details": format!("{}", e)
+                            }))
                         }
-                    },
-                    Ok(None) => {
-                        return HttpResponse::NotFound().json(json!({
-                            "success": false,
-                            "error": "Email not found"
-                        }));
-                    },
-                    Err(e) => {
-                        error!("Database error: {}", e);
-                        return HttpResponse::InternalServerError().json(json!({
-                            "error": "Database error",
-                            "details": format!("{}", e)
-                        }));
                     }
+                },
+                Ok(None) => {
+                    error!("No encryption keys found for user: {}", email);
+                    HttpResponse::BadRequest().json(json!({
+                        "success": false,
+                        "error": "No encryption keys found"
+                    }))
+                },
+                Err(e) => {
+                    error!("Failed to get encryption keys: {}", e);
+                    HttpResponse::InternalServerError().json(json!({
+                        "success": false,
+                        "error": "Failed to get encryption keys",
+                        "details": format!("{}", e)
+                    }))
                 }
-            },
-            Ok(None) => {
-                error!("Invalid session");
-                return HttpResponse::Unauthorized().json(json!({
-                    "success": false,
-                    "error": "Not authenticated"
-                }));
-            },
-            Err(e) => {
-                error!("Database error: {}", e);
-                return HttpResponse::InternalServerError().json(json!({
-                    "error": "Database error",
-                    "details": format!("{}", e)
-                }));
             }
+        } else {
+            HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "Email is marked as encrypted but has no encrypted content"
+            }))
         }
+    } else {
+        HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": "Email is not encrypted"
+        }))
     }
+}
+
+// Load an email for its sender or recipient. The recipient opens it in the same statement, so
+// it can no longer be recalled once decrypted; the sender reads their copy without marking it.
+async fn open_for_decryption(pool: &sqlx::PgPool, email_id: &str, user_email: &str) -> Result<crate::models::Email, HttpResponse> {
+    let database_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(json!({
+            "error": "Database error",
+            "details": format!("{}", e)
+        }))
+    };
     
-    HttpResponse::Unauthorized().json(json!({
-        "success": false,
-        "error": "Not authenticated"
-    }))
-} 
\ No newline at end of file
+    if let Some((opened, _)) = db::mark_email_read(pool, email_id, user_email).await.map_err(database_error)? {
+        return Ok(opened);
+    }
+    
+    match db::get_email(pool, email_id).await.map_err(database_error)? {
+        Some(found) if found.recipient_email == user_email && found.recalled_at.is_some() => {
+            Err(HttpResponse::Gone().json(json!({
+                "success": false,
+                "error": "This email was recalled by the sender"
+            })))
+        }
+        Some(found) if found.sender_email == user_email => Ok(found),
+        Some(_) => Err(HttpResponse::Forbidden().json(json!({
+            "success": false,
+            "error": "You don't have permission to view this email"
+        }))),
+        None => Err(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "error": "Email not found"
+        }))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    // Needs a throwaway database named by TEST_DATABASE_URL, whose schema it creates:
+    //   TEST_DATABASE_URL=postgres://localhost/quant_test cargo test -- --ignored
+    async fn test_pool() -> sqlx::PgPool {
+        let database_url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL");
+        let pool = sqlx::PgPool::connect(&database_url).await.expect("database");
+        db::init(&pool).await.expect("schema");
+        pool
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn decrypting_opens_the_email_so_it_cannot_be_recalled() {
+        let pool = test_pool().await;
+        let run = uuid::Uuid::new_v4();
+        let sender = format!("decrypt-sender-{}@test.io", run);
+        let recipient = format!("decrypt-recipient-{}@test.io", run);
+        let stranger = format!("decrypt-stranger-{}@test.io", run);
+        let store = || db::store_email(&pool, &sender, &sender, &recipient, "[Q-ENCRYPTED] Hi", "", true, Some("{}"));
+
+        let first = store().await.expect("email");
+        let second = store().await.expect("email");
+
+        let stranger_status = open_for_decryption(&pool, &first, &stranger).await.err().map(|r| r.status());
+        let sender_read = open_for_decryption(&pool, &first, &sender).await.map(|e| e.read_at);
+        let recall_after_sender = db::recall_email(&pool, &first, &sender).await;
+        let recipient_after_recall = open_for_decryption(&pool, &first, &recipient).await.err().map(|r| r.status());
+
+        let recipient_read = open_for_decryption(&pool, &second, &recipient).await.map(|e| e.read_at);
+        let recall_after_recipient = db::recall_email(&pool, &second, &sender).await;
+
+        sqlx::query("DELETE FROM emails WHERE sender_email = $1").bind(&sender).execute(&pool).await.expect("cleanup");
+
+        assert_eq!(stranger_status, Some(StatusCode::FORBIDDEN));
+        assert!(matches!(sender_read, Ok(None)), "the sender must not mark the email read");
+        assert!(matches!(recall_after_sender, Ok(db::RecallOutcome::Recalled(_))));
+        assert_eq!(recipient_after_recall, Some(StatusCode::GONE));
+        assert!(matches!(recipient_read, Ok(Some(_))));
+        assert!(matches!(recall_after_recipient, Ok(db::RecallOutcome::AlreadyRead(_))));
+    }
+}
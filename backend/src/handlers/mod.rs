@@ -4,6 +4,15 @@ pub mod user;
 pub mod admin;
 pub mod email;
 pub mod label;
+pub mod view;
+pub mod portal;
+pub mod transparency;
+pub mod session;
+pub mod access_token;
+pub mod account;
+pub mod push;
+pub mod thread;
+pub mod attachment;
 
 
 pub use welcome::*;
@@ -11,4 +20,13 @@ pub use auth::*;
 pub use user::*;
 pub use admin::*;
 pub use email::*;
-pub use label::*;
\ No newline at end of file
+pub use label::*;
+pub use view::*;
+pub use portal::*;
+pub use transparency::*;
+pub use session::*;
+pub use access_token::*;
+pub use account::*;
+pub use push::*;
+pub use thread::*;
+pub use attachment::*;
@@ -0,0 +1,299 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use serde_json::json;
+use log::{info, error, warn};
+
+use crate::db;
+use crate::encryption::portal;
+use crate::models::{Email, PortalOpenRequest, PortalReplyRequest, PortalMessage};
+
+type DbPool = web::Data<sqlx::PgPool>;
+
+// Client address and user agent for the portal audit log
+fn client_details(req: &HttpRequest) -> (Option<String>, Option<String>) {
+    let ip_address = req.connection_info().realip_remote_addr().map(|ip| ip.to_string());
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    (ip_address, user_agent)
+}
+
+// Check the portal link and passcode, returning the email and its decrypted body
+async fn unlock_portal_message(
+    req: &HttpRequest,
+    db_pool: &sqlx::PgPool,
+    access_token: &str,
+    passcode: &str,
+    action: &str,
+) -> Result<(Email, String), HttpResponse> {
+    let (ip_address, user_agent) = client_details(req);
+    
+    let access = match db::portal::get_portal_access(db_pool, access_token).await {
+        Ok(Some(access)) => access,
+        Ok(None) => {
+            warn!("Portal access with unknown token from {:?}", ip_address);
+            return Err(HttpResponse::NotFound().json(json!({
+                "success": false,
+                "error": "This link is not valid"
+            })));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Database error"
+            })));
+        }
+    };
+    
+    if access.is_expired() {
+        let _ = db::portal::log_portal_attempt(db_pool, &access.email_id, action, false, ip_address.as_deref(), user_agent.as_deref()).await;
+        return Err(HttpResponse::Gone().json(json!({
+            "success": false,
+            "reason": "expired",
+            "error": "This link has expired"
+        })));
+    }
+    
+    // Too many wrong passcodes over the link's life: it will not open again
+    if access.is_revoked() {
+        let _ = db::portal::log_portal_attempt(db_pool, &access.email_id, action, false, ip_address.as_deref(), user_agent.as_deref()).await;
+        return Err(HttpResponse::Gone().json(json!({
+            "success": false,
+            "reason": "revoked",
+            "error": "This link no longer works after too many incorrect passcodes, please ask the sender to send the message again"
+        })));
+    }
+    
+    // Locked links reject every attempt without counting it
+    if access.is_locked() {
+        let _ = db::portal::log_portal_attempt(db_pool, &access.email_id, action, false, ip_address.as_deref(), user_agent.as_deref()).await;
+        return Err(HttpResponse::TooManyRequests().json(json!({
+            "success": false,
+            "reason": "locked",
+            "error": "Too many incorrect passcodes, please try again later",
+            "locked_until": access.locked_until.map(|t| t.to_string())
+        })));
+    }
+    
+    let email = match db::get_email(db_pool, &access.email_id).await {
+        Ok(Some(email)) => email,
+        Ok(None) => {
+            return Err(HttpResponse::NotFound().json(json!({
+                "success": false,
+                "error": "Email not found"
+            })));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Database error"
+            })));
+        }
+    };
+    
+    if email.recalled_at.is_some() {
+        return Err(HttpResponse::Gone().json(json!({
+            "success": false,
+            "reason": "recalled",
+            "error": db::email::RECALLED_EMAIL_BODY
+        })));
+    }
+    
+    let envelope = match email.raw_encrypted_content.as_deref().map(portal::deserialize_passcode_envelope) {
+        Some(Ok(envelope)) => envelope,
+        _ => {
+            error!("Portal email {} has no passcode envelope", email.id);
+            return Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Message is not available"
+            })));
+        }
+    };
+    
+    // Counted before the key is derived, so the lockout holds however many guesses arrive at once
+    match db::portal::reserve_portal_attempt(db_pool, &email.id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            let _ = db::portal::log_portal_attempt(db_pool, &email.id, action, false, ip_address.as_deref(), user_agent.as_deref()).await;
+            return Err(HttpResponse::TooManyRequests().json(json!({
+                "success": false,
+                "reason": "locked",
+                "error": "Too many incorrect passcodes, please try again later"
+            })));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Database error"
+            })));
+        }
+    }
+    
+    // Deriving the key is deliberately slow, so it runs off the async workers
+    let passcode = passcode.to_string();
+    let decrypted = web::block(move || {
+        portal::decrypt_with_passcode(&envelope, &passcode).map_err(|e| e.to_string())
+    }).await;
+    match decrypted {
+        Ok(Ok(body)) => {
+            if let Err(e) = db::portal::record_portal_attempt(db_pool, &email.id, action, true, ip_address.as_deref(), user_agent.as_deref()).await {
+                error!("Failed to record portal attempt: {}", e);
+            }
+            Ok((email, body))
+        }
+        Ok(Err(_)) => {
+            warn!("Incorrect portal passcode for email {} from {:?}", email.id, ip_address);
+            if let Err(e) = db::portal::record_portal_attempt(db_pool, &email.id, action, false, ip_address.as_deref(), user_agent.as_deref()).await {
+                error!("Failed to record portal attempt: {}", e);
+            }
+            Err(HttpResponse::Unauthorized().json(json!({
+                "success": false,
+                "reason": "incorrect_passcode",
+                "error": "Incorrect passcode"
+            })))
+        }
+        Err(e) => {
+            error!("Failed to check portal passcode: {}", e);
+            Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to check passcode"
+            })))
+        }
+    }
+}
+
+// Open a protected message with its passcode
+pub async fn open_portal_message(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<PortalOpenRequest>,
+    db_pool: DbPool,
+) -> impl Responder {
+    let access_token = path.into_inner();
+    
+    let (email, decrypted_body) = match unlock_portal_message(&req, db_pool.get_ref(), &access_token, &body.passcode, "open").await {
+        Ok(unlocked) => unlocked,
+        Err(response) => return response,
+    };
+    
+    // The body is only handed out once the message is marked read; a recall that got in
+    // since it was decrypted wins
+    match db::mark_email_read(db_pool.get_ref(), &email.id, &email.recipient_email).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return HttpResponse::Gone().json(json!({
+                "success": false,
+                "reason": "recalled",
+                "error": db::email::RECALLED_EMAIL_BODY
+            }));
+        }
+        Err(e) => {
+            error!("Failed to persist read status for email {}: {}", email.id, e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Database error"
+            }));
+        }
+    }
+    
+    info!("Portal message {} opened by external recipient", email.id);
+    
+    HttpResponse::Ok().json(json!({
+        "success": true,
+        "message": PortalMessage {
+            sender_email: email.sender_email,
+            sender_name: email.sender_name,
+            recipient_email: email.recipient_email,
+            subject: email.subject,
+            body: decrypted_body,
+            sent_at: email.sent_at,
+        }
+    }))
+}
+
+// Reply to a protected message without an account
+pub async fn reply_portal_message(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<PortalReplyRequest>,
+    db_pool: DbPool,
+) -> impl Responder {
+    let access_token = path.into_inner();
+    
+    if body.body.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": "Reply cannot be empty"
+        }));
+    }
+    
+    let (email, _) = match unlock_portal_message(&req, db_pool.get_ref(), &access_token, &body.passcode, "reply").await {
+        Ok(unlocked) => unlocked,
+        Err(response) => return response,
+    };
+    
+    let subject = if email.subject.to_lowercase().starts_with("re:") {
+        email.subject.clone()
+    } else {
+        format!("Re: {}", email.subject)
+    };
+    
+    // Encrypt the reply for the original sender when they have keys
+    let (stored_body, raw_encrypted_content) = match crate::encryption::keys::get_public_key(db_pool.get_ref(), &email.sender_email).await {
+        Ok(Some(public_key)) => {
+            match crate::encryption::encrypt_message(&body.body, &public_key)
+                .and_then(|msg| crate::encryption::serialize_encrypted_message(&msg))
+            {
+                Ok(content) => (crate::encryption::format_encrypted_body(), Some(content)),
+                Err(e) => {
+                    error!("Failed to encrypt portal reply: {}", e);
+                    return HttpResponse::InternalServerError().json(json!({
+                        "success": false,
+                        "error": "Failed to encrypt reply"
+                    }));
+                }
+            }
+        }
+        Ok(None) => (body.body.clone(), None),
+        Err(e) => {
+            error!("Failed to get public key: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to encrypt reply"
+            }));
+        }
+    };
+    
+    match db::store_email(
+        db_pool.get_ref(),
+        &email.recipient_email,
+        &email.recipient_email,
+        &email.sender_email,
+        &subject,
+        &stored_body,
+        raw_encrypted_content.is_some(),
+        raw_encrypted_content.as_deref(),
+    ).await {
+        Ok(reply_id) => {
+            if let Err(e) = db::threads::link_reply(db_pool.get_ref(), &reply_id, &email.id).await {
+                error!("Failed to thread portal reply {} under {}: {}", reply_id, email.id, e);
+            }
+            info!("Portal reply {} stored for {}", reply_id, email.sender_email);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Reply sent securely"
+            }))
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to store reply"
+            }))
+        }
+    }
+}
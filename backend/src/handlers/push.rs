@@ -0,0 +1,297 @@
+use actix_web::{web, HttpResponse, Responder};
+use base64::{decode_config, STANDARD};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use log::{info, warn, error};
+
+use crate::cache::RedisCache;
+use crate::db;
+use crate::gmail::{self, GmailClient};
+use crate::sync;
+
+type DbPool = web::Data<sqlx::PgPool>;
+type GmailClientData = web::Data<std::sync::Arc<GmailClient>>;
+type RedisCacheData = web::Data<std::sync::Arc<RedisCache>>;
+type ConfigData = web::Data<crate::config::AppConfig>;
+
+#[derive(Debug, Deserialize)]
+pub struct PushQuery {
+    token: Option<String>,
+}
+
+// What a Pub/Sub push subscription posts
+#[derive(Debug, Deserialize)]
+struct PushEnvelope {
+    message: PushMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct PushMessage {
+    // Base64 of the JSON Gmail published
+    data: String,
+    #[serde(rename = "messageId")]
+    message_id: Option<String>,
+}
+
+// Gmail's notification: the mailbox that changed and its historyId after the change
+#[derive(Debug, Deserialize)]
+struct GmailNotification {
+    #[serde(rename = "emailAddress")]
+    email_address: String,
+    #[serde(rename = "historyId")]
+    history_id: Value,
+}
+
+// Compare without stopping at the first differing byte, so response timing does not reveal the token
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given.bytes().zip(expected.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn decode_notification(body: &[u8]) -> Result<(GmailNotification, Option<String>), String> {
+    let envelope: PushEnvelope = serde_json::from_slice(body).map_err(|e| format!("invalid push envelope: {}", e))?;
+    let data = decode_config(envelope.message.data.trim(), STANDARD).map_err(|e| format!("invalid message data: {}", e))?;
+    let notification = serde_json::from_slice(&data).map_err(|e| format!("invalid Gmail notification: {}", e))?;
+    Ok((notification, envelope.message.message_id))
+}
+
+// Pub/Sub push endpoint for Gmail notifications. Authenticated by the verification token in the
+// subscription's URL. Acknowledges at once and syncs in the background, since Pub/Sub redelivers
+// anything not acknowledged within its deadline.
+pub async fn gmail_push(
+    query: web::Query<PushQuery>,
+    body: web::Bytes,
+    db_pool: DbPool,
+    gmail_client: GmailClientData,
+    redis_cache: RedisCacheData,
+    config: ConfigData,
+) -> impl Responder {
+    let push = match config.google.push {
+        Some(ref push) => push,
+        None => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "error": "Gmail push notifications are not enabled"
+            }));
+        }
+    };
+
+    let token_valid = query.token.as_deref()
+        .is_some_and(|token| tokens_match(token, push.verification_token.expose()));
+    if !token_valid {
+        warn!("Rejected Gmail push with a missing or wrong token");
+        return HttpResponse::Forbidden().json(json!({
+            "success": false,
+            "error": "Invalid push token",
+            "reason": "invalid_push_token"
+        }));
+    }
+
+    let (notification, message_id) = match decode_notification(&body) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            warn!("Malformed Gmail push: {}", e);
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "Malformed push message",
+                "details": e
+            }));
+        }
+    };
+    // Gmail sends the historyId as a number, but tolerate a string
+    let history_id = match notification.history_id {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s,
+        other => other.to_string(),
+    };
+
+    info!(
+        "Gmail push {} for {} at history {}",
+        message_id.as_deref().unwrap_or("-"), notification.email_address, history_id
+    );
+
+    let email = match db::identities::find_user_by_mailbox(db_pool.get_ref(), &notification.email_address).await {
+        Ok(Some(email)) => email,
+        // A mailbox that was unlinked since its watch was registered; nothing to sync
+        Ok(None) => {
+            info!("No user has mailbox {} linked; ignoring push", notification.email_address);
+            return HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "No linked mailbox"
+            }));
+        }
+        Err(e) => {
+            // Not acknowledged, so Pub/Sub delivers it again
+            error!("Failed to look up mailbox {}: {}", notification.email_address, e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to look up mailbox"
+            }));
+        }
+    };
+
+    let pool = db_pool.get_ref().clone();
+    let gmail_client = gmail_client.get_ref().clone();
+    let redis_cache = redis_cache.get_ref().clone();
+    let user = email.clone();
+    actix_web::rt::spawn(gmail::in_background(async move {
+        match sync::sync_after_notification(&pool, &gmail_client, &redis_cache, &user, &history_id).await {
+            Ok(Some(outcome)) => info!("Push-triggered {} sync for {} done", outcome.mode.as_str(), user),
+            Ok(None) => info!("Push for {} at history {} needed no sync", user, history_id),
+            Err(e) => error!("Push-triggered sync for {} failed: {}", user, e),
+        }
+    }));
+
+    HttpResponse::Accepted().json(json!({
+        "success": true,
+        "message": "Sync started",
+        "user": email
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test::{call_service, init_service, TestRequest}, App};
+    use quantum_email_backend::gmail_push::push_envelope;
+
+    const TOKEN: &str = "push-test-verification-token";
+
+    fn push_config() -> crate::config::AppConfig {
+        toml::from_str(&format!(
+            "[google.push]\ntopic = \"projects/push-test/topics/gmail\"\nverification_token = \"{}\"\n",
+            TOKEN
+        ))
+        .expect("test config")
+    }
+
+    // Post a push to the endpoint, with `query` appended to its path, and return the response status
+    async fn push_status(pool: &sqlx::PgPool, config: &crate::config::AppConfig, query: &str, body: serde_json::Value) -> StatusCode {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(gmail::create_gmail_client(&config.google)))
+                .app_data(web::Data::new(crate::cache::create_redis_cache(&config.redis, &config.cache)))
+                .app_data(web::Data::new(config.clone()))
+                .route("/api/gmail/push", web::post().to(gmail_push)),
+        )
+        .await;
+        let request = TestRequest::post()
+            .uri(&format!("/api/gmail/push{}", query))
+            .set_json(body)
+            .to_request();
+        call_service(&app, request).await.status()
+    }
+
+    // Never connects: these requests are answered before the database is needed
+    fn unused_pool() -> sqlx::PgPool {
+        sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .expect("lazy pool")
+    }
+
+    fn envelope_with_data(data: &str) -> Vec<u8> {
+        json!({ "message": { "data": data, "messageId": "m-1" } }).to_string().into_bytes()
+    }
+
+    #[test]
+    fn envelopes_decode_to_the_mailbox_and_history_id() {
+        let body = push_envelope("Someone@Example.com", 4321, 7).to_string();
+        let (notification, message_id) = decode_notification(body.as_bytes()).expect("notification");
+        assert_eq!(notification.email_address, "Someone@Example.com");
+        assert_eq!(notification.history_id, json!(4321));
+        assert_eq!(message_id.as_deref(), Some("push-stub-4321-7"));
+
+        // Some publishers send the historyId as a string
+        let data = base64::encode_config(r#"{"emailAddress":"a@example.com","historyId":"99"}"#, STANDARD);
+        let (notification, _) = decode_notification(&envelope_with_data(&data)).expect("notification");
+        assert_eq!(notification.history_id, json!("99"));
+    }
+
+    #[test]
+    fn malformed_envelopes_are_rejected() {
+        let not_json = base64::encode_config("not json", STANDARD);
+        let no_mailbox = base64::encode_config(r#"{"historyId":1}"#, STANDARD);
+        for body in [
+            b"not json at all".to_vec(),
+            json!({ "subscription": "no message" }).to_string().into_bytes(),
+            envelope_with_data("not base64!"),
+            envelope_with_data(&not_json),
+            envelope_with_data(&no_mailbox),
+        ] {
+            assert!(decode_notification(&body).is_err(), "accepted {}", String::from_utf8_lossy(&body));
+        }
+    }
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(tokens_match(TOKEN, TOKEN));
+        assert!(!tokens_match("push-test-verification-tokeN", TOKEN));
+        assert!(!tokens_match("push-test", TOKEN));
+        assert!(!tokens_match("", TOKEN));
+    }
+
+    #[actix_web::test]
+    async fn gmail_push_rejects_bad_tokens_and_payloads() {
+        let (pool, config) = (unused_pool(), push_config());
+        let envelope = push_envelope("someone@example.com", 1, 1);
+
+        for query in ["", "?token=", "?token=not-the-verification-token"] {
+            assert_eq!(push_status(&pool, &config, query, envelope.clone()).await, StatusCode::FORBIDDEN, "token query {:?}", query);
+        }
+
+        let query = format!("?token={}", TOKEN);
+        for body in [
+            json!({ "message": { "data": "not base64!" } }),
+            json!({ "subscription": "no message" }),
+            json!({ "message": { "data": base64::encode_config("{}", STANDARD) } }),
+        ] {
+            assert_eq!(push_status(&pool, &config, &query, body).await, StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[actix_web::test]
+    async fn gmail_push_is_not_found_when_disabled() {
+        let status = push_status(
+            &unused_pool(),
+            &crate::config::AppConfig::default(),
+            &format!("?token={}", TOKEN),
+            push_envelope("someone@example.com", 1, 1),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    // Runs against a throwaway database named by TEST_DATABASE_URL, never DATABASE_URL, whose schema
+    // it creates. Gmail and Redis are never reached before the response.
+    //   TEST_DATABASE_URL=postgres://localhost/quant_test cargo test gmail_push -- --ignored
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn gmail_push_answers_the_stub_envelope() {
+        let database_url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL");
+        let pool = sqlx::PgPool::connect(&database_url).await.expect("database");
+        db::init(&pool).await.expect("schema");
+        let config = push_config();
+
+        let user = format!("push-test-{}@test.io", uuid::Uuid::new_v4());
+        let mailbox = format!("Mailbox-{}", user);
+        let query = format!("?token={}", TOKEN);
+
+        // Acknowledged so Pub/Sub stops redelivering, but nothing to sync
+        assert_eq!(push_status(&pool, &config, &query, push_envelope(&mailbox, 2, 1)).await, StatusCode::OK);
+
+        db::store_user(&pool, &user, &None, &None, &None).await.expect("user");
+        let linked = db::identities::link_mailbox(&pool, &user, &mailbox.to_lowercase(), &None).await;
+
+        // Mailboxes are matched without regard to case
+        let status = match linked {
+            Ok(_) => Some(push_status(&pool, &config, &query, push_envelope(&mailbox, 3, 1)).await),
+            Err(_) => None,
+        };
+
+        // Remove the user, and everything hanging off it, before anything can fail
+        sqlx::query("DELETE FROM users WHERE email = $1").bind(&user).execute(&pool).await.expect("cleanup");
+        assert_eq!(linked.expect("mailbox"), db::identities::MailboxLink::Linked);
+        assert_eq!(status, Some(StatusCode::ACCEPTED));
+    }
+}
@@ -0,0 +1,107 @@
+use actix_web::{web, HttpResponse, Responder, ResponseError};
+use serde_json::json;
+use log::{info, error};
+
+use crate::auth::{self, AuthenticatedUser};
+use crate::db;
+use crate::models::RevokeAllSessionsRequest;
+
+type DbPool = web::Data<sqlx::PgPool>;
+type ConfigData = web::Data<crate::config::AppConfig>;
+
+// List the signed-in user's active sessions across devices
+pub async fn list_sessions(user: AuthenticatedUser, db_pool: DbPool) -> impl Responder {
+    let session_token = match user.require_session() {
+        Ok(session_token) => session_token,
+        Err(e) => return e.error_response(),
+    };
+
+    match db::sessions::list_sessions(db_pool.get_ref(), &user.email, session_token).await {
+        Ok(sessions) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "sessions": sessions
+        })),
+        Err(e) => {
+            error!("Failed to list sessions: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to list sessions",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
+
+// Sign out one device
+pub async fn revoke_session(
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    db_pool: DbPool,
+) -> impl Responder {
+    let session_id = path.into_inner();
+
+    if let Err(e) = user.require_session() {
+        return e.error_response();
+    }
+
+    match db::sessions::revoke_session(db_pool.get_ref(), &user.email, &session_id).await {
+        Ok(true) => {
+            info!("Session {} revoked by {}", session_id, user.email);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Session revoked"
+            }))
+        }
+        Ok(false) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "error": "Session not found"
+        })),
+        Err(e) => {
+            error!("Failed to revoke session: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to revoke session",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
+
+// Sign out every device, optionally keeping this one
+pub async fn revoke_all_sessions(
+    user: AuthenticatedUser,
+    body: Option<web::Json<RevokeAllSessionsRequest>>,
+    db_pool: DbPool,
+    config: ConfigData,
+) -> impl Responder {
+    let keep_current = body.and_then(|b| b.keep_current).unwrap_or(false);
+
+    let session_token = match user.require_session() {
+        Ok(session_token) => session_token,
+        Err(e) => return e.error_response(),
+    };
+
+    let keep = if keep_current { Some(session_token) } else { None };
+    match db::sessions::revoke_all_sessions(db_pool.get_ref(), &user.email, keep).await {
+        Ok(revoked) => {
+            info!("{} sessions revoked by {}", revoked, user.email);
+            let mut response = HttpResponse::Ok();
+            if !keep_current {
+                response.cookie(auth::create_logout_cookie(config.server.cookies_secure()));
+            }
+            response.json(json!({
+                "success": true,
+                "revoked": revoked,
+                "message": "Sessions revoked"
+            }))
+        }
+        Err(e) => {
+            error!("Failed to revoke sessions: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to revoke sessions",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use actix_web::{web, HttpResponse, Responder, ResponseError};
+use serde_json::json;
+use log::error;
+
+use crate::auth::AuthenticatedUser;
+use crate::db;
+use crate::gmail::GmailClient;
+use crate::models::{Thread, ThreadListQuery, ThreadSource, TokenScope};
+use crate::sync;
+
+type DbPool = web::Data<sqlx::PgPool>;
+type GmailClientData = web::Data<std::sync::Arc<GmailClient>>;
+
+// Threads listed when the request sets no limit
+const DEFAULT_THREAD_LIMIT: i64 = 50;
+
+// List the user's conversations, Quant and Gmail alike, most recently active first
+pub async fn list_threads(
+    user: AuthenticatedUser,
+    query: web::Query<ThreadListQuery>,
+    db_pool: DbPool,
+) -> impl Responder {
+    if let Err(e) = user.require_scope(TokenScope::ReadMail) {
+        return e.error_response();
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_THREAD_LIMIT).clamp(1, db::threads::MAX_THREADS_LISTED);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    match db::threads::list_threads(db_pool.get_ref(), &user.email, limit, offset).await {
+        Ok(threads) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "count": threads.len(),
+            "threads": threads
+        })),
+        Err(e) => {
+            error!("Failed to list threads for {}: {}", user.email, e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to list threads",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
+
+// Get a whole conversation, oldest message first. Gmail threads (gmail_<threadId>) are read from
+// Gmail itself; Quant threads from the messages linked by In-Reply-To and References, and opening
+// one marks the messages the user received read.
+pub async fn get_thread(
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    db_pool: DbPool,
+    gmail_client: GmailClientData,
+) -> impl Responder {
+    if let Err(e) = user.require_scope(TokenScope::ReadMail) {
+        return e.error_response();
+    }
+    let thread_id = path.into_inner();
+    let email = user.email;
+
+    let thread = if let Some(gmail_thread_id) = thread_id.strip_prefix("gmail_") {
+        let refresh_token = match user.refresh_token {
+            Some(refresh_token) => refresh_token,
+            None => {
+                return HttpResponse::BadRequest().json(json!({
+                    "success": false,
+                    "error": "No Gmail refresh token found"
+                }));
+            }
+        };
+        let access_token = match gmail_client.get_token(&email, &refresh_token).await {
+            Ok(access_token) => access_token,
+            Err(e) => {
+                error!("Gmail token error: {}", e);
+                return e.error_response();
+            }
+        };
+        match gmail_client.get_thread(&email, &access_token, gmail_thread_id).await {
+            Ok(gmail_thread) => {
+                let messages = gmail_thread.messages.iter()
+                    .map(|message| sync::synced_message(message).to_email())
+                    .collect();
+                Thread::new(thread_id.clone(), ThreadSource::Gmail, &email, messages)
+            }
+            Err(e) => {
+                error!("Failed to fetch Gmail thread {}: {}", gmail_thread_id, e);
+                return e.error_response();
+            }
+        }
+    } else {
+        match db::threads::open_thread(db_pool.get_ref(), &email, &thread_id).await {
+            Ok(messages) => Thread::new(thread_id.clone(), ThreadSource::Quant, &email, messages),
+            Err(e) => {
+                error!("Failed to load thread {}: {}", thread_id, e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "error": "Failed to load thread",
+                    "details": format!("{}", e)
+                }));
+            }
+        }
+    };
+
+    // Also covers threads the user is not part of, without revealing that they exist
+    if thread.messages.is_empty() {
+        return HttpResponse::NotFound().json(json!({
+            "success": false,
+            "error": "Thread not found"
+        }));
+    }
+
+    HttpResponse::Ok().json(json!({
+        "success": true,
+        "thread": thread
+    }))
+}
@@ -0,0 +1,256 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde_json::json;
+use log::error;
+
+use crate::auth::AuthenticatedUser;
+use crate::encryption::transparency;
+use crate::models::{LogEntriesQuery, InclusionProofQuery, ConsistencyProofQuery};
+
+type DbPool = web::Data<sqlx::PgPool>;
+
+// Largest range of entries returned in one request
+const MAX_ENTRIES_PER_REQUEST: u64 = 1000;
+
+// Resolve an optional tree size against the latest signed tree head
+async fn resolve_tree_head(pool: &sqlx::PgPool, tree_size: Option<u64>) -> Result<transparency::SignedTreeHead, HttpResponse> {
+    let tree_head = match tree_size {
+        Some(size) => transparency::get_tree_head(pool, size).await,
+        None => transparency::get_latest_tree_head(pool).await,
+    };
+
+    match tree_head {
+        Ok(Some(tree_head)) => Ok(tree_head),
+        Ok(None) => Err(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "error": "No signed tree head for that size"
+        }))),
+        Err(e) => {
+            error!("Failed to load tree head: {}", e);
+            Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to load tree head",
+                "details": format!("{}", e)
+            })))
+        }
+    }
+}
+
+// Proofs are checked against the signed head before they are served,
+// so a tampered row or a changed signing key shows up as an error here
+fn integrity_error(what: &str) -> HttpResponse {
+    error!("Key transparency integrity check failed: {}", what);
+    HttpResponse::InternalServerError().json(json!({
+        "success": false,
+        "error": "Key transparency log failed its integrity check",
+        "details": what
+    }))
+}
+
+fn decode_path(path: &[String]) -> Option<Vec<transparency::Hash>> {
+    path.iter().map(|h| transparency::decode_hash(h).ok()).collect()
+}
+
+fn proof_verifies_inclusion(
+    leaf: &Option<transparency::LogLeaf>,
+    proof: &transparency::InclusionProof,
+    tree_head: &transparency::SignedTreeHead,
+) -> bool {
+    match (leaf, decode_path(&proof.audit_path), tree_head.root()) {
+        (Some(leaf), Some(path), Ok(root)) => {
+            transparency::verify_inclusion(leaf.leaf_index, tree_head.tree_size, &leaf.leaf_hash(), &path, &root)
+        }
+        _ => false,
+    }
+}
+
+fn proof_verifies_consistency(
+    proof: &transparency::ConsistencyProof,
+    first_head: &transparency::SignedTreeHead,
+    second_head: &transparency::SignedTreeHead,
+) -> bool {
+    match (decode_path(&proof.proof), first_head.root(), second_head.root()) {
+        (Some(path), Ok(first_root), Ok(second_root)) => {
+            transparency::verify_consistency(first_head.tree_size, second_head.tree_size, &first_root, &second_root, &path)
+        }
+        _ => false,
+    }
+}
+
+// Latest signed tree head and the key that signs it
+pub async fn get_transparency_head(db_pool: DbPool) -> impl Responder {
+    let log_public_key = transparency::log_public_key();
+    
+    match transparency::get_latest_tree_head(db_pool.get_ref()).await {
+        Ok(Some(ref tree_head)) if tree_head.verify(&log_public_key).is_err() => {
+            integrity_error("tree head signature does not match the log key")
+        }
+        Ok(tree_head) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "tree_head": tree_head,
+            "log_public_key": log_public_key
+        })),
+        Err(e) => {
+            error!("Failed to load tree head: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to load tree head",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
+
+// Leaf hashes so auditors can rebuild the tree themselves; the emails and keys behind them
+// are only served in a user's key history
+pub async fn get_transparency_entries(
+    query: web::Query<LogEntriesQuery>,
+    db_pool: DbPool,
+) -> impl Responder {
+    let start = query.start.unwrap_or(0);
+    let last_allowed = start.saturating_add(MAX_ENTRIES_PER_REQUEST);
+    let end = query.end.unwrap_or(last_allowed).min(last_allowed);
+    if end < start {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": "End must not be before start"
+        }));
+    }
+
+    match transparency::get_log_entries(db_pool.get_ref(), start, end).await {
+        Ok(entries) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "start": start,
+            "entries": entries.iter().map(transparency::LeafHashEntry::from).collect::<Vec<_>>()
+        })),
+        Err(e) => {
+            error!("Failed to load log entries: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to load log entries",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
+
+// Inclusion proof for one leaf against a signed tree head
+pub async fn get_inclusion_proof(
+    query: web::Query<InclusionProofQuery>,
+    db_pool: DbPool,
+) -> impl Responder {
+    let tree_head = match resolve_tree_head(db_pool.get_ref(), query.tree_size).await {
+        Ok(tree_head) => tree_head,
+        Err(response) => return response,
+    };
+
+    if query.leaf_index >= tree_head.tree_size {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": "Leaf index is outside the tree"
+        }));
+    }
+
+    let leaf = match transparency::get_log_entries(db_pool.get_ref(), query.leaf_index, query.leaf_index + 1).await {
+        Ok(mut entries) => entries.pop(),
+        Err(e) => {
+            error!("Failed to load log entry: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to load log entry",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+
+    match transparency::get_inclusion_proof(db_pool.get_ref(), query.leaf_index, tree_head.tree_size).await {
+        Ok(proof) if !proof_verifies_inclusion(&leaf, &proof, &tree_head) => {
+            integrity_error("inclusion proof does not match the signed root")
+        }
+        Ok(proof) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "leaf": leaf.as_ref().map(transparency::LeafHashEntry::from),
+            "inclusion_proof": proof,
+            "tree_head": tree_head
+        })),
+        Err(e) => {
+            error!("Failed to build inclusion proof: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to build inclusion proof",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
+
+// Consistency proof showing an older tree head is a prefix of a newer one
+pub async fn get_consistency_proof(
+    query: web::Query<ConsistencyProofQuery>,
+    db_pool: DbPool,
+) -> impl Responder {
+    let second_head = match resolve_tree_head(db_pool.get_ref(), query.second).await {
+        Ok(tree_head) => tree_head,
+        Err(response) => return response,
+    };
+    let first_head = match resolve_tree_head(db_pool.get_ref(), Some(query.first)).await {
+        Ok(tree_head) => tree_head,
+        Err(response) => return response,
+    };
+
+    if first_head.tree_size > second_head.tree_size {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": "First tree size must not exceed the second"
+        }));
+    }
+
+    match transparency::get_consistency_proof(db_pool.get_ref(), first_head.tree_size, second_head.tree_size).await {
+        Ok(proof) if !proof_verifies_consistency(&proof, &first_head, &second_head) => {
+            integrity_error("consistency proof does not match the signed roots")
+        }
+        Ok(proof) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "consistency_proof": proof,
+            "first_tree_head": first_head,
+            "second_tree_head": second_head
+        })),
+        Err(e) => {
+            error!("Failed to build consistency proof: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to build consistency proof",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
+
+// Audit bundle with a user's full key history, for the offline verifier.
+// Signed-in users may audit anyone they might write to; strangers cannot probe for accounts.
+pub async fn get_key_history(
+    _user: AuthenticatedUser,
+    path: web::Path<String>,
+    db_pool: DbPool,
+) -> impl Responder {
+    let email = path.into_inner();
+
+    match transparency::get_key_history(db_pool.get_ref(), &email).await {
+        Ok(Some(bundle)) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "bundle": bundle,
+            "log_public_key": transparency::log_public_key()
+        })),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "error": "Key transparency log is empty"
+        })),
+        Err(e) => {
+            error!("Failed to load key history: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to load key history",
+                "details": format!("{}", e)
+            }))
+        }
+    }
+}
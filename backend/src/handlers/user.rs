@@ -1,72 +1,71 @@
-use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use actix_web::{web, HttpResponse, Responder};
 use serde_json::json;
 
+use crate::auth::{AuthenticatedUser, AuthError};
 use crate::db;
 use crate::models::UserResponse;
 
 type DbPool = web::Data<sqlx::PgPool>;
 
-pub async fn get_user_info(req: HttpRequest, db_pool: DbPool) -> impl Responder {
-    if let Some(cookie) = req.cookie("session") {
-        let session_token = cookie.value().to_string();
-        
-        // Look up user by session token
-        match db::get_user_by_session(db_pool.get_ref(), &session_token).await {
-            Ok(Some((email, name, picture, _refresh_token))) => {
-                println!("User authenticated: {}", email);
+pub async fn get_user_info(user: Result<AuthenticatedUser, AuthError>, db_pool: DbPool) -> impl Responder {
+    match user {
+        Ok(user) => {
+            println!("User authenticated: {}", user.email);
+            
+            // Process the picture URL to ensure it's valid
+            let processed_picture = user.picture.as_ref().map(|pic_url| {
+                // Ensure it's using HTTPS
+                let https_url = if pic_url.starts_with("http://") {
+                    pic_url.replace("http://", "https://")
+                } else {
+                    pic_url.clone()
+                };
                 
-                // Process the picture URL to ensure it's valid
-                let processed_picture = picture.as_ref().map(|pic_url| {
-                    // Ensure it's using HTTPS
-                    let https_url = if pic_url.starts_with("http://") {
-                        pic_url.replace("http://", "https://")
-                    } else {
-                        pic_url.clone()
-                    };
-                    
-                    // Ensure the image size is adequate
-                    if https_url.contains("=s") {
-                        https_url.replace("=s96-c", "=s256-c")
-                    } else {
-                        https_url
-                    }
-                });
-                
-                return HttpResponse::Ok().json(UserResponse {
-                    authenticated: true,
-                    email: Some(email),
-                    name,
-                    picture: processed_picture,
-                    message: None,
-                });
-            }
-            Ok(None) => {
-                println!("Invalid session token: {}", session_token);
-                return HttpResponse::Ok().json(UserResponse {
-                    authenticated: false,
-                    email: None,
-                    name: None,
-                    picture: None,
-                    message: Some("Invalid session".to_string()),
-                });
-            }
-            Err(e) => {
-                println!("Database error during authentication check: {}", e);
-                return HttpResponse::InternalServerError().json(json!({
-                    "error": "Database error",
-                    "details": format!("{}", e)
-                }));
-            }
+                // Ensure the image size is adequate
+                if https_url.contains("=s") {
+                    https_url.replace("=s96-c", "=s256-c")
+                } else {
+                    https_url
+                }
+            });
+            
+            let mailbox_email = match db::identities::get_mailbox_email(db_pool.get_ref(), &user.email).await {
+                Ok(mailbox_email) => mailbox_email,
+                Err(e) => {
+                    println!("Error loading linked mailbox: {}", e);
+                    None
+                }
+            };
+            
+            HttpResponse::Ok().json(UserResponse {
+                authenticated: true,
+                email: Some(user.email),
+                name: user.name,
+                picture: processed_picture,
+                mailbox_email,
+                message: None,
+            })
+        }
+        Err(AuthError::Database(e)) => {
+            println!("Database error during authentication check: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "error": "Database error",
+                "details": format!("{}", e)
+            }))
+        }
+        Err(e) => {
+            println!("Not authenticated: {}", e);
+            HttpResponse::Ok().json(UserResponse {
+                authenticated: false,
+                email: None,
+                name: None,
+                picture: None,
+                mailbox_email: None,
+                message: match e {
+                    AuthError::NotAuthenticated => None,
+                    _ => Some(e.to_string()),
+                },
+            })
         }
-    } else {
-        println!("No session cookie found");
     }
-    
-    HttpResponse::Ok().json(UserResponse {
-        authenticated: false,
-        email: None,
-        name: None,
-        picture: None,
-        message: None,
-    })
 }
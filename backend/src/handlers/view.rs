@@ -0,0 +1,244 @@
+use actix_web::{web, HttpResponse, Responder, ResponseError};
+use serde::Deserialize;
+use serde_json::json;
+use log::{info, error, warn};
+
+use crate::auth::{self, AuthenticatedUser, AuthError, ViewLinkError};
+use crate::db;
+use crate::models::TokenScope;
+use crate::gmail::GmailClient;
+use crate::cache::RedisCache;
+use crate::handlers::email::{build_notification_message, sending_mailbox};
+
+type DbPool = web::Data<sqlx::PgPool>;
+type GmailClientData = web::Data<std::sync::Arc<GmailClient>>;
+type RedisCacheData = web::Data<std::sync::Arc<RedisCache>>;
+type ConfigData = web::Data<crate::config::AppConfig>;
+
+// Minimum time between two renewals of the same link
+const RENEW_COOLDOWN_SECONDS: usize = 600;
+// Renewals allowed for one message, and for one recipient across all messages, per window
+const RENEWALS_PER_EMAIL: u64 = 5;
+const RENEWALS_PER_RECIPIENT: u64 = 10;
+const RENEWAL_WINDOW_SECONDS: usize = 86400 * 30;
+const RECIPIENT_WINDOW_SECONDS: usize = 86400;
+
+#[derive(Deserialize)]
+pub struct RenewViewLinkRequest {
+    pub token: String,
+}
+
+// Check a view link token before the frontend loads the message
+pub async fn verify_view_link(
+    user: Result<AuthenticatedUser, AuthError>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let token = path.into_inner();
+    
+    let claims = match auth::verify_view_token(&token) {
+        Ok(claims) => claims,
+        Err(e) => {
+            let can_request_new = matches!(e, ViewLinkError::Expired);
+            let response = json!({
+                "success": false,
+                "reason": e.reason(),
+                "can_request_new": can_request_new,
+                "error": if can_request_new { "This link has expired" } else { "This link is not valid" }
+            });
+            return if can_request_new {
+                HttpResponse::Gone().json(response)
+            } else {
+                HttpResponse::BadRequest().json(response)
+            };
+        }
+    };
+    
+    match user {
+        Ok(user) => {
+            if let Err(e) = user.require_scope(TokenScope::ReadMail) {
+                return e.error_response();
+            }
+            
+            // Forwarded links must not open for anyone but the addressed recipient
+            if !user.email.eq_ignore_ascii_case(&claims.recipient) {
+                warn!("View link for {} opened by {}", claims.recipient, user.email);
+                return HttpResponse::Forbidden().json(json!({
+                    "success": false,
+                    "reason": "wrong_recipient",
+                    "can_request_new": true,
+                    "error": "This link was sent to a different account"
+                }));
+            }
+            
+            return HttpResponse::Ok().json(json!({
+                "success": true,
+                "email_id": claims.email_id,
+                "expires_at": claims.expires_at
+            }));
+        }
+        Err(AuthError::Database(e)) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "error": "Database error",
+                "details": format!("{}", e)
+            }));
+        }
+        Err(_) => {}
+    }
+    
+    HttpResponse::Unauthorized().json(json!({
+        "success": false,
+        "reason": "login_required",
+        "can_request_new": false,
+        "error": "Not authenticated"
+    }))
+}
+
+// Send a fresh view link to the recipient the original link was addressed to.
+// The link only ever goes to the address in the signed token, never to the caller.
+pub async fn renew_view_link(
+    body: web::Json<RenewViewLinkRequest>,
+    db_pool: DbPool,
+    gmail_client: GmailClientData,
+    redis_cache: RedisCacheData,
+    config: ConfigData,
+) -> impl Responder {
+    // Expired links are fine here, but the signature must still hold
+    let claims = match auth::verify_view_token_signature(&body.token) {
+        Ok(claims) => claims,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "reason": e.reason(),
+                "error": "This link is not valid"
+            }));
+        }
+    };
+    
+    let found_email = match db::get_email(db_pool.get_ref(), &claims.email_id).await {
+        Ok(Some(found_email)) if found_email.recipient_email.eq_ignore_ascii_case(&claims.recipient) => found_email,
+        Ok(_) => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "error": "Email not found"
+            }));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "error": "Database error",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    
+    if found_email.recalled_at.is_some() {
+        return HttpResponse::Gone().json(json!({
+            "success": false,
+            "reason": "recalled",
+            "error": "This message was recalled by the sender"
+        }));
+    }
+    
+    // Anyone holding an expired link can ask, so every renewal is bounded per link and per recipient.
+    // Without Redis the limits cannot be checked, and nothing is sent.
+    let recipient = found_email.recipient_email.to_lowercase();
+    let allowed = match redis_cache.claim_cooldown(&format!("view_link:{}", claims.email_id), RENEW_COOLDOWN_SECONDS).await {
+        Ok(false) => Ok(false),
+        Ok(true) => match redis_cache.count_attempt(&format!("view_link:{}", claims.email_id), RENEWAL_WINDOW_SECONDS).await {
+            Ok(attempts) if attempts > RENEWALS_PER_EMAIL => Ok(false),
+            Ok(_) => redis_cache
+                .count_attempt(&format!("view_link_recipient:{}", recipient), RECIPIENT_WINDOW_SECONDS)
+                .await
+                .map(|attempts| attempts <= RENEWALS_PER_RECIPIENT),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    match allowed {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("View link renewal for email {} to {} refused by rate limit", found_email.id, recipient);
+            return HttpResponse::TooManyRequests().json(json!({
+                "success": false,
+                "error": "A new link was sent recently, please check your inbox"
+            }));
+        }
+        Err(e) => {
+            error!("Failed to check view link renewal limits: {}", e);
+            return HttpResponse::ServiceUnavailable().json(json!({
+                "success": false,
+                "error": "New links cannot be sent right now, please try again later"
+            }));
+        }
+    }
+    
+    // The notification is sent from the original sender's mailbox
+    let sender = found_email.sender_email.clone();
+    let refresh_token = match db::get_user_refresh_token(db_pool.get_ref(), &sender).await {
+        Ok(Some(refresh_token)) => refresh_token,
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": "The sender can no longer send notifications"
+            }));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "error": "Database error",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    let mailbox_email = match sending_mailbox(db_pool.get_ref(), &sender).await {
+        Ok(mailbox_email) => mailbox_email,
+        Err(response) => return response,
+    };
+    
+    let sender_name = match db::get_user_info(db_pool.get_ref(), &sender).await {
+        Ok(Some(user_info)) => user_info.name.unwrap_or_else(|| sender.clone()),
+        _ => sender.clone(),
+    };
+    
+    let view_link = auth::create_view_link(&config.server.frontend_url, &found_email.id, &found_email.recipient_email);
+    let raw_message = match build_notification_message(&mailbox_email, &sender_name, &found_email.recipient_email, &view_link) {
+        Ok(raw_message) => raw_message,
+        Err(e) => {
+            error!("Failed to build notification email: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to build notification email",
+                "details": format!("{}", e)
+            }));
+        }
+    };
+    
+    match gmail_client.get_token(&sender, &refresh_token).await {
+        Ok(access_token) => match gmail_client.send_message(&sender, &access_token, raw_message).await {
+            Ok(_) => {
+                info!("Sent renewed view link for email {} to {}", found_email.id, found_email.recipient_email);
+                HttpResponse::Ok().json(json!({
+                    "success": true,
+                    "message": "A new link has been sent to the recipient's inbox"
+                }))
+            }
+            Err(e) => {
+                error!("Failed to send renewed view link for email {}: {}", found_email.id, e);
+                could_not_send()
+            }
+        },
+        Err(e) => {
+            error!("Gmail token error for {} while renewing a view link: {}", sender, e);
+            could_not_send()
+        }
+    }
+}
+
+// Anyone may ask for a renewal, so what went wrong with the sender's mailbox stays in the log
+fn could_not_send() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(json!({
+        "success": false,
+        "error": "Could not send a new link"
+    }))
+}
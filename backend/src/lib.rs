@@ -1,4 +1,6 @@
 // Export the necessary modules for external use
+pub mod config;
 pub mod encryption;
 pub mod db;
-pub mod models;
\ No newline at end of file
+pub mod models;
+pub mod gmail_push;
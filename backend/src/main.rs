@@ -1,7 +1,5 @@
 // Import section
-use actix_web::{web, App, HttpServer, HttpResponse, Responder, HttpRequest};
-use actix_cors::Cors;
-use std::env;
+use actix_web::{web, App, HttpServer, HttpResponse, Responder, middleware::from_fn};
 use dotenv::dotenv;
 use sqlx::postgres::PgPoolOptions;
 use env_logger::Builder;
@@ -16,6 +14,17 @@ mod handlers;
 mod gmail;
 mod cache;
 mod encryption;
+mod config;
+mod tls;
+mod security;
+mod sync;
+mod mime;
+
+// How often scheduled account deletions are checked
+const ACCOUNT_PURGE_INTERVAL_SECONDS: u64 = 3600;
+
+// How often Gmail watches are registered for new mailboxes and renewed before they expire
+const WATCH_RENEWAL_INTERVAL_SECONDS: u64 = 600;
 
 // Main function
 #[actix_web::main]
@@ -30,13 +39,19 @@ async fn main() -> std::io::Result<()> {
         .format_target(false)
         .init();
     
-    // Database setup
-    let database_url = env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set");
+    // Load and validate settings; every problem is reported before exiting
+    let config = match config::load() {
+        Ok(config) => config::install(config),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
     
+    // Database setup
     let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+        .max_connections(config.database.max_connections)
+        .connect(config.database.url.expose())
         .await
         .expect("Failed to create pool");
     
@@ -45,111 +60,232 @@ async fn main() -> std::io::Result<()> {
     
     // Run migrations
     db::migrate_profile_pictures(&pool).await.expect("Failed to migrate profile pictures");
+    db::migrate_session_tokens(&pool).await.expect("Failed to migrate session tokens");
+    db::migrate_refresh_token_encryption(&pool).await.expect("Failed to encrypt stored refresh tokens");
+    db::migrate_mailbox_links(&pool).await.expect("Failed to record linked mailboxes");
+    db::migrate_unique_mailbox_links(&pool).await.expect("Failed to make linked mailboxes unique");
+    
+    // Promote the configured first admin if nobody holds the role yet
+    if let Some(admin_email) = config.bootstrap_admin_email() {
+        if db::roles::bootstrap_admin(&pool, admin_email).await.expect("Failed to bootstrap admin") {
+            log::info!("Bootstrapped {} as the first admin", admin_email);
+        }
+    }
     
     // Create Gmail client
-    let gmail_client = gmail::create_gmail_client();
+    let gmail_client = gmail::create_gmail_client(&config.google);
     
     // Create Redis cache
-    let redis_cache = cache::create_redis_cache();
+    let redis_cache = cache::create_redis_cache(&config.redis, &config.cache);
+
+    // On-disk cache of downloaded Gmail attachments
+    let attachment_cache = cache::create_attachment_cache(&config.attachments);
+    
+    // Identity providers users can sign in with
+    let identity_providers = std::sync::Arc::new(auth::IdentityProviders::new(auth::identity_providers(&config)));
+    for provider in identity_providers.list() {
+        log::info!("Sign-in enabled for {} ({})", provider.display_name, provider.issuer);
+    }
+    
+    // Purge accounts whose deletion grace period has ended
+    {
+        let pool = pool.clone();
+        let gmail_client = gmail_client.clone();
+        let redis_cache = redis_cache.clone();
+        let attachment_cache = attachment_cache.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(ACCOUNT_PURGE_INTERVAL_SECONDS));
+            loop {
+                interval.tick().await;
+                gmail::in_background(handlers::purge_due_accounts(&pool, &gmail_client, &redis_cache, &attachment_cache)).await;
+            }
+        });
+    }
+    
+    // Keep Gmail publishing mailbox changes for push-triggered syncs
+    if let Some(push) = config.google.push.clone() {
+        log::info!("Gmail push notifications enabled on {}", push.topic);
+        let pool = pool.clone();
+        let gmail_client = gmail_client.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(WATCH_RENEWAL_INTERVAL_SECONDS));
+            loop {
+                interval.tick().await;
+                gmail::in_background(sync::renew_watches(&pool, &gmail_client, &push)).await;
+            }
+        });
+    }
     
     // Server setup
-    let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
-    let bind_address = format!("0.0.0.0:{}", port);
+    let bind_address = format!("0.0.0.0:{}", config.server.port);
     
-    log::info!("Server starting on {}", bind_address);
-    log::info!("Frontend URL: {}", auth::FRONTEND_URL);
+    log::info!("Server starting on {} ({})", bind_address, if config.server.tls.is_some() { "HTTPS" } else { "HTTP" });
+    log::info!("Frontend URL: {}", config.server.frontend_url);
+
+    // Built-in TLS; the certificate is reloaded when its files change
+    let rustls_config = match config.server.tls {
+        Some(ref tls_settings) => match tls::server_config(tls_settings) {
+            Ok((rustls_config, resolver)) => {
+                tls::spawn_certificate_reload(tls_settings.clone(), resolver);
+                Some(rustls_config)
+            }
+            Err(e) => {
+                eprintln!("Cannot load TLS certificate: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let hsts = config.server.tls.as_ref().and_then(tls::hsts_header);
+    let http_redirect_port = config.server.tls.as_ref().and_then(|tls| tls.http_redirect_port);
+    let redirect_config = config.clone();
+
+    // Largest send request: the attachment limit, grown by base64, plus room for the rest of the message
+    let send_body_limit = (config.attachments.max_size_bytes as usize) / 3 * 4 + 1_048_576;
 
     // Database pool to be shared with app
     let _db_pool = web::Data::new(pool.clone());
 
     // Start HTTP server
-    HttpServer::new(move || {
-        let cors = Cors::default()
-            .allow_any_origin()
-            .allow_any_method()
-            .allow_any_header()
-            .supports_credentials();
+    let server = HttpServer::new(move || {
+        // Only the configured frontend origins may call the API with the user's cookie
+        let cors = security::cors(&config.server);
 
         App::new()
             .app_data(web::Data::new(pool.clone()))
             .app_data(web::Data::new(gmail_client.clone()))
             .app_data(web::Data::new(redis_cache.clone()))
+            .app_data(web::Data::new(attachment_cache.clone()))
+            .app_data(web::Data::new(identity_providers.clone()))
+            .app_data(web::Data::from(config.clone()))
+            .wrap(from_fn(security::require_csrf_header))
             .wrap(cors)
+            .wrap(security::security_headers(hsts.as_deref()))
 
             // Base routes
             .route("/", web::get().to(handlers::welcome))
 
             // Auth routes
-            .route("/auth/google", web::get().to(handlers::auth_google))
-            .route("/auth/google/callback", web::get().to(handlers::auth_google_callback))
+            .route("/auth/google/link", web::get().to(handlers::auth_google_link))
+            .route("/auth/{provider}", web::get().to(handlers::auth_login))
+            .route("/auth/{provider}/callback", web::get().to(handlers::auth_callback))
+            .route("/api/auth/providers", web::get().to(handlers::list_identity_providers))
+            .route("/api/mailbox/unlink", web::post().to(handlers::unlink_mailbox))
             .route("/api/user", web::get().to(handlers::get_user_info))
             .route("/api/logout", web::post().to(handlers::logout))
+            .route("/api/sessions", web::get().to(handlers::list_sessions))
+            .route("/api/sessions/revoke-all", web::post().to(handlers::revoke_all_sessions))
+            .route("/api/sessions/{id}/revoke", web::post().to(handlers::revoke_session))
+            .route("/api/tokens", web::get().to(handlers::list_access_tokens))
+            .route("/api/tokens", web::post().to(handlers::create_access_token))
+            .route("/api/tokens/{id}/revoke", web::post().to(handlers::revoke_access_token))
+            .route("/api/account/delete", web::post().to(handlers::delete_account))
+            .route("/api/account/delete/undo", web::post().to(handlers::undo_account_deletion))
+            .route("/api/account/deletion/{id}", web::get().to(handlers::get_account_deletion))
 
             // Email routes
-            .route("/api/emails", web::get().to(handlers::get_emails))
-            .route("/api/emails", web::post().to(handlers::send_email))
+            .service(
+                web::resource("/api/emails")
+                    // Direct messages carry their attachments as base64 in the JSON body
+                    .app_data(web::JsonConfig::default().limit(send_body_limit))
+                    .route(web::get().to(handlers::get_emails))
+                    .route(web::post().to(handlers::send_email))
+            )
             .route("/api/emails/{id}", web::get().to(handlers::get_email))
             .route("/api/emails/{id}/read", web::post().to(handlers::mark_email_as_read))
+            .route("/api/emails/{id}/recall", web::post().to(handlers::recall_email))
+            .route("/api/emails/{id}/forward", web::post().to(handlers::forward_email))
+            .route("/api/emails/{id}/attachments/{part_id}", web::get().to(handlers::download_attachment))
+
+            // Conversation routes
+            .route("/api/threads", web::get().to(handlers::list_threads))
+            .route("/api/threads/{id}", web::get().to(handlers::get_thread))
+
+            // Notification view link routes
+            .route("/api/view/renew", web::post().to(handlers::renew_view_link))
+            .route("/api/view/{token}", web::get().to(handlers::verify_view_link))
+
+            // Secure portal routes for recipients without an account
+            .route("/api/portal/{token}/open", web::post().to(handlers::open_portal_message))
+            .route("/api/portal/{token}/reply", web::post().to(handlers::reply_portal_message))
 
             // Cache control routes
             .route("/api/emails/refresh", web::post().to(handlers::refresh_emails))
+            .route("/api/gmail/push", web::post().to(handlers::gmail_push))
 
-            // Admin routes
-            .route("/admin/users", web::get().to(handlers::list_users))
+            // Admin routes; auditors may read, only admins may change roles
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(auth::require_admin_role))
+                    .route("/users", web::get().to(handlers::list_users))
+                    .route("/users/{email}/role", web::post().to(handlers::grant_role))
+                    .route("/users/{email}/role/revoke", web::post().to(handlers::revoke_role))
+                    .route("/audit", web::get().to(handlers::get_admin_audit_log))
+                    .route("/gmail/quota", web::get().to(handlers::get_gmail_quota_metrics))
+            )
 
             // Label routes
             .route("/api/labels", web::get().to(handlers::get_labels))
             
             // Encryption routes
             .route("/api/keys/generate", web::post().to(handlers::generate_encryption_keys))
+            .route("/api/keys/revoke", web::post().to(handlers::revoke_encryption_keys))
             .route("/api/emails/{id}/decrypt", web::get().to(handlers::decrypt_email))
 
-            // Debug routes
-            .route("/api/debug/quantum", web::post().to(toggle_quantum_debug))
-    })
-        .bind(&bind_address)?
-        .run()
-        .await
+            // Key transparency log routes
+            .route("/api/transparency/head", web::get().to(handlers::get_transparency_head))
+            .route("/api/transparency/entries", web::get().to(handlers::get_transparency_entries))
+            .route("/api/transparency/proof/inclusion", web::get().to(handlers::get_inclusion_proof))
+            .route("/api/transparency/proof/consistency", web::get().to(handlers::get_consistency_proof))
+            .route("/api/transparency/keys/{email}", web::get().to(handlers::get_key_history))
+
+            // Debug routes change server-wide behaviour, so they sit behind the admin guard
+            .service(
+                web::scope("/api/debug")
+                    .wrap(from_fn(auth::require_admin_role))
+                    .route("/quantum", web::post().to(toggle_quantum_debug))
+            )
+    });
+    let server = match rustls_config {
+        Some(rustls_config) => server.bind_rustls_021(&bind_address, rustls_config)?,
+        None => server.bind(&bind_address)?,
+    }
+    .run();
+
+    // Plain HTTP listener that only redirects to HTTPS
+    match http_redirect_port {
+        Some(port) => {
+            log::info!("Redirecting HTTP on port {} to {}", port, redirect_config.server.backend_url);
+            let redirect = HttpServer::new(move || {
+                App::new()
+                    .app_data(web::Data::from(redirect_config.clone()))
+                    .default_service(web::to(tls::redirect_to_https))
+            })
+                .workers(1)
+                .bind(("0.0.0.0", port))?
+                .run();
+            futures::future::try_join(server, redirect).await.map(|_| ())
+        }
+        None => server.await,
+    }
 }
 
 // Add the handler function for toggling quantum debug mode
 async fn toggle_quantum_debug(
-    req: HttpRequest, 
+    actor: web::ReqData<auth::AdminActor>,
     body: web::Json<serde_json::Value>,
-    db_pool: web::Data<sqlx::PgPool>
 ) -> impl Responder {
-    // Only admins should be able to toggle debug mode
-    if let Some(cookie) = req.cookie("session") {
-        let session_token = cookie.value().to_string();
-        
-        // Check if the request has a valid user session
-        match db::get_user_by_session(db_pool.get_ref(), &session_token).await {
-            Ok(Some((email, _, _, _))) => {
-                // Get enabled status from request body
-                let enabled = body.get("enabled").and_then(|v| v.as_bool()).unwrap_or(true);
-                
-                // Set the debug mode
-                encryption::set_debug_mode(enabled);
-                
-                log::info!("Quantum debug mode toggled to {} by {}", enabled, email);
-                
-                HttpResponse::Ok().json(serde_json::json!({
-                    "success": true,
-                    "message": format!("Quantum debug mode set to: {}", if enabled { "enabled" } else { "disabled" }),
-                    "debug_mode": enabled
-                }))
-            },
-            _ => {
-                HttpResponse::Unauthorized().json(serde_json::json!({
-                    "success": false,
-                    "error": "Not authenticated"
-                }))
-            }
-        }
-    } else {
-        HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Not authenticated"
-        }))
-    }
+    // Get enabled status from request body
+    let enabled = body.get("enabled").and_then(|v| v.as_bool()).unwrap_or(true);
+    
+    // Set the debug mode
+    encryption::set_debug_mode(enabled);
+    
+    log::info!("Quantum debug mode toggled to {} by {}", enabled, actor.email);
+    
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": format!("Quantum debug mode set to: {}", if enabled { "enabled" } else { "disabled" }),
+        "debug_mode": enabled
+    }))
 }
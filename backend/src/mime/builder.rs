@@ -0,0 +1,529 @@
+use base64::{encode_config, STANDARD, URL_SAFE};
+use std::fmt;
+use uuid::Uuid;
+
+use super::transfer::{encode_base64_lines, encode_quoted_printable};
+
+// Lines are folded before this length where the value allows it
+const FOLD_WIDTH: usize = 78;
+
+// Bytes of text per RFC 2047 encoded-word, keeping each word within 75 characters
+const ENCODED_WORD_BYTES: usize = 45;
+
+// The longest address RFC 5321 lets through, and the longest local part
+const MAX_ADDRESS_LENGTH: usize = 254;
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+
+#[derive(Debug, Clone)]
+pub enum MessageError {
+    InvalidAddress(String),
+    NoRecipients,
+    InvalidMessageId(String),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::InvalidAddress(address) => write!(f, "{:?} is not a valid email address", address),
+            MessageError::NoRecipients => write!(f, "A message needs at least one recipient"),
+            MessageError::InvalidMessageId(id) => write!(f, "{:?} is not a valid message id", id),
+        }
+    }
+}
+
+impl std::error::Error for MessageError {}
+
+// A mailbox: an address with an optional display name
+#[derive(Debug, Clone)]
+pub struct Address {
+    pub name: Option<String>,
+    pub email: String,
+}
+
+impl Address {
+    // Accepts "user@example.com", "<user@example.com>" and "Name <user@example.com>"
+    pub fn parse(text: &str) -> Result<Address, MessageError> {
+        let text = text.trim();
+        let (name, email) = match text.rfind('<') {
+            Some(open) if text.ends_with('>') => (text[..open].trim(), &text[open + 1..text.len() - 1]),
+            _ => ("", text),
+        };
+        let name = unquote(name);
+        let address = Address {
+            name: Some(name).filter(|name| !name.is_empty()),
+            email: email.trim().to_string(),
+        };
+        check_address(&address.email)?;
+        Ok(address)
+    }
+
+    pub fn named(name: &str, email: &str) -> Address {
+        Address { name: Some(name.to_string()), email: email.to_string() }
+    }
+
+    fn header_value(&self) -> Result<String, MessageError> {
+        check_address(&self.email)?;
+        let name = self.name.as_deref().map(strip_controls).filter(|name| !name.trim().is_empty());
+        Ok(match name {
+            Some(name) => format!("{} <{}>", encode_phrase(name.trim()), self.email),
+            None => self.email.clone(),
+        })
+    }
+}
+
+// A file sent along with a message
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub filename: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+// An RFC 5322 message. Every header value is encoded or checked, so nothing a caller passes in can
+// add header lines; Date and, unless one is given, Message-ID are generated.
+#[derive(Debug, Clone)]
+pub struct MessageBuilder {
+    pub from: Address,
+    pub to: Vec<Address>,
+    pub cc: Vec<Address>,
+    // Gmail delivers to Bcc recipients and strips the header before the message leaves
+    pub bcc: Vec<Address>,
+    pub reply_to: Option<Address>,
+    pub subject: String,
+    pub text: String,
+    // With HTML the body becomes multipart/alternative, the text part serving as the fallback
+    pub html: Option<String>,
+    // With attachments the body becomes multipart/mixed
+    pub attachments: Vec<Attachment>,
+    // In angle brackets, as the header carries it
+    pub message_id: Option<String>,
+    pub in_reply_to: Option<String>,
+    pub references: Vec<String>,
+}
+
+impl MessageBuilder {
+    pub fn new(from: Address, to: Address, subject: &str, text: &str) -> MessageBuilder {
+        MessageBuilder {
+            from,
+            to: vec![to],
+            cc: Vec::new(),
+            bcc: Vec::new(),
+            reply_to: None,
+            subject: subject.to_string(),
+            text: text.to_string(),
+            html: None,
+            attachments: Vec::new(),
+            message_id: None,
+            in_reply_to: None,
+            references: Vec::new(),
+        }
+    }
+
+    pub fn build(&self) -> Result<Vec<u8>, MessageError> {
+        if self.to.is_empty() && self.cc.is_empty() && self.bcc.is_empty() {
+            return Err(MessageError::NoRecipients);
+        }
+
+        let mut headers = vec![("From", self.from.header_value()?)];
+        for (name, addresses) in [("To", &self.to), ("Cc", &self.cc), ("Bcc", &self.bcc)] {
+            if !addresses.is_empty() {
+                headers.push((name, address_list(addresses)?));
+            }
+        }
+        if let Some(ref reply_to) = self.reply_to {
+            headers.push(("Reply-To", reply_to.header_value()?));
+        }
+        headers.push(("Subject", encode_text(&self.subject)));
+        headers.push(("Date", chrono::Utc::now().to_rfc2822()));
+
+        let message_id = match self.message_id {
+            Some(ref id) => check_message_id(id)?,
+            None => {
+                let domain = self.from.email.rsplit_once('@').map_or("quant.local", |(_, domain)| domain);
+                format!("<{}@{}>", Uuid::new_v4().simple(), domain)
+            }
+        };
+        headers.push(("Message-ID", message_id));
+        if let Some(ref in_reply_to) = self.in_reply_to {
+            headers.push(("In-Reply-To", check_message_id(in_reply_to)?));
+        }
+        if !self.references.is_empty() {
+            let references = self.references.iter()
+                .map(|id| check_message_id(id))
+                .collect::<Result<Vec<_>, _>>()?;
+            headers.push(("References", references.join(" ")));
+        }
+        headers.push(("MIME-Version", "1.0".to_string()));
+
+        let mut message = String::new();
+        for (name, value) in headers {
+            write_header(&mut message, name, &value);
+        }
+        self.body().render(&mut message);
+        Ok(message.into_bytes())
+    }
+
+    // The message as the base64url `raw` field of users.messages.send
+    pub fn build_raw(&self) -> Result<String, MessageError> {
+        Ok(encode_config(self.build()?, URL_SAFE))
+    }
+
+    fn body(&self) -> Entity {
+        let text = Entity::text("plain", &self.text);
+        let content = match self.html {
+            Some(ref html) => Entity::multipart("alternative", vec![text, Entity::text("html", html)]),
+            None => text,
+        };
+        if self.attachments.is_empty() {
+            return content;
+        }
+
+        let mut parts = vec![content];
+        parts.extend(self.attachments.iter().map(Entity::attachment));
+        Entity::multipart("mixed", parts)
+    }
+}
+
+// One body part: its headers, then its content, already transfer-encoded
+struct Entity {
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl Entity {
+    fn text(subtype: &str, text: &str) -> Entity {
+        Entity {
+            headers: vec![
+                ("Content-Type", format!("text/{}; charset=utf-8", subtype)),
+                ("Content-Transfer-Encoding", "quoted-printable".to_string()),
+            ],
+            body: encode_quoted_printable(text),
+        }
+    }
+
+    fn multipart(subtype: &str, parts: Vec<Entity>) -> Entity {
+        // "=_" can appear in neither quoted-printable nor base64, so the boundary never occurs in a part
+        let boundary = format!("=_{}", Uuid::new_v4().simple());
+        let mut body = String::new();
+        for part in parts {
+            body.push_str(&format!("--{}\r\n", boundary));
+            part.render(&mut body);
+            body.push_str("\r\n");
+        }
+        body.push_str(&format!("--{}--", boundary));
+
+        Entity {
+            headers: vec![("Content-Type", format!("multipart/{}; boundary=\"{}\"", subtype, boundary))],
+            body,
+        }
+    }
+
+    fn attachment(attachment: &Attachment) -> Entity {
+        let mime_type = attachment.mime_type.trim().to_ascii_lowercase();
+        let mime_type = if is_mime_type(&mime_type) { mime_type } else { "application/octet-stream".to_string() };
+        let filename = filename_param(&attachment.filename);
+        Entity {
+            headers: vec![
+                ("Content-Type", format!("{}; {}", mime_type, filename.replacen("filename", "name", 1))),
+                ("Content-Disposition", format!("attachment; {}", filename)),
+                ("Content-Transfer-Encoding", "base64".to_string()),
+            ],
+            body: encode_base64_lines(&attachment.data),
+        }
+    }
+
+    fn render(&self, out: &mut String) {
+        for (name, value) in &self.headers {
+            write_header(out, name, value);
+        }
+        out.push_str("\r\n");
+        out.push_str(&self.body);
+    }
+}
+
+// Write "Name: value", folding at spaces so lines stay near FOLD_WIDTH. Values reach here already
+// encoded and free of line breaks; folding only turns a space into CRLF and that space. Runs of
+// spaces fold as one, so no folded line is only whitespace.
+fn write_header(out: &mut String, name: &str, value: &str) {
+    out.push_str(name);
+    out.push(':');
+    let mut width = name.len() + 1;
+    for (index, word) in value.split(' ').filter(|word| !word.is_empty()).enumerate() {
+        if index > 0 && width + 1 + word.len() > FOLD_WIDTH {
+            out.push_str("\r\n");
+            width = 0;
+        }
+        out.push(' ');
+        out.push_str(word);
+        width += 1 + word.len();
+    }
+    out.push_str("\r\n");
+}
+
+fn address_list(addresses: &[Address]) -> Result<String, MessageError> {
+    let values = addresses.iter().map(Address::header_value).collect::<Result<Vec<_>, _>>()?;
+    Ok(values.join(", "))
+}
+
+// Free text such as a subject: kept as is when it is plain ASCII, otherwise UTF-8 encoded-words
+fn encode_text(text: &str) -> String {
+    let text = strip_controls(text);
+    if text.is_ascii() {
+        text
+    } else {
+        encoded_words(&text)
+    }
+}
+
+// A display name: bare when it is only atoms, quoted when it is ASCII, otherwise encoded-words
+fn encode_phrase(name: &str) -> String {
+    if !name.is_ascii() {
+        return encoded_words(name);
+    }
+    if name.split(' ').all(|atom| !atom.is_empty() && atom.chars().all(is_atext)) {
+        return name.to_string();
+    }
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// RFC 2047 B encoding in words short enough to fold between; splits fall on character boundaries
+fn encoded_words(text: &str) -> String {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    for (at, c) in text.char_indices() {
+        if at + c.len_utf8() - start > ENCODED_WORD_BYTES {
+            words.push(&text[start..end]);
+            start = at;
+        }
+        end = at + c.len_utf8();
+    }
+    words.push(&text[start..]);
+
+    words.into_iter()
+        .map(|word| format!("=?UTF-8?B?{}?=", encode_config(word, STANDARD)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// A Content-Disposition filename: quoted when it is ASCII, RFC 2231 UTF-8 otherwise
+fn filename_param(filename: &str) -> String {
+    let filename: String = strip_controls(filename)
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | '"') { '_' } else { c })
+        .collect();
+    let filename = if filename.trim().is_empty() { "attachment".to_string() } else { filename };
+
+    if filename.is_ascii() {
+        return format!("filename=\"{}\"", filename);
+    }
+    let encoded: String = filename.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect();
+    format!("filename*=UTF-8''{}", encoded)
+}
+
+// Line breaks and other control characters become spaces; this is what keeps headers single-line
+fn strip_controls(text: &str) -> String {
+    text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+}
+
+fn unquote(text: &str) -> String {
+    match text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        Some(inner) => inner.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => text.to_string(),
+    }
+}
+
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c)
+}
+
+// A plain dot-atom address; quoted local parts and address literals are not accepted
+fn check_address(address: &str) -> Result<(), MessageError> {
+    let invalid = || MessageError::InvalidAddress(address.to_string());
+    let (local, domain) = address.rsplit_once('@').ok_or_else(invalid)?;
+
+    let dot_atom = |text: &str, allowed: fn(char) -> bool| {
+        !text.is_empty() && text.split('.').all(|atom| !atom.is_empty() && atom.chars().all(allowed))
+    };
+    let valid = address.len() <= MAX_ADDRESS_LENGTH
+        && local.len() <= MAX_LOCAL_PART_LENGTH
+        && dot_atom(local, is_atext)
+        && dot_atom(domain, |c| c.is_ascii_alphanumeric() || c == '-')
+        && domain.contains('.')
+        && domain.split('.').all(|label| !label.starts_with('-') && !label.ends_with('-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(invalid())
+    }
+}
+
+fn check_message_id(id: &str) -> Result<String, MessageError> {
+    let id = id.trim();
+    let valid = id.strip_prefix('<')
+        .and_then(|inner| inner.strip_suffix('>'))
+        .and_then(|inner| inner.split_once('@'))
+        .is_some_and(|(left, right)| {
+            [left, right].iter().all(|side| !side.is_empty() && side.chars().all(|c| c.is_ascii_graphic() && !"<>@\"\\".contains(c)))
+        });
+    if valid {
+        Ok(id.to_string())
+    } else {
+        Err(MessageError::InvalidMessageId(id.to_string()))
+    }
+}
+
+fn is_mime_type(mime_type: &str) -> bool {
+    let token = |text: &str| !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c));
+    matches!(mime_type.split_once('/'), Some((kind, subtype)) if token(kind) && token(subtype))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mime::{decode_encoded_words, parse_headers, split_head, MessageBody, MimePart};
+
+    fn sender() -> Address {
+        Address::named("Sender", "sender@example.com")
+    }
+
+    fn recipient() -> Address {
+        Address::parse("recipient@example.com").unwrap()
+    }
+
+    fn headers_of(message: &[u8]) -> Vec<(String, String)> {
+        parse_headers(split_head(message).0)
+    }
+
+    fn header_names(headers: &[(String, String)]) -> Vec<&str> {
+        headers.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn line_breaks_cannot_add_headers() {
+        let mut builder = MessageBuilder::new(
+            Address::named("Eve\r\nBcc: victim@example.com", "eve@example.com"),
+            recipient(),
+            "Hello\r\nBcc: victim@example.com\nX-Injected: yes",
+            "body",
+        );
+        builder.attachments.push(Attachment {
+            filename: "report.txt\r\nContent-Type: text/html".to_string(),
+            mime_type: "text/plain\r\nX-Injected: yes".to_string(),
+            data: b"data".to_vec(),
+        });
+        let message = builder.build().unwrap();
+
+        let headers = headers_of(&message);
+        assert_eq!(
+            header_names(&headers),
+            ["From", "To", "Subject", "Date", "Message-ID", "MIME-Version", "Content-Type"]
+        );
+        assert_eq!(decode_encoded_words(&headers[2].1), "Hello Bcc: victim@example.com X-Injected: yes");
+
+        let part = MimePart::parse(&message);
+        let attachment = &part.parts[1];
+        assert_eq!(header_names(&attachment.headers), ["Content-Type", "Content-Disposition", "Content-Transfer-Encoding"]);
+        assert_eq!(attachment.content_type().value, "application/octet-stream");
+        assert_eq!(attachment.filename().as_deref(), Some("report.txt Content-Type: text_html"));
+    }
+
+    #[test]
+    fn rejects_addresses_and_ids_that_could_break_headers() {
+        assert!(Address::parse("victim@example.com\r\nBcc: x@example.com").is_err());
+        assert!(Address::parse("Name <a@example.com>, b@example.com").is_err());
+        let builder = MessageBuilder {
+            in_reply_to: Some("<id@example.com>\r\nX-Injected: yes".to_string()),
+            ..MessageBuilder::new(sender(), recipient(), "Re", "body")
+        };
+        assert!(matches!(builder.build(), Err(MessageError::InvalidMessageId(_))));
+    }
+
+    #[test]
+    fn folds_long_headers_at_spaces() {
+        let mut out = String::new();
+        write_header(&mut out, "Subject", &format!("{}   spaced  {}", "word ".repeat(30), "x".repeat(100)));
+        let lines: Vec<&str> = out.strip_suffix("\r\n").unwrap().split("\r\n").collect();
+
+        assert!(lines.len() > 2);
+        for line in &lines[1..] {
+            assert!(line.starts_with(' '));
+            assert!(!line.trim().is_empty());
+        }
+        // Only a word longer than a line may overrun it
+        assert!(lines.iter().all(|line| line.len() <= FOLD_WIDTH || !line.trim().contains(' ')));
+        let unfolded = parse_headers(out.as_bytes());
+        assert_eq!(unfolded[0].1, format!("{} spaced {}", "word ".repeat(30).trim_end(), "x".repeat(100)));
+    }
+
+    #[test]
+    fn splits_encoded_words_on_character_boundaries() {
+        let subject = "Grüße aus Köln, 東京 und 🦀! ".repeat(6);
+        let encoded = encode_text(&subject);
+        for word in encoded.split(' ') {
+            assert!(word.len() <= 75);
+            let text = word.strip_prefix("=?UTF-8?B?").and_then(|w| w.strip_suffix("?=")).unwrap();
+            assert!(String::from_utf8(base64::decode(text).unwrap()).is_ok());
+        }
+        assert_eq!(decode_encoded_words(&encoded), subject);
+
+        let message = MessageBuilder::new(sender(), recipient(), &subject, "body").build().unwrap();
+        let headers = headers_of(&message);
+        assert_eq!(decode_encoded_words(&headers[2].1), subject);
+    }
+
+    #[test]
+    fn encodes_display_names() {
+        assert_eq!(Address::named("Plain Name", "a@example.com").header_value().unwrap(), "Plain Name <a@example.com>");
+        assert_eq!(
+            Address::named("Doe, \"J\"", "a@example.com").header_value().unwrap(),
+            "\"Doe, \\\"J\\\"\" <a@example.com>"
+        );
+        let encoded = Address::named("Zoë", "a@example.com").header_value().unwrap();
+        assert_eq!(decode_encoded_words(&encoded), "Zoë <a@example.com>");
+
+        let address = Address::parse("\"Doe, J\" <j@example.com>").unwrap();
+        assert_eq!(address.name.as_deref(), Some("Doe, J"));
+        assert_eq!(address.email, "j@example.com");
+    }
+
+    #[test]
+    fn round_trips_through_the_parser() {
+        let mut builder = MessageBuilder::new(sender(), recipient(), "Résumé", "Hi there,\nsee the attached résumé.");
+        builder.html = Some("<p>Hi there, see the attached <b>résumé</b>.</p>".to_string());
+        builder.cc.push(Address::named("Zoë", "zoe@example.com"));
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        builder.attachments.push(Attachment {
+            filename: "résumé final.pdf".to_string(),
+            mime_type: "Application/PDF".to_string(),
+            data: data.clone(),
+        });
+        let message = builder.build().unwrap();
+
+        let part = MimePart::parse(&message);
+        assert_eq!(part.content_type().value, "multipart/mixed");
+        assert_eq!(decode_encoded_words(part.header("Subject").unwrap()), "Résumé");
+        assert_eq!(decode_encoded_words(part.header("Cc").unwrap()), "Zoë <zoe@example.com>");
+
+        let body = MessageBody::from_part(&part);
+        assert_eq!(body.text, "Hi there,\r\nsee the attached résumé.");
+        assert!(body.html.unwrap().contains("<b>résumé</b>"));
+        assert_eq!(body.attachments.len(), 1);
+        assert_eq!(body.attachments[0].filename, "résumé final.pdf");
+        assert_eq!(body.attachments[0].mime_type, "application/pdf");
+        assert_eq!(part.parts[1].body, data);
+    }
+
+    #[test]
+    fn needs_a_recipient() {
+        let builder = MessageBuilder { to: Vec::new(), ..MessageBuilder::new(sender(), recipient(), "s", "b") };
+        assert!(matches!(builder.build(), Err(MessageError::NoRecipients)));
+    }
+}
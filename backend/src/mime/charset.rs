@@ -0,0 +1,68 @@
+use encoding_rs::{Encoding, WINDOWS_1252};
+
+// Decode text in the given charset. Unlabelled text is taken as UTF-8, falling back to
+// Windows-1252 (a superset of Latin-1) for the many senders that leave 8-bit text unlabelled.
+// An unknown label is treated the same way rather than failing.
+pub fn decode_charset(bytes: &[u8], charset: Option<&str>) -> String {
+    // RFC 2231 allows a language after the charset: utf-8*en
+    let label = charset.map(|c| c.split('*').next().unwrap_or(c).trim().trim_matches('"'));
+    let encoding = label.and_then(|label| Encoding::for_label(label.as_bytes()));
+
+    match encoding {
+        Some(encoding) => encoding.decode_without_bom_handling(bytes).0.into_owned(),
+        None => match std::str::from_utf8(bytes) {
+            Ok(text) => text.to_string(),
+            Err(_) => WINDOWS_1252.decode_without_bom_handling(bytes).0.into_owned(),
+        },
+    }
+}
+
+// The %XX escapes of an RFC 2231 extended parameter value
+pub fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = bytes.get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_labelled_charsets() {
+        assert_eq!(decode_charset(b"caf\xe9", Some("ISO-8859-1")), "café");
+        assert_eq!(decode_charset(b"caf\xc3\xa9", Some("\"utf-8\"")), "café");
+        assert_eq!(decode_charset(b"\x82\xa0", Some("Shift_JIS")), "あ");
+        // RFC 2231 language suffix
+        assert_eq!(decode_charset(b"caf\xc3\xa9", Some("utf-8*en")), "café");
+    }
+
+    #[test]
+    fn unlabelled_and_unknown_text_falls_back_to_windows_1252() {
+        assert_eq!(decode_charset("naïve".as_bytes(), None), "naïve");
+        assert_eq!(decode_charset(b"\x93quoted\x94", None), "\u{201c}quoted\u{201d}");
+        assert_eq!(decode_charset(b"caf\xe9", Some("x-no-such-charset")), "café");
+    }
+
+    #[test]
+    fn percent_decodes_and_keeps_malformed_escapes() {
+        assert_eq!(percent_decode("na%C3%AFve%20file"), "naïve file".as_bytes());
+        assert_eq!(percent_decode("100%"), b"100%");
+        assert_eq!(percent_decode("%zz%4"), b"%zz%4");
+    }
+}
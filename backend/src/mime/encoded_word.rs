@@ -0,0 +1,93 @@
+use base64::{decode_config, STANDARD, STANDARD_NO_PAD};
+
+use super::charset::decode_charset;
+use super::transfer::decode_quoted_printable;
+
+// Decode the RFC 2047 encoded-words (=?charset?B|Q?text?=) in a header value. Whitespace between
+// two adjacent encoded-words is dropped, as the RFC requires; anything that does not parse is kept as is.
+pub fn decode_encoded_words(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut after_word = false;
+
+    while let Some(start) = rest.find("=?") {
+        let literal = &rest[..start];
+        if !(after_word && literal.chars().all(char::is_whitespace)) {
+            decoded.push_str(literal);
+        }
+
+        match encoded_word(&rest[start..]) {
+            Some((text, length)) => {
+                decoded.push_str(&text);
+                rest = &rest[start + length..];
+                after_word = true;
+            }
+            None => {
+                decoded.push_str("=?");
+                rest = &rest[start + 2..];
+                after_word = false;
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+// One encoded-word at the start of `text`: its decoded text and how many bytes it took up
+fn encoded_word(text: &str) -> Option<(String, usize)> {
+    let inner = text.strip_prefix("=?")?;
+    let (charset, inner) = inner.split_once('?')?;
+    let (encoding, inner) = inner.split_once('?')?;
+    let end = inner.find("?=")?;
+    let encoded = &inner[..end];
+    if charset.is_empty() || charset.contains(char::is_whitespace) || encoded.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let bytes = match encoding {
+        // Some senders drop the padding
+        "B" | "b" => decode_config(encoded, STANDARD)
+            .or_else(|_| decode_config(encoded.trim_end_matches('='), STANDARD_NO_PAD))
+            .ok()?,
+        "Q" | "q" => decode_quoted_printable(encoded.as_bytes(), true),
+        _ => return None,
+    };
+    let length = 2 + charset.len() + 1 + encoding.len() + 1 + end + 2;
+    Some((decode_charset(&bytes, Some(charset)), length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_b_and_q_words() {
+        assert_eq!(decode_encoded_words("=?UTF-8?B?Y2Fmw6k=?="), "café");
+        assert_eq!(decode_encoded_words("=?iso-8859-1?q?caf=E9_au_lait?="), "café au lait");
+        // Unpadded base64
+        assert_eq!(decode_encoded_words("=?utf-8?b?Y2Fmw6k?="), "café");
+        assert_eq!(decode_encoded_words("Re: =?utf-8?Q?caf=C3=A9?= tomorrow"), "Re: café tomorrow");
+    }
+
+    #[test]
+    fn drops_whitespace_only_between_adjacent_words() {
+        assert_eq!(decode_encoded_words("=?utf-8?Q?one?= \r\n =?utf-8?Q?_two?="), "one two");
+        assert_eq!(decode_encoded_words("=?utf-8?Q?one?= and =?utf-8?Q?two?="), "one and two");
+    }
+
+    #[test]
+    fn keeps_what_does_not_parse() {
+        for value in [
+            "=?utf-8?Q?unterminated",
+            "=?utf-8?X?unknown?=",
+            "=??Q?no_charset?=",
+            "=?utf-8?Q?has space?=",
+            "=?utf-8?B?***?=",
+            "plain = ? text",
+            "=?",
+        ] {
+            assert_eq!(decode_encoded_words(value), value);
+        }
+        assert_eq!(decode_encoded_words("=?broken =?utf-8?Q?ok?="), "=?broken ok");
+    }
+}
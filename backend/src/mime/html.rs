@@ -0,0 +1,119 @@
+// Markup that ends a line of text when HTML is flattened
+const BLOCK_TAGS: [&str; 12] = ["br", "p", "div", "tr", "li", "h1", "h2", "h3", "h4", "h5", "h6", "blockquote"];
+
+// HTML that is safe to render in the client: no scripts, styles, event handlers or forms.
+// Links open without a referrer, and cid: image sources survive so inline parts can be shown.
+pub fn sanitize_html(html: &str) -> String {
+    ammonia::Builder::default()
+        .add_url_schemes(["cid"])
+        .add_generic_attributes(["align", "bgcolor", "dir"])
+        .clean(html)
+        .to_string()
+}
+
+// A plain-text rendering of HTML, for messages that come without a text/plain alternative
+pub fn html_to_text(html: &str) -> String {
+    // Drop what never renders as text
+    let html = ammonia::Builder::default().clean(html).to_string();
+
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html.as_str();
+    while let Some(open) = rest.find('<') {
+        text.push_str(&rest[..open]);
+        let close = rest[open..].find('>').map_or(rest.len(), |at| open + at + 1);
+        let tag = rest[open + 1..close]
+            .trim_end_matches('>')
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        if BLOCK_TAGS.contains(&tag.as_str()) {
+            text.push('\n');
+        }
+        rest = &rest[close..];
+    }
+    text.push_str(rest);
+
+    let text = decode_entities(&text);
+    // Collapse the blank lines that nested blocks leave behind
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        if !(line.trim().is_empty() && lines.last().is_none_or(|last| last.trim().is_empty())) {
+            lines.push(line);
+        }
+    }
+    lines.join("\n").trim().to_string()
+}
+
+// The entities ammonia leaves in text, plus numeric references
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest.find(';').filter(|end| *end <= 10).map(|end| &rest[1..end]);
+        let replacement = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" | "#39" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity.strip_prefix('#').and_then(|number| {
+                match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => number.parse().ok(),
+                }
+            }).and_then(char::from_u32),
+        });
+        match (entity, replacement) {
+            (Some(entity), Some(c)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_named_and_numeric_entities() {
+        assert_eq!(decode_entities("&lt;a&gt; &amp; &quot;b&quot; &apos;c&#39;"), "<a> & \"b\" 'c'");
+        assert_eq!(decode_entities("caf&#233; &#xE9;&#XE9; a&nbsp;b"), "café éé a b");
+    }
+
+    #[test]
+    fn keeps_unknown_and_truncated_entities() {
+        assert_eq!(decode_entities("&unknown; &amp"), "&unknown; &amp");
+        assert_eq!(decode_entities("AT&T; fish & chips &"), "AT&T; fish & chips &");
+        assert_eq!(decode_entities("&#xFFFFFFFF; &#; &verylongentityname;"), "&#xFFFFFFFF; &#; &verylongentityname;");
+    }
+
+    #[test]
+    fn flattens_html_to_text() {
+        let html = "<div><p>Hello&nbsp;<b>there</b></p><p></p><p>Line&lt;2&gt;</p></div><script>alert(1)</script>";
+        // Runs of blank lines collapse to one
+        assert_eq!(html_to_text(html), "Hello there\n\nLine<2>");
+        assert_eq!(html_to_text("one<br>two<br/>three"), "one\ntwo\nthree");
+        assert_eq!(html_to_text("unclosed <b"), "unclosed");
+    }
+
+    #[test]
+    fn sanitizes_html() {
+        let clean = sanitize_html(r#"<p onclick="x()">Hi<script>alert(1)</script><img src="cid:logo@example"><a href="https://example.com">link</a></p>"#);
+        assert!(!clean.contains("script") && !clean.contains("onclick"));
+        assert!(clean.contains(r#"src="cid:logo@example""#));
+        assert!(clean.contains(r#"rel="noopener noreferrer""#));
+    }
+}
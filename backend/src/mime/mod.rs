@@ -0,0 +1,138 @@
+// MIME message parsing: walks a message's part tree into plain text, sanitized HTML,
+// inline parts and attachment descriptors, decoding charsets, transfer encodings and
+// RFC 2047 encoded-word headers along the way, and builds outgoing RFC 5322 messages.
+mod builder;
+mod charset;
+mod encoded_word;
+mod html;
+mod part;
+mod transfer;
+
+pub use builder::{Address, Attachment, MessageBuilder, MessageError};
+pub use encoded_word::decode_encoded_words;
+pub use part::{parse_header_value, parse_headers, split_head, split_multipart, MimePart};
+
+use crate::models::AttachmentInfo;
+
+// What a reader sees of a message
+#[derive(Debug, Clone, Default)]
+pub struct MessageBody {
+    // Plain text; for HTML-only mail, a text rendering of the HTML
+    pub text: String,
+    // Sanitized HTML, when the message has an HTML part
+    pub html: Option<String>,
+    // Parts shown within the message, such as images the HTML refers to by cid:
+    pub inline_parts: Vec<AttachmentInfo>,
+    pub attachments: Vec<AttachmentInfo>,
+}
+
+impl MessageBody {
+    pub fn from_part(part: &MimePart) -> MessageBody {
+        let mut collected = Collected::default();
+        collected.walk(part);
+
+        let html = (!collected.html.is_empty()).then(|| collected.html.join("\n"));
+        let text = if collected.text.is_empty() {
+            html.as_deref().map(html::html_to_text).unwrap_or_default()
+        } else {
+            collected.text.join("\n\n")
+        };
+
+        MessageBody {
+            text,
+            html: html.as_deref().map(html::sanitize_html),
+            inline_parts: collected.inline_parts,
+            attachments: collected.attachments,
+        }
+    }
+
+    // Attachments and inline parts together, as the Email model lists them
+    pub fn all_attachments(&self) -> Vec<AttachmentInfo> {
+        self.attachments.iter().chain(&self.inline_parts).cloned().collect()
+    }
+}
+
+// Bodies and parts gathered while walking the tree
+#[derive(Default)]
+struct Collected {
+    text: Vec<String>,
+    html: Vec<String>,
+    inline_parts: Vec<AttachmentInfo>,
+    attachments: Vec<AttachmentInfo>,
+}
+
+impl Collected {
+    fn walk(&mut self, part: &MimePart) {
+        let content_type = part.content_type();
+        let mime_type = content_type.value.as_str();
+
+        if mime_type.starts_with("multipart/") {
+            if mime_type == "multipart/alternative" {
+                self.walk_alternatives(&part.parts);
+            } else {
+                // mixed, related, signed and the rest: every part counts, in order
+                for child in &part.parts {
+                    self.walk(child);
+                }
+            }
+            return;
+        }
+
+        let disposition = part.disposition();
+        let is_attachment = match disposition.as_ref() {
+            Some(disposition) => disposition.value == "attachment",
+            // Without a disposition, a named part is a file rather than body text
+            None => part.filename().is_some() && part.content_id().is_none(),
+        };
+
+        match mime_type {
+            "text/plain" if !is_attachment => self.text.push(part.text()),
+            "text/html" if !is_attachment => self.html.push(part.text()),
+            _ => {
+                let info = describe(part, mime_type, !is_attachment);
+                if is_attachment {
+                    self.attachments.push(info);
+                } else {
+                    self.inline_parts.push(info);
+                }
+            }
+        }
+    }
+
+    // The parts of multipart/alternative carry the same content, in increasing order of preference.
+    // Keep the last text and the last HTML rendering, and every attachment any of them carries.
+    fn walk_alternatives(&mut self, parts: &[MimePart]) {
+        let mut text = None;
+        let mut html = None;
+        for child in parts {
+            let mut alternative = Collected::default();
+            alternative.walk(child);
+            if !alternative.text.is_empty() {
+                text = Some(alternative.text);
+            }
+            if !alternative.html.is_empty() {
+                html = Some(alternative.html);
+            }
+            self.inline_parts.extend(alternative.inline_parts);
+            self.attachments.extend(alternative.attachments);
+        }
+        self.text.extend(text.unwrap_or_default());
+        self.html.extend(html.unwrap_or_default());
+    }
+}
+
+fn describe(part: &MimePart, mime_type: &str, inline: bool) -> AttachmentInfo {
+    let filename = part.filename().unwrap_or_else(|| match mime_type {
+        "message/rfc822" => "message.eml".to_string(),
+        _ => "attachment".to_string(),
+    });
+    AttachmentInfo {
+        part_id: part.part_id.clone(),
+        filename,
+        mime_type: mime_type.to_string(),
+        size: part.size.unwrap_or(part.body.len() as i64),
+        attachment_id: part.attachment_id.clone(),
+        content_id: part.content_id(),
+        inline,
+    }
+}
@@ -0,0 +1,395 @@
+use super::charset::{decode_charset, percent_decode};
+use super::encoded_word::decode_encoded_words;
+use super::transfer::decode_transfer_encoding;
+
+// One node of a MIME tree. Leaf bodies are held with their transfer encoding already undone.
+#[derive(Debug, Clone, Default)]
+pub struct MimePart {
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub parts: Vec<MimePart>,
+    // Set for parts that come from Gmail's parsed payload
+    pub part_id: Option<String>,
+    pub attachment_id: Option<String>,
+    // Size Gmail reports for a body it did not send along
+    pub size: Option<i64>,
+}
+
+// A Content-Type or Content-Disposition value: the lowercased value and its parameters
+#[derive(Debug, Clone, Default)]
+pub struct HeaderValue {
+    pub value: String,
+    // Names lowercased; RFC 2231 continuations and charsets already resolved
+    pub params: Vec<(String, String)>,
+}
+
+impl HeaderValue {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl MimePart {
+    // Parse an entity: headers, a blank line, then the body. Multipart bodies are split into
+    // their parts recursively; leaf bodies have their Content-Transfer-Encoding undone.
+    pub fn parse(raw: &[u8]) -> MimePart {
+        let (head, body) = split_head(raw);
+        let mut part = MimePart {
+            headers: parse_headers(head),
+            ..MimePart::default()
+        };
+
+        let content_type = part.content_type();
+        match content_type.param("boundary") {
+            Some(boundary) if content_type.value.starts_with("multipart/") => {
+                part.parts = split_multipart(body, boundary).into_iter().map(MimePart::parse).collect();
+            }
+            _ => part.body = decode_transfer_encoding(part.header("Content-Transfer-Encoding"), body),
+        }
+        part
+    }
+
+    // First header with this name, compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Content-Type, defaulting to text/plain as RFC 2045 does
+    pub fn content_type(&self) -> HeaderValue {
+        let mut content_type = self.header("Content-Type").map(parse_header_value).unwrap_or_default();
+        if content_type.value.is_empty() || !content_type.value.contains('/') {
+            content_type.value = "text/plain".to_string();
+        }
+        content_type
+    }
+
+    pub fn disposition(&self) -> Option<HeaderValue> {
+        self.header("Content-Disposition").map(parse_header_value)
+    }
+
+    // The attachment's name from Content-Disposition, or the older Content-Type name parameter
+    pub fn filename(&self) -> Option<String> {
+        self.disposition()
+            .and_then(|d| d.param("filename").map(str::to_string))
+            .or_else(|| self.content_type().param("name").map(str::to_string))
+            .map(|name| decode_encoded_words(&name))
+            .filter(|name| !name.trim().is_empty())
+    }
+
+    // Content-ID without its angle brackets, as cid: URLs refer to it
+    pub fn content_id(&self) -> Option<String> {
+        self.header("Content-ID")
+            .map(|id| id.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+            .filter(|id| !id.is_empty())
+    }
+
+    // The body as text, decoded from its charset
+    pub fn text(&self) -> String {
+        decode_charset(&self.body, self.content_type().param("charset"))
+    }
+}
+
+// Headers, and whatever follows the first blank line
+pub fn split_head(raw: &[u8]) -> (&[u8], &[u8]) {
+    let crlf = find(raw, b"\r\n\r\n").map(|at| (at, 4));
+    let lf = find(raw, b"\n\n").map(|at| (at, 2));
+    match [crlf, lf].into_iter().flatten().min() {
+        Some((at, len)) => (&raw[..at], &raw[at + len..]),
+        // A part that starts with a blank line has no headers at all
+        None if raw.starts_with(b"\r\n") => (&[], &raw[2..]),
+        None if raw.starts_with(b"\n") => (&[], &raw[1..]),
+        None => (raw, &[]),
+    }
+}
+
+// Header fields with folded lines joined. Values are kept raw; callers decode encoded-words.
+pub fn parse_headers(head: &[u8]) -> Vec<(String, String)> {
+    let text = String::from_utf8_lossy(head);
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in text.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    headers
+}
+
+// The bodies of a multipart entity, without the preamble, epilogue and delimiter lines
+pub fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    let mut parts = Vec::new();
+    let mut current: Option<usize> = None;
+    let mut line_start = 0;
+    while line_start < body.len() {
+        let line_end = find(&body[line_start..], b"\n").map_or(body.len(), |at| line_start + at + 1);
+        let line = trim_line_end(&body[line_start..line_end]);
+
+        // Only whitespace may follow the boundary, so a longer boundary that starts with it is not taken for it
+        let rest = line.strip_prefix(delimiter).map(|rest| match rest.strip_prefix(b"--") {
+            Some(after) => (true, after),
+            None => (false, rest),
+        });
+        if let Some((closing, _)) = rest.filter(|(_, after)| after.iter().all(u8::is_ascii_whitespace)) {
+            if let Some(start) = current {
+                // The line break before a delimiter belongs to the delimiter
+                parts.push(trim_line_end(&body[start..line_start]));
+            }
+            if closing {
+                return parts;
+            }
+            current = Some(line_end);
+        }
+        line_start = line_end;
+    }
+    // A body cut off before its closing delimiter still yields the parts it has
+    if let Some(start) = current.filter(|start| *start < body.len()) {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+// Parse `value; name=param; ...`, resolving RFC 2231 continuations (name*0, name*1) and
+// extended values (name*=charset''%XX)
+pub fn parse_header_value(raw: &str) -> HeaderValue {
+    let mut pieces = split_params(raw).into_iter();
+    let value = pieces.next().unwrap_or_default().trim().to_ascii_lowercase();
+
+    let mut sections: Vec<(String, Vec<Section>)> = Vec::new();
+    for piece in pieces {
+        let Some((key, text)) = piece.split_once('=') else { continue };
+        let key = key.trim().to_ascii_lowercase();
+        let text = unquote(text.trim());
+
+        let (key, extended) = match key.strip_suffix('*') {
+            Some(key) => (key.to_string(), true),
+            None => (key, false),
+        };
+        let (name, section) = match key.rsplit_once('*') {
+            Some((name, index)) if index.chars().all(|c| c.is_ascii_digit()) && !index.is_empty() => {
+                (name.to_string(), index.parse().unwrap_or(0))
+            }
+            _ => (key, 0),
+        };
+
+        match sections.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, list)) => list.push((section, extended, text)),
+            None => sections.push((name, vec![(section, extended, text)])),
+        }
+    }
+
+    let params = sections.into_iter().map(|(name, mut list)| {
+        list.sort_by_key(|(section, _, _)| *section);
+        if !list.iter().any(|(_, extended, _)| *extended) {
+            let joined: String = list.into_iter().map(|(_, _, text)| text).collect();
+            return (name, joined);
+        }
+
+        // Only the first section names the charset: charset'language'text
+        let mut charset = None;
+        let mut bytes = Vec::new();
+        for (index, (_, extended, text)) in list.into_iter().enumerate() {
+            if !extended {
+                bytes.extend_from_slice(text.as_bytes());
+                continue;
+            }
+            let mut encoded = text.as_str();
+            if index == 0 {
+                let mut fields = text.splitn(3, '\'');
+                if let (Some(set), Some(_language), Some(rest)) = (fields.next(), fields.next(), fields.next()) {
+                    charset = Some(set.to_string()).filter(|set| !set.is_empty());
+                    encoded = rest;
+                }
+            }
+            bytes.extend(percent_decode(encoded));
+        }
+        (name, decode_charset(&bytes, charset.as_deref()))
+    }).collect();
+
+    HeaderValue { value, params }
+}
+
+// One piece of an RFC 2231 parameter: its section number, whether it is extended, and its text
+type Section = (u32, bool, String);
+
+// Split at semicolons outside quoted strings
+fn split_params(raw: &str) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in raw.chars() {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' if quoted => {
+                current.push(c);
+                escaped = true;
+            }
+            '"' => {
+                current.push(c);
+                quoted = !quoted;
+            }
+            ';' if !quoted => pieces.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    pieces.push(current);
+    pieces.into_iter().filter(|piece| !piece.trim().is_empty()).collect()
+}
+
+fn unquote(text: &str) -> String {
+    match text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        Some(inner) => {
+            let mut unquoted = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => unquoted.extend(chars.next()),
+                    _ => unquoted.push(c),
+                }
+            }
+            unquoted
+        }
+        None => text.to_string(),
+    }
+}
+
+fn trim_line_end(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_parameters_and_quoting() {
+        let value = parse_header_value(r#"Multipart/Mixed; Boundary="a;b \"c\""; charset=utf-8;;"#);
+        assert_eq!(value.value, "multipart/mixed");
+        assert_eq!(value.param("boundary"), Some(r#"a;b "c""#));
+        assert_eq!(value.param("charset"), Some("utf-8"));
+        assert_eq!(value.param("missing"), None);
+    }
+
+    #[test]
+    fn resolves_rfc_2231_continuations_and_charsets() {
+        let value = parse_header_value("attachment; filename*1=\"name.txt\"; filename*0=\"long \"");
+        assert_eq!(value.param("filename"), Some("long name.txt"));
+
+        let value = parse_header_value("attachment; filename*=iso-8859-1'fr'caf%E9.txt");
+        assert_eq!(value.param("filename"), Some("café.txt"));
+
+        // Only the first section carries the charset; later sections may be plain
+        let value = parse_header_value("attachment; filename*0*=UTF-8''na%C3%AF; filename*1=\"ve.pdf\"");
+        assert_eq!(value.param("filename"), Some("naïve.pdf"));
+    }
+
+    #[test]
+    fn tolerates_malformed_parameters() {
+        let value = parse_header_value("text/plain; noequals; =empty; charset=\"unterminated");
+        assert_eq!(value.value, "text/plain");
+        assert_eq!(value.param("charset"), Some("\"unterminated"));
+        assert_eq!(parse_header_value("").value, "");
+    }
+
+    #[test]
+    fn joins_folded_headers() {
+        let headers = parse_headers(b"Subject: one\r\n  two\r\n\tthree\r\nX-Empty:\r\n continued\r\nnot a header\r\n");
+        assert_eq!(headers, vec![
+            ("Subject".to_string(), "one two three".to_string()),
+            ("X-Empty".to_string(), " continued".to_string()),
+        ]);
+        // A continuation before any header has nowhere to go
+        assert!(parse_headers(b" stray\r\n").is_empty());
+    }
+
+    #[test]
+    fn splits_head_from_body() {
+        assert_eq!(split_head(b"A: 1\r\n\r\nbody\r\n\r\nmore"), (&b"A: 1"[..], &b"body\r\n\r\nmore"[..]));
+        assert_eq!(split_head(b"A: 1\n\nbody"), (&b"A: 1"[..], &b"body"[..]));
+        assert_eq!(split_head(b"\r\nbody only"), (&b""[..], &b"body only"[..]));
+        assert_eq!(split_head(b"A: 1"), (&b"A: 1"[..], &b""[..]));
+    }
+
+    #[test]
+    fn splits_multipart_without_preamble_and_epilogue() {
+        // --b-not-a-delimiter starts with the delimiter but is content, as a nested part's boundary could be
+        let body = b"preamble\r\n--b\r\nfirst\r\n--b-not-a-delimiter\r\n--b\r\n\r\nsecond\r\n--b--\r\nepilogue\r\n--b\r\nignored";
+        assert_eq!(split_multipart(body, "b"), vec![&b"first\r\n--b-not-a-delimiter"[..], &b"\r\nsecond"[..]]);
+    }
+
+    #[test]
+    fn keeps_parts_of_a_truncated_multipart() {
+        assert_eq!(split_multipart(b"--b\r\nfirst\r\n--b\r\nsecond, cut off", "b"), vec![&b"first"[..], &b"second, cut off"[..]]);
+        assert_eq!(split_multipart(b"--b\r\n", "b"), Vec::<&[u8]>::new());
+        assert_eq!(split_multipart(b"no delimiters at all", "b"), Vec::<&[u8]>::new());
+    }
+
+    #[test]
+    fn parses_a_nested_message() {
+        let raw = concat!(
+            "Content-Type: multipart/mixed; boundary=outer\r\n",
+            "\r\n",
+            "--outer\r\n",
+            "Content-Type: multipart/alternative; boundary=\"inner\"\r\n",
+            "\r\n",
+            "--inner\r\n",
+            "Content-Type: text/plain; charset=iso-8859-1\r\n",
+            "Content-Transfer-Encoding: quoted-printable\r\n",
+            "\r\n",
+            "caf=E9\r\n",
+            "--inner\r\n",
+            "Content-Type: text/html\r\n",
+            "\r\n",
+            "<p>caf&eacute;</p>\r\n",
+            "--inner--\r\n",
+            "--outer\r\n",
+            "Content-Type: application/pdf; name=\"=?utf-8?Q?r=C3=A9sum=C3=A9.pdf?=\"\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "Content-ID: <cv@example>\r\n",
+            "\r\n",
+            "JVBERi0=\r\n",
+            "--outer--\r\n",
+        );
+        let part = MimePart::parse(raw.as_bytes());
+        assert_eq!(part.parts.len(), 2);
+        let alternative = &part.parts[0];
+        assert_eq!(alternative.parts.len(), 2);
+        assert_eq!(alternative.parts[0].text(), "café");
+        assert_eq!(alternative.parts[1].content_type().value, "text/html");
+
+        let attachment = &part.parts[1];
+        assert_eq!(attachment.body, b"%PDF-");
+        assert_eq!(attachment.filename().as_deref(), Some("résumé.pdf"));
+        assert_eq!(attachment.content_id().as_deref(), Some("cv@example"));
+    }
+
+    #[test]
+    fn defaults_to_text_plain() {
+        let part = MimePart::parse(b"Content-Type: garbage\r\n\r\nbody");
+        assert_eq!(part.content_type().value, "text/plain");
+        // A multipart type without a boundary is kept as a leaf
+        let part = MimePart::parse(b"Content-Type: multipart/mixed\r\n\r\nbody");
+        assert!(part.parts.is_empty());
+        assert_eq!(part.body, b"body");
+        assert_eq!(MimePart::parse(b"").text(), "");
+    }
+}
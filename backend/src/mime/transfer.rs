@@ -0,0 +1,142 @@
+use base64::{decode_config, encode_config, STANDARD};
+
+// Longest line RFC 2045 allows in quoted-printable and base64 bodies
+const MAX_ENCODED_LINE: usize = 76;
+
+// Undo a Content-Transfer-Encoding. 7bit, 8bit, binary and unknown encodings pass through,
+// as does base64 that does not decode, so a mislabelled part is shown rather than lost.
+pub fn decode_transfer_encoding(encoding: Option<&str>, data: &[u8]) -> Vec<u8> {
+    match encoding.map(|e| e.trim().to_ascii_lowercase()).as_deref() {
+        Some("base64") => {
+            let compact: Vec<u8> = data.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+            decode_config(compact, STANDARD).unwrap_or_else(|_| data.to_vec())
+        }
+        Some("quoted-printable") => decode_quoted_printable(data, false),
+        _ => data.to_vec(),
+    }
+}
+
+// Quoted-printable as in RFC 2045: =XX escapes and =<newline> soft line breaks. With `underscores`
+// set, "_" stands for a space, as in RFC 2047 Q encoding. Malformed escapes are kept literally.
+pub fn decode_quoted_printable(data: &[u8], underscores: bool) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'=' => {
+                let rest = &data[i + 1..];
+                if rest.starts_with(b"\r\n") {
+                    i += 3;
+                } else if rest.starts_with(b"\n") {
+                    i += 2;
+                } else if let Some(byte) = rest.get(..2).and_then(hex_byte) {
+                    decoded.push(byte);
+                    i += 3;
+                } else {
+                    decoded.push(b'=');
+                    i += 1;
+                }
+            }
+            b'_' if underscores => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+// Quoted-printable for text bodies: lines end in CRLF, and longer lines are wrapped with soft breaks
+pub fn encode_quoted_printable(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len() + text.len() / 8);
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            encoded.push_str("\r\n");
+        }
+        let bytes = line.strip_suffix('\r').unwrap_or(line).as_bytes();
+        let mut width = 0;
+        for (at, &byte) in bytes.iter().enumerate() {
+            let piece = match byte {
+                // Whitespace at the end of a line may be stripped in transit
+                b' ' | b'\t' if at + 1 < bytes.len() => (byte as char).to_string(),
+                b'!'..=b'<' | b'>'..=b'~' => (byte as char).to_string(),
+                _ => format!("={:02X}", byte),
+            };
+            // Leave room for the "=" of a soft break
+            if width + piece.len() > MAX_ENCODED_LINE - 1 {
+                encoded.push_str("=\r\n");
+                width = 0;
+            }
+            encoded.push_str(&piece);
+            width += piece.len();
+        }
+    }
+    encoded
+}
+
+// Base64 for binary bodies, broken into lines
+pub fn encode_base64_lines(data: &[u8]) -> String {
+    let encoded = encode_config(data, STANDARD);
+    encoded.as_bytes()
+        .chunks(MAX_ENCODED_LINE)
+        .map(|line| std::str::from_utf8(line).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+fn hex_byte(digits: &[u8]) -> Option<u8> {
+    let text = std::str::from_utf8(digits).ok()?;
+    u8::from_str_radix(text, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_quoted_printable() {
+        assert_eq!(decode_quoted_printable(b"caf=C3=A9 =3D equal", false), "café = equal".as_bytes());
+        // Soft line breaks with either line ending
+        assert_eq!(decode_quoted_printable(b"one=\r\ntwo=\nthree", false), b"onetwothree");
+        assert_eq!(decode_quoted_printable(b"a_b", false), b"a_b");
+        assert_eq!(decode_quoted_printable(b"a_b=5F", true), b"a b_");
+    }
+
+    #[test]
+    fn keeps_malformed_and_truncated_escapes() {
+        assert_eq!(decode_quoted_printable(b"=ZZ=4", false), b"=ZZ=4");
+        assert_eq!(decode_quoted_printable(b"end=", false), b"end=");
+        assert_eq!(decode_quoted_printable(b"=\xc3\xa9", false), b"=\xc3\xa9");
+    }
+
+    #[test]
+    fn undoes_transfer_encodings() {
+        assert_eq!(decode_transfer_encoding(Some(" Base64 "), b"aGVs\r\nbG8=\r\n"), b"hello");
+        assert_eq!(decode_transfer_encoding(Some("quoted-printable"), b"h=C3=A9"), "hé".as_bytes());
+        assert_eq!(decode_transfer_encoding(Some("8bit"), b"as is"), b"as is");
+        assert_eq!(decode_transfer_encoding(None, b"as is"), b"as is");
+        // Base64 that does not decode is shown rather than lost
+        assert_eq!(decode_transfer_encoding(Some("base64"), b"not*base64"), b"not*base64");
+    }
+
+    #[test]
+    fn encoded_quoted_printable_round_trips() {
+        let text = format!("café\ntrailing space \n{}", "x".repeat(200));
+        let encoded = encode_quoted_printable(&text);
+        assert!(encoded.split("\r\n").all(|line| line.len() <= MAX_ENCODED_LINE));
+        assert!(encoded.contains("space=20\r\n"));
+        assert_eq!(decode_quoted_printable(encoded.as_bytes(), false), text.replace('\n', "\r\n").as_bytes());
+    }
+
+    #[test]
+    fn base64_lines_are_wrapped() {
+        let data: Vec<u8> = (0..=255).collect();
+        let encoded = encode_base64_lines(&data);
+        assert!(encoded.split("\r\n").all(|line| line.len() <= MAX_ENCODED_LINE));
+        assert_eq!(decode_transfer_encoding(Some("base64"), encoded.as_bytes()), data);
+    }
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+// What a personal access token may be used for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenScope {
+    ReadMail,
+    SendMail,
+    ManageKeys,
+}
+
+impl TokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::ReadMail => "read-mail",
+            TokenScope::SendMail => "send-mail",
+            TokenScope::ManageKeys => "manage-keys",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<TokenScope> {
+        match value {
+            "read-mail" => Some(TokenScope::ReadMail),
+            "send-mail" => Some(TokenScope::SendMail),
+            "manage-keys" => Some(TokenScope::ManageKeys),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateAccessTokenRequest {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    // Tokens without an expiry stay valid until revoked
+    pub expires_in_days: Option<i32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AccessTokenInfo {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    // First characters of the token, enough to recognise it
+    pub token_prefix: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub expires_at: Option<String>,
+}
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug)]
+pub struct DeleteAccountRequest {
+    // Must match the signed-in account, guarding against accidental deletion
+    pub confirm_email: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UndoAccountDeletionRequest {
+    pub receipt_id: String,
+    pub undo_token: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AccountDeletionReceipt {
+    pub receipt_id: String,
+    // pending, cancelled or completed
+    pub status: String,
+    pub requested_at: String,
+    pub scheduled_for: String,
+    pub completed_at: Option<String>,
+    pub google_grant_revoked: Option<bool>,
+    // Only returned once, when the deletion is scheduled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub undo_token: Option<String>,
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+// A non-text part of a message: a file attachment, or an inline part such as an embedded image
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AttachmentInfo {
+    // Gmail's partId, when the message came from Gmail
+    pub part_id: Option<String>,
+    pub filename: String,
+    pub mime_type: String,
+    // Decoded size in bytes
+    pub size: i64,
+    // Gmail's id for fetching a body it did not send along with the message
+    pub attachment_id: Option<String>,
+    // Content-ID without angle brackets; HTML refers to inline parts as cid:<content_id>
+    pub content_id: Option<String>,
+    // Shown within the message rather than offered as a download
+    pub inline: bool,
+}
+
+// A file to send with a direct message
+#[derive(Deserialize, Debug)]
+pub struct OutgoingAttachment {
+    pub filename: String,
+    // Sent as application/octet-stream when omitted
+    pub mime_type: Option<String>,
+    // Base64 of the content
+    pub data: String,
+}
@@ -2,7 +2,10 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize)]
 pub struct AuthQuery {
-    pub code: String,
+    pub code: Option<String>,
+    pub state: Option<String>,
+    // Set by the provider when the user cancels or the request is rejected
+    pub error: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -10,6 +13,21 @@ pub struct GoogleUserInfo {
     pub email: String,
     pub name: Option<String>,
     pub picture: Option<String>,
-    #[serde(skip_serializing)]
-    pub refresh_token: Option<String>,
+}
+
+// What the callback needs to finish a login, kept server-side under the OAuth state
+#[derive(Deserialize, Serialize)]
+pub struct OAuthLoginState {
+    pub provider: String,
+    pub pkce_verifier: String,
+    pub nonce: String,
+    // Set when a signed-in user is linking a Gmail mailbox rather than logging in
+    pub link_user: Option<String>,
+}
+
+// A sign-in option shown on the welcome page
+#[derive(Serialize)]
+pub struct IdentityProviderInfo {
+    pub id: String,
+    pub name: String,
 }
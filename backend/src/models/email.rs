@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use super::{AttachmentInfo, OutgoingAttachment};
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Email {
     pub id: String,
@@ -8,13 +10,25 @@ pub struct Email {
     pub sender_name: Option<String>,
     pub recipient_email: String,
     pub subject: String,
+    // Plain text; for HTML-only mail, a text rendering of the HTML
     pub body: String,
+    // Sanitized HTML, when the message has an HTML part
+    #[serde(default)]
+    pub html_body: Option<String>,
+    // Attachments and inline parts, without their content
+    #[serde(default)]
+    pub attachments: Vec<AttachmentInfo>,
     pub sent_at: String,
     pub read_at: Option<String>,
     pub gmail_id: Option<String>,
     pub label_ids: Option<Vec<String>>,
     pub is_encrypted: bool,
     pub raw_encrypted_content: Option<String>,
+    #[serde(default)]
+    pub recalled_at: Option<String>,
+    // Conversation the message belongs to: the id of its first message, or gmail_<threadId>
+    #[serde(default)]
+    pub thread_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -37,6 +51,27 @@ pub struct SendEmailRequest {
     pub subject: String,
     pub body: String,
     pub encrypt: Option<bool>,
+    // Passcode for recipients without an account; generated when omitted
+    pub passcode: Option<String>,
+    pub send_passcode_separately: Option<bool>,
+    // Id of the message this one answers; the reply joins its thread
+    pub in_reply_to: Option<String>,
+    // Send through Gmail as an ordinary email, content included, instead of a Quant notification.
+    // Only direct messages can carry the fields below.
+    pub direct: Option<bool>,
+    #[serde(default)]
+    pub cc: Vec<String>,
+    #[serde(default)]
+    pub bcc: Vec<String>,
+    pub reply_to: Option<String>,
+    pub html_body: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<OutgoingAttachment>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForwardEmailRequest {
+    pub recipient_email: String,
 }
 
 #[derive(Deserialize, Debug, Default)]
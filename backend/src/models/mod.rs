@@ -3,9 +3,25 @@ mod auth;
 mod response;
 mod email;
 mod label;
+mod portal;
+mod transparency;
+mod session;
+mod role;
+mod access_token;
+mod account;
+mod thread;
+mod attachment;
 
 // Re-export public items
-pub use auth::{AuthQuery, GoogleUserInfo};
+pub use auth::{AuthQuery, GoogleUserInfo, OAuthLoginState, IdentityProviderInfo};
 pub use response::UserResponse;
-pub use email::{Email, SendEmailRequest, EmailFilter, SortField, SortOrder};
+pub use email::{Email, SendEmailRequest, ForwardEmailRequest, EmailFilter, SortField, SortOrder};
 pub use label::{GmailLabel, LabelColor};
+pub use portal::{PortalOpenRequest, PortalReplyRequest, PortalMessage};
+pub use transparency::{LogEntriesQuery, InclusionProofQuery, ConsistencyProofQuery};
+pub use session::{SessionInfo, RevokeAllSessionsRequest};
+pub use role::{Role, GrantRoleRequest, AdminAuditEntry};
+pub use access_token::{TokenScope, CreateAccessTokenRequest, AccessTokenInfo};
+pub use account::{DeleteAccountRequest, UndoAccountDeletionRequest, AccountDeletionReceipt};
+pub use thread::{Thread, ThreadSummary, ThreadSource, ThreadListQuery, snippet};
+pub use attachment::{AttachmentInfo, OutgoingAttachment};
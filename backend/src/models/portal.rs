@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug)]
+pub struct PortalOpenRequest {
+    pub passcode: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PortalReplyRequest {
+    pub passcode: String,
+    pub body: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PortalMessage {
+    pub sender_email: String,
+    pub sender_name: Option<String>,
+    pub recipient_email: String,
+    pub subject: String,
+    pub body: String,
+    pub sent_at: String,
+}
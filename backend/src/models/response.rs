@@ -6,5 +6,7 @@ pub struct UserResponse {
     pub email: Option<String>,
     pub name: Option<String>,
     pub picture: Option<String>,
+    // Gmail address linked for reading and sending mail; None until a mailbox is linked
+    pub mailbox_email: Option<String>,
     pub message: Option<String>,
 }
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+// Access level stored on each user
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    // Read-only access to admin endpoints
+    Auditor,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Auditor => "auditor",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Role> {
+        match value {
+            "user" => Some(Role::User),
+            "auditor" => Some(Role::Auditor),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    // Auditors may look, only admins may change anything
+    pub fn can_read_admin(&self) -> bool {
+        matches!(self, Role::Admin | Role::Auditor)
+    }
+
+    pub fn can_write_admin(&self) -> bool {
+        matches!(self, Role::Admin)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GrantRoleRequest {
+    pub role: Role,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AdminAuditEntry {
+    pub id: i64,
+    pub actor_email: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub allowed: bool,
+    pub reason: String,
+    pub ip_address: Option<String>,
+    pub created_at: String,
+}
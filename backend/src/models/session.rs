@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Debug)]
+pub struct SessionInfo {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: String,
+    pub last_seen_at: String,
+    pub expires_at: String,
+    // True for the session making the request
+    pub current: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct RevokeAllSessionsRequest {
+    // Keep the requesting device signed in
+    pub keep_current: Option<bool>,
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+
+use super::Email;
+
+// Longest snippet shown for a thread's latest message, in characters
+const SNIPPET_LENGTH: usize = 160;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadSource {
+    // Messages sent through Quant Client, threaded by In-Reply-To and References
+    #[serde(rename = "quant")]
+    Quant,
+    // The linked Gmail mailbox, threaded by Gmail's threadId
+    #[serde(rename = "gmail")]
+    Gmail,
+}
+
+// One conversation in the thread list
+#[derive(Serialize, Debug, Clone)]
+pub struct ThreadSummary {
+    // The id of the thread's first message for Quant threads, gmail_<threadId> for Gmail ones
+    pub id: String,
+    pub source: ThreadSource,
+    // Subject of the first message
+    pub subject: String,
+    pub participants: Vec<String>,
+    pub message_count: i64,
+    pub unread_count: i64,
+    // Start of the latest message's body
+    pub snippet: String,
+    pub latest_at: String,
+}
+
+// A whole conversation, oldest message first
+#[derive(Serialize, Debug)]
+pub struct Thread {
+    #[serde(flatten)]
+    pub summary: ThreadSummary,
+    pub messages: Vec<Email>,
+}
+
+impl Thread {
+    // Summarise the messages of a thread as `user_email` sees it; `messages` must be oldest first
+    pub fn new(id: String, source: ThreadSource, user_email: &str, messages: Vec<Email>) -> Self {
+        let mut participants: Vec<String> = messages.iter()
+            .flat_map(|message| [message.sender_email.clone(), message.recipient_email.clone()])
+            .filter(|participant| !participant.is_empty())
+            .collect();
+        participants.sort();
+        participants.dedup();
+
+        let unread_count = messages.iter()
+            .filter(|message| match source {
+                ThreadSource::Quant => message.recipient_email == user_email && message.read_at.is_none(),
+                ThreadSource::Gmail => message.label_ids.as_ref().is_some_and(|labels| labels.iter().any(|l| l == "UNREAD")),
+            })
+            .count() as i64;
+
+        let latest = messages.last();
+        let summary = ThreadSummary {
+            id,
+            source,
+            subject: messages.first().map(|message| message.subject.clone()).unwrap_or_default(),
+            participants,
+            message_count: messages.len() as i64,
+            unread_count,
+            snippet: latest.map(|message| snippet(&message.body)).unwrap_or_default(),
+            latest_at: latest.map(|message| message.sent_at.clone()).unwrap_or_default(),
+        };
+        Thread { summary, messages }
+    }
+}
+
+// A body squeezed onto one line and cut to SNIPPET_LENGTH characters
+pub fn snippet(body: &str) -> String {
+    let line = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    match line.char_indices().nth(SNIPPET_LENGTH) {
+        Some((cut, _)) => format!("{}…", &line[..cut]),
+        None => line,
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ThreadListQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
@@ -0,0 +1,21 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+pub struct LogEntriesQuery {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InclusionProofQuery {
+    pub leaf_index: u64,
+    // Defaults to the latest tree head
+    pub tree_size: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ConsistencyProofQuery {
+    pub first: u64,
+    // Defaults to the latest tree head
+    pub second: Option<u64>,
+}
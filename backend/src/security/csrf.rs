@@ -0,0 +1,51 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header, Method},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use serde_json::json;
+use log::warn;
+
+use crate::config::AppConfig;
+
+// Header the frontend sends with every state-changing request. Setting it cross-origin needs a CORS
+// preflight, which only the allowed origins pass, so a forged form post or image request cannot carry it.
+pub const CSRF_HEADER: &str = "x-requested-with";
+
+// Reject cookie-authenticated, state-changing requests that did not come from an allowed origin.
+// Bearer tokens and anonymous requests are not sent automatically by browsers and pass untouched.
+pub async fn require_csrf_header<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let safe_method = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let cookie_authenticated = req.cookie("session").is_some() && !req.headers().contains_key(header::AUTHORIZATION);
+    if safe_method || !cookie_authenticated {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let has_header = req.headers().get(CSRF_HEADER).is_some_and(|value| !value.is_empty());
+    // Browsers always send Origin on cross-origin posts; when present it must be one we trust
+    let origin_allowed = match req.headers().get(header::ORIGIN).map(|value| value.to_str()) {
+        None => true,
+        Some(Ok(origin)) => req.app_data::<web::Data<AppConfig>>()
+            .is_some_and(|config| config.server.trusted_origins().iter().any(|trusted| trusted == origin)),
+        Some(Err(_)) => false,
+    };
+
+    let reason = match (has_header, origin_allowed) {
+        (true, true) => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+        (false, _) => "missing_csrf_header",
+        (true, false) => "origin_not_allowed",
+    };
+
+    warn!("Blocked {} {}: {}", req.method(), req.path(), reason);
+    let response = HttpResponse::Forbidden().json(json!({
+        "success": false,
+        "error": "Cross-site request blocked",
+        "reason": reason
+    }));
+    Ok(req.into_response(response).map_into_right_body())
+}
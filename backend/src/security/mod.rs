@@ -0,0 +1,49 @@
+// Browser-facing protections: CORS, response security headers and the CSRF guard
+mod csrf;
+
+pub use csrf::{require_csrf_header, CSRF_HEADER};
+
+use actix_cors::Cors;
+use actix_web::http::{header, Method};
+use actix_web::middleware::DefaultHeaders;
+
+use crate::config::ServerConfig;
+
+// The API only returns JSON and redirects, so nothing may be loaded, framed or submitted from its responses
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; frame-ancestors 'none'; base-uri 'none'; form-action 'none'";
+
+// How long browsers may cache a preflight response
+const CORS_MAX_AGE_SECONDS: usize = 3600;
+
+// Credentialed CORS limited to the configured origins
+pub fn cors(server: &ServerConfig) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods([Method::GET, Method::POST, Method::DELETE])
+        .allowed_headers([
+            header::ACCEPT,
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::HeaderName::from_static(CSRF_HEADER),
+        ])
+        .supports_credentials()
+        .max_age(CORS_MAX_AGE_SECONDS);
+    for origin in server.trusted_origins() {
+        cors = cors.allowed_origin(&origin);
+    }
+    cors
+}
+
+// Headers added to every response; `hsts` is set when this server terminates TLS
+pub fn security_headers(hsts: Option<&str>) -> DefaultHeaders {
+    let headers = DefaultHeaders::new()
+        .add((header::CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY))
+        .add((header::X_FRAME_OPTIONS, "DENY"))
+        .add((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        // View links and portal tokens travel in URLs; never leak them to other sites
+        .add((header::REFERRER_POLICY, "no-referrer"));
+
+    match hsts {
+        Some(hsts) => headers.add((header::STRICT_TRANSPORT_SECURITY, hsts.to_string())),
+        None => headers,
+    }
+}
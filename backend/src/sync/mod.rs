@@ -0,0 +1,336 @@
+// Keeps the Postgres copy of each user's Gmail mailbox current, and the Redis lists built from it
+mod watch;
+
+pub use watch::renew_watches;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use log::{info, warn, error};
+use serde_json::json;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cache::RedisCache;
+use crate::db;
+use crate::db::mailbox_sync::{self, SyncedMessage};
+use crate::gmail::{parse_gmail_message, GmailClient, GmailError, GmailMessage, MailboxChange, MessageListQuery};
+
+// How many of the newest messages a full sync copies
+const FULL_SYNC_MESSAGES: usize = 500;
+
+// Messages kept in each cached list
+const CACHED_LIST_SIZE: i64 = 200;
+
+// The cached lists rebuilt after a sync, and the Gmail label that selects their messages
+const CACHED_LISTS: [(&str, &str); 2] = [("received", "INBOX"), ("sent", "SENT")];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncMode {
+    // Changes since the stored historyId were applied
+    Incremental,
+    // The mailbox was copied again from scratch
+    Full,
+}
+
+impl SyncMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncMode::Incremental => "incremental",
+            SyncMode::Full => "full",
+        }
+    }
+}
+
+// What a sync did
+#[derive(Debug)]
+pub struct SyncOutcome {
+    pub mode: SyncMode,
+    pub added: usize,
+    pub deleted: usize,
+    pub relabeled: usize,
+    // The historyId the synced copy is now current as of
+    pub history_id: String,
+}
+
+#[derive(Debug)]
+pub enum SyncError {
+    Gmail(GmailError),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Gmail(e) => write!(f, "{}", e),
+            SyncError::Database(e) => write!(f, "Failed to store synced messages: {}", e),
+        }
+    }
+}
+
+impl From<GmailError> for SyncError {
+    fn from(e: GmailError) -> Self {
+        SyncError::Gmail(e)
+    }
+}
+
+impl From<sqlx::Error> for SyncError {
+    fn from(e: sqlx::Error) -> Self {
+        SyncError::Database(e)
+    }
+}
+
+impl ResponseError for SyncError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SyncError::Gmail(e) => e.status_code(),
+            SyncError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SyncError::Gmail(e) => e.error_response(),
+            SyncError::Database(_) => HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": "Failed to store synced messages",
+                "reason": "sync_storage_failed"
+            })),
+        }
+    }
+}
+
+// Bring the user's synced mailbox up to date. Applies the Gmail history since the stored historyId,
+// or copies the newest messages again when there is none or Gmail no longer has that far back.
+// The historyId is only advanced once every change is stored, so a failed sync is retried from the same point.
+pub async fn sync_mailbox(
+    pool: &PgPool,
+    gmail: &GmailClient,
+    cache: &RedisCache,
+    email: &str,
+    access_token: &str,
+) -> Result<SyncOutcome, SyncError> {
+    let outcome = match mailbox_sync::get_history_id(pool, email).await? {
+        Some(start_history_id) => match gmail.list_history(email, access_token, &start_history_id).await {
+            Ok(history) => {
+                let (outcome, touched) = apply_history(pool, gmail, email, access_token, history.changes, history.history_id).await?;
+                for gmail_id in &touched {
+                    cache.remove_cached_email(email, gmail_id).await
+                        .unwrap_or_else(|e| error!("Failed to drop cached email {}: {}", gmail_id, e));
+                }
+                outcome
+            }
+            Err(GmailError::NotFound(e)) => {
+                warn!("Gmail history {} for {} is no longer available ({}); running a full sync", start_history_id, email, e);
+                full_sync(pool, gmail, cache, email, access_token).await?
+            }
+            Err(e) => return Err(e.into()),
+        },
+        None => full_sync(pool, gmail, cache, email, access_token).await?,
+    };
+
+    refresh_cached_lists(pool, cache, email).await;
+
+    info!(
+        "{} sync for {}: {} added, {} deleted, {} relabeled, now at history {}",
+        outcome.mode.as_str(), email, outcome.added, outcome.deleted, outcome.relabeled, outcome.history_id
+    );
+    Ok(outcome)
+}
+
+// Sync the user's mailbox after Gmail reported a change at notified_history_id.
+// Returns None when there was nothing to do: the change is already synced or the user has no Gmail grant.
+pub async fn sync_after_notification(
+    pool: &PgPool,
+    gmail: &GmailClient,
+    cache: &RedisCache,
+    email: &str,
+    notified_history_id: &str,
+) -> Result<Option<SyncOutcome>, SyncError> {
+    // Gmail notifies once per change, so a burst of changes is usually covered by the first sync
+    if let Some(stored) = mailbox_sync::get_history_id(pool, email).await? {
+        if let (Ok(stored), Ok(notified)) = (stored.parse::<u64>(), notified_history_id.parse::<u64>()) {
+            if notified <= stored {
+                return Ok(None);
+            }
+        }
+    }
+
+    let refresh_token = match db::get_user_refresh_token(pool, email).await? {
+        Some(refresh_token) => refresh_token,
+        None => return Ok(None),
+    };
+    let access_token = gmail.get_token(email, &refresh_token).await?;
+    let outcome = sync_mailbox(pool, gmail, cache, email, &access_token).await?;
+
+    cache.set_last_sync(email).await
+        .unwrap_or_else(|e| error!("Failed to update last sync timestamp: {}", e));
+    Ok(Some(outcome))
+}
+
+// Apply history records in the order Gmail recorded them; returns the ids of every message touched
+async fn apply_history(
+    pool: &PgPool,
+    gmail: &GmailClient,
+    email: &str,
+    access_token: &str,
+    changes: Vec<MailboxChange>,
+    history_id: String,
+) -> Result<(SyncOutcome, Vec<String>), SyncError> {
+    let mut added_ids: Vec<String> = changes.iter()
+        .filter_map(|change| match change {
+            MailboxChange::MessageAdded(id) => Some(id.clone()),
+            _ => None,
+        })
+        .collect();
+    added_ids.sort();
+    added_ids.dedup();
+
+    let mut fetched: HashMap<String, SyncedMessage> = fetch_messages(gmail, email, access_token, added_ids).await?
+        .into_iter()
+        .map(|message| (message.gmail_id.clone(), message))
+        .collect();
+
+    let mut outcome = SyncOutcome {
+        mode: SyncMode::Incremental,
+        added: 0,
+        deleted: 0,
+        relabeled: 0,
+        history_id,
+    };
+    let mut touched = Vec::new();
+
+    for change in changes {
+        match change {
+            MailboxChange::MessageAdded(id) => {
+                // Gone again by the time we asked for it; its deletion is in the history too
+                if let Some(message) = fetched.remove(&id) {
+                    mailbox_sync::upsert_synced_message(pool, email, &message).await?;
+                    outcome.added += 1;
+                }
+                touched.push(id);
+            }
+            MailboxChange::MessageDeleted(id) => {
+                mailbox_sync::delete_synced_message(pool, email, &id).await?;
+                outcome.deleted += 1;
+                touched.push(id);
+            }
+            MailboxChange::LabelsAdded { message_id, label_ids } => {
+                mailbox_sync::update_synced_labels(pool, email, &message_id, &label_ids, &[]).await?;
+                outcome.relabeled += 1;
+                touched.push(message_id);
+            }
+            MailboxChange::LabelsRemoved { message_id, label_ids } => {
+                mailbox_sync::update_synced_labels(pool, email, &message_id, &[], &label_ids).await?;
+                outcome.relabeled += 1;
+                touched.push(message_id);
+            }
+        }
+    }
+
+    mailbox_sync::save_history_id(pool, email, &outcome.history_id).await?;
+
+    touched.sort();
+    touched.dedup();
+    Ok((outcome, touched))
+}
+
+// Copy the newest messages of the mailbox again, replacing whatever was synced before
+async fn full_sync(
+    pool: &PgPool,
+    gmail: &GmailClient,
+    cache: &RedisCache,
+    email: &str,
+    access_token: &str,
+) -> Result<SyncOutcome, SyncError> {
+    // Read the historyId before listing, so changes made while we list are picked up by the next sync
+    let profile = gmail.get_profile(email, access_token).await?;
+
+    let query = MessageListQuery {
+        max_messages: Some(FULL_SYNC_MESSAGES),
+        ..MessageListQuery::default()
+    };
+    let ids = gmail.collect_message_ids(email, access_token, query).await?
+        .into_iter()
+        .map(|message| message.id)
+        .collect();
+    let messages = fetch_messages(gmail, email, access_token, ids).await?;
+
+    mailbox_sync::replace_synced_messages(pool, email, &messages, &profile.history_id).await?;
+
+    // Every cached email may be stale now; the lists are rebuilt by the caller
+    cache.invalidate_user_cache(email).await
+        .unwrap_or_else(|e| error!("Failed to invalidate cache for {}: {}", email, e));
+
+    Ok(SyncOutcome {
+        mode: SyncMode::Full,
+        added: messages.len(),
+        deleted: 0,
+        relabeled: 0,
+        history_id: profile.history_id,
+    })
+}
+
+// Fetch message details in batches. Messages deleted in the meantime are skipped. A message whose part
+// of a batch failed otherwise is fetched once more on its own; if that fails too the sync fails,
+// so nothing is silently missed.
+async fn fetch_messages(
+    gmail: &GmailClient,
+    email: &str,
+    access_token: &str,
+    ids: Vec<String>,
+) -> Result<Vec<SyncedMessage>, GmailError> {
+    let mut messages = Vec::with_capacity(ids.len());
+    for batched in gmail.get_messages_batch(email, access_token, &ids).await? {
+        let result = match batched.result {
+            Err(GmailError::NotFound(_)) => continue,
+            Err(e) => {
+                warn!("Batched fetch of message {} failed ({}); fetching it on its own", batched.message_id, e);
+                gmail.get_message_detail(email, access_token, &batched.message_id).await
+            }
+            ok => ok,
+        };
+        match result {
+            Ok(message) => messages.push(synced_message(&message)),
+            Err(GmailError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(messages)
+}
+
+// The stored form of a message fetched from Gmail
+pub fn synced_message(message: &GmailMessage) -> SyncedMessage {
+    let (subject, sender_email, sender_name, recipient_email, body) = parse_gmail_message(message);
+
+    SyncedMessage {
+        gmail_id: message.id.clone(),
+        thread_id: message.thread_id.clone(),
+        label_ids: message.label_ids.clone().unwrap_or_default(),
+        sender_email,
+        sender_name: Some(sender_name),
+        recipient_email,
+        is_encrypted: subject.contains("[Q-ENCRYPTED]"),
+        subject,
+        attachments: body.all_attachments(),
+        html_body: body.html,
+        body: body.text,
+        internal_date: message.internal_date.as_deref().and_then(|date| date.parse().ok()).unwrap_or(0),
+    }
+}
+
+// Rebuild the cached received and sent lists from Postgres. Redis is only a cache,
+// so a failure here is logged and the lists are rebuilt on the next sync.
+async fn refresh_cached_lists(pool: &PgPool, cache: &RedisCache, email: &str) {
+    for (category, label_id) in CACHED_LISTS {
+        let emails: Vec<_> = match mailbox_sync::get_synced_messages(pool, email, label_id, CACHED_LIST_SIZE).await {
+            Ok(messages) => messages.iter().map(SyncedMessage::to_email).collect(),
+            Err(e) => {
+                error!("Failed to load synced {} emails for {}: {}", category, email, e);
+                continue;
+            }
+        };
+
+        cache.cache_emails_paginated(email, category, &emails, None).await
+            .unwrap_or_else(|e| error!("Failed to cache {} emails: {}", category, e));
+    }
+}
@@ -0,0 +1,35 @@
+use log::{info, warn, error};
+use sqlx::PgPool;
+
+use crate::config::GmailPushConfig;
+use crate::db::mailbox_sync;
+use crate::gmail::{GmailClient, GmailError};
+
+// Register a Gmail watch for every linked mailbox that has none on the configured topic,
+// and renew the ones about to expire. Failures are logged and retried on the next run.
+pub async fn renew_watches(pool: &PgPool, gmail: &GmailClient, push: &GmailPushConfig) {
+    let due = match mailbox_sync::get_mailboxes_due_for_watch(pool, &push.topic, push.renew_before_seconds as i64).await {
+        Ok(due) => due,
+        Err(e) => {
+            error!("Failed to list mailboxes due for a Gmail watch: {}", e);
+            return;
+        }
+    };
+
+    for (email, refresh_token) in due {
+        let watch = match gmail.get_token(&email, &refresh_token).await {
+            Ok(access_token) => gmail.watch(&email, &access_token, &push.topic, &push.label_ids).await,
+            Err(e) => Err(e),
+        };
+
+        match watch {
+            Ok(watch) => match mailbox_sync::save_watch(pool, &email, &push.topic, watch.expires_at_millis()).await {
+                Ok(()) => info!("Gmail watch for {} renewed until {}", email, watch.expiration),
+                Err(e) => error!("Failed to record Gmail watch for {}: {}", email, e),
+            },
+            // Nothing to renew until the user grants access again
+            Err(e @ GmailError::AuthRevoked(_)) => warn!("Cannot watch mailbox of {}: {}", email, e),
+            Err(e) => error!("Failed to register Gmail watch for {}: {}", email, e),
+        }
+    }
+}
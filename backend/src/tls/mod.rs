@@ -0,0 +1,124 @@
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{AppConfig, TlsConfig};
+
+// Read a PEM certificate chain and private key into a key rustls can serve
+fn load_certified_key(tls: &TlsConfig) -> io::Result<CertifiedKey> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let open = |path: &str| File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot open {}: {}", path, e)));
+
+    let mut cert_reader = open(&tls.cert_path)?;
+    let chain: Vec<Certificate> = rustls_pemfile::certs(&mut cert_reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if chain.is_empty() {
+        return Err(invalid(format!("no certificates found in {}", tls.cert_path)));
+    }
+
+    let mut key_reader = open(&tls.key_path)?;
+    let key = rustls_pemfile::read_all(&mut key_reader)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid(format!("no private key found in {}", tls.key_path)))?;
+    let signing_key = any_supported_type(&key)
+        .map_err(|e| invalid(format!("unsupported private key in {}: {}", tls.key_path, e)))?;
+
+    Ok(CertifiedKey::new(chain, signing_key))
+}
+
+fn modified_at(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Hands every handshake the most recently loaded certificate
+pub struct CertificateResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.current.read().ok().map(|key| key.clone())
+    }
+}
+
+// Load the certificate and build the rustls config the HTTPS listener binds with
+pub fn server_config(tls: &TlsConfig) -> io::Result<(ServerConfig, Arc<CertificateResolver>)> {
+    let resolver = Arc::new(CertificateResolver {
+        current: RwLock::new(Arc::new(load_certified_key(tls)?)),
+    });
+
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok((config, resolver))
+}
+
+// Poll the certificate files and swap in a renewed certificate without a restart.
+// A failed reload keeps serving the previous certificate.
+pub fn spawn_certificate_reload(tls: TlsConfig, resolver: Arc<CertificateResolver>) {
+    actix_web::rt::spawn(async move {
+        let mut last_seen = (modified_at(&tls.cert_path), modified_at(&tls.key_path));
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(tls.reload_interval_seconds));
+        loop {
+            interval.tick().await;
+            let seen = (modified_at(&tls.cert_path), modified_at(&tls.key_path));
+            if seen == last_seen {
+                continue;
+            }
+
+            match load_certified_key(&tls) {
+                Ok(key) => {
+                    if let Ok(mut current) = resolver.current.write() {
+                        *current = Arc::new(key);
+                    }
+                    last_seen = seen;
+                    log::info!("Reloaded TLS certificate from {}", tls.cert_path);
+                }
+                // Renewal tools may write the two files one after the other; try again next tick
+                Err(e) => log::warn!("Keeping the current TLS certificate: {}", e),
+            }
+        }
+    });
+}
+
+// Strict-Transport-Security value, or None when HSTS is disabled
+pub fn hsts_header(tls: &TlsConfig) -> Option<String> {
+    if tls.hsts_max_age_seconds == 0 {
+        return None;
+    }
+    let mut value = format!("max-age={}", tls.hsts_max_age_seconds);
+    if tls.hsts_include_subdomains {
+        value.push_str("; includeSubDomains");
+    }
+    Some(value)
+}
+
+// Default service of the plain HTTP listener: send every request to the same path on the public HTTPS URL.
+// The target comes from backend_url rather than the Host header, so it cannot be pointed elsewhere.
+pub async fn redirect_to_https(req: HttpRequest, config: web::Data<AppConfig>) -> HttpResponse {
+    let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let location = format!("{}{}", config.server.backend_url.trim_end_matches('/'), path);
+
+    // 308 keeps the method and body of a POST
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
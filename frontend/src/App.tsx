@@ -1,27 +1,52 @@
 // App.tsx
 import React, { useEffect, useState } from 'react';
 import './App.css';
-import { Welcome, Dashboard } from './pages';
+import { Welcome, Dashboard, ViewLinkError, Portal } from './pages';
 import { AuthProvider, useAuth } from './context/AuthContext';
+import { EmailService } from './services/EmailService';
+
+// View links survive the login redirect in session storage
+const PENDING_VIEW_TOKEN_KEY = 'pendingViewToken';
 
 // Wrapper component to handle routing based on authentication
 const AppRouter = () => {
     const { isAuthenticated, isLoading, userEmail } = useAuth();
     const [messageToView, setMessageToView] = useState<string | null>(null);
+    const [viewLinkError, setViewLinkError] = useState<{ token: string; reason: string; canRequestNew: boolean } | null>(null);
 
     useEffect(() => {
-        // Parse URL parameters to check for view parameter
+        // Parse URL parameters to check for a signed view link
         const params = new URLSearchParams(window.location.search);
         const viewParam = params.get('view');
         if (viewParam) {
-            setMessageToView(viewParam);
+            sessionStorage.setItem(PENDING_VIEW_TOKEN_KEY, viewParam);
             // Clear the parameter from URL to avoid reopening the same message
             window.history.replaceState(null, '', window.location.pathname);
         }
+
+        // Check the link before loading anything; wait for login if needed
+        const pendingToken = sessionStorage.getItem(PENDING_VIEW_TOKEN_KEY);
+        if (pendingToken && !isLoading) {
+            EmailService.verifyViewLink(pendingToken).then(result => {
+                if (result.success && result.emailId) {
+                    sessionStorage.removeItem(PENDING_VIEW_TOKEN_KEY);
+                    setMessageToView(result.emailId);
+                } else if (result.reason !== 'login_required') {
+                    sessionStorage.removeItem(PENDING_VIEW_TOKEN_KEY);
+                    setViewLinkError({ token: pendingToken, reason: result.reason || 'invalid', canRequestNew: !!result.canRequestNew });
+                }
+            });
+        }
         
         console.log('Auth state changed:', { isAuthenticated, isLoading, userEmail, currentPath: window.location.pathname });
     }, [isAuthenticated, isLoading, userEmail]);
 
+    // The secure portal works without an account
+    const portalMatch = window.location.pathname.match(/^\/portal\/([^/]+)$/);
+    if (portalMatch) {
+        return <Portal token={portalMatch[1]} />;
+    }
+
     // Show loading state
     if (isLoading) {
         console.log('App is loading...');
@@ -32,6 +57,18 @@ const AppRouter = () => {
         );
     }
 
+    // Show a friendly page for expired, forwarded or broken view links
+    if (viewLinkError) {
+        return (
+            <ViewLinkError
+                token={viewLinkError.token}
+                reason={viewLinkError.reason}
+                canRequestNew={viewLinkError.canRequestNew}
+                onDismiss={() => setViewLinkError(null)}
+            />
+        );
+    }
+
     // If authenticated, always show dashboard
     if (isAuthenticated && userEmail) {
         console.log('User is authenticated as:', userEmail);
@@ -0,0 +1,104 @@
+// Portal.tsx
+import React, { useState } from 'react';
+import { PortalService, PortalMessage } from '../services/PortalService';
+
+interface PortalProps {
+    token: string;
+}
+
+// Minimal page for recipients without a Quant Client account
+function Portal({ token }: PortalProps) {
+    const [passcode, setPasscode] = useState('');
+    const [message, setMessage] = useState<PortalMessage | null>(null);
+    const [error, setError] = useState<string | null>(null);
+    const [reply, setReply] = useState('');
+    const [replyStatus, setReplyStatus] = useState<string | null>(null);
+    const [isBusy, setIsBusy] = useState(false);
+
+    const handleOpen = async (e: React.FormEvent) => {
+        e.preventDefault();
+        setIsBusy(true);
+        setError(null);
+        const result = await PortalService.openMessage(token, passcode);
+        if (result.success && result.data) {
+            setMessage(result.data);
+        } else {
+            setError(result.error || 'Unable to open message');
+        }
+        setIsBusy(false);
+    };
+
+    const handleReply = async (e: React.FormEvent) => {
+        e.preventDefault();
+        setIsBusy(true);
+        const result = await PortalService.reply(token, passcode, reply);
+        setReplyStatus(result.success ? 'Your reply was sent securely.' : result.error || 'Unable to send reply');
+        if (result.success) {
+            setReply('');
+        }
+        setIsBusy(false);
+    };
+
+    return (
+        <div className="bg-black min-h-screen flex items-center justify-center text-white p-4">
+            <div className="bg-gray-900/70 backdrop-blur-lg rounded-lg p-6 w-full max-w-2xl border border-gray-800/50 shadow-lg">
+                <h1 className="text-xl font-medium mb-1">Quant Client Secure Portal</h1>
+                <p className="text-gray-400 text-sm mb-6">A protected message was sent to you. No account is needed to read it.</p>
+
+                {!message && (
+                    <form onSubmit={handleOpen} className="space-y-4">
+                        <label className="block text-sm text-gray-300">
+                            Passcode
+                            <input
+                                type="text"
+                                value={passcode}
+                                onChange={e => setPasscode(e.target.value)}
+                                autoComplete="off"
+                                className="mt-1 w-full bg-gray-800 border border-gray-700 rounded-lg px-3 py-2 text-white tracking-widest"
+                                placeholder="XXXX-XXXX-XXXX"
+                            />
+                        </label>
+                        {error && <p className="text-red-400 text-sm">{error}</p>}
+                        <button
+                            type="submit"
+                            disabled={isBusy || !passcode}
+                            className="bg-gradient-to-r from-blue-500 to-purple-600 hover:from-blue-600 hover:to-purple-700 text-white px-4 py-2 rounded-lg font-medium text-sm disabled:opacity-50"
+                        >
+                            {isBusy ? 'Opening...' : 'Open message'}
+                        </button>
+                    </form>
+                )}
+
+                {message && (
+                    <div>
+                        <div className="border-b border-gray-800 pb-4 mb-4">
+                            <h2 className="text-lg font-medium">{message.subject}</h2>
+                            <p className="text-gray-400 text-sm">From {message.sender_name || message.sender_email}</p>
+                        </div>
+                        <p className="text-gray-200 whitespace-pre-wrap mb-6">{message.body}</p>
+
+                        <form onSubmit={handleReply} className="space-y-3">
+                            <textarea
+                                value={reply}
+                                onChange={e => setReply(e.target.value)}
+                                rows={5}
+                                className="w-full bg-gray-800 border border-gray-700 rounded-lg px-3 py-2 text-white"
+                                placeholder="Write a secure reply..."
+                            />
+                            {replyStatus && <p className="text-blue-400 text-sm">{replyStatus}</p>}
+                            <button
+                                type="submit"
+                                disabled={isBusy || !reply.trim()}
+                                className="bg-gradient-to-r from-blue-500 to-purple-600 hover:from-blue-600 hover:to-purple-700 text-white px-4 py-2 rounded-lg font-medium text-sm disabled:opacity-50"
+                            >
+                                {isBusy ? 'Sending...' : 'Send secure reply'}
+                            </button>
+                        </form>
+                    </div>
+                )}
+            </div>
+        </div>
+    );
+}
+
+export default Portal;
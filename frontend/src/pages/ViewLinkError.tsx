@@ -0,0 +1,67 @@
+// ViewLinkError.tsx
+import React, { useState } from 'react';
+import { EmailService } from '../services/EmailService';
+
+interface ViewLinkErrorProps {
+    token: string;
+    reason: string;
+    canRequestNew: boolean;
+    onDismiss: () => void;
+}
+
+const MESSAGES: Record<string, { title: string; description: string }> = {
+    expired: {
+        title: 'This link has expired',
+        description: 'Links in notification emails are only valid for a limited time to keep your messages safe.',
+    },
+    wrong_recipient: {
+        title: 'This link was meant for someone else',
+        description: 'The message was sent to a different account. If it was forwarded to you, ask the sender to send it to you directly.',
+    },
+    invalid: {
+        title: 'This link is not valid',
+        description: 'The link may have been copied incorrectly. Please open it again from the original notification email.',
+    },
+};
+
+function ViewLinkError({ token, reason, canRequestNew, onDismiss }: ViewLinkErrorProps) {
+    const [status, setStatus] = useState<string | null>(null);
+    const [isRequesting, setIsRequesting] = useState(false);
+    const content = MESSAGES[reason] || MESSAGES.invalid;
+
+    const handleRequestNew = async () => {
+        setIsRequesting(true);
+        const result = await EmailService.requestNewViewLink(token);
+        setStatus(result.success ? 'A new link is on its way to the recipient\'s inbox.' : result.message);
+        setIsRequesting(false);
+    };
+
+    return (
+        <div className="bg-black min-h-screen flex items-center justify-center text-white">
+            <div className="bg-gray-900/70 backdrop-blur-lg rounded-lg p-6 max-w-md mx-auto border border-gray-800/50 shadow-lg text-center">
+                <h1 className="text-xl font-medium mb-2">{content.title}</h1>
+                <p className="text-gray-400 text-sm mb-6">{content.description}</p>
+                {status && <p className="text-blue-400 text-sm mb-4">{status}</p>}
+                <div className="flex justify-center gap-3">
+                    {canRequestNew && !status && (
+                        <button
+                            onClick={handleRequestNew}
+                            disabled={isRequesting}
+                            className="bg-gradient-to-r from-blue-500 to-purple-600 hover:from-blue-600 hover:to-purple-700 text-white px-4 py-2 rounded-lg font-medium text-sm disabled:opacity-50"
+                        >
+                            {isRequesting ? 'Requesting...' : 'Request a new link'}
+                        </button>
+                    )}
+                    <button
+                        onClick={onDismiss}
+                        className="bg-gray-800 hover:bg-gray-700 text-white px-4 py-2 rounded-lg font-medium text-sm"
+                    >
+                        Go to inbox
+                    </button>
+                </div>
+            </div>
+        </div>
+    );
+}
+
+export default ViewLinkError;
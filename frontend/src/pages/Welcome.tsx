@@ -5,6 +5,44 @@ function Welcome() {
     const aboutSectionRef = useRef<HTMLDivElement>(null);
     const [mousePosition, setMousePosition] = useState({ x: 0, y: 0 });
     const [activeFaq, setActiveFaq] = useState<number | null>(null);
+    const [authError, setAuthError] = useState<string | null>(null);
+    const [ssoProviders, setSsoProviders] = useState<{ id: string; name: string }[]>([]);
+
+    // Messages for failed logins reported by the backend callback
+    const authErrorMessages: { [reason: string]: string } = {
+        access_denied: 'Sign-in was cancelled. Please try again when you are ready.',
+        missing_state: 'Your sign-in session could not be verified. Please sign in again.',
+        state_mismatch: 'Your sign-in session could not be verified. Please sign in again.',
+        state_expired: 'Your sign-in attempt took too long and expired. Please sign in again.',
+        missing_code: 'Your sign-in provider did not complete the sign-in. Please try again.',
+        exchange_failed: 'We could not complete the sign-in with your provider. Please try again.',
+        provider_error: 'Your sign-in provider reported an error. Please try again.',
+        unknown_provider: 'That sign-in option is not available.',
+        invalid_id_token: 'Your sign-in could not be verified. Please sign in again.',
+        email_unverified: 'Your provider has not verified your email address, so it cannot be used to sign in.',
+        identity_conflict: 'An account with this email already exists. Sign in the way you did before, then link this provider.',
+        mailbox_in_use: 'That Gmail mailbox is already linked to another account. Unlink it there first.',
+        unavailable: 'Sign-in is temporarily unavailable. Please try again in a moment.',
+        deletion_pending: 'This account is scheduled for deletion. Use the undo link from your deletion receipt to restore it.',
+    };
+
+    useEffect(() => {
+        const params = new URLSearchParams(window.location.search);
+        const reason = params.get('auth_error');
+        if (reason) {
+            setAuthError(authErrorMessages[reason] || 'Sign-in failed. Please try again.');
+            window.history.replaceState(null, '', window.location.pathname);
+        }
+        // eslint-disable-next-line react-hooks/exhaustive-deps
+    }, []);
+
+    // Single sign-on options configured on the backend besides Google
+    useEffect(() => {
+        fetch('http://localhost:8080/api/auth/providers')
+            .then((response) => response.json())
+            .then((data) => setSsoProviders((data.providers || []).filter((p: { id: string }) => p.id !== 'google')))
+            .catch(() => setSsoProviders([]));
+    }, []);
 
     // FAQ data
     const faqItems = [
@@ -96,6 +134,10 @@ function Welcome() {
         window.location.href = 'http://localhost:8080/auth/google';
     };
 
+    const handleSsoLogin = (providerId: string) => {
+        window.location.href = `http://localhost:8080/auth/${encodeURIComponent(providerId)}`;
+    };
+
     const scrollToAbout = () => {
         aboutSectionRef.current?.scrollIntoView({ behavior: 'smooth' });
     };
@@ -199,20 +241,42 @@ function Welcome() {
                     </div>
                     <h1 className="text-xl font-bold bg-gradient-to-r from-gray-400 to-gray-600 bg-clip-text text-transparent">Quantum Email</h1>
                 </div>
-                <button
-                    onClick={handleGoogleLogin}
-                    className="bg-gray-700 hover:bg-gray-600 text-white px-4 py-2 rounded-lg font-medium transition-all duration-200 flex items-center hover:scale-105 hover:shadow-lg hover:shadow-gray-500/25"
-                >
-                    <svg xmlns="http://www.w3.org/2000/svg" className="h-5 w-5 mr-2" viewBox="0 0 24 24" fill="currentColor">
-                        <path d="M22.56 12.25c0-.78-.07-1.53-.2-2.25H12v4.26h5.92c-.26 1.37-1.04 2.53-2.21 3.31v2.77h3.57c2.08-1.92 3.28-4.74 3.28-8.09z" fill="#4285F4"/>
-                        <path d="M12 23c2.97 0 5.46-.98 7.28-2.66l-3.57-2.77c-.98.66-2.23 1.06-3.71 1.06-2.86 0-5.29-1.93-6.16-4.53H2.18v2.84C3.99 20.53 7.7 23 12 23z" fill="#34A853"/>
-                        <path d="M5.84 14.09c-.22-.66-.35-1.36-.35-2.09s.13-1.43.35-2.09V7.07H2.18C1.43 8.55 1 10.22 1 12s.43 3.45 1.18 4.93l2.85-2.22.81-.62z" fill="#FBBC05"/>
-                        <path d="M12 5.38c1.62 0 3.06.56 4.21 1.64l3.15-3.15C17.45 2.09 14.97 1 12 1 7.7 1 3.99 3.47 2.18 7.07l3.66 2.84c.87-2.6 3.3-4.53 6.16-4.53z" fill="#EA4335"/>
-                        <path d="M1 1h22v22H1z" fill="none"/>
-                    </svg>
-                    Sign in with Google
-                </button>
+                <div className="flex items-center">
+                    <button
+                        onClick={handleGoogleLogin}
+                        className="bg-gray-700 hover:bg-gray-600 text-white px-4 py-2 rounded-lg font-medium transition-all duration-200 flex items-center hover:scale-105 hover:shadow-lg hover:shadow-gray-500/25"
+                    >
+                        <svg xmlns="http://www.w3.org/2000/svg" className="h-5 w-5 mr-2" viewBox="0 0 24 24" fill="currentColor">
+                            <path d="M22.56 12.25c0-.78-.07-1.53-.2-2.25H12v4.26h5.92c-.26 1.37-1.04 2.53-2.21 3.31v2.77h3.57c2.08-1.92 3.28-4.74 3.28-8.09z" fill="#4285F4"/>
+                            <path d="M12 23c2.97 0 5.46-.98 7.28-2.66l-3.57-2.77c-.98.66-2.23 1.06-3.71 1.06-2.86 0-5.29-1.93-6.16-4.53H2.18v2.84C3.99 20.53 7.7 23 12 23z" fill="#34A853"/>
+                            <path d="M5.84 14.09c-.22-.66-.35-1.36-.35-2.09s.13-1.43.35-2.09V7.07H2.18C1.43 8.55 1 10.22 1 12s.43 3.45 1.18 4.93l2.85-2.22.81-.62z" fill="#FBBC05"/>
+                            <path d="M12 5.38c1.62 0 3.06.56 4.21 1.64l3.15-3.15C17.45 2.09 14.97 1 12 1 7.7 1 3.99 3.47 2.18 7.07l3.66 2.84c.87-2.6 3.3-4.53 6.16-4.53z" fill="#EA4335"/>
+                            <path d="M1 1h22v22H1z" fill="none"/>
+                        </svg>
+                        Sign in with Google
+                    </button>
+                    {ssoProviders.map((provider) => (
+                        <button
+                            key={provider.id}
+                            onClick={() => handleSsoLogin(provider.id)}
+                            className="ml-3 bg-gray-800 hover:bg-gray-700 text-white px-4 py-2 rounded-lg font-medium transition-all duration-200 hover:scale-105"
+                        >
+                            Sign in with {provider.name}
+                        </button>
+                    ))}
+                </div>
             </header>
+            {authError && (
+                <div className="mx-6 mb-4 z-10 relative bg-red-900/40 border border-red-700 text-red-200 px-4 py-3 rounded-lg flex items-center justify-between">
+                    <span>{authError}</span>
+                    <button
+                        onClick={handleGoogleLogin}
+                        className="ml-4 bg-gray-700 hover:bg-gray-600 text-white px-3 py-1 rounded-lg font-medium"
+                    >
+                        Try again
+                    </button>
+                </div>
+            )}
             <main className="flex-grow flex flex-col items-center px-4">
                 <div className="h-[90vh] flex items-center justify-center">
                     <div className="max-w-4xl mx-auto text-center">
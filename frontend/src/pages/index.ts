@@ -1,2 +1,3 @@
 export { default as Welcome } from "./Welcome"
-export { default as Dashboard } from "./Dashboard"
\ No newline at end of file
+export { default as Dashboard } from "./Dashboard"export { default as ViewLinkError } from "./ViewLinkError"
+export { default as Portal } from "./Portal"
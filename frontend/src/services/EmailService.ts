@@ -271,6 +271,7 @@ export const EmailService = {
         credentials: 'include',
         headers: {
           'Content-Type': 'application/json',
+          'X-Requested-With': 'XMLHttpRequest',
         },
         body: JSON.stringify(emailRequest),
       });
@@ -306,6 +307,7 @@ export const EmailService = {
         credentials: 'include',
         headers: {
           'Content-Type': 'application/json',
+          'X-Requested-With': 'XMLHttpRequest',
         },
       });
       
@@ -379,6 +381,7 @@ export const EmailService = {
         credentials: 'include',
         headers: {
           'Content-Type': 'application/json',
+          'X-Requested-With': 'XMLHttpRequest',
         }
       });
       
@@ -405,6 +408,7 @@ export const EmailService = {
         credentials: 'include',
         headers: {
           'Content-Type': 'application/json',
+          'X-Requested-With': 'XMLHttpRequest',
         },
         body: JSON.stringify(draftRequest),
       });
@@ -497,6 +501,7 @@ export const EmailService = {
         credentials: 'include',
         headers: {
           'Content-Type': 'application/json',
+          'X-Requested-With': 'XMLHttpRequest',
         },
         body: JSON.stringify(payload),
       });
@@ -554,6 +559,50 @@ export const EmailService = {
     }
   },
 
+  // Check a signed view link from a notification email before opening the message
+  async verifyViewLink(token: string): Promise<{ success: boolean, emailId?: string, reason?: string, canRequestNew?: boolean }> {
+    try {
+      const response = await fetch(`${API_URL}/api/view/${encodeURIComponent(token)}`, {
+        method: 'GET',
+        credentials: 'include',
+        headers: {
+          'Content-Type': 'application/json',
+        },
+      });
+
+      const data = await response.json();
+      if (!response.ok || !data.success) {
+        return { success: false, reason: data.reason || 'invalid', canRequestNew: !!data.can_request_new };
+      }
+
+      return { success: true, emailId: data.email_id };
+    } catch (error) {
+      console.error('Error verifying view link:', error);
+      return { success: false, reason: 'invalid', canRequestNew: false };
+    }
+  },
+
+  // Ask for a fresh view link to be sent to the recipient's inbox
+  async requestNewViewLink(token: string): Promise<{ success: boolean, message: string }> {
+    try {
+      const response = await fetch(`${API_URL}/api/view/renew`, {
+        method: 'POST',
+        credentials: 'include',
+        headers: {
+          'Content-Type': 'application/json',
+          'X-Requested-With': 'XMLHttpRequest',
+        },
+        body: JSON.stringify({ token }),
+      });
+
+      const data = await response.json();
+      return { success: !!data.success, message: data.message || data.error || 'Unable to request a new link' };
+    } catch (error) {
+      console.error('Error requesting new view link:', error);
+      return { success: false, message: 'Unable to request a new link' };
+    }
+  },
+
   // Restore email from trash
   async restoreFromTrash(emailId: string): Promise<boolean> {
     try {
@@ -562,6 +611,7 @@ export const EmailService = {
         credentials: 'include',
         headers: {
           'Content-Type': 'application/json',
+          'X-Requested-With': 'XMLHttpRequest',
         },
       });
 
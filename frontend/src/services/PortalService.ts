@@ -0,0 +1,66 @@
+// PortalService.ts
+const API_URL = 'http://localhost:8080';
+
+export interface PortalMessage {
+  sender_email: string;
+  sender_name: string | null;
+  recipient_email: string;
+  subject: string;
+  body: string;
+  sent_at: string;
+}
+
+interface PortalResult<T> {
+  success: boolean;
+  data?: T;
+  error?: string;
+  reason?: string;
+}
+
+export const PortalService = {
+  // Open a protected message with the passcode shared by the sender
+  async openMessage(token: string, passcode: string): Promise<PortalResult<PortalMessage>> {
+    try {
+      const response = await fetch(`${API_URL}/api/portal/${encodeURIComponent(token)}/open`, {
+        method: 'POST',
+        headers: {
+          'Content-Type': 'application/json',
+        },
+        body: JSON.stringify({ passcode }),
+      });
+
+      const data = await response.json();
+      if (!response.ok || !data.success) {
+        return { success: false, error: data.error || 'Unable to open message', reason: data.reason };
+      }
+
+      return { success: true, data: data.message };
+    } catch (error) {
+      console.error('Error opening portal message:', error);
+      return { success: false, error: 'Unable to reach the server' };
+    }
+  },
+
+  // Send a reply back to the original sender
+  async reply(token: string, passcode: string, body: string): Promise<PortalResult<void>> {
+    try {
+      const response = await fetch(`${API_URL}/api/portal/${encodeURIComponent(token)}/reply`, {
+        method: 'POST',
+        headers: {
+          'Content-Type': 'application/json',
+        },
+        body: JSON.stringify({ passcode, body }),
+      });
+
+      const data = await response.json();
+      if (!response.ok || !data.success) {
+        return { success: false, error: data.error || 'Unable to send reply', reason: data.reason };
+      }
+
+      return { success: true };
+    } catch (error) {
+      console.error('Error sending portal reply:', error);
+      return { success: false, error: 'Unable to reach the server' };
+    }
+  },
+};
@@ -31,6 +31,8 @@ export interface SendEmailRequest {
   subject: string;
   body: string;
   encrypt?: boolean;
+  passcode?: string; // For recipients without an account; generated when omitted
+  send_passcode_separately?: boolean;
 }
 
 export interface SaveDraftRequest {